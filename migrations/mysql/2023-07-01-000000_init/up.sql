@@ -55,4 +55,3 @@ ALTER TABLE `access_profiles_permissions` ADD FOREIGN KEY (`permission_id`) REFE
 ALTER TABLE `users_permissions` ADD FOREIGN KEY (`user_id`) REFERENCES `users` (`id`);
 
 ALTER TABLE `users_permissions` ADD FOREIGN KEY (`permission_id`) REFERENCES `permissions` (`id`);
-
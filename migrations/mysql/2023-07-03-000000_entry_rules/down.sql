@@ -0,0 +1,3 @@
+DROP TABLE `users_pins`;
+ALTER TABLE `access_profiles_settings` DROP COLUMN `two_person_window`;
+ALTER TABLE `access_profiles_settings` DROP COLUMN `entry_rule`;
@@ -0,0 +1,10 @@
+ALTER TABLE `access_profiles_settings` ADD COLUMN `entry_rule` varchar(255) NOT NULL DEFAULT 'single';
+
+ALTER TABLE `access_profiles_settings` ADD COLUMN `two_person_window` int NOT NULL DEFAULT 30;
+
+CREATE TABLE `users_pins` (
+  `user_id` int PRIMARY KEY,
+  `pin_hash` varchar(255) NOT NULL
+);
+
+ALTER TABLE `users_pins` ADD FOREIGN KEY (`user_id`) REFERENCES `users` (`id`);
@@ -0,0 +1,13 @@
+CREATE TABLE `visitor_passes` (
+  `id` int PRIMARY KEY AUTO_INCREMENT,
+  `guest_name` varchar(255) NOT NULL,
+  `host_user` int NOT NULL,
+  `code` varchar(255) UNIQUE NOT NULL,
+  `valid_from` datetime NOT NULL,
+  `valid_until` datetime NOT NULL,
+  `max_uses` int NOT NULL,
+  `uses` int NOT NULL,
+  `entered_at` datetime
+);
+
+ALTER TABLE `visitor_passes` ADD FOREIGN KEY (`host_user`) REFERENCES `users` (`id`);
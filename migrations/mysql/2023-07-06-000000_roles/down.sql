@@ -0,0 +1,3 @@
+DROP TABLE `web_ui_users_roles`;
+DROP TABLE `roles_capabilities`;
+DROP TABLE `roles`;
@@ -0,0 +1,23 @@
+CREATE TABLE `roles` (
+  `id` int PRIMARY KEY AUTO_INCREMENT,
+  `name` varchar(255) UNIQUE NOT NULL,
+  `description` varchar(255) NOT NULL
+);
+
+CREATE TABLE `roles_capabilities` (
+  `role_id` int NOT NULL,
+  `capability` varchar(255) NOT NULL,
+  PRIMARY KEY (`role_id`, `capability`)
+);
+
+CREATE TABLE `web_ui_users_roles` (
+  `web_ui_user_id` int NOT NULL,
+  `role_id` int NOT NULL,
+  PRIMARY KEY (`web_ui_user_id`, `role_id`)
+);
+
+ALTER TABLE `roles_capabilities` ADD FOREIGN KEY (`role_id`) REFERENCES `roles` (`id`);
+
+ALTER TABLE `web_ui_users_roles` ADD FOREIGN KEY (`web_ui_user_id`) REFERENCES `web_ui_users` (`id`);
+
+ALTER TABLE `web_ui_users_roles` ADD FOREIGN KEY (`role_id`) REFERENCES `roles` (`id`);
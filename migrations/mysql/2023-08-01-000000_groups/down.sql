@@ -0,0 +1,3 @@
+DROP TABLE `groups_permissions`;
+DROP TABLE `groups_users`;
+DROP TABLE `groups`;
@@ -0,0 +1,30 @@
+CREATE TABLE `webhooks` (
+  `id` int PRIMARY KEY AUTO_INCREMENT,
+  `name` varchar(255) UNIQUE NOT NULL,
+  `url` varchar(2048) NOT NULL,
+  `secret` varchar(255) NOT NULL,
+  `active` boolean NOT NULL
+);
+
+CREATE TABLE `webhooks_events` (
+  `webhook_id` int NOT NULL,
+  `event` varchar(64) NOT NULL,
+  PRIMARY KEY (`webhook_id`, `event`)
+);
+
+CREATE TABLE `webhook_deliveries` (
+  `id` int PRIMARY KEY AUTO_INCREMENT,
+  `webhook_id` int NOT NULL,
+  `event` varchar(64) NOT NULL,
+  `payload` text NOT NULL,
+  `status` varchar(16) NOT NULL,
+  `attempts` int NOT NULL,
+  `response_status` int,
+  `error` varchar(1024),
+  `created_at` datetime NOT NULL,
+  `last_attempt_at` datetime
+);
+
+ALTER TABLE `webhooks_events` ADD FOREIGN KEY (`webhook_id`) REFERENCES `webhooks` (`id`);
+
+ALTER TABLE `webhook_deliveries` ADD FOREIGN KEY (`webhook_id`) REFERENCES `webhooks` (`id`);
@@ -0,0 +1,8 @@
+DROP TABLE "web_ui_users";
+DROP TABLE "users_permissions";
+DROP TABLE "access_profiles_permissions";
+DROP TABLE "permissions";
+DROP TABLE "access_profiles";
+DROP TABLE "access_codes";
+DROP TABLE "users";
+DROP TYPE access_profile_access_mode;
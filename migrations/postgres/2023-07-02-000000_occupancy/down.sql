@@ -0,0 +1,2 @@
+DROP TABLE "access_profiles_settings";
+DROP TABLE "occupancy";
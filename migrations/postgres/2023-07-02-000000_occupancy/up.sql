@@ -0,0 +1,14 @@
+CREATE TABLE "occupancy" (
+  "user_id" int PRIMARY KEY,
+  "entered_at" timestamp NOT NULL
+);
+
+CREATE TABLE "access_profiles_settings" (
+  "access_profile_id" int PRIMARY KEY,
+  "anti_passback" boolean NOT NULL,
+  "max_occupancy" int
+);
+
+ALTER TABLE "occupancy" ADD FOREIGN KEY ("user_id") REFERENCES "users" ("id");
+
+ALTER TABLE "access_profiles_settings" ADD FOREIGN KEY ("access_profile_id") REFERENCES "access_profiles" ("id");
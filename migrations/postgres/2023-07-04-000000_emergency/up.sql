@@ -0,0 +1,6 @@
+CREATE TABLE "emergency_state" (
+  "id" int PRIMARY KEY,
+  "mode" varchar(255) NOT NULL,
+  "changed_by" varchar(255) NOT NULL,
+  "changed_at" timestamp NOT NULL
+);
@@ -0,0 +1,6 @@
+CREATE TABLE "active_access_profile" (
+  "id" int PRIMARY KEY,
+  "access_profile_id" int NOT NULL
+);
+
+ALTER TABLE "active_access_profile" ADD FOREIGN KEY ("access_profile_id") REFERENCES "access_profiles" ("id");
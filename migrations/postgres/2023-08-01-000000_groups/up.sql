@@ -0,0 +1,25 @@
+CREATE TABLE "groups" (
+  "id" serial PRIMARY KEY,
+  "name" varchar(255) UNIQUE NOT NULL,
+  "description" varchar(255) NOT NULL
+);
+
+CREATE TABLE "groups_users" (
+  "group_id" int NOT NULL,
+  "user_id" int NOT NULL,
+  PRIMARY KEY ("group_id", "user_id")
+);
+
+CREATE TABLE "groups_permissions" (
+  "group_id" int NOT NULL,
+  "permission_id" int NOT NULL,
+  PRIMARY KEY ("group_id", "permission_id")
+);
+
+ALTER TABLE "groups_users" ADD FOREIGN KEY ("group_id") REFERENCES "groups" ("id");
+
+ALTER TABLE "groups_users" ADD FOREIGN KEY ("user_id") REFERENCES "users" ("id");
+
+ALTER TABLE "groups_permissions" ADD FOREIGN KEY ("group_id") REFERENCES "groups" ("id");
+
+ALTER TABLE "groups_permissions" ADD FOREIGN KEY ("permission_id") REFERENCES "permissions" ("id");
@@ -0,0 +1,52 @@
+CREATE TABLE "users" (
+  "id" integer PRIMARY KEY AUTOINCREMENT,
+  "name" varchar(255) UNIQUE NOT NULL,
+  "full_name" varchar(255) NOT NULL,
+  "role" varchar(255) NOT NULL
+);
+
+CREATE TABLE "access_codes" (
+  "id" integer PRIMARY KEY AUTOINCREMENT,
+  "code" varchar(255) UNIQUE NOT NULL,
+  "user" int NOT NULL,
+  FOREIGN KEY ("user") REFERENCES "users" ("id")
+);
+
+CREATE TABLE "access_profiles" (
+  "id" integer PRIMARY KEY AUTOINCREMENT,
+  "name" varchar(255) UNIQUE NOT NULL,
+  "description" varchar(255) NOT NULL,
+  "display_text" varchar(255) NOT NULL,
+  "color" varchar(255) NOT NULL,
+  "access_mode" varchar(255) NOT NULL CHECK ("access_mode" IN ('OpenLock', 'AllowAnyone', 'CheckAccess'))
+);
+
+CREATE TABLE "permissions" (
+  "id" integer PRIMARY KEY AUTOINCREMENT,
+  "name" varchar(255) UNIQUE NOT NULL,
+  "description" varchar(255) NOT NULL
+);
+
+CREATE TABLE "access_profiles_permissions" (
+  "access_profile_id" int NOT NULL,
+  "permission_id" int NOT NULL,
+  PRIMARY KEY ("access_profile_id", "permission_id"),
+  FOREIGN KEY ("access_profile_id") REFERENCES "access_profiles" ("id"),
+  FOREIGN KEY ("permission_id") REFERENCES "permissions" ("id")
+);
+
+CREATE TABLE "users_permissions" (
+  "user_id" int NOT NULL,
+  "permission_id" int NOT NULL,
+  PRIMARY KEY ("user_id", "permission_id"),
+  FOREIGN KEY ("user_id") REFERENCES "users" ("id"),
+  FOREIGN KEY ("permission_id") REFERENCES "permissions" ("id")
+);
+
+CREATE TABLE "web_ui_users" (
+  "id" integer PRIMARY KEY AUTOINCREMENT,
+  "name" varchar(255) UNIQUE NOT NULL,
+  "password_hash" varchar(255) NOT NULL,
+  "is_admin" boolean NOT NULL,
+  "ac_does_not_expire" boolean NOT NULL
+);
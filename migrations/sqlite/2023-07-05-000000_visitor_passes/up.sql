@@ -0,0 +1,12 @@
+CREATE TABLE "visitor_passes" (
+  "id" integer PRIMARY KEY AUTOINCREMENT,
+  "guest_name" varchar(255) NOT NULL,
+  "host_user" int NOT NULL,
+  "code" varchar(255) UNIQUE NOT NULL,
+  "valid_from" timestamp NOT NULL,
+  "valid_until" timestamp NOT NULL,
+  "max_uses" int NOT NULL,
+  "uses" int NOT NULL,
+  "entered_at" timestamp,
+  FOREIGN KEY ("host_user") REFERENCES "users" ("id")
+);
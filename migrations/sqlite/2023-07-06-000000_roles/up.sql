@@ -0,0 +1,20 @@
+CREATE TABLE "roles" (
+  "id" integer PRIMARY KEY AUTOINCREMENT,
+  "name" varchar(255) UNIQUE NOT NULL,
+  "description" varchar(255) NOT NULL
+);
+
+CREATE TABLE "roles_capabilities" (
+  "role_id" int NOT NULL,
+  "capability" varchar(255) NOT NULL,
+  PRIMARY KEY ("role_id", "capability"),
+  FOREIGN KEY ("role_id") REFERENCES "roles" ("id")
+);
+
+CREATE TABLE "web_ui_users_roles" (
+  "web_ui_user_id" int NOT NULL,
+  "role_id" int NOT NULL,
+  PRIMARY KEY ("web_ui_user_id", "role_id"),
+  FOREIGN KEY ("web_ui_user_id") REFERENCES "web_ui_users" ("id"),
+  FOREIGN KEY ("role_id") REFERENCES "roles" ("id")
+);
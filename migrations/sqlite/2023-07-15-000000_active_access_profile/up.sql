@@ -0,0 +1,5 @@
+CREATE TABLE "active_access_profile" (
+  "id" integer PRIMARY KEY,
+  "access_profile_id" integer NOT NULL,
+  FOREIGN KEY ("access_profile_id") REFERENCES "access_profiles" ("id")
+);
@@ -0,0 +1,21 @@
+CREATE TABLE "groups" (
+  "id" integer PRIMARY KEY AUTOINCREMENT,
+  "name" varchar(255) UNIQUE NOT NULL,
+  "description" varchar(255) NOT NULL
+);
+
+CREATE TABLE "groups_users" (
+  "group_id" int NOT NULL,
+  "user_id" int NOT NULL,
+  PRIMARY KEY ("group_id", "user_id"),
+  FOREIGN KEY ("group_id") REFERENCES "groups" ("id"),
+  FOREIGN KEY ("user_id") REFERENCES "users" ("id")
+);
+
+CREATE TABLE "groups_permissions" (
+  "group_id" int NOT NULL,
+  "permission_id" int NOT NULL,
+  PRIMARY KEY ("group_id", "permission_id"),
+  FOREIGN KEY ("group_id") REFERENCES "groups" ("id"),
+  FOREIGN KEY ("permission_id") REFERENCES "permissions" ("id")
+);
@@ -0,0 +1,251 @@
+use std::collections::HashSet;
+
+use cherrydoor_models::{insert::{UserPermissionInsert, AccessProfilePermissionInsert}, schema::{users, permissions, access_profiles, users_permissions, access_profiles_permissions}};
+use diesel::{QueryDsl, ExpressionMethods, BoolExpressionMethods};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+
+use crate::{db::DbConnection, error::{ApiError, FieldError}, validation::Validate};
+
+/// An item of a batch, given either by id or by name.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum IdOrName {
+    Id(i32),
+    Name(String)
+}
+
+/// Body of the `.../batch` routes. `remove` is applied after `add`.
+#[derive(Deserialize)]
+pub struct BatchChange {
+    #[serde(default)]
+    add :Vec<IdOrName>,
+    #[serde(default)]
+    remove :Vec<IdOrName>
+}
+
+impl Validate for BatchChange {}
+
+/// Body of the `PUT` routes replacing the whole set.
+impl Validate for Vec<IdOrName> {}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Added,
+    AlreadyAssigned,
+    Removed,
+    NotAssigned
+}
+
+/// What happened to one item of a batch.
+#[derive(Serialize)]
+pub struct BatchItem {
+    item :IdOrName,
+    id :i32,
+    status :BatchStatus
+}
+
+/// The resource after a batch, with what happened to each item.
+#[derive(Serialize)]
+pub struct Batched<T> {
+    #[serde(flatten)]
+    pub resource :T,
+    pub changes :Vec<BatchItem>
+}
+
+/// One side of a many-to-many relation: the owner's id and the table its items come from.
+pub enum Relation {
+    UserPermissions(i32),
+    PermissionUsers(i32),
+    PermissionAccessProfiles(i32),
+    AccessProfilePermissions(i32)
+}
+
+impl Relation {
+    /// Applies the additions, then the removals. Nothing is changed if any item doesn't exist.
+    pub async fn change<'a>(&self, change :BatchChange, conn :&mut DbConnection<'a>) -> Result<Vec<BatchItem>, ApiError> {
+        let mut errors = vec![];
+        let add = self.resolve("add", change.add, &mut errors, conn).await?;
+        let remove = self.resolve("remove", change.remove, &mut errors, conn).await?;
+        if !errors.is_empty() {
+            return Err(ApiError::Validation(errors));
+        }
+
+        let mut current = self.current(conn).await?;
+        let mut changes = vec![];
+
+        for (item, id) in add {
+            let status = if current.insert(id) {
+                self.add(id, conn).await?;
+                BatchStatus::Added
+            } else {
+                BatchStatus::AlreadyAssigned
+            };
+            changes.push(BatchItem { item, id, status });
+        }
+        for (item, id) in remove {
+            let status = if current.remove(&id) {
+                self.remove(id, conn).await?;
+                BatchStatus::Removed
+            } else {
+                BatchStatus::NotAssigned
+            };
+            changes.push(BatchItem { item, id, status });
+        }
+
+        Ok(changes)
+    }
+
+    /// Makes the given items the whole set. Items dropped from the set are reported by id.
+    pub async fn replace<'a>(&self, items :Vec<IdOrName>, conn :&mut DbConnection<'a>) -> Result<Vec<BatchItem>, ApiError> {
+        let mut errors = vec![];
+        let items = self.resolve("", items, &mut errors, conn).await?;
+        if !errors.is_empty() {
+            return Err(ApiError::Validation(errors));
+        }
+        let wanted :HashSet<i32> = items.iter().map(|(_, id)| *id).collect();
+
+        let mut current = self.current(conn).await?;
+        let mut changes = vec![];
+
+        let mut dropped :Vec<i32> = current.difference(&wanted).copied().collect();
+        dropped.sort_unstable();
+        for id in dropped {
+            self.remove(id, conn).await?;
+            current.remove(&id);
+            changes.push(BatchItem { item: IdOrName::Id(id), id, status: BatchStatus::Removed });
+        }
+        for (item, id) in items {
+            let status = if current.insert(id) {
+                self.add(id, conn).await?;
+                BatchStatus::Added
+            } else {
+                BatchStatus::AlreadyAssigned
+            };
+            changes.push(BatchItem { item, id, status });
+        }
+
+        Ok(changes)
+    }
+
+    /// Finds the ids of the items. Items that don't exist are left out and reported in `errors`.
+    async fn resolve<'a>(&self, field :&str, items :Vec<IdOrName>, errors :&mut Vec<FieldError>, conn :&mut DbConnection<'a>) -> Result<Vec<(IdOrName, i32)>, ApiError> {
+        let ids :Vec<i32> = items.iter().filter_map(|item| match item { IdOrName::Id(id) => Some(*id), _ => None }).collect();
+        let names :Vec<&str> = items.iter().filter_map(|item| match item { IdOrName::Name(name) => Some(name.as_str()), _ => None }).collect();
+
+        let found :Result<Vec<(i32, String)>, diesel::result::Error> = match self {
+            Self::PermissionUsers(_) => users::table
+                .select((users::id, users::name))
+                .filter(users::id.eq_any(&ids).or(users::name.eq_any(&names)))
+            .load(conn).await,
+            Self::PermissionAccessProfiles(_) => access_profiles::table
+                .select((access_profiles::id, access_profiles::name))
+                .filter(access_profiles::id.eq_any(&ids).or(access_profiles::name.eq_any(&names)))
+            .load(conn).await,
+            Self::UserPermissions(_) | Self::AccessProfilePermissions(_) => permissions::table
+                .select((permissions::id, permissions::name))
+                .filter(permissions::id.eq_any(&ids).or(permissions::name.eq_any(&names)))
+            .load(conn).await
+        };
+        let found = match found {
+            Ok(found) => found,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        let what = match self {
+            Self::PermissionUsers(_) => "user",
+            Self::PermissionAccessProfiles(_) => "access profile",
+            Self::UserPermissions(_) | Self::AccessProfilePermissions(_) => "permission"
+        };
+
+        let mut resolved = vec![];
+        for (i, item) in items.into_iter().enumerate() {
+            let id = found.iter().find(|(id, name)| match &item {
+                IdOrName::Id(wanted) => id == wanted,
+                IdOrName::Name(wanted) => name == wanted
+            }).map(|(id, _)| *id);
+
+            match id {
+                Some(id) => resolved.push((item, id)),
+                None => errors.push(FieldError::new(&format!("{}[{}]", field, i), &format!("There's no such {}.", what)))
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    async fn current<'a>(&self, conn :&mut DbConnection<'a>) -> Result<HashSet<i32>, ApiError> {
+        let current :Result<Vec<i32>, diesel::result::Error> = match *self {
+            Self::UserPermissions(owner) => users_permissions::table
+                .select(users_permissions::permission_id)
+                .filter(users_permissions::user_id.eq(owner))
+            .load(conn).await,
+            Self::PermissionUsers(owner) => users_permissions::table
+                .select(users_permissions::user_id)
+                .filter(users_permissions::permission_id.eq(owner))
+            .load(conn).await,
+            Self::PermissionAccessProfiles(owner) => access_profiles_permissions::table
+                .select(access_profiles_permissions::access_profile_id)
+                .filter(access_profiles_permissions::permission_id.eq(owner))
+            .load(conn).await,
+            Self::AccessProfilePermissions(owner) => access_profiles_permissions::table
+                .select(access_profiles_permissions::permission_id)
+                .filter(access_profiles_permissions::access_profile_id.eq(owner))
+            .load(conn).await
+        };
+
+        match current {
+            Ok(current) => Ok(current.into_iter().collect()),
+            Err(e) => Err(ApiError::Internal(format!("{}", e)))
+        }
+    }
+
+    async fn add<'a>(&self, id :i32, conn :&mut DbConnection<'a>) -> Result<(), ApiError> {
+        let result = match *self {
+            Self::UserPermissions(owner) => diesel::insert_into(users_permissions::table)
+                .values(UserPermissionInsert { user_id: owner, permission_id: id })
+            .execute(conn).await,
+            Self::PermissionUsers(owner) => diesel::insert_into(users_permissions::table)
+                .values(UserPermissionInsert { user_id: id, permission_id: owner })
+            .execute(conn).await,
+            Self::PermissionAccessProfiles(owner) => diesel::insert_into(access_profiles_permissions::table)
+                .values(AccessProfilePermissionInsert { access_profile_id: id, permission_id: owner })
+            .execute(conn).await,
+            Self::AccessProfilePermissions(owner) => diesel::insert_into(access_profiles_permissions::table)
+                .values(AccessProfilePermissionInsert { access_profile_id: owner, permission_id: id })
+            .execute(conn).await
+        };
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ApiError::Internal(format!("{}", e)))
+        }
+    }
+
+    async fn remove<'a>(&self, id :i32, conn :&mut DbConnection<'a>) -> Result<(), ApiError> {
+        let result = match *self {
+            Self::UserPermissions(owner) => diesel::delete(users_permissions::table)
+                .filter(users_permissions::user_id.eq(owner))
+                .filter(users_permissions::permission_id.eq(id))
+            .execute(conn).await,
+            Self::PermissionUsers(owner) => diesel::delete(users_permissions::table)
+                .filter(users_permissions::user_id.eq(id))
+                .filter(users_permissions::permission_id.eq(owner))
+            .execute(conn).await,
+            Self::PermissionAccessProfiles(owner) => diesel::delete(access_profiles_permissions::table)
+                .filter(access_profiles_permissions::access_profile_id.eq(id))
+                .filter(access_profiles_permissions::permission_id.eq(owner))
+            .execute(conn).await,
+            Self::AccessProfilePermissions(owner) => diesel::delete(access_profiles_permissions::table)
+                .filter(access_profiles_permissions::access_profile_id.eq(owner))
+                .filter(access_profiles_permissions::permission_id.eq(id))
+            .execute(conn).await
+        };
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ApiError::Internal(format!("{}", e)))
+        }
+    }
+}
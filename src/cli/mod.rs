@@ -0,0 +1,322 @@
+// Administration commands, for operating the system without the web UI. They go through the same functions
+// as the route handlers, so the rules are the same as over HTTP.
+
+pub mod transfer;
+
+use std::{error::Error, io::{BufRead, Write}};
+
+use cherrydoor_models::insert::UserInsert;
+
+use crate::{
+    db::{DB, get_connection}, config::Config, error::{ApiError, FieldError}, validation::Validate, metrics::Metrics, migrations, events::EventBus, door::Door,
+    routes::{users::{list_users, create_user, delete_user, access_codes::{revoke_code, normalize_codes}}, web_ui_users::{WebUIUserCreate, WebUIUserPatch, create_web_ui_user, update_web_ui_user}, active_access_profile::ActiveAccessProfile}
+};
+
+pub const USAGE :&str = "\
+Usage: cherrydoor-web [COMMAND]
+
+Commands:
+    serve                                   Run the web server (the default)
+    migrate                                 Apply pending database migrations
+    user add <name> <full name> [--role <role>]
+    user list
+    user delete <name>                      Also deletes the user's access codes and permissions
+    webui-user add <name> [--admin] [--no-expire] [--password <password>]
+    webui-user reset-password <name> [--password <password>]
+    profile activate <name>                 Make the access profile (name or id) active and push it to the door
+    code revoke <code>                      Delete an access code, whoever it belongs to
+    code normalize                          Store codes saved before they were normalized in the canonical form
+    export [<file>]                         Write users, permissions, profiles and web UI users as JSON
+    import <file>                           Add everything from an export that doesn't exist yet
+    help
+
+Passwords not given with --password are read from the standard input.
+Settings are read the same way as for the server, see the documentation.";
+
+pub enum Command {
+    Serve,
+    Migrate,
+    UserAdd {name :String, full_name :String, role :String},
+    UserList,
+    UserDelete {name :String},
+    WebUIUserAdd {name :String, password :Option<String>, is_admin :bool, ac_does_not_expire :bool},
+    WebUIUserResetPassword {name :String, password :Option<String>},
+    ProfileActivate {name :String},
+    CodeRevoke {code :String},
+    CodeNormalize,
+    Export {file :Option<String>},
+    Import {file :String},
+    Help
+}
+
+/// Flags given after the positional arguments of a command.
+struct Flags {
+    switches :Vec<String>,
+    options :Vec<(String, String)>
+}
+
+impl Flags {
+    fn parse(args :&[&str], switches :&[&str], options :&[&str]) -> Result<Self, String> {
+        let mut flags = Self { switches: vec![], options: vec![] };
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            if switches.contains(arg) {
+                flags.switches.push(arg.to_string());
+            } else if options.contains(arg) {
+                match args.next() {
+                    Some(value) => flags.options.push((arg.to_string(), value.to_string())),
+                    None => return Err(format!("{} needs a value", arg))
+                }
+            } else {
+                return Err(format!("unexpected argument '{}'", arg))
+            }
+        }
+
+        Ok(flags)
+    }
+
+    fn has(&self, switch :&str) -> bool {
+        self.switches.iter().any(|s| s == switch)
+    }
+
+    fn get(&self, option :&str) -> Option<String> {
+        self.options.iter().rev().find(|(o, _)| o == option).map(|(_, v)| v.clone())
+    }
+}
+
+pub fn parse(args :&[String]) -> Result<Command, String> {
+    let args :Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    match args.as_slice() {
+        [] | ["serve"] => Ok(Command::Serve),
+        ["migrate"] => Ok(Command::Migrate),
+        ["help"] | ["--help"] | ["-h"] => Ok(Command::Help),
+
+        ["user", "add", name, full_name, rest @ ..] => {
+            let flags = Flags::parse(rest, &[], &["--role"])?;
+            Ok(Command::UserAdd {
+                name: name.to_string(),
+                full_name: full_name.to_string(),
+                role: flags.get("--role").unwrap_or_default()
+            })
+        },
+        ["user", "list"] => Ok(Command::UserList),
+        ["user", "delete", name] => Ok(Command::UserDelete { name: name.to_string() }),
+
+        ["webui-user", "add", name, rest @ ..] => {
+            let flags = Flags::parse(rest, &["--admin", "--no-expire"], &["--password"])?;
+            Ok(Command::WebUIUserAdd {
+                name: name.to_string(),
+                password: flags.get("--password"),
+                is_admin: flags.has("--admin"),
+                ac_does_not_expire: flags.has("--no-expire")
+            })
+        },
+        ["webui-user", "reset-password", name, rest @ ..] => {
+            let flags = Flags::parse(rest, &[], &["--password"])?;
+            Ok(Command::WebUIUserResetPassword {
+                name: name.to_string(),
+                password: flags.get("--password")
+            })
+        },
+
+        ["profile", "activate", name] => Ok(Command::ProfileActivate { name: name.to_string() }),
+        ["code", "revoke", code] => Ok(Command::CodeRevoke { code: code.to_string() }),
+        ["code", "normalize"] => Ok(Command::CodeNormalize),
+
+        ["export"] => Ok(Command::Export { file: None }),
+        ["export", file] => Ok(Command::Export { file: Some(file.to_string()) }),
+        ["import", file] => Ok(Command::Import { file: file.to_string() }),
+
+        _ => Err(format!("unknown command '{}'", args.join(" ")))
+    }
+}
+
+/// Runs an administration command, returns the exit code.
+pub async fn run(command :Command, config :&Config) -> i32 {
+    let metrics = Metrics::new();
+    let db = DB::new(&config.database, metrics.db_wait.clone());
+
+    match execute(command, config, &db, metrics).await {
+        Ok(_) => 0,
+        Err(CliError::Api(ApiError::Validation(fields))) => {
+            for FieldError { field, message } in fields {
+                eprintln!("error: {}: {}", field, message);
+            }
+            1
+        },
+        Err(CliError::Api(e)) => {
+            eprintln!("error: {}", e);
+            1
+        },
+        Err(CliError::Other(e)) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+enum CliError {
+    Api(ApiError),
+    Other(String)
+}
+
+impl From<ApiError> for CliError {
+    fn from(e :ApiError) -> Self {
+        Self::Api(e)
+    }
+}
+
+impl From<Box<dyn Error>> for CliError {
+    fn from(e :Box<dyn Error>) -> Self {
+        Self::Other(format!("{}", e))
+    }
+}
+
+/// The same checks as for request bodies.
+fn validate<T :Validate>(value :&T) -> Result<(), CliError> {
+    let mut errors = vec![];
+    value.validate(&mut errors);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(CliError::Api(ApiError::Validation(errors)))
+    }
+}
+
+fn read_password(password :Option<String>) -> Result<String, CliError> {
+    if let Some(password) = password {
+        return Ok(password)
+    }
+
+    eprint!("Password: ");
+    std::io::stderr().flush().ok();
+
+    let mut line = String::new();
+    if let Err(e) = std::io::stdin().lock().read_line(&mut line) {
+        return Err(CliError::Other(format!("couldn't read the password: {}", e)))
+    }
+
+    Ok(String::from(line.trim_end_matches(['\r', '\n'])))
+}
+
+async fn execute(command :Command, config :&Config, db :&DB, metrics :Metrics) -> Result<(), CliError> {
+    let mut conn = get_connection(db).await?;
+
+    match command {
+        Command::Serve | Command::Help => unreachable!("handled in main"),
+
+        Command::Migrate => {
+            migrations::run(&mut conn).await?;
+            println!("The database is up to date.");
+        },
+
+        Command::UserAdd { name, full_name, role } => {
+            let user = UserInsert { name, full_name, role };
+            validate(&user)?;
+
+            let user = create_user(user, &mut conn).await?;
+            println!("Added user {}.", user.full.user.name);
+        },
+        Command::UserList => {
+            let mut page = 0;
+
+            loop {
+                let users = list_users(page, &mut conn).await?;
+                if users.is_empty() {
+                    break
+                }
+
+                for user in users {
+                    println!("{}\t{}\t{}", user.name, user.full_name, user.role);
+                }
+                page += 1;
+            }
+        },
+        Command::UserDelete { name } => {
+            let deleted = delete_user(&name, &mut conn).await?;
+            println!(
+                "Deleted user {} with {} access codes, {} permissions and {} visitor passes.",
+                deleted.user.full.user.name, deleted.removed.access_codes, deleted.removed.permissions, deleted.removed.visitor_passes
+            );
+        },
+
+        Command::WebUIUserAdd { name, password, is_admin, ac_does_not_expire } => {
+            let wu_user = WebUIUserCreate {
+                name,
+                password: read_password(password)?,
+                is_admin,
+                ac_does_not_expire
+            };
+            validate(&wu_user)?;
+
+            let wu_user = create_web_ui_user(wu_user, &mut conn).await?;
+            println!("Added web UI user {}{}.", wu_user.name, if wu_user.is_admin { " as an admin" } else { "" });
+        },
+        Command::WebUIUserResetPassword { name, password } => {
+            let patch = WebUIUserPatch {
+                password: Some(read_password(password)?),
+                is_admin: None,
+                ac_does_not_expire: None
+            };
+            validate(&patch)?;
+
+            update_web_ui_user(&name, patch, &mut conn).await?;
+            println!("Changed the password of {}.", name);
+        },
+
+        Command::ProfileActivate { name } => {
+            // Webhooks hear about it once the server picks the change up.
+            let aacp = ActiveAccessProfile::new(Door::from_config(&config.command_server, metrics.clone()), EventBus::new());
+
+            aacp.activate(&name, &mut conn).await?;
+            metrics.profile_changes.inc();
+            println!("Activated access profile {}.", name);
+        },
+        Command::CodeRevoke { code } => {
+            let ac = revoke_code(&code, config.door.code_format, &mut conn).await?;
+            println!("Revoked the access code of user #{}.", ac.user);
+        },
+        Command::CodeNormalize => {
+            let result = normalize_codes(config.door.code_format, &mut conn).await?;
+            println!("Normalized {} access codes.", result.normalized);
+            for code in result.kept {
+                eprintln!("Kept {} as it is: it isn't a {:?} code, or it's taken in the normalized form.", code, config.door.code_format);
+            }
+        },
+
+        Command::Export { file } => {
+            let export = transfer::export(&mut conn).await?;
+
+            let json = match serde_json::to_string_pretty(&export) {
+                Ok(json) => json,
+                Err(e) => return Err(CliError::Other(format!("{}", e)))
+            };
+
+            match file {
+                Some(file) => if let Err(e) = std::fs::write(&file, json) {
+                    return Err(CliError::Other(format!("couldn't write {}: {}", file, e)))
+                },
+                None => println!("{}", json)
+            }
+        },
+        Command::Import { file } => {
+            let json = match std::fs::read_to_string(&file) {
+                Ok(json) => json,
+                Err(e) => return Err(CliError::Other(format!("couldn't read {}: {}", file, e)))
+            };
+
+            let export = match serde_json::from_str(&json) {
+                Ok(export) => export,
+                Err(e) => return Err(CliError::Other(format!("{} isn't a valid export: {}", file, e)))
+            };
+
+            let summary = transfer::import(export, config.door.code_format, &mut conn).await?;
+            println!("{}", summary);
+        }
+    }
+
+    Ok(())
+}
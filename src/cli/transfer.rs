@@ -0,0 +1,497 @@
+// Moving the configuration of one installation to another. Everything is referred to by name, as ids differ
+// between databases. Occupancy, PINs, visitor passes and the event history aren't included.
+
+use std::{error::Error, fmt};
+
+use cherrydoor_models::{schema::{users, access_codes, access_profiles, permissions, access_profiles_permissions, users_permissions, web_ui_users, AccessProfileAccessMode}, insert::{UserInsert, AccessCodeInsert, AccessProfileInsert, PermissionInsert, AccessProfilePermissionInsert, UserPermissionInsert, WebUIUserInsert}};
+use diesel::{QueryDsl, ExpressionMethods, OptionalExtension};
+use diesel_async::RunQueryDsl;
+use serde::{Serialize, Deserialize};
+
+use crate::{db::DbConnection, codes::{self, CodeFormat}, schema::{roles, roles_capabilities, web_ui_users_roles, groups, groups_users, groups_permissions}, models::{RoleInsert, GroupInsert}};
+
+#[derive(Serialize, Deserialize)]
+pub struct Export {
+    pub permissions :Vec<ExportedPermission>,
+    pub access_profiles :Vec<ExportedAccessProfile>,
+    pub users :Vec<ExportedUser>,
+    /// Missing from exports made before groups existed.
+    #[serde(default)]
+    pub groups :Vec<ExportedGroup>,
+    pub roles :Vec<ExportedRole>,
+    pub web_ui_users :Vec<ExportedWebUIUser>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedPermission {
+    pub name :String,
+    pub description :String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedAccessProfile {
+    pub name :String,
+    pub description :String,
+    pub display_text :String,
+    pub color :String,
+    pub access_mode :AccessProfileAccessMode,
+    /// Names of the permissions.
+    pub permissions :Vec<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedUser {
+    pub name :String,
+    pub full_name :String,
+    pub role :String,
+    pub access_codes :Vec<String>,
+    /// Names of the permissions.
+    pub permissions :Vec<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedGroup {
+    pub name :String,
+    pub description :String,
+    /// Names of the members.
+    pub users :Vec<String>,
+    /// Names of the permissions.
+    pub permissions :Vec<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedRole {
+    pub name :String,
+    pub description :String,
+    pub capabilities :Vec<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedWebUIUser {
+    pub name :String,
+    /// Passwords themselves aren't stored, so the hash is moved as it is.
+    pub password_hash :String,
+    pub is_admin :bool,
+    pub ac_does_not_expire :bool,
+    /// Names of the roles.
+    pub roles :Vec<String>
+}
+
+/// What an import changed.
+#[derive(Default)]
+pub struct ImportSummary {
+    pub added :u32,
+    pub skipped :u32,
+    pub linked :u32
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f :&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Added {}, skipped {} already existing, linked {} relations.", self.added, self.skipped, self.linked)
+    }
+}
+
+pub async fn export<'a>(db :&mut DbConnection<'a>) -> Result<Export, Box<dyn Error>> {
+    let permission_rows :Vec<(i32, String, String)> = permissions::table
+        .select((permissions::columns::id, permissions::columns::name, permissions::columns::description))
+        .order(permissions::columns::name)
+    .load(db).await?;
+
+    let permission_name = |id :i32| permission_rows.iter().find(|p| p.0 == id).map(|p| p.1.clone());
+
+    let profile_rows :Vec<(i32, String, String, String, String, AccessProfileAccessMode)> = access_profiles::table
+        .select((
+            access_profiles::columns::id,
+            access_profiles::columns::name,
+            access_profiles::columns::description,
+            access_profiles::columns::display_text,
+            access_profiles::columns::color,
+            access_profiles::columns::access_mode
+        ))
+        .order(access_profiles::columns::name)
+    .load(db).await?;
+
+    let profile_links :Vec<(i32, i32)> = access_profiles_permissions::table
+        .select((access_profiles_permissions::columns::access_profile_id, access_profiles_permissions::columns::permission_id))
+    .load(db).await?;
+
+    let access_profiles = profile_rows.into_iter().map(|(id, name, description, display_text, color, access_mode)| ExportedAccessProfile {
+        name,
+        description,
+        display_text,
+        color,
+        access_mode,
+        permissions: profile_links.iter().filter(|l| l.0 == id).filter_map(|l| permission_name(l.1)).collect()
+    }).collect();
+
+    let user_rows :Vec<(i32, String, String, String)> = users::table
+        .select((users::columns::id, users::columns::name, users::columns::full_name, users::columns::role))
+        .order(users::columns::name)
+    .load(db).await?;
+
+    let codes :Vec<(i32, String)> = access_codes::table
+        .select((access_codes::columns::user, access_codes::columns::code))
+    .load(db).await?;
+
+    let user_links :Vec<(i32, i32)> = users_permissions::table
+        .select((users_permissions::columns::user_id, users_permissions::columns::permission_id))
+    .load(db).await?;
+
+    let group_rows :Vec<(i32, String, String)> = groups::table
+        .select((groups::columns::id, groups::columns::name, groups::columns::description))
+        .order(groups::columns::name)
+    .load(db).await?;
+
+    let member_links :Vec<(i32, i32)> = groups_users::table
+        .select((groups_users::columns::group_id, groups_users::columns::user_id))
+    .load(db).await?;
+
+    let group_links :Vec<(i32, i32)> = groups_permissions::table
+        .select((groups_permissions::columns::group_id, groups_permissions::columns::permission_id))
+    .load(db).await?;
+
+    let groups = group_rows.into_iter().map(|(id, name, description)| ExportedGroup {
+        name,
+        description,
+        users: member_links.iter()
+            .filter(|l| l.0 == id)
+            .filter_map(|l| user_rows.iter().find(|u| u.0 == l.1).map(|u| u.1.clone()))
+        .collect(),
+        permissions: group_links.iter().filter(|l| l.0 == id).filter_map(|l| permission_name(l.1)).collect()
+    }).collect();
+
+    let users = user_rows.into_iter().map(|(id, name, full_name, role)| ExportedUser {
+        name,
+        full_name,
+        role,
+        access_codes: codes.iter().filter(|c| c.0 == id).map(|c| c.1.clone()).collect(),
+        permissions: user_links.iter().filter(|l| l.0 == id).filter_map(|l| permission_name(l.1)).collect()
+    }).collect();
+
+    let role_rows :Vec<(i32, String, String)> = roles::table
+        .select((roles::columns::id, roles::columns::name, roles::columns::description))
+        .order(roles::columns::name)
+    .load(db).await?;
+
+    let capabilities :Vec<(i32, String)> = roles_capabilities::table
+        .select((roles_capabilities::columns::role_id, roles_capabilities::columns::capability))
+    .load(db).await?;
+
+    let wu_user_rows :Vec<(i32, String, String, bool, bool)> = web_ui_users::table
+        .select((
+            web_ui_users::columns::id,
+            web_ui_users::columns::name,
+            web_ui_users::columns::password_hash,
+            web_ui_users::columns::is_admin,
+            web_ui_users::columns::ac_does_not_expire
+        ))
+        .order(web_ui_users::columns::name)
+    .load(db).await?;
+
+    let role_links :Vec<(i32, i32)> = web_ui_users_roles::table
+        .select((web_ui_users_roles::columns::web_ui_user_id, web_ui_users_roles::columns::role_id))
+    .load(db).await?;
+
+    let web_ui_users = wu_user_rows.into_iter().map(|(id, name, password_hash, is_admin, ac_does_not_expire)| ExportedWebUIUser {
+        name,
+        password_hash,
+        is_admin,
+        ac_does_not_expire,
+        roles: role_links.iter()
+            .filter(|l| l.0 == id)
+            .filter_map(|l| role_rows.iter().find(|r| r.0 == l.1).map(|r| r.1.clone()))
+        .collect()
+    }).collect();
+
+    let roles = role_rows.into_iter().map(|(id, name, description)| ExportedRole {
+        name,
+        description,
+        capabilities: capabilities.iter().filter(|c| c.0 == id).map(|c| c.1.clone()).collect()
+    }).collect();
+
+    Ok(Export {
+        permissions: permission_rows.into_iter().map(|(_, name, description)| ExportedPermission { name, description }).collect(),
+        access_profiles,
+        users,
+        groups,
+        roles,
+        web_ui_users
+    })
+}
+
+/// Adds whatever doesn't exist yet. Existing entries are left as they are, but missing relations to them
+/// are still added, so importing the same file twice changes nothing.
+pub async fn import<'a>(export :Export, format :CodeFormat, db :&mut DbConnection<'a>) -> Result<ImportSummary, Box<dyn Error>> {
+    let mut summary = ImportSummary::default();
+
+    for permission in export.permissions {
+        if permission_id(&permission.name, db).await?.is_some() {
+            summary.skipped += 1;
+            continue
+        }
+
+        diesel::insert_into(permissions::table)
+            .values(PermissionInsert { name: permission.name, description: permission.description })
+        .execute(db).await?;
+        summary.added += 1;
+    }
+
+    for profile in export.access_profiles {
+        let id = match access_profile_id(&profile.name, db).await? {
+            Some(id) => {
+                summary.skipped += 1;
+                id
+            },
+            None => {
+                diesel::insert_into(access_profiles::table)
+                    .values(AccessProfileInsert {
+                        name: profile.name.clone(),
+                        description: profile.description,
+                        display_text: profile.display_text,
+                        color: profile.color,
+                        access_mode: profile.access_mode
+                    })
+                .execute(db).await?;
+                summary.added += 1;
+
+                access_profile_id(&profile.name, db).await?.ok_or_else(|| format!("access profile {} disappeared", profile.name))?
+            }
+        };
+
+        for name in profile.permissions {
+            let Some(permission_id) = permission_id(&name, db).await? else { continue };
+
+            let count :i64 = access_profiles_permissions::table
+                .filter(access_profiles_permissions::columns::access_profile_id.eq(id))
+                .filter(access_profiles_permissions::columns::permission_id.eq(permission_id))
+                .count()
+            .get_result(db).await?;
+
+            if count == 0 {
+                diesel::insert_into(access_profiles_permissions::table)
+                    .values(AccessProfilePermissionInsert { access_profile_id: id, permission_id })
+                .execute(db).await?;
+                summary.linked += 1;
+            }
+        }
+    }
+
+    for user in export.users {
+        let id = match user_id(&user.name, db).await? {
+            Some(id) => {
+                summary.skipped += 1;
+                id
+            },
+            None => {
+                diesel::insert_into(users::table)
+                    .values(UserInsert { name: user.name.clone(), full_name: user.full_name, role: user.role })
+                .execute(db).await?;
+                summary.added += 1;
+
+                user_id(&user.name, db).await?.ok_or_else(|| format!("user {} disappeared", user.name))?
+            }
+        };
+
+        for code in user.access_codes {
+            // Exports from before codes were normalized have them as the reader reported them. Ones that aren't in
+            // `door.code_format` are kept as they are.
+            let code = codes::normalize(&code, format).unwrap_or(code);
+
+            // Codes are unique, one taken by someone else in this installation stays theirs.
+            let count :i64 = access_codes::table
+                .filter(access_codes::columns::code.eq(&code))
+                .count()
+            .get_result(db).await?;
+
+            if count == 0 {
+                diesel::insert_into(access_codes::table)
+                    .values(AccessCodeInsert { code, user: id })
+                .execute(db).await?;
+                summary.linked += 1;
+            }
+        }
+
+        for name in user.permissions {
+            let Some(permission_id) = permission_id(&name, db).await? else { continue };
+
+            let count :i64 = users_permissions::table
+                .filter(users_permissions::columns::user_id.eq(id))
+                .filter(users_permissions::columns::permission_id.eq(permission_id))
+                .count()
+            .get_result(db).await?;
+
+            if count == 0 {
+                diesel::insert_into(users_permissions::table)
+                    .values(UserPermissionInsert { user_id: id, permission_id })
+                .execute(db).await?;
+                summary.linked += 1;
+            }
+        }
+    }
+
+    for group in export.groups {
+        let id = match group_id(&group.name, db).await? {
+            Some(id) => {
+                summary.skipped += 1;
+                id
+            },
+            None => {
+                diesel::insert_into(groups::table)
+                    .values(GroupInsert { name: group.name.clone(), description: group.description })
+                .execute(db).await?;
+                summary.added += 1;
+
+                group_id(&group.name, db).await?.ok_or_else(|| format!("group {} disappeared", group.name))?
+            }
+        };
+
+        for name in group.users {
+            let Some(user_id) = user_id(&name, db).await? else { continue };
+
+            let count :i64 = groups_users::table
+                .filter(groups_users::columns::group_id.eq(id))
+                .filter(groups_users::columns::user_id.eq(user_id))
+                .count()
+            .get_result(db).await?;
+
+            if count == 0 {
+                diesel::insert_into(groups_users::table)
+                    .values((groups_users::columns::group_id.eq(id), groups_users::columns::user_id.eq(user_id)))
+                .execute(db).await?;
+                summary.linked += 1;
+            }
+        }
+
+        for name in group.permissions {
+            let Some(permission_id) = permission_id(&name, db).await? else { continue };
+
+            let count :i64 = groups_permissions::table
+                .filter(groups_permissions::columns::group_id.eq(id))
+                .filter(groups_permissions::columns::permission_id.eq(permission_id))
+                .count()
+            .get_result(db).await?;
+
+            if count == 0 {
+                diesel::insert_into(groups_permissions::table)
+                    .values((groups_permissions::columns::group_id.eq(id), groups_permissions::columns::permission_id.eq(permission_id)))
+                .execute(db).await?;
+                summary.linked += 1;
+            }
+        }
+    }
+
+    for role in export.roles {
+        let id = match role_id(&role.name, db).await? {
+            Some(id) => {
+                summary.skipped += 1;
+                id
+            },
+            None => {
+                diesel::insert_into(roles::table)
+                    .values(RoleInsert { name: role.name.clone(), description: role.description })
+                .execute(db).await?;
+                summary.added += 1;
+
+                role_id(&role.name, db).await?.ok_or_else(|| format!("role {} disappeared", role.name))?
+            }
+        };
+
+        for capability in role.capabilities {
+            let count :i64 = roles_capabilities::table
+                .filter(roles_capabilities::columns::role_id.eq(id))
+                .filter(roles_capabilities::columns::capability.eq(&capability))
+                .count()
+            .get_result(db).await?;
+
+            if count == 0 {
+                diesel::insert_into(roles_capabilities::table)
+                    .values((roles_capabilities::columns::role_id.eq(id), roles_capabilities::columns::capability.eq(capability)))
+                .execute(db).await?;
+                summary.linked += 1;
+            }
+        }
+    }
+
+    for wu_user in export.web_ui_users {
+        let id = match web_ui_user_id(&wu_user.name, db).await? {
+            Some(id) => {
+                summary.skipped += 1;
+                id
+            },
+            None => {
+                diesel::insert_into(web_ui_users::table)
+                    .values(WebUIUserInsert {
+                        name: wu_user.name.clone(),
+                        password_hash: wu_user.password_hash,
+                        is_admin: wu_user.is_admin,
+                        ac_does_not_expire: wu_user.ac_does_not_expire
+                    })
+                .execute(db).await?;
+                summary.added += 1;
+
+                web_ui_user_id(&wu_user.name, db).await?.ok_or_else(|| format!("web UI user {} disappeared", wu_user.name))?
+            }
+        };
+
+        for name in wu_user.roles {
+            let Some(role_id) = role_id(&name, db).await? else { continue };
+
+            let count :i64 = web_ui_users_roles::table
+                .filter(web_ui_users_roles::columns::web_ui_user_id.eq(id))
+                .filter(web_ui_users_roles::columns::role_id.eq(role_id))
+                .count()
+            .get_result(db).await?;
+
+            if count == 0 {
+                diesel::insert_into(web_ui_users_roles::table)
+                    .values((web_ui_users_roles::columns::web_ui_user_id.eq(id), web_ui_users_roles::columns::role_id.eq(role_id)))
+                .execute(db).await?;
+                summary.linked += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+async fn permission_id<'a>(name :&str, db :&mut DbConnection<'a>) -> Result<Option<i32>, diesel::result::Error> {
+    permissions::table
+        .filter(permissions::columns::name.eq(name))
+        .select(permissions::columns::id)
+    .first(db).await.optional()
+}
+
+async fn access_profile_id<'a>(name :&str, db :&mut DbConnection<'a>) -> Result<Option<i32>, diesel::result::Error> {
+    access_profiles::table
+        .filter(access_profiles::columns::name.eq(name))
+        .select(access_profiles::columns::id)
+    .first(db).await.optional()
+}
+
+async fn user_id<'a>(name :&str, db :&mut DbConnection<'a>) -> Result<Option<i32>, diesel::result::Error> {
+    users::table
+        .filter(users::columns::name.eq(name))
+        .select(users::columns::id)
+    .first(db).await.optional()
+}
+
+async fn group_id<'a>(name :&str, db :&mut DbConnection<'a>) -> Result<Option<i32>, diesel::result::Error> {
+    groups::table
+        .filter(groups::columns::name.eq(name))
+        .select(groups::columns::id)
+    .first(db).await.optional()
+}
+
+async fn role_id<'a>(name :&str, db :&mut DbConnection<'a>) -> Result<Option<i32>, diesel::result::Error> {
+    roles::table
+        .filter(roles::columns::name.eq(name))
+        .select(roles::columns::id)
+    .first(db).await.optional()
+}
+
+async fn web_ui_user_id<'a>(name :&str, db :&mut DbConnection<'a>) -> Result<Option<i32>, diesel::result::Error> {
+    web_ui_users::table
+        .filter(web_ui_users::columns::name.eq(name))
+        .select(web_ui_users::columns::id)
+    .first(db).await.optional()
+}
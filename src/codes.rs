@@ -0,0 +1,153 @@
+// Access codes are stored in one canonical form, so a card is the same code however it was read or typed in:
+// the bytes of the UID, most significant first, as uppercase hex without separators, e.g. `04A1B2C3`.
+
+use serde::Deserialize;
+
+/// Longest UID, in bytes. ISO 14443 UIDs have at most 10, this leaves room for other cards.
+const MAX_BYTES :usize = 16;
+
+/// How a code is written before it's normalized.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeFormat {
+    /// The bytes of the UID in hex, in any case, with or without separators, e.g. `04:a1:b2:c3` or `0x04A1B2C3`.
+    #[default]
+    Hex,
+    /// Like `hex`, but least significant byte first, as some readers report UIDs.
+    HexReversed,
+    /// Wiegand 26: an 8-bit facility code and a 16-bit card number, either as `<facility>/<card>` in decimal
+    /// (`:`, `,` and `-` separate them as well), or as a single decimal number of all 24 bits.
+    Wiegand26,
+    /// Wiegand 34: like `wiegand26`, with a 16-bit facility code.
+    Wiegand34
+}
+
+/// Returns the code in the canonical form, or why it isn't written in `format`.
+pub fn normalize(code :&str, format :CodeFormat) -> Result<String, &'static str> {
+    let code = code.trim();
+
+    match format {
+        CodeFormat::Hex => parse_hex(code).map(|bytes| to_hex(&bytes)),
+        CodeFormat::HexReversed => parse_hex(code).map(|mut bytes| {
+            bytes.reverse();
+            to_hex(&bytes)
+        }),
+        CodeFormat::Wiegand26 => parse_wiegand(code, 8).map(|value| format!("{:06X}", value)),
+        CodeFormat::Wiegand34 => parse_wiegand(code, 16).map(|value| format!("{:08X}", value))
+    }
+}
+
+/// The values a code read in `format` may be stored as: the canonical one, and the code as it is, in case it was
+/// saved before codes were normalized or isn't a card at all, like a visitor pass.
+pub fn candidates(code :&str, format :CodeFormat) -> Vec<String> {
+    match normalize(code, format) {
+        Ok(normalized) if normalized != code => vec![normalized, String::from(code)],
+        _ => vec![String::from(code)]
+    }
+}
+
+fn parse_hex(code :&str) -> Result<Vec<u8>, &'static str> {
+    let code = code.strip_prefix("0x").or_else(|| code.strip_prefix("0X")).unwrap_or(code);
+    let digits :String = code.chars().filter(|c| !matches!(c, ':' | '-' | ' ' | '.')).collect();
+
+    if digits.is_empty() {
+        return Err("Can't be empty.")
+    }
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("Must be hex digits, optionally separated with ':', '-', '.' or spaces.")
+    }
+
+    // Readers printing the UID as a number drop the leading zero.
+    let digits = if digits.len() % 2 == 1 { format!("0{}", digits) } else { digits };
+    if digits.len() / 2 > MAX_BYTES {
+        return Err("Can't be longer than 16 bytes.")
+    }
+
+    Ok((0..digits.len()).step_by(2)
+        .filter_map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+    .collect())
+}
+
+/// The facility code followed by the card number, without the parity bits.
+fn parse_wiegand(code :&str, facility_bits :u32) -> Result<u64, &'static str> {
+    let max_facility = (1u64 << facility_bits) - 1;
+
+    match code.split_once(['/', ':', ',', '-']) {
+        Some((facility, card)) => {
+            let facility :u64 = match facility.trim().parse() {
+                Ok(facility) if facility <= max_facility => facility,
+                _ => return Err("The facility code must be a decimal number that fits the format.")
+            };
+            let card :u64 = match card.trim().parse() {
+                Ok(card) if card <= 0xFFFF => card,
+                _ => return Err("The card number must be a decimal number up to 65535.")
+            };
+
+            Ok((facility << 16) | card)
+        },
+        None => match code.parse::<u64>() {
+            Ok(value) if value < 1 << (facility_bits + 16) => Ok(value),
+            _ => Err("Must be <facility>/<card>, or a single decimal number that fits the format.")
+        }
+    }
+}
+
+pub(crate) fn to_hex(bytes :&[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_is_uppercased_without_separators() {
+        for code in ["04A1B2C3", "04:a1:b2:c3", "0x04a1b2c3", " 04-A1-B2-C3 ", "04 a1.b2 c3"] {
+            assert_eq!(normalize(code, CodeFormat::Hex), Ok(String::from("04A1B2C3")), "{}", code);
+        }
+        assert_eq!(normalize("4A1B2C3", CodeFormat::Hex), Ok(String::from("04A1B2C3")), "an odd length gets its zero back");
+        assert_eq!(normalize("c3:b2:a1:04", CodeFormat::HexReversed), Ok(String::from("04A1B2C3")));
+    }
+
+    #[test]
+    fn bad_hex_is_refused() {
+        for code in ["", "  ", "0x", "::", "04A1G2", "ABC/123"] {
+            assert!(normalize(code, CodeFormat::Hex).is_err(), "{:?}", code);
+        }
+        assert!(normalize(&"AB".repeat(16), CodeFormat::Hex).is_ok());
+        assert!(normalize(&"AB".repeat(17), CodeFormat::Hex).is_err());
+    }
+
+    #[test]
+    fn wiegand_codes_fit_their_bits() {
+        assert_eq!(normalize("123/4567", CodeFormat::Wiegand26), Ok(String::from("7B11D7")));
+        assert_eq!(normalize("123: 4567", CodeFormat::Wiegand26), Ok(String::from("7B11D7")));
+        assert_eq!(normalize("8065495", CodeFormat::Wiegand26), Ok(String::from("7B11D7")));
+        assert_eq!(normalize("255/65535", CodeFormat::Wiegand26), Ok(String::from("FFFFFF")));
+        assert_eq!(normalize("16777215", CodeFormat::Wiegand26), Ok(String::from("FFFFFF")));
+        for code in ["256/1", "1/65536", "16777216", "", "a/1", "04A1B2C3"] {
+            assert!(normalize(code, CodeFormat::Wiegand26).is_err(), "{:?}", code);
+        }
+
+        assert_eq!(normalize("65535/65535", CodeFormat::Wiegand34), Ok(String::from("FFFFFFFF")));
+        assert_eq!(normalize("4294967295", CodeFormat::Wiegand34), Ok(String::from("FFFFFFFF")));
+        assert_eq!(normalize("1,2", CodeFormat::Wiegand34), Ok(String::from("00010002")));
+        for code in ["65536/1", "4294967296"] {
+            assert!(normalize(code, CodeFormat::Wiegand34).is_err(), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn candidates_keep_the_code_as_read() {
+        assert_eq!(candidates("04:a1:b2:c3", CodeFormat::Hex), vec!["04A1B2C3", "04:a1:b2:c3"]);
+        assert_eq!(candidates("04A1B2C3", CodeFormat::Hex), vec!["04A1B2C3"]);
+        assert_eq!(candidates("visitor-pass", CodeFormat::Hex), vec!["visitor-pass"]);
+        assert_eq!(candidates("", CodeFormat::Hex), vec![""]);
+    }
+
+    #[test]
+    fn to_hex_pads_every_byte() {
+        assert_eq!(to_hex(&[0x04, 0xA1, 0x0B, 0x00]), "04A10B00");
+        assert_eq!(to_hex(&[]), "");
+    }
+}
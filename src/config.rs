@@ -0,0 +1,352 @@
+use rocket::figment::{Figment, providers::Env};
+use serde::Deserialize;
+
+use crate::{db, codes::CodeFormat, validation::{check_name, check_not_empty, is_http_url, is_host_port}};
+
+/// Settings of the server, read with Rocket's figment: `Rocket.toml`, then `ROCKET_*` environment variables,
+/// then `CHERRYDOOR_*` ones, with `__` separating nested keys (e.g. `CHERRYDOOR_DATABASE__URI`).
+#[derive(Deserialize)]
+pub struct Config {
+    pub database :DatabaseConfig,
+    pub command_server :CommandServerConfig,
+    #[serde(default)]
+    pub cors :CorsConfig,
+    #[serde(default)]
+    pub tokens :TokensConfig,
+    #[serde(default)]
+    pub door :DoorConfig,
+    #[serde(default)]
+    pub rate_limits :RateLimitsConfig,
+    #[serde(default)]
+    pub bootstrap :BootstrapConfig,
+    #[serde(default)]
+    pub webhooks :WebhooksConfig,
+    /// The MQTT bridge is off unless this is set.
+    #[cfg(feature = "mqtt")]
+    #[serde(default)]
+    pub mqtt :Option<MqttConfig>
+}
+
+#[derive(Deserialize)]
+pub struct DatabaseConfig {
+    pub uri :String,
+    #[serde(default = "default_pool_size")]
+    pub pool_size :u32,
+    /// Whether to apply pending migrations at startup.
+    #[serde(default = "default_run_migrations")]
+    pub run_migrations :bool
+}
+
+/// Where the door controller is and how to talk to it, see `crate::door`.
+#[derive(Deserialize)]
+pub struct CommandServerConfig {
+    #[serde(default)]
+    pub transport :Transport,
+    /// A URL for `http`, `host:port` for `tcp`, the path of the device for `serial`. Unused by `simulator`.
+    #[serde(default)]
+    pub address :String
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// The command server.
+    #[default]
+    Http,
+    Tcp,
+    Serial,
+    /// No door at all, for development.
+    Simulator
+}
+
+#[derive(Deserialize)]
+pub struct CorsConfig {
+    /// Origins the web UI can be served from, `["*"]` allows any.
+    pub allowed_origins :Vec<String>
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TokensConfig {
+    /// Seconds a web UI token is valid for.
+    pub lifetime :u64,
+    /// Seconds a token of a user whose access doesn't expire is valid for. Unset means forever.
+    pub non_expiring_lifetime :Option<u64>
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct DoorConfig {
+    /// Milliseconds the door stays open when opened from the web UI.
+    pub open_duration :u16,
+    /// Seconds a card registration waits for a card before it expires.
+    pub registration_timeout :u64,
+    /// How the controller writes the codes it reads.
+    pub code_format :CodeFormat
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RateLimitsConfig {
+    /// Login attempts allowed from a single address per `login_window`. 0 turns the limit off.
+    pub login_attempts :u32,
+    /// Seconds.
+    pub login_window :u64,
+    /// Wrong PINs allowed for a single user per `pin_window`, after which their card is refused until it passes.
+    /// 0 turns the limit off.
+    pub pin_attempts :u32,
+    /// Seconds.
+    pub pin_window :u64,
+    /// Swipes of codes nobody has allowed from a single door controller, i.e. address, per `unknown_code_window`.
+    /// After that every visitor pass at that door is refused until the window ends, whoever swipes it, as the
+    /// controller can't tell people apart. Registered cards still work. 0 turns the limit off.
+    pub unknown_codes :u32,
+    /// Seconds.
+    pub unknown_code_window :u64
+}
+
+/// What a fresh installation is set up with.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct BootstrapConfig {
+    pub admin_name :String,
+    /// Password of the first admin. If unset, a one-time setup token is logged instead, see `POST /setup`.
+    pub admin_password :Option<String>
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    /// Attempts at delivering an event, including the first one.
+    pub max_attempts :u32,
+    /// Milliseconds before the first retry, doubled after every further failed attempt.
+    pub retry_delay :u64,
+    /// Seconds to wait for the receiver to respond.
+    pub timeout :u64
+}
+
+#[cfg(feature = "mqtt")]
+#[derive(Deserialize, Clone)]
+pub struct MqttConfig {
+    /// Host name or address of the broker.
+    pub host :String,
+    #[serde(default = "default_mqtt_port")]
+    pub port :u16,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id :String,
+    pub username :Option<String>,
+    pub password :Option<String>,
+    #[serde(default)]
+    pub topics :MqttTopicsConfig,
+    /// Seconds between status messages.
+    #[serde(default = "default_mqtt_status_interval")]
+    pub status_interval :u64
+}
+
+#[cfg(feature = "mqtt")]
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct MqttTopicsConfig {
+    /// Events are published to `<events>/<type>`, e.g. `cherrydoor/events/access_denied`.
+    pub events :String,
+    /// The status is retained, so new subscribers get it right away.
+    pub status :String,
+    pub commands :String,
+    /// Where the outcome of every command is published.
+    pub results :String
+}
+
+fn default_pool_size() -> u32 {
+    10
+}
+
+fn default_run_migrations() -> bool {
+    true
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self { allowed_origins: vec![String::from("*")] }
+    }
+}
+
+impl Default for TokensConfig {
+    fn default() -> Self {
+        Self { lifetime: 3600, non_expiring_lifetime: None }
+    }
+}
+
+impl Default for DoorConfig {
+    fn default() -> Self {
+        Self { open_duration: 5000, registration_timeout: 60, code_format: CodeFormat::Hex }
+    }
+}
+
+impl Default for BootstrapConfig {
+    fn default() -> Self {
+        Self { admin_name: String::from("admin"), admin_password: None }
+    }
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self { max_attempts: 5, retry_delay: 10000, timeout: 10 }
+    }
+}
+
+#[cfg(feature = "mqtt")]
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+#[cfg(feature = "mqtt")]
+fn default_mqtt_client_id() -> String {
+    String::from("cherrydoor-web")
+}
+
+#[cfg(feature = "mqtt")]
+fn default_mqtt_status_interval() -> u64 {
+    60
+}
+
+#[cfg(feature = "mqtt")]
+impl Default for MqttTopicsConfig {
+    fn default() -> Self {
+        Self {
+            events: String::from("cherrydoor/events"),
+            status: String::from("cherrydoor/status"),
+            commands: String::from("cherrydoor/commands"),
+            results: String::from("cherrydoor/results")
+        }
+    }
+}
+
+impl Default for RateLimitsConfig {
+    fn default() -> Self {
+        Self { login_attempts: 10, login_window: 60, pin_attempts: 5, pin_window: 300, unknown_codes: 10, unknown_code_window: 60 }
+    }
+}
+
+impl Config {
+    /// Reads the configuration on top of Rocket's figment. Returns every problem found, not just the first one.
+    pub fn load(figment :&Figment) -> Result<Self, Vec<String>> {
+        let figment = figment.clone()
+            // The variables older deployments use
+            .merge(Env::raw().only(&["DATABASE_URI"]).map(|_| "database.uri".into()).global())
+            .merge(Env::raw().only(&["COMMAND_ADDRESS"]).map(|_| "command_server.address".into()).global())
+            .merge(Env::prefixed("CHERRYDOOR_").split("__").global());
+
+        let mut config :Self = match figment.extract() {
+            Ok(config) => config,
+            Err(e) => return Err(e.into_iter().map(|e| e.to_string()).collect())
+        };
+
+        // Paths are appended to the address with a slash.
+        if config.command_server.transport == Transport::Http {
+            config.command_server.address = String::from(config.command_server.address.trim_end_matches('/'));
+        }
+
+        let errors = config.validate();
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+
+        if !db::URI_SCHEMES.iter().any(|scheme| self.database.uri.starts_with(scheme)) {
+            errors.push(format!("database.uri: this build supports {} only, the URI must start with {}", db::BACKEND, db::URI_SCHEMES.join(" or ")));
+        }
+        if self.database.pool_size == 0 {
+            errors.push(String::from("database.pool_size: must be at least 1"));
+        }
+
+        let address = &self.command_server.address;
+        match self.command_server.transport {
+            Transport::Http if !is_http_url(address) => {
+                errors.push(format!("command_server.address: '{}' isn't an http:// or https:// URL", address));
+            },
+            Transport::Tcp if !is_host_port(address) => {
+                errors.push(format!("command_server.address: '{}' isn't a host:port address", address));
+            },
+            Transport::Serial if address.is_empty() => {
+                errors.push(String::from("command_server.address: the path of the serial device is required"));
+            },
+            _ => {}
+        }
+
+        let origins = &self.cors.allowed_origins;
+        if origins.iter().any(|origin| origin == "*") {
+            if origins.len() > 1 {
+                errors.push(String::from("cors.allowed_origins: '*' can't be combined with other origins"));
+            }
+        } else {
+            for origin in origins {
+                if !is_http_url(origin) {
+                    errors.push(format!("cors.allowed_origins: '{}' isn't an http:// or https:// origin", origin));
+                }
+            }
+        }
+
+        if self.tokens.lifetime == 0 {
+            errors.push(String::from("tokens.lifetime: must be at least 1 second"));
+        }
+        if self.tokens.non_expiring_lifetime == Some(0) {
+            errors.push(String::from("tokens.non_expiring_lifetime: must be at least 1 second, or unset"));
+        }
+
+        if self.door.open_duration == 0 {
+            errors.push(String::from("door.open_duration: must be at least 1 millisecond"));
+        }
+        if self.door.registration_timeout == 0 {
+            errors.push(String::from("door.registration_timeout: must be at least 1 second"));
+        }
+
+        if self.rate_limits.login_attempts > 0 && self.rate_limits.login_window == 0 {
+            errors.push(String::from("rate_limits.login_window: must be at least 1 second"));
+        }
+        if self.rate_limits.pin_attempts > 0 && self.rate_limits.pin_window == 0 {
+            errors.push(String::from("rate_limits.pin_window: must be at least 1 second"));
+        }
+        if self.rate_limits.unknown_codes > 0 && self.rate_limits.unknown_code_window == 0 {
+            errors.push(String::from("rate_limits.unknown_code_window: must be at least 1 second"));
+        }
+
+        if self.webhooks.max_attempts == 0 {
+            errors.push(String::from("webhooks.max_attempts: must be at least 1"));
+        }
+        if self.webhooks.timeout == 0 {
+            errors.push(String::from("webhooks.timeout: must be at least 1 second"));
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &self.mqtt {
+            if mqtt.host.is_empty() {
+                errors.push(String::from("mqtt.host: can't be empty"));
+            }
+            if mqtt.status_interval == 0 {
+                errors.push(String::from("mqtt.status_interval: must be at least 1 second"));
+            }
+
+            let topics = &mqtt.topics;
+            for (name, topic) in [("events", &topics.events), ("status", &topics.status), ("commands", &topics.commands), ("results", &topics.results)] {
+                if topic.is_empty() || topic.contains(['+', '#']) {
+                    errors.push(format!("mqtt.topics.{}: '{}' has to be a topic name, without wildcards", name, topic));
+                }
+            }
+        }
+
+        let mut field_errors = vec![];
+        check_name("bootstrap.admin_name", &self.bootstrap.admin_name, &mut field_errors);
+        if let Some(password) = &self.bootstrap.admin_password {
+            check_not_empty("bootstrap.admin_password", password, &mut field_errors);
+        }
+        errors.extend(field_errors.into_iter().map(|e| format!("{}: {}", e.field, e.message)));
+
+        errors
+    }
+}
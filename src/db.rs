@@ -1,14 +1,187 @@
-use diesel_async::{pooled_connection::bb8::{Pool, PooledConnection}, AsyncMysqlConnection};
+use diesel_async::{AsyncConnection, pooled_connection::{bb8::{Pool, PooledConnection}, AsyncDieselConnectionManager}, scoped_futures::ScopedBoxFuture};
 
-use crate::error::ApiError;
+use std::time::Instant;
 
-pub type DbPool = Pool<AsyncMysqlConnection>;
-pub type DbConnection<'a> = PooledConnection<'a, AsyncMysqlConnection>;
-pub struct DB(pub DbPool);
+use prometheus::Histogram;
+
+use crate::{error::ApiError, config::DatabaseConfig};
+
+// The backend is chosen at build time with one of the `mysql` (default), `postgres` or `sqlite` features.
+#[cfg(not(any(feature = "mysql", feature = "postgres", feature = "sqlite")))]
+compile_error!("enable one of the database features: mysql, postgres or sqlite");
+
+#[cfg(any(all(feature = "mysql", feature = "postgres"), all(feature = "mysql", feature = "sqlite"), all(feature = "postgres", feature = "sqlite")))]
+compile_error!("only one database feature can be enabled, use --no-default-features with postgres or sqlite");
+
+#[cfg(feature = "mysql")]
+pub type Connection = diesel_async::AsyncMysqlConnection;
+#[cfg(feature = "postgres")]
+pub type Connection = diesel_async::AsyncPgConnection;
+// diesel-async has no SQLite driver of its own, so the synchronous one runs on a blocking thread.
+#[cfg(feature = "sqlite")]
+pub type Connection = diesel_async::sync_connection_wrapper::SyncConnectionWrapper<diesel::SqliteConnection>;
+
+/// The backend this build uses, and the schemes its `database.uri` can start with.
+#[cfg(feature = "mysql")]
+pub const BACKEND :&str = "mysql";
+#[cfg(feature = "mysql")]
+pub const URI_SCHEMES :&[&str] = &["mysql://"];
+#[cfg(feature = "postgres")]
+pub const BACKEND :&str = "postgres";
+#[cfg(feature = "postgres")]
+pub const URI_SCHEMES :&[&str] = &["postgres://", "postgresql://"];
+#[cfg(feature = "sqlite")]
+pub const BACKEND :&str = "sqlite";
+#[cfg(feature = "sqlite")]
+pub const URI_SCHEMES :&[&str] = &["sqlite://"];
+
+pub type DbPool = Pool<Connection>;
+pub type DbConnection<'a> = PooledConnection<'a, Connection>;
+/// The connection pool, and a histogram of how long getting a connection from it took.
+#[derive(Clone)]
+pub struct DB(pub DbPool, pub Histogram);
+
+impl DB {
+    /// Connections are made lazily, so this succeeds even if the database isn't up yet.
+    pub fn new(config :&DatabaseConfig, wait :Histogram) -> Self {
+        Self(
+            Pool::builder()
+                .max_size(pool_size(config))
+                .build_unchecked(manager(connection_uri(&config.uri))),
+            wait
+        )
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn pool_size(config :&DatabaseConfig) -> u32 {
+    config.pool_size
+}
+
+/// SQLite allows one writer at a time anyway, and every connection to `:memory:` would get a database of its own.
+#[cfg(feature = "sqlite")]
+fn pool_size(_config :&DatabaseConfig) -> u32 {
+    1
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn manager(uri :&str) -> AsyncDieselConnectionManager<Connection> {
+    AsyncDieselConnectionManager::new(uri)
+}
+
+/// SQLite only enforces foreign keys on connections that ask for it, so every new one in the pool does.
+#[cfg(feature = "sqlite")]
+fn manager(uri :&str) -> AsyncDieselConnectionManager<Connection> {
+    let mut config = diesel_async::pooled_connection::ManagerConfig::default();
+    config.custom_setup = Box::new(establish_with_foreign_keys);
+    AsyncDieselConnectionManager::new_with_config(uri, config)
+}
+
+#[cfg(feature = "sqlite")]
+fn establish_with_foreign_keys(uri :&str) -> futures_util::future::BoxFuture<'_, diesel::ConnectionResult<Connection>> {
+    use diesel_async::SimpleAsyncConnection;
+    use futures_util::FutureExt;
+
+    async move {
+        let mut conn = Connection::establish(uri).await?;
+        match conn.batch_execute("PRAGMA foreign_keys = ON").await {
+            Ok(_) => Ok(conn),
+            Err(e) => Err(diesel::ConnectionError::CouldntSetupConfiguration(e))
+        }
+    }.boxed()
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn connection_uri(uri :&str) -> &str {
+    uri
+}
+
+/// The SQLite driver takes a path, or `:memory:`.
+#[cfg(feature = "sqlite")]
+fn connection_uri(uri :&str) -> &str {
+    uri.trim_start_matches("sqlite://")
+}
 
 pub async fn get_connection(db :& DB) -> Result<DbConnection<'_>, ApiError> {
-    match db.0.get().await {
+    let started = Instant::now();
+    let res = db.0.get().await;
+    db.1.observe(started.elapsed().as_secs_f64());
+
+    match res {
         Ok(conn) => Ok(conn),
         Err(e) => Err(ApiError::Internal(format!("{}", e)))
     }
-}
\ No newline at end of file
+}
+
+/// Runs `f` in a transaction, committed if it returns `Ok` and rolled back otherwise. Anything writing more than
+/// once, or reading back what it wrote, goes through this, so nobody sees half of a change or someone else's in
+/// the middle of it. Nested calls become savepoints. Wrap the body of `f` in `async move { ... }.scope_boxed()`.
+pub async fn transaction<'a, 'f, R, F>(
+    conn :&mut DbConnection<'a>,
+    f :F
+) -> Result<R, ApiError>
+where
+    F :for<'r> FnOnce(&'r mut DbConnection<'a>) -> ScopedBoxFuture<'f, 'r, Result<R, ApiError>> + Send + 'f,
+    R :Send + 'f
+{
+    conn.transaction(f).await
+}
+
+/// Id of the row the last insert on this connection made, for tables with an auto-incremented id and nothing unique
+/// to find the row by. MySQL has no `RETURNING`.
+pub async fn last_insert_id<'a>(conn :&mut DbConnection<'a>) -> Result<i32, ApiError> {
+    #[cfg(feature = "mysql")]
+    const QUERY :&str = "CAST(LAST_INSERT_ID() AS SIGNED)";
+    #[cfg(feature = "postgres")]
+    const QUERY :&str = "lastval()";
+    #[cfg(feature = "sqlite")]
+    const QUERY :&str = "last_insert_rowid()";
+
+    match diesel_async::RunQueryDsl::get_result::<i64>(
+        diesel::select(diesel::dsl::sql::<diesel::sql_types::BigInt>(QUERY)), conn
+    ).await {
+        Ok(id) => match i32::try_from(id) {
+            Ok(id) => Ok(id),
+            Err(e) => Err(ApiError::Internal(format!("{}", e)))
+        },
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// `SELECT ... FOR UPDATE`: the rows read stay locked until the transaction ends, so whatever is decided from them
+/// still holds when they're written. SQLite has no row locks, but with its single connection transactions
+/// already run one at a time.
+#[cfg(not(feature = "sqlite"))]
+macro_rules! for_update {
+    ($query:expr) => { diesel::QueryDsl::for_update($query) };
+}
+
+#[cfg(feature = "sqlite")]
+macro_rules! for_update {
+    ($query:expr) => { $query };
+}
+
+pub(crate) use for_update;
+
+/// Looks a row up by the key from a URL: by id if the key is a number and such a row exists, by name otherwise.
+/// Names made only of digits can't be given anymore, but older ones stay reachable as long as no id shadows them.
+macro_rules! by_key {
+    ($query:expr, $id:expr, $name:expr, $key:expr, $conn:expr) => {{
+        let key :&str = $key;
+        let by_id = match key.parse::<i32>() {
+            Ok(id) => diesel::OptionalExtension::optional(diesel_async::RunQueryDsl::first(
+                diesel::QueryDsl::filter($query, diesel::ExpressionMethods::eq($id, id)), &mut *$conn
+            ).await),
+            Err(_) => Ok(None)
+        };
+
+        match by_id {
+            Ok(None) => diesel::OptionalExtension::optional(diesel_async::RunQueryDsl::first(
+                diesel::QueryDsl::filter($query, diesel::ExpressionMethods::eq($name, key)), &mut *$conn
+            ).await),
+            found => found
+        }
+    }};
+}
+
+pub(crate) use by_key;
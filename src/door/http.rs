@@ -0,0 +1,73 @@
+use cherrydoor_command::Command;
+use reqwest::StatusCode;
+
+use super::{DoorController, DoorError};
+
+/// The command server: commands are POSTed to `/`, and `GET /register` responds with the code of the next card
+/// swiped, or 404 if none was.
+pub struct HttpController {
+    address :String,
+    client :reqwest::Client
+}
+
+impl HttpController {
+    pub fn new(address :&str) -> Self {
+        Self {
+            address: String::from(address),
+            client: reqwest::Client::new()
+        }
+    }
+}
+
+async fn rejected(res :reqwest::Response) -> DoorError {
+    DoorError::Rejected(res.text().await.unwrap_or("garbage".to_string()))
+}
+
+#[rocket::async_trait]
+impl DoorController for HttpController {
+    fn describe(&self) -> String {
+        self.address.clone()
+    }
+
+    async fn send(&self, command :&Command) -> Result<(), DoorError> {
+        let res = match self.client.post(format!("{}/", self.address)).json(command).send().await {
+            Ok(res) => res,
+            Err(e) => return Err(DoorError::Unreachable(e.to_string()))
+        };
+
+        match res.status() {
+            StatusCode::NO_CONTENT => Ok(()),
+            _ => Err(rejected(res).await)
+        }
+    }
+
+    async fn register(&self) -> Result<String, DoorError> {
+        let res = match self.client.get(format!("{}/register", self.address)).send().await {
+            Ok(res) => res,
+            Err(e) => return Err(DoorError::Unreachable(e.to_string()))
+        };
+
+        match res.status() {
+            StatusCode::OK => match res.text().await {
+                Ok(code) => Ok(code),
+                Err(_) => Err(DoorError::Rejected(String::from("garbage")))
+            },
+            StatusCode::NOT_FOUND => Err(DoorError::NoCard),
+            _ => Err(rejected(res).await)
+        }
+    }
+
+    /// Any successful response will do, the command server doesn't have a dedicated health route.
+    async fn status(&self) -> Result<(), DoorError> {
+        let res = match self.client.get(format!("{}/", self.address)).send().await {
+            Ok(res) => res,
+            Err(e) => return Err(DoorError::Unreachable(e.to_string()))
+        };
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(DoorError::Rejected(format!("status {}", res.status())))
+        }
+    }
+}
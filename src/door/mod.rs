@@ -0,0 +1,129 @@
+// Talking to the door controller. The server only needs three things from it: taking commands, reading a card
+// for registration and telling whether it's there. How that happens depends on how the controller is wired up,
+// see `command_server.transport`.
+
+mod http;
+mod stream;
+mod simulator;
+
+use std::{fmt::Display, sync::Arc, time::Instant};
+
+use cherrydoor_command::Command;
+
+use crate::{config::{CommandServerConfig, Transport}, error::{ApiError, ErrorCode}, metrics::Metrics};
+
+pub use self::{http::HttpController, stream::StreamController, simulator::Simulator};
+
+#[derive(Debug)]
+pub enum DoorError {
+    /// The controller couldn't be reached.
+    Unreachable(String),
+    /// The controller responded, but with an error.
+    Rejected(String),
+    /// Nobody swiped a card before the controller gave up on registration.
+    NoCard
+}
+
+impl std::error::Error for DoorError {}
+
+impl Display for DoorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreachable(s) => write!(f, "Couldn't reach the door controller: {}", s),
+            Self::Rejected(s) => write!(f, "The door controller returned {}", s),
+            Self::NoCard => write!(f, "No code was read in time.")
+        }
+    }
+}
+
+impl From<DoorError> for ApiError {
+    fn from(e :DoorError) -> Self {
+        match e {
+            DoorError::Unreachable(_) => Self::CommandServerUnreachable(e.to_string()),
+            DoorError::Rejected(_) => Self::CommandServer(e.to_string()),
+            DoorError::NoCard => Self::NotFound(ErrorCode::RegistrationTimedOut)
+        }
+    }
+}
+
+/// A way of talking to the door controller.
+#[rocket::async_trait]
+pub trait DoorController :Send + Sync {
+    /// Where the controller is, for logs.
+    fn describe(&self) -> String;
+
+    async fn send(&self, command :&Command) -> Result<(), DoorError>;
+
+    /// Waits for a card to be swiped and returns its code.
+    async fn register(&self) -> Result<String, DoorError>;
+
+    /// Succeeds if the controller is reachable.
+    async fn status(&self) -> Result<(), DoorError>;
+}
+
+/// The controller the server was configured with. Cloning is cheap, clones share the controller.
+#[derive(Clone)]
+pub struct Door {
+    controller :Arc<dyn DoorController>,
+    metrics :Metrics
+}
+
+impl Door {
+    pub fn new(controller :Arc<dyn DoorController>, metrics :Metrics) -> Self {
+        Self { controller, metrics }
+    }
+
+    pub fn from_config(config :&CommandServerConfig, metrics :Metrics) -> Self {
+        let controller :Arc<dyn DoorController> = match config.transport {
+            Transport::Http => Arc::new(HttpController::new(&config.address)),
+            Transport::Tcp => Arc::new(StreamController::tcp(&config.address)),
+            Transport::Serial => Arc::new(StreamController::serial(&config.address)),
+            Transport::Simulator => Arc::new(Simulator::new())
+        };
+
+        Self::new(controller, metrics)
+    }
+
+    /// Sends the command, `action` labels it in the metrics.
+    #[tracing::instrument(name = "door", skip_all, fields(controller = %self.controller.describe(), action))]
+    pub async fn send(&self, action :&str, command :&Command) -> Result<(), DoorError> {
+        let started = Instant::now();
+        let res = self.controller.send(command).await;
+
+        match &res {
+            Ok(()) => tracing::debug!("command sent"),
+            Err(e) => tracing::warn!(error = %e, "command failed")
+        }
+        self.metrics.command(action, started, error_kind(&res));
+
+        res
+    }
+
+    #[tracing::instrument(name = "door", skip_all, fields(controller = %self.controller.describe()))]
+    pub async fn register(&self) -> Result<String, DoorError> {
+        let started = Instant::now();
+        let res = self.controller.register().await;
+
+        match &res {
+            Ok(_) => tracing::debug!("code read"),
+            Err(DoorError::NoCard) => tracing::info!("no code was read in time"),
+            Err(e) => tracing::warn!(error = %e, "registration failed")
+        }
+        self.metrics.command("register", started, error_kind(&res));
+
+        res
+    }
+
+    pub async fn status(&self) -> Result<(), DoorError> {
+        self.controller.status().await
+    }
+}
+
+/// Nobody swiping a card in time isn't a failure of the controller.
+fn error_kind<T>(res :&Result<T, DoorError>) -> Option<&'static str> {
+    match res {
+        Ok(_) | Err(DoorError::NoCard) => None,
+        Err(DoorError::Unreachable(_)) => Some("unreachable"),
+        Err(DoorError::Rejected(_)) => Some("rejected")
+    }
+}
@@ -0,0 +1,97 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use async_mutex::Mutex;
+use cherrydoor_command::Command;
+use rand::Rng;
+use serde_json::Value;
+
+use crate::codes;
+
+use super::{DoorController, DoorError};
+
+/// Commands kept, older ones are forgotten.
+const KEPT_COMMANDS :usize = 100;
+
+#[derive(Default)]
+struct SimulatorState {
+    commands :Vec<Value>,
+    /// Codes of the cards to be swiped at the next registrations.
+    cards :VecDeque<String>,
+    offline :bool
+}
+
+/// A door that isn't there, for development without the hardware. Commands are logged and kept, and registration
+/// reads a queued card, or makes a random one up. Cloning is cheap, clones share the same door.
+#[derive(Clone, Default)]
+pub struct Simulator {
+    state :Arc<Mutex<SimulatorState>>
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// For the tests to drive the door and see what it got.
+#[cfg(test)]
+impl Simulator {
+    /// The last commands sent, oldest first, as JSON.
+    pub async fn commands(&self) -> Vec<Value> {
+        self.state.lock().await.commands.clone()
+    }
+
+    /// Queues a card to be read at the next registration.
+    pub async fn swipe(&self, code :&str) {
+        self.state.lock().await.cards.push_back(String::from(code));
+    }
+
+    /// An offline door fails every request as unreachable.
+    pub async fn set_offline(&self, offline :bool) {
+        self.state.lock().await.offline = offline;
+    }
+}
+
+#[rocket::async_trait]
+impl DoorController for Simulator {
+    fn describe(&self) -> String {
+        String::from("simulator")
+    }
+
+    async fn send(&self, command :&Command) -> Result<(), DoorError> {
+        let mut state = self.state.lock().await;
+        if state.offline {
+            return Err(DoorError::Unreachable(String::from("the simulated door is offline")))
+        }
+
+        let command = serde_json::to_value(command).unwrap_or_default();
+        tracing::info!(%command, "the simulated door got a command");
+        if state.commands.len() == KEPT_COMMANDS {
+            state.commands.remove(0);
+        }
+        state.commands.push(command);
+
+        Ok(())
+    }
+
+    async fn register(&self) -> Result<String, DoorError> {
+        let mut state = self.state.lock().await;
+        if state.offline {
+            return Err(DoorError::Unreachable(String::from("the simulated door is offline")))
+        }
+
+        Ok(match state.cards.pop_front() {
+            Some(code) => code,
+            // A 4 byte UID, which the default `hex` code format takes as it is.
+            None => codes::to_hex(&rand::thread_rng().gen::<[u8; 4]>())
+        })
+    }
+
+    async fn status(&self) -> Result<(), DoorError> {
+        if self.state.lock().await.offline {
+            return Err(DoorError::Unreachable(String::from("the simulated door is offline")))
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use async_mutex::Mutex;
+use cherrydoor_command::Command;
+use rocket::tokio::{
+    fs::OpenOptions, net::TcpStream, time::timeout,
+    io::{AsyncRead, AsyncWrite, AsyncBufReadExt, AsyncWriteExt, BufReader}
+};
+use serde::{Deserialize, Serialize};
+
+use super::{DoorController, DoorError};
+
+const CONNECT_TIMEOUT :Duration = Duration::from_secs(5);
+/// How long a reply to a command or a status request may take.
+const REPLY_TIMEOUT :Duration = Duration::from_secs(10);
+/// The controller decides when to give up waiting for a card, this only guards against it never replying.
+const REGISTER_TIMEOUT :Duration = Duration::from_secs(120);
+
+/// One JSON object per line, e.g. `{"type": "command", "command": {...}}`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request<'a> {
+    Command {command :&'a Command},
+    Register,
+    Status
+}
+
+/// Answers every request with one line, e.g. `{"ok": true, "code": "..."}` or `{"ok": false, "error": "no_card"}`.
+#[derive(Deserialize)]
+struct Reply {
+    ok :bool,
+    code :Option<String>,
+    error :Option<String>
+}
+
+trait Connection :AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T :AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+enum Endpoint {
+    /// `host:port`
+    Tcp(String),
+    /// Path of the device, e.g. `/dev/ttyUSB0`.
+    Serial(String)
+}
+
+/// A controller wired straight to the server, speaking line-delimited JSON over a TCP socket or a serial port.
+/// The connection is kept open between requests and made again after any failure. Requests take turns on it, so
+/// while the controller waits for a card, commands, opening the door too, wait behind it. Registration sessions
+/// ask for a card in short attempts to let them through; an attempt given up on drops the connection.
+pub struct StreamController {
+    endpoint :Endpoint,
+    connection :Mutex<Option<BufReader<Box<dyn Connection>>>>,
+    /// How the last finished request went, `Err` if the controller couldn't be reached. `None` before the first.
+    last_outcome :std::sync::Mutex<Option<Result<(), String>>>
+}
+
+impl StreamController {
+    pub fn tcp(address :&str) -> Self {
+        Self::new(Endpoint::Tcp(String::from(address)))
+    }
+
+    /// The line settings (baud rate, raw mode) aren't touched, set them up beforehand, e.g. with `stty`.
+    pub fn serial(device :&str) -> Self {
+        Self::new(Endpoint::Serial(String::from(device)))
+    }
+
+    fn new(endpoint :Endpoint) -> Self {
+        Self { endpoint, connection: Mutex::new(None), last_outcome: std::sync::Mutex::new(None) }
+    }
+
+    async fn connect(&self) -> Result<BufReader<Box<dyn Connection>>, DoorError> {
+        let connection :Box<dyn Connection> = match &self.endpoint {
+            Endpoint::Tcp(address) => match timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+                Ok(Ok(stream)) => Box::new(stream),
+                Ok(Err(e)) => return Err(DoorError::Unreachable(e.to_string())),
+                Err(_) => return Err(DoorError::Unreachable(String::from("timed out while connecting")))
+            },
+            Endpoint::Serial(device) => match OpenOptions::new().read(true).write(true).open(device).await {
+                Ok(file) => Box::new(file),
+                Err(e) => return Err(DoorError::Unreachable(e.to_string()))
+            }
+        };
+
+        Ok(BufReader::new(connection))
+    }
+
+    /// Requests are made one at a time, as replies carry nothing to match them with.
+    async fn request(&self, request :&Request<'_>, wait :Duration) -> Result<Reply, DoorError> {
+        let res = self.make_request(request, wait).await;
+
+        let outcome = match &res {
+            Err(DoorError::Unreachable(e)) => Err(e.clone()),
+            _ => Ok(())
+        };
+        if let Ok(mut last_outcome) = self.last_outcome.lock() {
+            *last_outcome = Some(outcome);
+        }
+
+        res
+    }
+
+    async fn make_request(&self, request :&Request<'_>, wait :Duration) -> Result<Reply, DoorError> {
+        let mut line = match serde_json::to_string(request) {
+            Ok(line) => line,
+            Err(e) => return Err(DoorError::Rejected(format!("an unserializable request: {}", e)))
+        };
+        line.push('\n');
+
+        let mut connection = self.connection.lock().await;
+
+        // Taken out until the reply arrives, so a request cut short by a timeout, or by the caller giving up,
+        // doesn't leave its reply to be read as the reply to the next one.
+        let mut stream = match connection.take() {
+            Some(stream) => stream,
+            None => self.connect().await?
+        };
+
+        let reply = match timeout(wait, exchange(&mut stream, &line)).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(e)) => return Err(DoorError::Unreachable(e.to_string())),
+            Err(_) => return Err(DoorError::Unreachable(String::from("no reply in time")))
+        };
+
+        let reply = match serde_json::from_str(&reply) {
+            Ok(reply) => reply,
+            Err(_) => return Err(DoorError::Rejected(String::from("garbage")))
+        };
+
+        *connection = Some(stream);
+        Ok(reply)
+    }
+}
+
+async fn exchange(stream :&mut BufReader<Box<dyn Connection>>, line :&str) -> std::io::Result<String> {
+    stream.write_all(line.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut reply = String::new();
+    if stream.read_line(&mut reply).await? == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "the controller closed the connection"))
+    }
+
+    Ok(reply)
+}
+
+fn rejected(reply :Reply) -> DoorError {
+    DoorError::Rejected(reply.error.unwrap_or("garbage".to_string()))
+}
+
+#[rocket::async_trait]
+impl DoorController for StreamController {
+    fn describe(&self) -> String {
+        match &self.endpoint {
+            Endpoint::Tcp(address) => format!("tcp://{}", address),
+            Endpoint::Serial(device) => device.clone()
+        }
+    }
+
+    async fn send(&self, command :&Command) -> Result<(), DoorError> {
+        let reply = self.request(&Request::Command { command }, REPLY_TIMEOUT).await?;
+
+        if reply.ok { Ok(()) } else { Err(rejected(reply)) }
+    }
+
+    async fn register(&self) -> Result<String, DoorError> {
+        let reply = self.request(&Request::Register, REGISTER_TIMEOUT).await?;
+
+        if !reply.ok {
+            return match reply.error.as_deref() {
+                Some("no_card") => Err(DoorError::NoCard),
+                _ => Err(rejected(reply))
+            }
+        }
+
+        match reply.code {
+            Some(code) => Ok(code),
+            None => Err(DoorError::Rejected(String::from("no code")))
+        }
+    }
+
+    async fn status(&self) -> Result<(), DoorError> {
+        // Someone else's request, a registration say, may hold the connection for a while. The controller is as
+        // it was at the last request then.
+        if self.connection.try_lock().is_none() {
+            let last_outcome = match self.last_outcome.lock() {
+                Ok(last_outcome) => last_outcome.clone(),
+                Err(_) => None
+            };
+
+            return match last_outcome {
+                Some(Ok(())) => Ok(()),
+                Some(Err(e)) => Err(DoorError::Unreachable(format!("busy, and unreachable at the last request: {}", e))),
+                None => Err(DoorError::Unreachable(String::from("busy with the first request, not known yet")))
+            }
+        }
+
+        let reply = self.request(&Request::Status, REPLY_TIMEOUT).await?;
+
+        if reply.ok { Ok(()) } else { Err(rejected(reply)) }
+    }
+}
@@ -1,39 +1,492 @@
-use rocket::{response::Responder, http::{Status, ContentType}, Response, catch, serde::json::Json};
+use rocket::{response::Responder, http::{Status, ContentType}, Response, Request, catch, serde::json::Json};
 use serde::Serialize;
 
 use std::{io::Cursor, fmt::Display};
 
+use crate::{validation::BodyErrors, i18n::Language, fairings::{request_id::RequestId, trace::RequestSpan}};
+
 #[derive(Serialize)]
 pub struct ErrorResponse {
-    message :String
+    code :&'static str,
+    message :String,
+    request_id :String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields :Vec<FieldError>
+}
+
+impl ErrorResponse {
+    fn new(req :&Request, code :&'static str, message :String, fields :Vec<FieldError>) -> Self {
+        Self {
+            code,
+            message,
+            request_id: String::from(RequestId::of(req)),
+            fields
+        }
+    }
+}
+
+/// A single invalid field of a request body.
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldError {
+    pub field :String,
+    pub message :String
+}
+
+impl FieldError {
+    pub fn new(field :&str, message :&str) -> Self {
+        Self {
+            field: String::from(field),
+            message: String::from(message)
+        }
+    }
+}
+
+/// What went wrong, in a way clients can match on. The codes are part of the API and must not change.
+#[derive(Debug, Clone)]
+pub enum ErrorCode {
+    // Missing resources
+    UserNotFound(String),
+    WebUIUserNotFound(String),
+    PermissionNotFound(String),
+    AccessProfileNotFound(String),
+    RoleNotFound(String),
+    GroupNotFound(String),
+    WebhookNotFound(String),
+    VisitorPassNotFound(i32),
+    AccessCodeNotFound {id :i32, user :String},
+    PinNotFound(String),
+    UserNotInside(String),
+    UserPermissionNotFound {id :i32, user :String},
+    PermissionUserNotFound {id :i32, permission :String},
+    PermissionAccessProfileNotFound {id :i32, permission :String},
+    AccessProfilePermissionNotFound {id :i32, access_profile :String},
+    WebUIUserRoleNotFound {id :i32, user :String},
+    GroupUserNotFound {id :i32, group :String},
+    GroupPermissionNotFound {id :i32, group :String},
+    RegistrationNotFound {id :u32, user :String},
+
+    // Conflicts
+    UserConflict(String),
+    WebUIUserConflict(String),
+    PermissionConflict(String),
+    AccessProfileConflict(String),
+    RoleConflict(String),
+    GroupConflict(String),
+    WebhookConflict(String),
+    AccessCodeConflict,
+    UserPermissionConflict(String),
+    PermissionUserConflict(String),
+    AccessProfilePermissionConflict(String),
+    PermissionAccessProfileConflict(String),
+    WebUIUserRoleConflict(String),
+    GroupUserConflict(String),
+    GroupPermissionConflict(String),
+    AccessProfileActive(String),
+    VisitorCodeConflict,
+    EmergencyActive,
+    RegistrationInProgress,
+    RegistrationFinished(u32),
+
+    // Failed preconditions
+    ResourceModified,
+
+    // Authentication and authorization
+    BadPassword,
+    AdminRequired,
+    AdminTargetRequiresAdmin,
+    CapabilityNotHeld(String),
+    OwnRolesLocked,
+    TooManyLoginAttempts,
+    BadSetupToken,
+    AlreadySetUp,
+
+    // Door access
+    AccessCodeNotRegistered,
+    RegistrationTimedOut,
+    Lockdown,
+    AccessDenied,
+    AntiPassback,
+    RoomFull,
+    PinRequired,
+    WrongPin,
+    TooManyWrongPins,
+    TooManyUnknownCodes,
+    VisitorPassNotYetValid,
+    VisitorPassExpired,
+    VisitorPassUsedUp,
+    HostNotPermitted
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UserNotFound(_) => "user_not_found",
+            Self::WebUIUserNotFound(_) => "web_ui_user_not_found",
+            Self::PermissionNotFound(_) => "permission_not_found",
+            Self::AccessProfileNotFound(_) => "access_profile_not_found",
+            Self::RoleNotFound(_) => "role_not_found",
+            Self::GroupNotFound(_) => "group_not_found",
+            Self::WebhookNotFound(_) => "webhook_not_found",
+            Self::VisitorPassNotFound(_) => "visitor_pass_not_found",
+            Self::AccessCodeNotFound {..} => "access_code_not_found",
+            Self::PinNotFound(_) => "pin_not_found",
+            Self::UserNotInside(_) => "user_not_inside",
+            Self::UserPermissionNotFound {..} => "user_permission_not_found",
+            Self::PermissionUserNotFound {..} => "permission_user_not_found",
+            Self::PermissionAccessProfileNotFound {..} => "permission_access_profile_not_found",
+            Self::AccessProfilePermissionNotFound {..} => "access_profile_permission_not_found",
+            Self::WebUIUserRoleNotFound {..} => "web_ui_user_role_not_found",
+            Self::GroupUserNotFound {..} => "group_user_not_found",
+            Self::GroupPermissionNotFound {..} => "group_permission_not_found",
+            Self::RegistrationNotFound {..} => "registration_not_found",
+
+            Self::UserConflict(_) => "user_conflict",
+            Self::WebUIUserConflict(_) => "web_ui_user_conflict",
+            Self::PermissionConflict(_) => "permission_conflict",
+            Self::AccessProfileConflict(_) => "access_profile_conflict",
+            Self::RoleConflict(_) => "role_conflict",
+            Self::GroupConflict(_) => "group_conflict",
+            Self::WebhookConflict(_) => "webhook_conflict",
+            Self::AccessCodeConflict => "access_code_conflict",
+            Self::UserPermissionConflict(_) | Self::PermissionUserConflict(_) => "user_permission_conflict",
+            Self::AccessProfilePermissionConflict(_) | Self::PermissionAccessProfileConflict(_) => "access_profile_permission_conflict",
+            Self::WebUIUserRoleConflict(_) => "web_ui_user_role_conflict",
+            Self::GroupUserConflict(_) => "group_user_conflict",
+            Self::GroupPermissionConflict(_) => "group_permission_conflict",
+            Self::AccessProfileActive(_) => "access_profile_active",
+            Self::VisitorCodeConflict => "visitor_code_conflict",
+            Self::EmergencyActive => "emergency_active",
+            Self::RegistrationInProgress => "registration_in_progress",
+            Self::RegistrationFinished(_) => "registration_finished",
+
+            Self::ResourceModified => "resource_modified",
+
+            Self::BadPassword => "bad_password",
+            Self::AdminRequired => "admin_required",
+            Self::AdminTargetRequiresAdmin => "admin_target_requires_admin",
+            Self::CapabilityNotHeld(_) => "capability_not_held",
+            Self::OwnRolesLocked => "own_roles_locked",
+            Self::TooManyLoginAttempts => "too_many_login_attempts",
+            Self::BadSetupToken => "bad_setup_token",
+            Self::AlreadySetUp => "already_set_up",
+
+            Self::AccessCodeNotRegistered => "access_code_not_registered",
+            Self::RegistrationTimedOut => "registration_timed_out",
+            Self::Lockdown => "lockdown",
+            Self::AccessDenied => "access_denied",
+            Self::AntiPassback => "anti_passback",
+            Self::RoomFull => "room_full",
+            Self::PinRequired => "pin_required",
+            Self::WrongPin => "wrong_pin",
+            Self::TooManyWrongPins => "too_many_wrong_pins",
+            Self::TooManyUnknownCodes => "too_many_unknown_codes",
+            Self::VisitorPassNotYetValid => "visitor_pass_not_yet_valid",
+            Self::VisitorPassExpired => "visitor_pass_expired",
+            Self::VisitorPassUsedUp => "visitor_pass_used_up",
+            Self::HostNotPermitted => "host_not_permitted"
+        }
+    }
+
+    pub fn message(&self, language :Language) -> String {
+        let (en, pl) = match self {
+            Self::UserNotFound(name) | Self::WebUIUserNotFound(name) => (
+                format!("User {} not found.", name),
+                format!("Nie znaleziono użytkownika {}.", name)
+            ),
+            Self::PermissionNotFound(name) => (
+                format!("Permission {} not found.", name),
+                format!("Nie znaleziono uprawnienia {}.", name)
+            ),
+            Self::AccessProfileNotFound(name) => (
+                format!("Access profile {} not found.", name),
+                format!("Nie znaleziono profilu dostępu {}.", name)
+            ),
+            Self::RoleNotFound(name) => (
+                format!("Role {} not found.", name),
+                format!("Nie znaleziono roli {}.", name)
+            ),
+            Self::GroupNotFound(name) => (
+                format!("Group {} not found.", name),
+                format!("Nie znaleziono grupy {}.", name)
+            ),
+            Self::WebhookNotFound(name) => (
+                format!("Webhook {} not found.", name),
+                format!("Nie znaleziono webhooka {}.", name)
+            ),
+            Self::VisitorPassNotFound(id) => (
+                format!("Visitor pass {} not found.", id),
+                format!("Nie znaleziono przepustki {}.", id)
+            ),
+            Self::AccessCodeNotFound {id, user} => (
+                format!("Access code {} does not exist or does not belong to user {}.", id, user),
+                format!("Kod dostępu {} nie istnieje lub nie należy do użytkownika {}.", id, user)
+            ),
+            Self::PinNotFound(name) => (
+                format!("User {} has no PIN set.", name),
+                format!("Użytkownik {} nie ma ustawionego PIN-u.", name)
+            ),
+            Self::UserNotInside(name) => (
+                format!("User {} is not inside.", name),
+                format!("Użytkownika {} nie ma w pomieszczeniu.", name)
+            ),
+            Self::UserPermissionNotFound {id, user} => (
+                format!("Permission {} either does not exist, or does not belong to user {}.", id, user),
+                format!("Uprawnienie {} nie istnieje lub nie należy do użytkownika {}.", id, user)
+            ),
+            Self::PermissionUserNotFound {id, permission} => (
+                format!("User with ID {} either does not exist, or does not have permission {}.", id, permission),
+                format!("Użytkownik o ID {} nie istnieje lub nie ma uprawnienia {}.", id, permission)
+            ),
+            Self::PermissionAccessProfileNotFound {id, permission} => (
+                format!("Access profile with ID {} either does not exist, or is not associated with permission {}.", id, permission),
+                format!("Profil dostępu o ID {} nie istnieje lub nie jest powiązany z uprawnieniem {}.", id, permission)
+            ),
+            Self::AccessProfilePermissionNotFound {id, access_profile} => (
+                format!("Permission with ID {} either does not exist, or is not associated with profile {}.", id, access_profile),
+                format!("Uprawnienie o ID {} nie istnieje lub nie jest powiązane z profilem {}.", id, access_profile)
+            ),
+            Self::WebUIUserRoleNotFound {id, user} => (
+                format!("Role {} either does not exist, or is not assigned to user {}.", id, user),
+                format!("Rola {} nie istnieje lub nie jest przypisana do użytkownika {}.", id, user)
+            ),
+            Self::GroupUserNotFound {id, group} => (
+                format!("User with ID {} either does not exist, or is not a member of group {}.", id, group),
+                format!("Użytkownik o ID {} nie istnieje lub nie należy do grupy {}.", id, group)
+            ),
+            Self::GroupPermissionNotFound {id, group} => (
+                format!("Permission with ID {} either does not exist, or is not granted to group {}.", id, group),
+                format!("Uprawnienie o ID {} nie istnieje lub nie jest nadane grupie {}.", id, group)
+            ),
+            Self::RegistrationNotFound {id, user} => (
+                format!("Registration {} either does not exist, or is not for user {}.", id, user),
+                format!("Rejestracja {} nie istnieje lub nie dotyczy użytkownika {}.", id, user)
+            ),
+
+            Self::UserConflict(name) | Self::WebUIUserConflict(name) => (
+                format!("User {} already exists.", name),
+                format!("Użytkownik {} już istnieje.", name)
+            ),
+            Self::PermissionConflict(name) => (
+                format!("Permission {} already exists.", name),
+                format!("Uprawnienie {} już istnieje.", name)
+            ),
+            Self::AccessProfileConflict(name) => (
+                format!("Access profile {} already exists.", name),
+                format!("Profil dostępu {} już istnieje.", name)
+            ),
+            Self::RoleConflict(name) => (
+                format!("Role {} already exists.", name),
+                format!("Rola {} już istnieje.", name)
+            ),
+            Self::GroupConflict(name) => (
+                format!("Group {} already exists.", name),
+                format!("Grupa {} już istnieje.", name)
+            ),
+            Self::WebhookConflict(name) => (
+                format!("Webhook {} already exists.", name),
+                format!("Webhook {} już istnieje.", name)
+            ),
+            Self::AccessCodeConflict => (
+                String::from("This access code is already registered."),
+                String::from("Ten kod dostępu jest już zarejestrowany.")
+            ),
+            Self::UserPermissionConflict(name) => (
+                format!("User {} already has this permission.", name),
+                format!("Użytkownik {} ma już to uprawnienie.", name)
+            ),
+            Self::PermissionUserConflict(name) => (
+                format!("The user already has the permission {}.", name),
+                format!("Użytkownik ma już uprawnienie {}.", name)
+            ),
+            Self::AccessProfilePermissionConflict(name) => (
+                format!("The permission is already assigned to profile {}.", name),
+                format!("Uprawnienie jest już przypisane do profilu {}.", name)
+            ),
+            Self::PermissionAccessProfileConflict(name) => (
+                format!("The access profile is already assigned to permission {}.", name),
+                format!("Profil dostępu jest już przypisany do uprawnienia {}.", name)
+            ),
+            Self::WebUIUserRoleConflict(name) => (
+                format!("User {} already has this role.", name),
+                format!("Użytkownik {} ma już tę rolę.", name)
+            ),
+            Self::GroupUserConflict(name) => (
+                format!("The user is already a member of group {}.", name),
+                format!("Użytkownik należy już do grupy {}.", name)
+            ),
+            Self::GroupPermissionConflict(name) => (
+                format!("Group {} already has this permission.", name),
+                format!("Grupa {} ma już to uprawnienie.", name)
+            ),
+            Self::AccessProfileActive(name) => (
+                format!("Access profile {} is active and can't be deleted.", name),
+                format!("Profil dostępu {} jest aktywny i nie może zostać usunięty.", name)
+            ),
+            Self::VisitorCodeConflict => (
+                String::from("Could not generate a unique code, try again."),
+                String::from("Nie udało się wygenerować unikalnego kodu, spróbuj ponownie.")
+            ),
+            Self::EmergencyActive => (
+                String::from("This can't be done during an emergency."),
+                String::from("Nie można tego zrobić w trakcie stanu awaryjnego.")
+            ),
+            Self::RegistrationInProgress => (
+                String::from("Another card registration is in progress, finish or cancel it first."),
+                String::from("Trwa już rejestracja innej karty, zakończ ją lub anuluj.")
+            ),
+            Self::RegistrationFinished(id) => (
+                format!("Registration {} is no longer waiting for a card.", id),
+                format!("Rejestracja {} nie czeka już na kartę.", id)
+            ),
+
+            Self::ResourceModified => (
+                String::from("This has been changed since you last loaded it. Load it again and retry."),
+                String::from("Od ostatniego wczytania coś tu zmieniono. Wczytaj ponownie i spróbuj jeszcze raz.")
+            ),
+
+            Self::BadPassword => (
+                String::from("Bad password."),
+                String::from("Błędne hasło.")
+            ),
+            Self::AdminRequired => (
+                String::from("Only admins can grant or revoke admin rights."),
+                String::from("Tylko administratorzy mogą nadawać i odbierać uprawnienia administracyjne.")
+            ),
+            Self::AdminTargetRequiresAdmin => (
+                String::from("Only admins can change or delete admin accounts."),
+                String::from("Tylko administratorzy mogą zmieniać i usuwać konta administratorów.")
+            ),
+            Self::CapabilityNotHeld(capability) => (
+                format!("You can't grant the capability {}, as you don't have it yourself.", capability),
+                format!("Nie możesz nadać uprawnienia {}, bo sam go nie masz.", capability)
+            ),
+            Self::OwnRolesLocked => (
+                String::from("You can't assign roles to yourself."),
+                String::from("Nie możesz przypisywać ról samemu sobie.")
+            ),
+            Self::TooManyLoginAttempts => (
+                String::from("Too many login attempts, try again later."),
+                String::from("Zbyt wiele prób logowania, spróbuj ponownie później.")
+            ),
+            Self::BadSetupToken => (
+                String::from("Bad setup token."),
+                String::from("Błędny token konfiguracji.")
+            ),
+            Self::AlreadySetUp => (
+                String::from("The first admin has already been created."),
+                String::from("Pierwszy administrator został już utworzony.")
+            ),
+
+            Self::AccessCodeNotRegistered => (
+                String::from("Access code not registered."),
+                String::from("Kod dostępu nie jest zarejestrowany.")
+            ),
+            Self::RegistrationTimedOut => (
+                String::from("Request timed out."),
+                String::from("Upłynął czas oczekiwania na kod.")
+            ),
+            Self::Lockdown => (
+                String::from("The room is in lockdown."),
+                String::from("Pomieszczenie jest zablokowane.")
+            ),
+            Self::AccessDenied => (
+                String::from("You don't have the permission to enter now."),
+                String::from("Nie masz teraz uprawnień do wejścia.")
+            ),
+            Self::AntiPassback => (
+                String::from("You have to exit before entering again."),
+                String::from("Musisz wyjść, zanim wejdziesz ponownie.")
+            ),
+            Self::RoomFull => (
+                String::from("The room is full."),
+                String::from("Pomieszczenie jest pełne.")
+            ),
+            Self::PinRequired => (
+                String::from("You don't have a PIN set."),
+                String::from("Nie masz ustawionego PIN-u.")
+            ),
+            Self::WrongPin => (
+                String::from("Wrong PIN."),
+                String::from("Błędny PIN.")
+            ),
+            Self::TooManyWrongPins => (
+                String::from("Too many wrong PINs, try again later."),
+                String::from("Zbyt wiele błędnych PIN-ów, spróbuj ponownie później.")
+            ),
+            Self::TooManyUnknownCodes => (
+                String::from("Too many unknown codes, try again later."),
+                String::from("Zbyt wiele nieznanych kodów, spróbuj ponownie później.")
+            ),
+            Self::VisitorPassNotYetValid => (
+                String::from("This visitor pass is not valid yet."),
+                String::from("Ta przepustka nie jest jeszcze ważna.")
+            ),
+            Self::VisitorPassExpired => (
+                String::from("This visitor pass has expired."),
+                String::from("Ta przepustka wygasła.")
+            ),
+            Self::VisitorPassUsedUp => (
+                String::from("This visitor pass has been used up."),
+                String::from("Ta przepustka została już wykorzystana.")
+            ),
+            Self::HostNotPermitted => (
+                String::from("Your host doesn't have the permission to enter now."),
+                String::from("Twój gospodarz nie ma teraz uprawnień do wejścia.")
+            )
+        };
+
+        match language {
+            Language::English => en,
+            Language::Polish => pl
+        }
+    }
 }
 
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum ApiError {
-    BadRequest(String),     // 400
-    Unauthorized(String),   // 401
-    Forbidden(String),      // 403
-    NotFound(String),       // 404
-    Timeout(String),        // 408
-    Conflict(String),       // 409
+    BadRequest(ErrorCode),      // 400
+    Unauthorized(ErrorCode),    // 401
+    Forbidden(ErrorCode),       // 403
+    NotFound(ErrorCode),        // 404
+    Timeout(ErrorCode),         // 408
+    Conflict(ErrorCode),        // 409
+    PreconditionFailed(ErrorCode),  // 412
+    TooManyRequests(ErrorCode), // 429
+    Validation(Vec<FieldError>),    // 422
 
-    Internal(String),       // 500
-    NotImplemented(String)  // 501
+    // The details of these are logged, but never shown to the client.
+    Internal(String),           // 500
+    CommandServer(String),      // 502, the command server responded with an error
+    CommandServerUnreachable(String)    // 503, the command server couldn't be reached
 }
 
 impl Display for ApiError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", match self {
-            Self::BadRequest(s) |
-            Self::Unauthorized (s) |
-            Self::Forbidden(s) |
-            Self::NotFound(s) |
-            Self::Timeout(s) |
-            Self::Conflict(s) |
+        match self {
+            Self::BadRequest(c) |
+            Self::Unauthorized (c) |
+            Self::Forbidden(c) |
+            Self::NotFound(c) |
+            Self::Timeout(c) |
+            Self::Conflict(c) |
+            Self::PreconditionFailed(c) |
+            Self::TooManyRequests(c) => write!(f, "{}", c.message(Language::English)),
+            Self::Validation(_) => write!(f, "Some fields are invalid."),
             Self::Internal(s) |
-            Self::NotImplemented(s) => s
-        })
+            Self::CommandServer(s) |
+            Self::CommandServerUnreachable(s) => write!(f, "{}", s)
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+// Lets transactions report a failed BEGIN, COMMIT or ROLLBACK. Errors of the queries themselves are still matched on
+// where they happen, as some of them mean a conflict rather than a bug.
+impl From<diesel::result::Error> for ApiError {
+    fn from(e :diesel::result::Error) -> Self {
+        Self::Internal(format!("{}", e))
     }
 }
 
@@ -46,21 +499,76 @@ impl ApiError {
             Self::NotFound(_) => Status::NotFound,
             Self::Timeout(_) => Status::RequestTimeout,
             Self::Conflict(_) => Status::Conflict,
+            Self::PreconditionFailed(_) => Status::PreconditionFailed,
+            Self::TooManyRequests(_) => Status::TooManyRequests,
+            Self::Validation(_) => Status::UnprocessableEntity,
             Self::Internal(_) => Status::InternalServerError,
-            Self::NotImplemented(_) => Status::NotImplemented
+            Self::CommandServer(_) => Status::BadGateway,
+            Self::CommandServerUnreachable(_) => Status::ServiceUnavailable
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match &self {
+            Self::BadRequest(c) |
+            Self::Unauthorized(c) |
+            Self::Forbidden(c) |
+            Self::NotFound(c) |
+            Self::Timeout(c) |
+            Self::Conflict(c) |
+            Self::PreconditionFailed(c) |
+            Self::TooManyRequests(c) => c.code(),
+            Self::Validation(_) => "validation_failed",
+            Self::Internal(_) => "internal_error",
+            Self::CommandServer(_) => "command_server_error",
+            Self::CommandServerUnreachable(_) => "command_server_unreachable"
+        }
+    }
+
+    pub fn message(&self, language :Language) -> String {
+        match &self {
+            Self::BadRequest(c) |
+            Self::Unauthorized(c) |
+            Self::Forbidden(c) |
+            Self::NotFound(c) |
+            Self::Timeout(c) |
+            Self::Conflict(c) |
+            Self::PreconditionFailed(c) |
+            Self::TooManyRequests(c) => c.message(language),
+            Self::Validation(_) => translate(language, "Some fields are invalid.", "Niektóre pola są nieprawidłowe."),
+            Self::Internal(_) => internal_message(language),
+            Self::CommandServer(_) => translate(language,
+                "The door controller returned an error.",
+                "Sterownik drzwi zwrócił błąd."
+            ),
+            Self::CommandServerUnreachable(_) => translate(language,
+                "The door controller can't be reached.",
+                "Brak połączenia ze sterownikiem drzwi."
+            )
         }
     }
 }
 
 impl<'r> Responder<'r, 'static> for ApiError {
-    fn respond_to(self, _: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+    fn respond_to(self, req :&'r rocket::Request<'_>) -> rocket::response::Result<'static> {
         let status = self.status();
-        let body = serde_json::to_string(&ErrorResponse {
-            message: self.to_string()
-        }).unwrap();
-        
+
+        if let Self::Internal(_) | Self::CommandServer(_) | Self::CommandServerUnreachable(_) = &self {
+            RequestSpan::span(req).in_scope(|| tracing::error!(code = self.code(), error = %self, "error while handling the request"));
+        }
+
+        let body = serde_json::to_string(&ErrorResponse::new(
+            req,
+            self.code(),
+            self.message(Language::of(req)),
+            match &self {
+                Self::Validation(fields) => fields.clone(),
+                _ => vec![]
+            }
+        )).unwrap();
+
         let mut res = Response::build();
-        
+
         res.status(status)
             .header(ContentType::JSON)
             .sized_body(body.len(), Cursor::new(body));
@@ -73,37 +581,74 @@ impl<'r> Responder<'r, 'static> for ApiError {
     }
 }
 
-#[catch(401)]
-pub fn unauthorized() -> Json<ErrorResponse> {
-    Json(ErrorResponse {
-        message: String::from("You need to authenticate to access this resource.")
+fn translate(language :Language, en :&str, pl :&str) -> String {
+    String::from(match language {
+        Language::English => en,
+        Language::Polish => pl
     })
 }
 
+fn internal_message(language :Language) -> String {
+    translate(language,
+        "Something went very wrong, and it most likely isn't your fault.",
+        "Coś poszło bardzo nie tak i najpewniej nie jest to Twoja wina."
+    )
+}
+
+/// Field errors left behind by a failed `Validated` body, if any.
+fn body_errors(req :&Request) -> Vec<FieldError> {
+    req.local_cache(|| BodyErrors(vec![])).0.clone()
+}
+
+#[catch(400)]
+pub fn bad_request(req :&Request) -> Json<ErrorResponse> {
+    Json(ErrorResponse::new(req, "bad_request", translate(Language::of(req),
+        "The request is malformed.",
+        "Żądanie jest nieprawidłowe."
+    ), body_errors(req)))
+}
+
+#[catch(401)]
+pub fn unauthorized(req :&Request) -> Json<ErrorResponse> {
+    Json(ErrorResponse::new(req, "unauthorized", translate(Language::of(req),
+        "You need to authenticate to access this resource.",
+        "Musisz się zalogować, aby uzyskać dostęp do tego zasobu."
+    ), vec![]))
+}
+
 #[catch(403)]
-pub fn forbidden() -> Json<ErrorResponse> {
-    Json(ErrorResponse {
-        message: String::from("You don't have permission to access this resource.")
-    })
+pub fn forbidden(req :&Request) -> Json<ErrorResponse> {
+    Json(ErrorResponse::new(req, "forbidden", translate(Language::of(req),
+        "You don't have permission to access this resource.",
+        "Nie masz uprawnień do tego zasobu."
+    ), vec![]))
 }
 
 #[catch(404)]
-pub fn not_found() -> Json<ErrorResponse> {
-    Json(ErrorResponse { 
-        message: String::from("URL not found.")
-    })
+pub fn not_found(req :&Request) -> Json<ErrorResponse> {
+    Json(ErrorResponse::new(req, "url_not_found", translate(Language::of(req),
+        "URL not found.",
+        "Nie znaleziono adresu URL."
+    ), vec![]))
+}
+
+#[catch(413)]
+pub fn payload_too_large(req :&Request) -> Json<ErrorResponse> {
+    Json(ErrorResponse::new(req, "payload_too_large", translate(Language::of(req),
+        "The request body is too large.",
+        "Treść żądania jest zbyt duża."
+    ), body_errors(req)))
 }
 
 #[catch(422)]
-pub fn unprocessable() -> Json<ErrorResponse> {
-    Json(ErrorResponse {
-        message: String::from("The request was well-formed but was unable to be followed due to semantic errors.")
-    })
+pub fn unprocessable(req :&Request) -> Json<ErrorResponse> {
+    Json(ErrorResponse::new(req, "validation_failed", translate(Language::of(req),
+        "The request was well-formed but was unable to be followed due to semantic errors.",
+        "Żądanie jest poprawne składniowo, ale nie może zostać wykonane z powodu błędów semantycznych."
+    ), body_errors(req)))
 }
 
 #[catch(500)]
-pub fn internal() -> Json<ErrorResponse> {
-    Json(ErrorResponse {
-        message: String::from("Something went very wrong, and it most likely isn't your fault.")
-    })
-}
\ No newline at end of file
+pub fn internal(req :&Request) -> Json<ErrorResponse> {
+    Json(ErrorResponse::new(req, "internal_error", internal_message(Language::of(req)), vec![]))
+}
@@ -0,0 +1,54 @@
+use std::convert::Infallible;
+
+use rocket::{Request, request::{FromRequest, Outcome}, response::{self, Responder, Response}, serde::json::Json};
+use serde::Serialize;
+
+use crate::error::{ApiError, ErrorCode};
+
+/// The ETag of a resource, a hash of its JSON representation.
+pub fn etag<T :Serialize>(resource :&T) -> String {
+    format!("\"{}\"", sha256::digest(serde_json::to_string(resource).unwrap_or_default()))
+}
+
+/// A JSON response with the `ETag` of its body. Clients send it back in `If-Match` when changing the resource.
+pub struct Tagged<T>(pub T);
+
+impl<'r, T :Serialize> Responder<'r, 'static> for Tagged<T> {
+    fn respond_to(self, req :&'r Request<'_>) -> response::Result<'static> {
+        let etag = etag(&self.0);
+
+        Response::build_from(Json(self.0).respond_to(req)?)
+            .raw_header("ETag", etag)
+        .ok()
+    }
+}
+
+/// The `If-Match` header. Without it, changes are applied whatever they overwrite.
+pub struct IfMatch(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = Infallible;
+
+    async fn from_request(req :&'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Self(req.headers().get_one("If-Match").map(String::from)))
+    }
+}
+
+impl IfMatch {
+    /// Fails unless the resource still has one of the ETags the client listed, i.e. nobody changed it since the client
+    /// read it. Call it with the resource locked, see `db::for_update`.
+    pub fn check<T :Serialize>(&self, current :&T) -> Result<(), ApiError> {
+        let tags = match &self.0 {
+            Some(tags) => tags,
+            None => return Ok(())
+        };
+        let current = etag(current);
+
+        if tags.split(',').map(str::trim).any(|tag| tag == "*" || tag == current) {
+            Ok(())
+        } else {
+            Err(ApiError::PreconditionFailed(ErrorCode::ResourceModified))
+        }
+    }
+}
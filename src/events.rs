@@ -0,0 +1,73 @@
+use rocket::tokio::sync::broadcast::{self, Receiver, Sender};
+use serde::Serialize;
+
+use crate::models::EmergencyMode;
+
+/// Things happening in the system that other parties may want to be notified about.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    EmergencyChanged {mode :EmergencyMode, by :String},
+    /// `user` is the owner of the swiped code, if it belongs to anyone. `reason` is the error code.
+    AccessDenied {user :Option<String>, reason :String},
+    DoorOpened {by :String},
+    ActiveProfileChanged {id :i32, name :String},
+    /// A command didn't reach the door after the previous one did.
+    ControllerOffline {error :String},
+    UserCreated {id :i32, name :String},
+    UserDeleted {id :i32, name :String},
+    AccessCodeCreated {id :i32, user :String},
+    AccessCodeDeleted {id :i32, user :String},
+    /// Never emitted, only sent straight to a webhook to try it out.
+    Ping
+}
+
+/// The `type` of every event webhooks can subscribe to.
+pub const ALL :[&str; 9] = [
+    "emergency_changed",
+    "access_denied",
+    "door_opened",
+    "active_profile_changed",
+    "controller_offline",
+    "user_created",
+    "user_deleted",
+    "access_code_created",
+    "access_code_deleted"
+];
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::EmergencyChanged {..} => ALL[0],
+            Self::AccessDenied {..} => ALL[1],
+            Self::DoorOpened {..} => ALL[2],
+            Self::ActiveProfileChanged {..} => ALL[3],
+            Self::ControllerOffline {..} => ALL[4],
+            Self::UserCreated {..} => ALL[5],
+            Self::UserDeleted {..} => ALL[6],
+            Self::AccessCodeCreated {..} => ALL[7],
+            Self::AccessCodeDeleted {..} => ALL[8],
+            Self::Ping => "ping"
+        }
+    }
+}
+
+/// Cloning is cheap, clones emit to the same subscribers.
+#[derive(Clone)]
+pub struct EventBus(Sender<Event>);
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(64);
+        Self(sender)
+    }
+
+    pub fn emit(&self, event :Event) {
+        // Sending only fails when nobody is listening, which is fine.
+        let _ = self.0.send(event);
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.0.subscribe()
+    }
+}
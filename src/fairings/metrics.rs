@@ -0,0 +1,39 @@
+use std::time::Instant;
+
+use rocket::{Request, Response, Data, fairing::{Fairing, Info, Kind}};
+
+use crate::metrics::Metrics;
+
+struct RequestStart(Instant);
+
+/// Counts handled requests and measures how long they took, by route.
+pub struct MetricsFairing;
+
+#[rocket::async_trait]
+impl Fairing for MetricsFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "HTTP metrics",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    async fn on_request(&self, req :&mut Request<'_>, _ :&mut Data<'_>) {
+        req.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req :&'r Request<'_>, res :&mut Response<'r>) {
+        let metrics = match req.rocket().state::<Metrics>() {
+            Some(metrics) => metrics,
+            None => return
+        };
+
+        // Unmatched requests are lumped together, so random URLs can't blow up the number of series.
+        let route = req.route().map(|route| route.uri.to_string()).unwrap_or(String::from("unmatched"));
+        let method = req.method().as_str();
+        let started = req.local_cache(|| RequestStart(Instant::now())).0;
+
+        metrics.http_requests.with_label_values(&[method, &route, &res.status().code.to_string()]).inc();
+        metrics.http_duration.with_label_values(&[method, &route]).observe(started.elapsed().as_secs_f64());
+    }
+}
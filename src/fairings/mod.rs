@@ -0,0 +1,3 @@
+pub mod request_id;
+pub mod trace;
+pub mod metrics;
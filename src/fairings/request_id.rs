@@ -0,0 +1,49 @@
+use rand::{Rng, distributions::Alphanumeric};
+use rocket::{Request, Response, Data, fairing::{Fairing, Info, Kind}};
+
+pub const HEADER :&str = "X-Request-Id";
+
+/// Identifies a single request in the logs and in error responses.
+pub struct RequestId(pub String);
+
+impl RequestId {
+    fn generate() -> Self {
+        Self(rand::thread_rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect())
+    }
+
+    /// An ID passed by a proxy in front of us is kept, as long as it's reasonably short and plain.
+    fn from_header(value :&str) -> Option<Self> {
+        if !value.is_empty() && value.len() <= 64 && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            Some(Self(String::from(value)))
+        } else {
+            None
+        }
+    }
+
+    pub fn of<'r>(req :&'r Request<'_>) -> &'r str {
+        &req.local_cache(|| {
+            req.headers().get_one(HEADER).and_then(RequestId::from_header).unwrap_or_else(RequestId::generate)
+        }).0
+    }
+}
+
+/// Assigns an ID to every request and echoes it in the response headers.
+pub struct RequestIdFairing;
+
+#[rocket::async_trait]
+impl Fairing for RequestIdFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request ID",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    async fn on_request(&self, req :&mut Request<'_>, _ :&mut Data<'_>) {
+        RequestId::of(req);
+    }
+
+    async fn on_response<'r>(&self, req :&'r Request<'_>, res :&mut Response<'r>) {
+        res.set_raw_header(HEADER, String::from(RequestId::of(req)));
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Instant;
+
+use rocket::{Request, Response, Data, fairing::{Fairing, Info, Kind}, http::StatusClass};
+use tracing::{Span, field, info_span};
+
+use super::request_id::RequestId;
+
+/// The span covering a single request. Fields that aren't known up front are recorded as the request goes on.
+pub struct RequestSpan {
+    span :Span,
+    start :Instant
+}
+
+impl RequestSpan {
+    fn of<'r>(req :&'r Request<'_>) -> &'r Self {
+        req.local_cache(|| RequestSpan {
+            span: info_span!("request",
+                id = RequestId::of(req),
+                method = %req.method(),
+                uri = %req.uri(),
+                route = field::Empty,
+                user = field::Empty
+            ),
+            start: Instant::now()
+        })
+    }
+
+    pub fn span<'r>(req :&'r Request<'_>) -> &'r Span {
+        &Self::of(req).span
+    }
+}
+
+/// Opens a span for every request and logs its outcome once the response is ready.
+pub struct TraceFairing;
+
+#[rocket::async_trait]
+impl Fairing for TraceFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request tracing",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    async fn on_request(&self, req :&mut Request<'_>, _ :&mut Data<'_>) {
+        RequestSpan::of(req);
+    }
+
+    async fn on_response<'r>(&self, req :&'r Request<'_>, res :&mut Response<'r>) {
+        let trace = RequestSpan::of(req);
+        let status = res.status().code;
+        let latency_ms = trace.start.elapsed().as_millis() as u64;
+
+        if let Some(route) = req.route() {
+            trace.span.record("route", field::display(&route.uri));
+        }
+
+        trace.span.in_scope(|| match res.status().class() {
+            StatusClass::ServerError => tracing::error!(status, latency_ms, "request failed"),
+            StatusClass::ClientError => tracing::warn!(status, latency_ms, "request rejected"),
+            _ => tracing::info!(status, latency_ms, "request completed")
+        });
+    }
+}
@@ -1,8 +1,13 @@
 use std::marker::PhantomData;
 
+use cherrydoor_models::{schema::web_ui_users, models::WebUIUser};
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, OptionalExtension, QueryResult};
+use diesel_async::RunQueryDsl;
 use rocket::{request::{FromRequest, Outcome}, Request, http::Status, State, outcome};
 use serde::{Serialize, Deserialize};
 
+use crate::{fairings::trace::RequestSpan, db::{DB, DbConnection, get_connection}, error::ApiError, schema::{web_ui_users_roles, roles_capabilities}};
+
 pub struct SecretKeyWrapper {
     pub key :rocket::config::SecretKey
 }
@@ -12,7 +17,16 @@ pub struct WebUIUserAuthorization {
     pub exp :u64,
     pub name :String,
     pub is_admin :bool,
-    pub ac_does_not_expire :bool
+    pub ac_does_not_expire :bool,
+    #[serde(default)]
+    pub capabilities :Vec<String>
+}
+
+impl WebUIUserAuthorization {
+    /// Admins implicitly have every capability.
+    pub fn has_capability(&self, capability :&str) -> bool {
+        self.is_admin || self.capabilities.iter().any(|c| c == capability)
+    }
 }
 
 pub trait AuthorizationProvider {
@@ -22,6 +36,15 @@ pub trait AuthorizationProvider {
 pub struct OperatorUser;
 pub struct AdminUser;
 
+/// Requires the capability `C`, granted through the user's roles. See `guards::capabilities`.
+pub struct Capable<C :Capability> {
+    capability :PhantomData<C>
+}
+
+pub trait Capability {
+    const NAME :&'static str;
+}
+
 impl AuthorizationProvider for OperatorUser {
     fn authorize(_auth :&WebUIUserAuthorization) -> bool {
         true
@@ -34,6 +57,12 @@ impl AuthorizationProvider for AdminUser {
     }
 }
 
+impl<C :Capability> AuthorizationProvider for Capable<C> {
+    fn authorize(auth :&WebUIUserAuthorization) -> bool {
+        auth.has_capability(C::NAME)
+    }
+}
+
 pub struct Auth<T :AuthorizationProvider> {
     auth_provider :PhantomData<T>,
     pub claim :WebUIUserAuthorization
@@ -61,18 +90,27 @@ impl<T :AuthorizationProvider, 'r> FromRequest<'r> for Auth<T> {
             None => return Outcome::Failure((Status::Unauthorized, ()))
         };
 
-        let secret = match request.guard::<&State<SecretKeyWrapper>>().await.map(|conf| &conf.key) {
+        let secret = match request.guard::<&State<SecretKeyWrapper>>().await {
             outcome::Outcome::Success(key) => key,
             _ => return Outcome::Failure((Status::InternalServerError, ()))
         };
 
-        let claim = match jsonwebtoken::decode::<WebUIUserAuthorization>(
-            auth_token, 
-            &jsonwebtoken::DecodingKey::from_secret(secret.to_string().as_bytes()),
-            &jsonwebtoken::Validation::default()
-        ) {
-            Ok(claim) => claim.claims,
-            Err(_) => return Outcome::Failure((Status::Unauthorized, ()))
+        let claim = match decode_token(auth_token, &secret.key) {
+            Some(claim) => claim,
+            None => return Outcome::Failure((Status::Unauthorized, ()))
+        };
+
+        RequestSpan::span(request).record("user", claim.name.as_str());
+
+        let db = match request.guard::<&State<DB>>().await {
+            outcome::Outcome::Success(db) => db,
+            _ => return Outcome::Failure((Status::InternalServerError, ()))
+        };
+
+        let claim = match refresh_claim(claim, db).await {
+            Ok(Some(claim)) => claim,
+            Ok(None) => return Outcome::Failure((Status::Unauthorized, ())),
+            Err(e) => return Outcome::Failure((e.status(), ()))
         };
 
         if !T::authorize(&claim) {
@@ -81,4 +119,56 @@ impl<T :AuthorizationProvider, 'r> FromRequest<'r> for Auth<T> {
 
         return Outcome::Success(Auth {claim, auth_provider: PhantomData});
     }
-}
\ No newline at end of file
+}
+
+/// The claim of a token, if it was signed with the key and hasn't expired. For commands coming in some other way than HTTP.
+pub fn decode_token(token :&str, key :&rocket::config::SecretKey) -> Option<WebUIUserAuthorization> {
+    match jsonwebtoken::decode::<WebUIUserAuthorization>(
+        token,
+        &jsonwebtoken::DecodingKey::from_secret(key.to_string().as_bytes()),
+        &jsonwebtoken::Validation::default()
+    ) {
+        Ok(claim) => Some(claim.claims),
+        Err(_) => None
+    }
+}
+
+/// Replaces the admin rights and capabilities in a claim with the current ones, so that changes to them apply to tokens
+/// already handed out. `None` if the user has been deleted or renamed since.
+pub async fn refresh_claim(claim :WebUIUserAuthorization, db :&DB) -> Result<Option<WebUIUserAuthorization>, ApiError> {
+    let mut conn = get_connection(db).await?;
+
+    let user :WebUIUser = match web_ui_users::table
+        .select(WebUIUser::as_select())
+        .filter(web_ui_users::columns::name.eq(&claim.name))
+    .first(&mut conn).await.optional() {
+        Ok(Some(user)) => user,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let capabilities = match load_capabilities(user.id, &mut conn).await {
+        Ok(capabilities) => capabilities,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    Ok(Some(WebUIUserAuthorization {
+        is_admin: user.is_admin,
+        capabilities,
+        ..claim
+    }))
+}
+
+/// All capabilities granted to a web UI user through their roles.
+pub async fn load_capabilities<'a>(web_ui_user_id :i32, db :&mut DbConnection<'a>) -> QueryResult<Vec<String>> {
+    let role_ids :Vec<i32> = web_ui_users_roles::table
+        .select(web_ui_users_roles::columns::role_id)
+        .filter(web_ui_users_roles::columns::web_ui_user_id.eq(web_ui_user_id))
+    .load(db).await?;
+
+    roles_capabilities::table
+        .select(roles_capabilities::columns::capability)
+        .filter(roles_capabilities::columns::role_id.eq_any(role_ids))
+        .distinct()
+    .load(db).await
+}
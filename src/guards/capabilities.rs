@@ -0,0 +1,54 @@
+use super::auth::Capability;
+
+pub struct DoorOpen;
+pub struct UsersWrite;
+pub struct PermissionsWrite;
+pub struct ProfilesWrite;
+pub struct ProfilesActivate;
+pub struct VisitorsWrite;
+pub struct WebUIUsersManage;
+pub struct WebhooksManage;
+
+impl Capability for DoorOpen {
+    const NAME :&'static str = "door.open";
+}
+
+impl Capability for UsersWrite {
+    const NAME :&'static str = "users.write";
+}
+
+impl Capability for PermissionsWrite {
+    const NAME :&'static str = "permissions.write";
+}
+
+impl Capability for ProfilesWrite {
+    const NAME :&'static str = "profiles.write";
+}
+
+impl Capability for ProfilesActivate {
+    const NAME :&'static str = "profiles.activate";
+}
+
+impl Capability for VisitorsWrite {
+    const NAME :&'static str = "visitors.write";
+}
+
+impl Capability for WebUIUsersManage {
+    const NAME :&'static str = "webui_users.manage";
+}
+
+impl Capability for WebhooksManage {
+    const NAME :&'static str = "webhooks.manage";
+}
+
+/// Every capability a role can be given.
+pub const ALL :[&str; 8] = [
+    DoorOpen::NAME,             // Opening the door manually
+    UsersWrite::NAME,           // Creating, editing and deleting users, their access codes and PINs
+    PermissionsWrite::NAME,     // Creating, editing and deleting permissions, and granting them to users and profiles
+    ProfilesWrite::NAME,        // Creating, editing and deleting access profiles
+    ProfilesActivate::NAME,     // Changing the active access profile
+    VisitorsWrite::NAME,        // Issuing and revoking visitor passes
+    WebUIUsersManage::NAME,     // Managing web UI users and their roles
+    WebhooksManage::NAME        // Managing webhooks, which includes seeing their secrets
+];
@@ -0,0 +1,41 @@
+use rocket::Request;
+
+/// Languages human-readable messages are available in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Language {
+    English,
+    Polish      // Same as the text shown by the door hardware
+}
+
+impl Language {
+    /// Picks the most preferred supported language from the `Accept-Language` header, English if there is none.
+    pub fn of(req :&Request) -> Self {
+        let header = match req.headers().get_one("Accept-Language") {
+            Some(header) => header,
+            None => return Self::English
+        };
+
+        let mut languages :Vec<(Self, f32)> = header.split(',').filter_map(|part| {
+            let mut params = part.trim().split(';');
+            let tag = params.next()?.trim().to_ascii_lowercase();
+            let q :f32 = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+            .unwrap_or(1.0);
+
+            let language = match tag.split('-').next()? {
+                "pl" => Self::Polish,
+                "en" => Self::English,
+                _ => return None
+            };
+
+            // q=0 means "not acceptable"
+            if q > 0.0 { Some((language, q)) } else { None }
+        }).collect();
+
+        // The sort is stable, so languages with equal weights keep the order of the header.
+        languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        languages.first().map(|v| v.0).unwrap_or(Self::English)
+    }
+}
@@ -0,0 +1,17 @@
+use tracing_subscriber::EnvFilter;
+
+/// Sets up structured logging. The format is chosen with `LOG_FORMAT` (`pretty`, the default, or `json`),
+/// and the verbosity with `RUST_LOG` (`info` by default). Rocket's own log output is passed through as well.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let res = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().flatten_event(true).with_current_span(true).with_span_list(false).try_init(),
+        _ => builder.pretty().try_init()
+    };
+
+    if let Err(e) = res {
+        eprintln!("Logging is already set up: {}", e);
+    }
+}
@@ -2,37 +2,117 @@
 extern crate rocket;
 mod db;
 mod error;
+mod schema;
+mod models;
+mod events;
+mod codes;
+mod door;
+mod webhooks;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod validation;
+mod etag;
+mod batch;
+mod i18n;
+mod logging;
+mod config;
+mod rate_limit;
+mod migrations;
+mod setup;
+mod metrics;
+mod cli;
 
 mod guards;
+mod fairings;
 mod routes;
 
-use diesel_async::{pooled_connection::{bb8::Pool, AsyncDieselConnectionManager}, AsyncMysqlConnection};
+#[cfg(test)]
+mod tests;
+
+use std::time::Duration;
+
+use config::Config;
 use guards::auth::SecretKeyWrapper;
-use rocket::{launch, routes, http::Method, catchers};
+use rate_limit::{RateLimiter, LoginRateLimiter};
+use setup::SetupToken;
+use rocket::{Rocket, Build, routes, http::Method, catchers};
 
 use rocket_cors::{CorsOptions, AllowedOrigins};
-use routes::{auth, web_ui_users, users, permissions, access_profiles, access::{self, CommandAddress}, status, active_access_profile};
+use routes::{auth, web_ui_users, roles, users, groups, permissions, access_profiles, access::{self, Swipes}, status, active_access_profile, occupancy, emergency, events as event_routes, webhooks as webhooks_routes, visitors, metrics as metrics_routes, health, setup as setup_routes};
 
-#[launch]
-async fn rocket() -> _ {
+#[rocket::main]
+async fn main() {
     dotenv::dotenv().ok();
+    logging::init();
+
+    let args :Vec<String> = std::env::args().skip(1).collect();
+
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2)
+        }
+    };
+
+    if let cli::Command::Help = command {
+        println!("{}", cli::USAGE);
+        return
+    }
 
     let app = rocket::build();
 
-    let db_uri = std::env::var("DATABASE_URI").unwrap();
+    let config = match Config::load(app.figment()) {
+        Ok(config) => config,
+        Err(errors) => {
+            for error in errors {
+                tracing::error!("invalid configuration: {}", error);
+            }
+            std::process::exit(1)
+        }
+    };
+
+    match command {
+        cli::Command::Serve => {
+            if let Err(e) = server(app, config).await.launch().await {
+                tracing::error!("the server stopped: {}", e);
+                std::process::exit(1)
+            }
+        },
+        command => std::process::exit(cli::run(command, &config).await)
+    }
+}
+
+async fn server(app :Rocket<Build>, config :Config) -> Rocket<Build> {
     let key = SecretKeyWrapper { key: rocket::Config::from(app.figment()).secret_key };
-    let command_addr = CommandAddress(std::env::var("COMMAND_ADDRESS").unwrap());
+    let metrics = metrics::Metrics::new();
+    let controller = door::Door::from_config(&config.command_server, metrics.clone());
+
+    // The server starts even if the database isn't up yet, /readyz tells when it is.
+    let db = db::DB::new(&config.database, metrics.db_wait.clone());
+
+    let bus = events::EventBus::new();
+    let aacp = active_access_profile::ActiveAccessProfile::new(controller.clone(), bus.clone());
+    let setup_token = SetupToken::new();
+
+    setup::run(&db, config.database.run_migrations, config.bootstrap.clone(), &aacp, setup_token.clone()).await;
+
+    let allowed_origins = if config.cors.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowedOrigins::all()
+    } else {
+        AllowedOrigins::some_exact(&config.cors.allowed_origins)
+    };
 
-    let db = db::DB(
-        Pool::builder()
-            .build(AsyncDieselConnectionManager::<AsyncMysqlConnection>::new(db_uri))
-        .await.unwrap()
-    );
+    let dispatcher = webhooks::Dispatcher::new(db.clone(), config.webhooks.clone());
+    dispatcher.start(&bus);
 
-    let aacp = active_access_profile::ActiveAccessProfile::new(&db, std::env::var("COMMAND_ADDRESS").unwrap()).await;
+    let login_limiter = LoginRateLimiter(RateLimiter::new(
+        config.rate_limits.login_attempts,
+        Duration::from_secs(config.rate_limits.login_window)
+    ));
 
-    let cors = CorsOptions::default()
-        .allowed_origins(AllowedOrigins::all())
+    let cors = match CorsOptions::default()
+        .allowed_origins(allowed_origins)
         .allowed_methods(
             vec![Method::Get, Method::Post, Method::Patch, Method::Put, Method::Delete]
                 .into_iter()
@@ -40,15 +120,42 @@ async fn rocket() -> _ {
                 .collect(),
         )
         .allow_credentials(true)
-    .to_cors().unwrap();
+        // Browsers hide response headers from scripts unless told otherwise, and the web UI needs the ETag for If-Match.
+        .expose_headers(["ETag"].iter().map(ToString::to_string).collect())
+    .to_cors() {
+        Ok(cors) => cors,
+        Err(e) => {
+            tracing::error!("invalid configuration: cors: {}", e);
+            std::process::exit(1)
+        }
+    };
+
+    #[cfg(feature = "mqtt")]
+    let app = match config.mqtt.clone() {
+        Some(mqtt) => app.attach(mqtt::Bridge::fairing(mqtt)),
+        None => app
+    };
 
-    
     app
         .attach(cors)
+        .attach(fairings::request_id::RequestIdFairing)
+        .attach(fairings::trace::TraceFairing)
+        .attach(fairings::metrics::MetricsFairing)
         .manage(db)
         .manage(key)
-        .manage(command_addr)
+        .manage(controller)
         .manage(aacp)
+        .manage(Swipes::new(&config.rate_limits))
+        .manage(users::registrations::Registrations::new())
+        .manage(bus)
+        .manage(dispatcher)
+        .manage(metrics)
+        .manage(login_limiter)
+        .manage(setup_token)
+        .manage(config)
+        .mount("/setup", routes![
+            setup_routes::create_admin  // POST /
+        ])
         .mount("/auth", routes![
             auth::authenticate,     // POST /
         ])
@@ -57,7 +164,17 @@ async fn rocket() -> _ {
             web_ui_users::get,      // GET /<name>
             web_ui_users::create,   // POST /
             web_ui_users::update,   // PATCH /<name>
-            web_ui_users::delete    // DELETE /<name>
+            web_ui_users::delete,   // DELETE /<name>
+            web_ui_users::roles::list,      // GET /<name>/roles
+            web_ui_users::roles::assign,    // POST /<name>/roles
+            web_ui_users::roles::remove     // DELETE /<name>/roles/<id>
+        ])
+        .mount("/roles", routes![
+            roles::list,    // GET /
+            roles::get,     // GET /<name>
+            roles::create,  // POST /
+            roles::update,  // PATCH /<name>
+            roles::delete   // DELETE /<name>
         ])
         .mount("/users", routes![
             users::list,    // GET /
@@ -68,11 +185,32 @@ async fn rocket() -> _ {
             users::access_codes::list,          // GET /<name>/access-codes
             users::access_codes::manual_add,    // POST /<name>/access-codes
             users::access_codes::register,      // POST /<name>/access-codes/register
+            users::registrations::start,    // POST /<name>/access-codes/registrations
+            users::registrations::get,      // GET /<name>/access-codes/registrations/<id>
+            users::registrations::events,   // GET /<name>/access-codes/registrations/<id>/events
+            users::registrations::cancel,   // DELETE /<name>/access-codes/registrations/<id>
             users::access_codes::get,           // GET /<name>/access-codes/<id>
             users::access_codes::delete,        // DELETE /<name>/access-codes/<id>
             users::permissions::list,       // GET /<name>/permissions
             users::permissions::assign,     // POST /<name>/permissions
-            users::permissions::remove      // DELETE /<name>/permissions/<id>
+            users::permissions::batch,      // POST /<name>/permissions/batch
+            users::permissions::replace,    // PUT /<name>/permissions
+            users::permissions::remove,     // DELETE /<name>/permissions/<id>
+            users::pin::set,        // PUT /<name>/pin
+            users::pin::remove      // DELETE /<name>/pin
+        ])
+        .mount("/groups", routes![
+            groups::list,       // GET /
+            groups::get,        // GET /<name>
+            groups::create,     // POST /
+            groups::update,     // PATCH /<name>
+            groups::delete,     // DELETE /<name>
+            groups::users::list,        // GET /<name>/users
+            groups::users::assign,      // POST /<name>/users
+            groups::users::remove,      // DELETE /<name>/users/<user-id>
+            groups::permissions::list,      // GET /<name>/permissions
+            groups::permissions::assign,    // POST /<name>/permissions
+            groups::permissions::remove     // DELETE /<name>/permissions/<id>
         ])
         .mount("/permissions", routes![
             permissions::list,      // GET /
@@ -82,9 +220,13 @@ async fn rocket() -> _ {
             permissions::delete,    // DELETE /<name>
             permissions::users::list,   // GET /<name>/users
             permissions::users::assign, // POST /<name>/users
+            permissions::users::batch,  // POST /<name>/users/batch
+            permissions::users::replace,    // PUT /<name>/users
             permissions::users::remove, // DELETE /<name>/users/<user-id>
             permissions::access_profiles::list,     // GET /<name>/access-profiles
             permissions::access_profiles::assign,   // POST /<name>/access-profiles
+            permissions::access_profiles::batch,    // POST /<name>/access-profiles/batch
+            permissions::access_profiles::replace,  // PUT /<name>/access-profiles
             permissions::access_profiles::remove,   // DELETE /<name>/access-profiles/<profile-id>
         ])
         .mount("/access-profiles", routes![
@@ -95,12 +237,51 @@ async fn rocket() -> _ {
             access_profiles::delete,    // DELETE /<name>
             access_profiles::permissions::list,     // GET /<name>/permissions
             access_profiles::permissions::assign,   // POST /<name>/permissions
+            access_profiles::permissions::batch,    // POST /<name>/permissions/batch
+            access_profiles::permissions::replace,  // PUT /<name>/permissions
             access_profiles::permissions::remove,   // DELETE /<name>/permissions/<id>
+            access_profiles::settings::get,         // GET /<name>/settings
+            access_profiles::settings::update,      // PATCH /<name>/settings
         ])
         .mount("/access", routes![
             access::open,   // POST /access/open
             access::code    // POST /access/code
         ])
+        .mount("/occupancy", routes![
+            occupancy::list,    // GET /
+            occupancy::remove   // DELETE /<name>
+        ])
+        .mount("/emergency", routes![
+            emergency::get,         // GET /
+            emergency::lockdown,    // POST /lockdown
+            emergency::evacuate,    // POST /evacuate
+            emergency::clear        // DELETE /
+        ])
+        .mount("/visitors", routes![
+            visitors::list,     // GET /
+            visitors::get,      // GET /<id>
+            visitors::create,   // POST /
+            visitors::delete    // DELETE /<id>
+        ])
+        .mount("/webhooks", routes![
+            webhooks_routes::list,          // GET /
+            webhooks_routes::get,           // GET /<name>
+            webhooks_routes::create,        // POST /
+            webhooks_routes::update,        // PATCH /<name>
+            webhooks_routes::delete,        // DELETE /<name>
+            webhooks_routes::deliveries,    // GET /<name>/deliveries
+            webhooks_routes::test           // POST /<name>/test
+        ])
+        .mount("/events", routes![
+            event_routes::stream    // GET /
+        ])
+        .mount("/", routes![
+            health::healthz,        // GET /healthz
+            health::readyz          // GET /readyz
+        ])
+        .mount("/metrics", routes![
+            metrics_routes::get     // GET /
+        ])
         .mount("/status", routes![
             status::get
         ])
@@ -109,9 +290,11 @@ async fn rocket() -> _ {
             active_access_profile::set  // POST /
         ])
         .register("/", catchers![
+            error::bad_request,
             error::unauthorized,
             error::forbidden,
             error::not_found,
+            error::payload_too_large,
             error::unprocessable,
             error::internal
         ])
@@ -0,0 +1,91 @@
+use std::time::Instant;
+
+use prometheus::{Registry, IntCounter, IntCounterVec, IntGauge, Histogram, HistogramVec, HistogramOpts, Opts, TextEncoder, Encoder};
+
+/// Prometheus metrics of the whole server. Cloning is cheap, clones share the same metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    registry :Registry,
+    pub swipes :IntCounterVec,              // outcome, profile
+    pub manual_opens :IntCounter,
+    pub profile_changes :IntCounter,
+    pub command_duration :HistogramVec,     // action
+    pub command_errors :IntCounterVec,      // action, kind
+    pub db_connections :IntGauge,
+    pub db_idle_connections :IntGauge,
+    pub db_wait :Histogram,
+    pub http_requests :IntCounterVec,       // method, route, status
+    pub http_duration :HistogramVec,        // method, route
+    pub logins :IntCounterVec               // outcome
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some(String::from("cherrydoor")), None).unwrap();
+
+        let metrics = Self {
+            swipes: IntCounterVec::new(
+                Opts::new("swipes_total", "Access codes used at the door, by outcome and active profile."),
+                &["outcome", "profile"]
+            ).unwrap(),
+            manual_opens: IntCounter::new("manual_opens_total", "Door openings requested from the web UI.").unwrap(),
+            profile_changes: IntCounter::new("profile_changes_total", "Changes of the active access profile.").unwrap(),
+            command_duration: HistogramVec::new(
+                HistogramOpts::new("command_server_request_duration_seconds", "Duration of requests to the command server."),
+                &["action"]
+            ).unwrap(),
+            command_errors: IntCounterVec::new(
+                Opts::new("command_server_errors_total", "Failed requests to the command server, by kind of failure."),
+                &["action", "kind"]
+            ).unwrap(),
+            db_connections: IntGauge::new("db_pool_connections", "Connections currently held by the database pool.").unwrap(),
+            db_idle_connections: IntGauge::new("db_pool_idle_connections", "Idle connections in the database pool.").unwrap(),
+            db_wait: Histogram::with_opts(
+                HistogramOpts::new("db_pool_wait_seconds", "Time spent waiting for a database connection.")
+            ).unwrap(),
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "Handled HTTP requests."),
+                &["method", "route", "status"]
+            ).unwrap(),
+            http_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "Duration of handling HTTP requests."),
+                &["method", "route"]
+            ).unwrap(),
+            logins: IntCounterVec::new(
+                Opts::new("logins_total", "Web UI login attempts, by outcome."),
+                &["outcome"]
+            ).unwrap(),
+            registry
+        };
+
+        metrics.registry.register(Box::new(metrics.swipes.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.manual_opens.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.profile_changes.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.command_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.command_errors.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.db_connections.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.db_idle_connections.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.db_wait.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.http_requests.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.http_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.logins.clone())).unwrap();
+
+        metrics
+    }
+
+    /// Records a finished request to the command server. `error` is the kind of failure, if it failed.
+    pub fn command(&self, action :&str, started :Instant, error :Option<&str>) {
+        self.command_duration.with_label_values(&[action]).observe(started.elapsed().as_secs_f64());
+
+        if let Some(kind) = error {
+            self.command_errors.with_label_values(&[action, kind]).inc();
+        }
+    }
+
+    /// All metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
@@ -0,0 +1,161 @@
+// A small runner for the migrations in /migrations/<backend>, embedded in the binary. diesel_migrations needs
+// a synchronous connection, so instead this one keeps the same bookkeeping table, which leaves the diesel CLI
+// usable as well.
+
+use std::error::Error;
+
+use cherrydoor_models::schema::{users, access_codes, access_profiles, permissions, access_profiles_permissions, users_permissions, web_ui_users};
+use diesel::{QueryDsl, ExpressionMethods, OptionalExtension};
+use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+
+use crate::db::DbConnection;
+
+diesel::table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+        run_on -> Timestamp,
+    }
+}
+
+#[cfg(feature = "mysql")]
+macro_rules! migration {
+    ($dir:literal) => { include_str!(concat!("../migrations/mysql/", $dir, "/up.sql")) };
+}
+#[cfg(feature = "postgres")]
+macro_rules! migration {
+    ($dir:literal) => { include_str!(concat!("../migrations/postgres/", $dir, "/up.sql")) };
+}
+#[cfg(feature = "sqlite")]
+macro_rules! migration {
+    ($dir:literal) => { include_str!(concat!("../migrations/sqlite/", $dir, "/up.sql")) };
+}
+
+pub struct Migration {
+    /// The directory name without dashes up to the first underscore, as diesel stores it.
+    pub version :&'static str,
+    pub name :&'static str,
+    pub up :&'static str
+}
+
+/// In the order they are applied.
+pub const MIGRATIONS :&[Migration] = &[
+    Migration {
+        version: "20230701000000",
+        name: "init",
+        up: migration!("2023-07-01-000000_init")
+    },
+    Migration {
+        version: "20230702000000",
+        name: "occupancy",
+        up: migration!("2023-07-02-000000_occupancy")
+    },
+    Migration {
+        version: "20230703000000",
+        name: "entry_rules",
+        up: migration!("2023-07-03-000000_entry_rules")
+    },
+    Migration {
+        version: "20230704000000",
+        name: "emergency",
+        up: migration!("2023-07-04-000000_emergency")
+    },
+    Migration {
+        version: "20230705000000",
+        name: "visitor_passes",
+        up: migration!("2023-07-05-000000_visitor_passes")
+    },
+    Migration {
+        version: "20230706000000",
+        name: "roles",
+        up: migration!("2023-07-06-000000_roles")
+    },
+    Migration {
+        version: "20230715000000",
+        name: "active_access_profile",
+        up: migration!("2023-07-15-000000_active_access_profile")
+    },
+    Migration {
+        version: "20230801000000",
+        name: "groups",
+        up: migration!("2023-08-01-000000_groups")
+    },
+    Migration {
+        version: "20230815000000",
+        name: "webhooks",
+        up: migration!("2023-08-15-000000_webhooks")
+    }
+];
+
+async fn applied_versions<'a>(conn :&mut DbConnection<'a>) -> Result<Vec<String>, diesel::result::Error> {
+    __diesel_schema_migrations::table
+        .select(__diesel_schema_migrations::columns::version)
+    .load(conn).await
+}
+
+/// Migrations not applied to the database yet.
+pub async fn pending<'a>(conn :&mut DbConnection<'a>) -> Result<Vec<&'static Migration>, diesel::result::Error> {
+    let applied = applied_versions(conn).await?;
+
+    Ok(MIGRATIONS.iter().filter(|m| !applied.iter().any(|v| v == m.version)).collect())
+}
+
+/// Applies every pending migration.
+pub async fn run<'a>(conn :&mut DbConnection<'a>) -> Result<(), Box<dyn Error>> {
+    conn.batch_execute(
+        "CREATE TABLE IF NOT EXISTS __diesel_schema_migrations (
+            version varchar(50) PRIMARY KEY NOT NULL,
+            run_on timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );"
+    ).await?;
+
+    if applied_versions(conn).await?.is_empty() {
+        adopt_existing_schema(conn).await?;
+    }
+
+    for migration in pending(conn).await? {
+        tracing::info!(version = migration.version, name = migration.name, "applying migration");
+
+        // MySQL commits DDL statements right away, so a failed migration has to be fixed up by hand there.
+        if let Err(e) = conn.batch_execute(migration.up).await {
+            return Err(format!("migration {}_{} failed: {}", migration.version, migration.name, e).into())
+        }
+
+        diesel::insert_into(__diesel_schema_migrations::table)
+            .values(__diesel_schema_migrations::columns::version.eq(migration.version))
+        .execute(conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Databases set up by hand from the old db/init.sql already have the initial schema, the tables of the init
+/// migration. If all of it is there, it is recorded as applied and the later migrations bring the database up to
+/// date; if only part of it is, there is no safe way forward.
+async fn adopt_existing_schema<'a>(conn :&mut DbConnection<'a>) -> Result<(), Box<dyn Error>> {
+    // Selecting nothing from a table fails only if the table is missing.
+    let checks = [
+        users::table.count().limit(0).get_result::<i64>(conn).await.optional().is_ok(),
+        access_codes::table.count().limit(0).get_result::<i64>(conn).await.optional().is_ok(),
+        access_profiles::table.count().limit(0).get_result::<i64>(conn).await.optional().is_ok(),
+        permissions::table.count().limit(0).get_result::<i64>(conn).await.optional().is_ok(),
+        access_profiles_permissions::table.count().limit(0).get_result::<i64>(conn).await.optional().is_ok(),
+        users_permissions::table.count().limit(0).get_result::<i64>(conn).await.optional().is_ok(),
+        web_ui_users::table.count().limit(0).get_result::<i64>(conn).await.optional().is_ok()
+    ];
+
+    if checks.iter().all(|exists| !exists) {
+        return Ok(())
+    }
+
+    if checks.iter().any(|exists| !exists) {
+        return Err("the database has only part of the initial schema, create the missing tables by hand or start from an empty database".into())
+    }
+
+    tracing::info!("found a schema created without migrations, recording it as the initial migration");
+
+    diesel::insert_into(__diesel_schema_migrations::table)
+        .values(__diesel_schema_migrations::columns::version.eq(MIGRATIONS[0].version))
+    .execute(conn).await?;
+
+    Ok(())
+}
@@ -0,0 +1,408 @@
+// Models for the tables in crate::schema.
+use argon2::{Argon2, PasswordHasher, PasswordVerifier, password_hash::{self, PasswordHash, SaltString}};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::schema::{occupancy, access_profiles_settings, users_pins, emergency_state, active_access_profile, visitor_passes, roles, roles_capabilities, web_ui_users_roles, groups, groups_users, groups_permissions, webhooks, webhooks_events, webhook_deliveries};
+
+#[derive(Queryable, Selectable, Identifiable, Insertable, Serialize, Clone)]
+#[diesel(table_name = occupancy)]
+#[diesel(primary_key(user_id))]
+pub struct Occupancy {
+    pub user_id :i32,
+    pub entered_at :NaiveDateTime
+}
+
+/// What is required to enter while the profile is active, on top of the permission.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryRule {
+    Single,         // A single authorized card
+    TwoPerson,      // Two distinct authorized cards within the two-person window
+    CardAndPin      // An authorized card together with the card owner's PIN
+}
+
+impl From<EntryRule> for String {
+    fn from(rule :EntryRule) -> Self {
+        String::from(match rule {
+            EntryRule::Single => "single",
+            EntryRule::TwoPerson => "two_person",
+            EntryRule::CardAndPin => "card_and_pin"
+        })
+    }
+}
+
+impl TryFrom<String> for EntryRule {
+    type Error = String;
+
+    fn try_from(value :String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "single" => Ok(EntryRule::Single),
+            "two_person" => Ok(EntryRule::TwoPerson),
+            "card_and_pin" => Ok(EntryRule::CardAndPin),
+            _ => Err(format!("Unknown entry rule {}.", value))
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Identifiable, Insertable, AsChangeset, Serialize, Clone)]
+#[diesel(table_name = access_profiles_settings)]
+#[diesel(primary_key(access_profile_id))]
+#[diesel(treat_none_as_null = true)]
+pub struct AccessProfileSettings {
+    #[serde(skip)]
+    pub access_profile_id :i32,
+    pub anti_passback :bool,
+    pub max_occupancy :Option<i32>,
+    #[diesel(serialize_as = String, deserialize_as = String)]
+    pub entry_rule :EntryRule,
+    pub two_person_window :i32      // In seconds
+}
+
+impl AccessProfileSettings {
+    pub fn default_for(access_profile_id :i32) -> Self {
+        Self {
+            access_profile_id,
+            anti_passback: false,
+            max_occupancy: None,
+            entry_rule: EntryRule::Single,
+            two_person_window: 30
+        }
+    }
+
+    pub fn apply(&mut self, update :AccessProfileSettingsUpdate) {
+        if let Some(anti_passback) = update.anti_passback {
+            self.anti_passback = anti_passback;
+        }
+        if let Some(max_occupancy) = update.max_occupancy {
+            self.max_occupancy = max_occupancy;
+        }
+        if let Some(entry_rule) = update.entry_rule {
+            self.entry_rule = entry_rule;
+        }
+        if let Some(two_person_window) = update.two_person_window {
+            self.two_person_window = two_person_window;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AccessProfileSettingsUpdate {
+    pub anti_passback :Option<bool>,
+    #[serde(default, with = "double_option")]
+    pub max_occupancy :Option<Option<i32>>,
+    pub entry_rule :Option<EntryRule>,
+    pub two_person_window :Option<i32>
+}
+
+#[derive(Queryable, Selectable, Identifiable, Insertable, AsChangeset)]
+#[diesel(table_name = users_pins)]
+#[diesel(primary_key(user_id))]
+pub struct UserPin {
+    pub user_id :i32,
+    pub pin_hash :String
+}
+
+impl UserPin {
+    /// A PIN is only a few digits, so it's hashed with Argon2 and a random salt, which makes trying them all on a
+    /// copy of the database slow.
+    pub fn new(user_id :i32, pin :&str) -> Result<Self, password_hash::Error> {
+        let salt = SaltString::encode_b64(&rand::thread_rng().gen::<[u8; 16]>())?;
+
+        Ok(Self {
+            user_id,
+            pin_hash: Argon2::default().hash_password(pin.as_bytes(), &salt)?.to_string()
+        })
+    }
+
+    /// Compares in constant time.
+    pub fn matches(&self, pin :&str) -> bool {
+        match PasswordHash::new(&self.pin_hash) {
+            Ok(hash) => Argon2::default().verify_password(pin.as_bytes(), &hash).is_ok(),
+            Err(_) => false
+        }
+    }
+}
+
+// Distinguishes a missing field (no change) from an explicit null (clear the value).
+mod double_option {
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, T, D>(deserializer :D) -> Result<Option<Option<T>>, D::Error>
+    where
+        T :Deserialize<'de>,
+        D :Deserializer<'de>
+    {
+        Option::<T>::deserialize(deserializer).map(Some)
+    }
+}
+
+/// Emergency modes override the active access profile until they are cleared.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EmergencyMode {
+    Normal,
+    Lockdown,       // Nobody but holders of the break-glass permission may enter
+    Evacuation      // The door is held open
+}
+
+impl From<EmergencyMode> for String {
+    fn from(mode :EmergencyMode) -> Self {
+        String::from(match mode {
+            EmergencyMode::Normal => "normal",
+            EmergencyMode::Lockdown => "lockdown",
+            EmergencyMode::Evacuation => "evacuation"
+        })
+    }
+}
+
+impl TryFrom<String> for EmergencyMode {
+    type Error = String;
+
+    fn try_from(value :String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "normal" => Ok(EmergencyMode::Normal),
+            "lockdown" => Ok(EmergencyMode::Lockdown),
+            "evacuation" => Ok(EmergencyMode::Evacuation),
+            _ => Err(format!("Unknown emergency mode {}.", value))
+        }
+    }
+}
+
+/// The table holds at most a single row, with ID `EmergencyState::ID`.
+#[derive(Queryable, Selectable, Identifiable, Insertable, AsChangeset, Serialize, Clone)]
+#[diesel(table_name = emergency_state)]
+pub struct EmergencyState {
+    #[serde(skip)]
+    pub id :i32,
+    #[diesel(serialize_as = String, deserialize_as = String)]
+    pub mode :EmergencyMode,
+    pub changed_by :String,
+    pub changed_at :NaiveDateTime
+}
+
+impl EmergencyState {
+    pub const ID :i32 = 1;
+}
+
+/// The table holds at most a single row, with ID `ActiveProfileState::ID`.
+#[derive(Queryable, Selectable, Identifiable, Insertable, AsChangeset, Clone)]
+#[diesel(table_name = active_access_profile)]
+pub struct ActiveProfileState {
+    pub id :i32,
+    pub access_profile_id :i32
+}
+
+impl ActiveProfileState {
+    pub const ID :i32 = 1;
+}
+
+#[derive(Queryable, Selectable, Identifiable, Serialize, Clone)]
+#[diesel(table_name = visitor_passes)]
+pub struct VisitorPass {
+    pub id :i32,
+    pub guest_name :String,
+    pub host_user :i32,
+    pub code :String,
+    pub valid_from :NaiveDateTime,
+    pub valid_until :NaiveDateTime,
+    pub max_uses :i32,
+    pub uses :i32,
+    /// When the visitor came in, while they're inside.
+    pub entered_at :Option<NaiveDateTime>
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = visitor_passes)]
+pub struct VisitorPassInsert {
+    pub guest_name :String,
+    pub host_user :i32,
+    pub code :String,
+    pub valid_from :NaiveDateTime,
+    pub valid_until :NaiveDateTime,
+    pub max_uses :i32,
+    pub uses :i32
+}
+
+/// A named set of capabilities, assignable to web UI users.
+#[derive(Queryable, Selectable, Identifiable, Serialize, Clone)]
+#[diesel(table_name = roles)]
+pub struct Role {
+    pub id :i32,
+    pub name :String,
+    pub description :String
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = roles)]
+pub struct RoleInsert {
+    pub name :String,
+    pub description :String
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = roles)]
+pub struct RoleUpdate {
+    pub name :Option<String>,
+    pub description :Option<String>
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable)]
+#[diesel(table_name = roles_capabilities)]
+#[diesel(primary_key(role_id, capability))]
+#[diesel(belongs_to(Role))]
+pub struct RoleCapability {
+    pub role_id :i32,
+    pub capability :String
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable)]
+#[diesel(table_name = web_ui_users_roles)]
+#[diesel(primary_key(web_ui_user_id, role_id))]
+#[diesel(belongs_to(Role))]
+pub struct WebUIUserRole {
+    pub web_ui_user_id :i32,
+    pub role_id :i32
+}
+
+/// A named set of users, e.g. a class. Permissions granted to the group apply to all of its members.
+#[derive(Queryable, Selectable, Identifiable, Serialize, Clone)]
+#[diesel(table_name = groups)]
+pub struct Group {
+    pub id :i32,
+    pub name :String,
+    pub description :String
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = groups)]
+pub struct GroupInsert {
+    pub name :String,
+    pub description :String
+}
+
+#[derive(AsChangeset, Deserialize)]
+#[diesel(table_name = groups)]
+pub struct GroupUpdate {
+    pub name :Option<String>,
+    pub description :Option<String>
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable)]
+#[diesel(table_name = groups_users)]
+#[diesel(primary_key(group_id, user_id))]
+#[diesel(belongs_to(Group))]
+pub struct GroupUser {
+    pub group_id :i32,
+    pub user_id :i32
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable)]
+#[diesel(table_name = groups_permissions)]
+#[diesel(primary_key(group_id, permission_id))]
+#[diesel(belongs_to(Group))]
+pub struct GroupPermission {
+    pub group_id :i32,
+    pub permission_id :i32
+}
+
+/// A URL events are posted to, signed with the secret.
+#[derive(Queryable, Selectable, Identifiable, Serialize, Clone)]
+#[diesel(table_name = webhooks)]
+pub struct Webhook {
+    pub id :i32,
+    pub name :String,
+    pub url :String,
+    pub secret :String,
+    pub active :bool
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhooks)]
+pub struct WebhookInsert {
+    pub name :String,
+    pub url :String,
+    pub secret :String,
+    pub active :bool
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = webhooks)]
+pub struct WebhookUpdate {
+    pub name :Option<String>,
+    pub url :Option<String>,
+    pub secret :Option<String>,
+    pub active :Option<bool>
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Insertable)]
+#[diesel(table_name = webhooks_events)]
+#[diesel(primary_key(webhook_id, event))]
+#[diesel(belongs_to(Webhook))]
+pub struct WebhookEvent {
+    pub webhook_id :i32,
+    pub event :String
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,        // Not delivered yet, more attempts will follow
+    Delivered,
+    Failed          // Every attempt failed
+}
+
+impl From<DeliveryStatus> for String {
+    fn from(status :DeliveryStatus) -> Self {
+        String::from(match status {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed"
+        })
+    }
+}
+
+impl TryFrom<String> for DeliveryStatus {
+    type Error = String;
+
+    fn try_from(value :String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "pending" => Ok(DeliveryStatus::Pending),
+            "delivered" => Ok(DeliveryStatus::Delivered),
+            "failed" => Ok(DeliveryStatus::Failed),
+            _ => Err(format!("Unknown delivery status {}.", value))
+        }
+    }
+}
+
+/// A single event sent to a webhook, with the outcome of the last attempt.
+#[derive(Queryable, Selectable, Identifiable, Associations, Serialize, Clone)]
+#[diesel(table_name = webhook_deliveries)]
+#[diesel(belongs_to(Webhook))]
+pub struct WebhookDelivery {
+    pub id :i32,
+    pub webhook_id :i32,
+    pub event :String,
+    pub payload :String,
+    #[diesel(deserialize_as = String)]
+    pub status :DeliveryStatus,
+    pub attempts :i32,
+    pub response_status :Option<i32>,
+    pub error :Option<String>,
+    pub created_at :NaiveDateTime,
+    pub last_attempt_at :Option<NaiveDateTime>
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDeliveryInsert {
+    pub webhook_id :i32,
+    pub event :String,
+    pub payload :String,
+    #[diesel(serialize_as = String)]
+    pub status :DeliveryStatus,
+    pub attempts :i32,
+    pub created_at :NaiveDateTime
+}
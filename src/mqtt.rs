@@ -0,0 +1,284 @@
+// Bridges the server to an MQTT broker, for building automation. Events from the bus are published as they
+// happen, the status is published periodically, and commands on the command topic go through the same functions
+// as `POST /access/open` and `POST /active-profile`, authenticated with a web UI token.
+
+use std::time::Duration;
+
+use rocket::{fairing::AdHoc, tokio::{self, sync::broadcast::error::RecvError}};
+use rumqttc::{AsyncClient, EventLoop, MqttOptions, LastWill, QoS, Event as MqttEvent, Packet};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{DB, get_connection}, config::{Config, MqttConfig, DoorConfig}, error::ApiError, metrics::Metrics, events::{Event, EventBus},
+    guards::{auth::{SecretKeyWrapper, AuthorizationProvider, Capable, decode_token, refresh_claim}, capabilities::{DoorOpen, ProfilesActivate}},
+    models::EmergencyMode, door::Door, routes::{access::open_door, active_access_profile::{ActiveAccessProfile, ActiveAccessProfileInfo}}
+};
+
+/// How long to wait before connecting again after the connection to the broker failed.
+const RECONNECT_DELAY :Duration = Duration::from_secs(5);
+
+/// A message on the command topic, e.g. `{"token": "...", "action": "activate_profile", "profile": "night"}`.
+#[derive(Deserialize)]
+struct Command {
+    token :String,
+    id :Option<String>,     // Echoed in the result, to tell the results apart
+    #[serde(flatten)]
+    action :Action
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Action {
+    Open,
+    /// By id or name.
+    ActivateProfile {profile :String}
+}
+
+/// Published to the results topic for every command.
+#[derive(Serialize)]
+struct CommandResult<'a> {
+    id :Option<&'a str>,
+    ok :bool,
+    /// An error code, like in the HTTP API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error :Option<&'a str>
+}
+
+#[derive(Serialize)]
+struct Status {
+    online :bool,
+    /// Whether the last command reached the door.
+    in_sync :bool,
+    emergency :EmergencyMode,
+    active_profile :Option<ActiveAccessProfileInfo>
+}
+
+/// What the broker publishes as the status if the server goes away without disconnecting.
+#[derive(Serialize)]
+struct Offline {
+    online :bool
+}
+
+fn connection_options(config :&MqttConfig) -> MqttOptions {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+
+    // Retained, like the status it replaces.
+    let offline = serde_json::to_vec(&Offline { online: false }).unwrap_or_default();
+    options.set_last_will(LastWill::new(&config.topics.status, offline, QoS::AtLeastOnce, true));
+
+    options
+}
+
+/// Cloning is cheap, clones share the connection to the broker.
+#[derive(Clone)]
+pub struct Bridge {
+    client :AsyncClient,
+    config :MqttConfig,
+    db :DB,
+    key :rocket::config::SecretKey,
+    controller :Door,
+    aacp :ActiveAccessProfile,
+    metrics :Metrics,
+    door :DoorConfig,
+    bus :EventBus
+}
+
+impl Bridge {
+    /// Connects to the broker once the server is up, and keeps reconnecting until it stops.
+    pub fn fairing(config :MqttConfig) -> AdHoc {
+        AdHoc::on_liftoff("MQTT bridge", |rocket| Box::pin(async move {
+            let state = (
+                rocket.state::<DB>(),
+                rocket.state::<SecretKeyWrapper>(),
+                rocket.state::<Door>(),
+                rocket.state::<ActiveAccessProfile>(),
+                rocket.state::<Metrics>(),
+                rocket.state::<Config>(),
+                rocket.state::<EventBus>()
+            );
+
+            let (client, eventloop) = AsyncClient::new(connection_options(&config), 64);
+
+            let bridge = match state {
+                (Some(db), Some(key), Some(controller), Some(aacp), Some(metrics), Some(server_config), Some(bus)) => Self {
+                    client,
+                    config,
+                    db: db.clone(),
+                    key: key.key.clone(),
+                    controller: controller.clone(),
+                    aacp: aacp.clone(),
+                    metrics: metrics.clone(),
+                    door: server_config.door.clone(),
+                    bus: bus.clone()
+                },
+                _ => {
+                    tracing::error!("the MQTT bridge couldn't start, the server is missing some of its state");
+                    return
+                }
+            };
+
+            tokio::spawn(bridge.clone().run(eventloop));
+            tokio::spawn(bridge.clone().publish_events());
+            tokio::spawn(bridge.publish_status_periodically());
+        }))
+    }
+
+    #[tracing::instrument(name = "mqtt", skip_all, fields(host = %self.config.host, port = self.config.port))]
+    async fn run(self, mut eventloop :EventLoop) {
+        loop {
+            match eventloop.poll().await {
+                Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                    tracing::info!("connected to the broker");
+
+                    // The session is clean, so the subscription has to be made on every connection.
+                    if let Err(e) = self.client.try_subscribe(&self.config.topics.commands, QoS::AtLeastOnce) {
+                        tracing::warn!(error = %e, "couldn't subscribe to the command topic");
+                    }
+
+                    let bridge = self.clone();
+                    tokio::spawn(async move { bridge.publish_status().await });
+                },
+                Ok(MqttEvent::Incoming(Packet::Publish(publish))) => {
+                    let bridge = self.clone();
+                    tokio::spawn(async move { bridge.handle(&publish.payload).await });
+                },
+                Ok(_) => {},
+                Err(e) => {
+                    tracing::warn!(error = %e, "connection to the broker lost");
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    async fn publish_events(self) {
+        let mut rx = self.bus.subscribe();
+
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "MQTT fell behind, some events won't be published");
+                    continue
+                }
+            };
+
+            self.publish(&format!("{}/{}", self.config.topics.events, event.kind()), &event, false).await;
+
+            // These change what the status says.
+            if matches!(event, Event::ActiveProfileChanged {..} | Event::ControllerOffline {..} | Event::EmergencyChanged {..}) {
+                self.publish_status().await;
+            }
+        }
+    }
+
+    async fn publish_status_periodically(self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.status_interval));
+
+        loop {
+            interval.tick().await;
+            self.publish_status().await;
+        }
+    }
+
+    async fn publish_status(&self) {
+        let status = Status {
+            online: true,
+            in_sync: self.aacp.is_in_sync(),
+            emergency: self.aacp.emergency().await,
+            active_profile: self.aacp.get().await.map(ActiveAccessProfileInfo::from)
+        };
+
+        self.publish(&self.config.topics.status, &status, true).await;
+    }
+
+    async fn publish<T :Serialize>(&self, topic :&str, payload :&T, retain :bool) {
+        let payload = match serde_json::to_vec(payload) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::warn!(error = %e, topic, "couldn't serialize an MQTT message");
+                return
+            }
+        };
+
+        if let Err(e) = self.client.publish(topic, QoS::AtLeastOnce, retain, payload).await {
+            tracing::warn!(error = %e, topic, "couldn't publish to the broker");
+        }
+    }
+
+    /// Runs a command from the command topic and publishes the outcome.
+    async fn handle(&self, payload :&[u8]) {
+        let command :Command = match serde_json::from_slice(payload) {
+            Ok(command) => command,
+            Err(e) => {
+                tracing::info!(error = %e, "ignored a malformed command");
+                self.publish(&self.config.topics.results, &CommandResult { id: None, ok: false, error: Some("bad_request") }, false).await;
+                return
+            }
+        };
+
+        let error = self.execute(&command).await.err();
+
+        self.publish(&self.config.topics.results, &CommandResult {
+            id: command.id.as_deref(),
+            ok: error.is_none(),
+            error
+        }, false).await;
+    }
+
+    /// Returns the error code if the command failed.
+    async fn execute(&self, command :&Command) -> Result<(), &'static str> {
+        let claim = match decode_token(&command.token, &self.key) {
+            Some(claim) => claim,
+            None => return Err("unauthorized")
+        };
+        let claim = match refresh_claim(claim, &self.db).await {
+            Ok(Some(claim)) => claim,
+            Ok(None) => return Err("unauthorized"),
+            Err(e) => return Err(e.code())
+        };
+
+        let permitted = match command.action {
+            Action::Open => Capable::<DoorOpen>::authorize(&claim),
+            Action::ActivateProfile {..} => Capable::<ProfilesActivate>::authorize(&claim)
+        };
+        if !permitted {
+            return Err("forbidden")
+        }
+
+        let res = match &command.action {
+            Action::Open => {
+                tracing::info!(user = %claim.name, "opening the door on a command over MQTT");
+                open_door(claim.name, &self.controller, &self.aacp, &self.metrics, &self.door, &self.bus).await
+            },
+            Action::ActivateProfile { profile } => {
+                tracing::info!(user = %claim.name, profile = %profile, "activating a profile on a command over MQTT");
+                self.activate(profile).await
+            }
+        };
+
+        match res {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if let ApiError::Internal(message) = &e {
+                    tracing::error!(error = %message, "an MQTT command failed");
+                }
+                Err(e.code())
+            }
+        }
+    }
+
+    async fn activate(&self, profile :&str) -> Result<(), ApiError> {
+        let mut conn = get_connection(&self.db).await?;
+
+        self.aacp.activate(profile, &mut conn).await?;
+        self.metrics.profile_changes.inc();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use std::{collections::HashMap, hash::Hash, net::IpAddr, time::{Duration, Instant}};
+
+use async_mutex::Mutex;
+
+/// Counts hits per key, a client address unless said otherwise, in fixed windows.
+pub struct RateLimiter<K = IpAddr> {
+    max_hits :u32,
+    window :Duration,
+    hits :Mutex<HashMap<K, (Instant, u32)>>     // Start of the current window, hits in it
+}
+
+impl<K :Hash + Eq> RateLimiter<K> {
+    /// `max_hits` of 0 lets everything through.
+    pub fn new(max_hits :u32, window :Duration) -> Self {
+        Self {
+            max_hits,
+            window,
+            hits: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Records a hit from `key`, returns whether it is within the limit.
+    pub async fn hit(&self, key :K) -> bool {
+        if self.max_hits == 0 {
+            return true
+        }
+
+        let mut hits = self.hits.lock().await;
+        let now = Instant::now();
+
+        // Forget finished windows, so the map doesn't grow with every address ever seen.
+        hits.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+
+        let (_, count) = hits.entry(key).or_insert((now, 0));
+        *count += 1;
+
+        *count <= self.max_hits
+    }
+
+    /// Whether `key` has used up its hits in the current window, without recording one.
+    pub async fn exhausted(&self, key :&K) -> bool {
+        if self.max_hits == 0 {
+            return false
+        }
+
+        match self.hits.lock().await.get(key) {
+            Some((start, count)) => start.elapsed() < self.window && *count >= self.max_hits,
+            None => false
+        }
+    }
+}
+
+/// Limits login attempts, to slow down guessing passwords.
+pub struct LoginRateLimiter(pub RateLimiter);
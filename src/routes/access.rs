@@ -4,71 +4,246 @@ use cherrydoor_models::{
 };
 use cherrydoor_command::Command;
 use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, OptionalExtension, BelongingToDsl};
-use diesel_async::RunQueryDsl;
-use reqwest::StatusCode;
-use rocket::{post, State, serde::json::Json, response::status::NoContent};
-use serde::Deserialize;
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rocket::{post, State, Request, serde::json::Json, response::{status::{NoContent, Custom}, Responder}, request::{FromRequest, Outcome}, http::Status, outcome};
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, time::{Duration, Instant}};
+use async_mutex::Mutex;
 
-use crate::{db::{DB, get_connection}, error::ApiError, guards::auth::{Auth, OperatorUser}};
+use crate::{db::{DB, DbConnection, get_connection, transaction}, metrics::Metrics, config::{Config, DoorConfig, RateLimitsConfig}, error::{ApiError, ErrorCode, FieldError}, validation::{Validate, Validated, check_not_empty}, guards::{auth::{Auth, Capable}, capabilities::DoorOpen}, models::{EntryRule, EmergencyMode, AccessProfileSettings}, events::{EventBus, Event}, door::Door, rate_limit::RateLimiter, codes};
 
-use super::active_access_profile::ActiveAccessProfile;
+use super::{active_access_profile::ActiveAccessProfile, groups, access_profiles::settings::get_settings, occupancy::{self, Entrant}, users::pin::get_pin, emergency::BREAK_GLASS_PERMISSION, visitors};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessDirection {
+    #[default]
+    Entry,
+    Exit
+}
 
 #[derive(Deserialize)]
 pub struct AccessCodeAccess {
-    code :String
+    code :String,
+    #[serde(default)]
+    direction :AccessDirection,
+    pin :Option<String>
+}
+
+impl Validate for AccessCodeAccess {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_not_empty("code", &self.code, errors);
+    }
+}
+
+#[derive(Serialize)]
+pub struct AccessPending {
+    reason :String
+}
+
+#[derive(Responder)]
+pub enum AccessOutcome {
+    Granted(NoContent),
+    Pending(Custom<Json<AccessPending>>)
+}
+
+impl AccessOutcome {
+    fn pending(reason :&str) -> Self {
+        Self::Pending(Custom(Status::Accepted, Json(AccessPending {
+            reason: String::from(reason)
+        })))
+    }
+}
+
+#[derive(Clone)]
+pub struct PendingEntry {
+    entrant :Entrant,
+    access_profile_id :i32,
+    swiped_at :Instant
 }
 
-pub struct CommandAddress(pub String);
+/// What's kept in memory between swipes.
+pub struct Swipes {
+    /// The first swipe of a two-person entry, waiting for the second one.
+    pending :Mutex<Option<PendingEntry>>,
+    /// Wrong PINs, per user.
+    pins :RateLimiter<i32>,
+    /// Codes nobody has, per client address. Swipes all come from the door controller, so this is per door, not per
+    /// person.
+    unknown_codes :RateLimiter
+}
+
+impl Swipes {
+    pub fn new(limits :&RateLimitsConfig) -> Self {
+        Self {
+            pending: Mutex::new(None),
+            pins: RateLimiter::new(limits.pin_attempts, Duration::from_secs(limits.pin_window)),
+            unknown_codes: RateLimiter::new(limits.unknown_codes, Duration::from_secs(limits.unknown_code_window))
+        }
+    }
+}
+
+/// A swipe's view of `Swipes`, along with the address it came from.
+pub struct Swipe<'r> {
+    swipes :&'r Swipes,
+    client_ip :Option<IpAddr>
+}
+
+impl Swipe<'_> {
+    /// Whether the address swiped too many codes nobody has, i.e. seems to be guessing visitor passes.
+    async fn guessing(&self) -> bool {
+        match self.client_ip {
+            Some(ip) => self.swipes.unknown_codes.exhausted(&ip).await,
+            None => false
+        }
+    }
+
+    async fn unknown_code(&self) {
+        if let Some(ip) = self.client_ip {
+            self.swipes.unknown_codes.hit(ip).await;
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Swipe<'r> {
+    type Error = ();
+
+    async fn from_request(request :&'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.guard::<&State<Swipes>>().await {
+            outcome::Outcome::Success(swipes) => Outcome::Success(Swipe { swipes, client_ip: request.client_ip() }),
+            _ => Outcome::Failure((Status::InternalServerError, ()))
+        }
+    }
+}
 
 #[post("/open")]
 pub async fn open(
-    _auth :Auth<OperatorUser>,
-    command_addr :&State<CommandAddress>
+    auth :Auth<Capable<DoorOpen>>,
+    controller :&State<Door>,
+    aacp :&State<ActiveAccessProfile>,
+    metrics :&State<Metrics>,
+    config :&State<Config>,
+    bus :&State<EventBus>
 ) -> Result<NoContent, ApiError> {
+    open_door(auth.claim.name, controller, aacp, metrics, &config.door, bus).await?;
+
+    Ok(NoContent)
+}
+
+/// Opens the door for `door.open_duration`, on behalf of the web UI user `by`.
+pub async fn open_door(
+    by :String,
+    controller :&Door,
+    aacp :&ActiveAccessProfile,
+    metrics :&Metrics,
+    door :&DoorConfig,
+    bus :&EventBus
+) -> Result<(), ApiError> {
+    if aacp.emergency().await != EmergencyMode::Normal {
+        return Err(ApiError::Conflict(ErrorCode::EmergencyActive))
+    }
+
+    let duration = door.open_duration;
     let command = &Command::new()
-        .open_for(5000)
-        .display_text_for("Wejdz".to_string(), 5000)
-        .set_color_for(0, 0, 0, 5000)
+        .open_for(duration.into())
+        .display_text_for("Wejdz".to_string(), duration.into())
+        .set_color_for(0, 0, 0, duration.into())
         .play_sound(1);
 
-    let client = reqwest::Client::new();
+    controller.send("open", command).await?;
 
-    match client.post(format!("{}/", command_addr.0))
-        .json(command)
-        .send()
-    .await {
-        Ok(res) => {
-            match res.status() {
-                StatusCode::NO_CONTENT => {
-                    Ok(NoContent)
-                },
-                _ => {
-                    Err(ApiError::Internal(format!("Command server returned {}", res.text().await.unwrap_or("garbage".to_string()))))
-                }
-            }
-        },
-        Err(e) => Err(ApiError::Internal(format!("Error while connecting to command server: {}", e)))
-    }
+    metrics.manual_opens.inc();
+    bus.emit(Event::DoorOpened { by });
+
+    Ok(())
 }
 
 #[post("/code", format = "application/json", data = "<access>")]
 pub async fn code(
-    access :Json<AccessCodeAccess>,
+    access :Validated<AccessCodeAccess>,
 
+    swipe :Swipe<'_>,
     aacp :&State<ActiveAccessProfile>,
-    db :&State<DB>
-) -> Result<NoContent, ApiError> {
+    metrics :&State<Metrics>,
+    config :&State<Config>,
+    db :&State<DB>,
+    bus :&State<EventBus>
+) -> Result<AccessOutcome, ApiError> {
     let mut conn = get_connection(db).await?;
 
-    let apn = aacp.get().await;
+    aacp.refresh(&mut conn).await?;
+    let active = aacp.get().await;
+    let active_id = active.as_ref().map(|profile| profile.id);
+    let candidates = codes::candidates(&access.code, config.door.code_format);
+    let lookup = candidates.clone();
+    let swipe = &swipe;
+
+    // Held until the swipe is decided, and changed only if its transaction commits, so an entry that was rolled
+    // back doesn't use up the first swipe of a pair.
+    let mut pending = swipe.swipes.pending.lock().await;
+    let first = pending.clone();
+    let result = match transaction(&mut conn, |conn| async move {
+        let mut next = first;
+        let outcome = check_code(&access, &lookup, active_id, aacp, swipe, &mut next, conn).await?;
+        Ok((outcome, next))
+    }.scope_boxed()).await {
+        Ok((outcome, next)) => {
+            *pending = next;
+            Ok(outcome)
+        },
+        Err(e) => Err(e)
+    };
+    drop(pending);
+
+    if let Err(ApiError::NotFound(ErrorCode::AccessCodeNotRegistered)) = &result {
+        swipe.unknown_code().await;
+    }
+
+    // Only refusals, not failures on the way to a decision.
+    if let Err(ApiError::BadRequest(e) | ApiError::NotFound(e) | ApiError::TooManyRequests(e)) = &result {
+        // The door gets its answer whether or not the owner can be named.
+        let user = match get_code_owner(&candidates, &mut conn).await {
+            Ok(user) => user,
+            Err(e) => {
+                tracing::warn!(error = %e, "couldn't find the owner of a denied code");
+                None
+            }
+        };
+        bus.emit(Event::AccessDenied { user, reason: String::from(e.code()) });
+    }
+
+    let outcome = match &result {
+        Ok(AccessOutcome::Granted(_)) => "granted",
+        Ok(AccessOutcome::Pending(_)) => "pending",
+        Err(e) => e.code()
+    };
+    let profile = active.map(|profile| profile.name).unwrap_or_default();
+    metrics.swipes.with_label_values(&[outcome, &profile]).inc();
 
+    result
+}
+
+/// Decides whether the swiped code lets its owner through while the profile with `active_id` is the active one.
+/// `candidates` are the values the code may be stored as. `pending` is the first swipe of a two-person entry.
+async fn check_code<'a>(
+    access :&AccessCodeAccess,
+    candidates :&[String],
+    active_id :Option<i32>,
+    aacp :&ActiveAccessProfile,
+    swipe :&Swipe<'_>,
+    pending :&mut Option<PendingEntry>,
+    conn :&mut DbConnection<'a>
+) -> Result<AccessOutcome, ApiError> {
     let ac :AccessCode = match access_codes::table
         .select(AccessCode::as_select())
-        .filter(access_codes::columns::code.eq(&access.0.code))
-    .first(&mut conn).await.optional() {
+        .filter(access_codes::columns::code.eq_any(candidates))
+    .first(conn).await.optional() {
         Ok(maybe_ac) => match maybe_ac {
             Some(ac) => ac,
-            None => return Err(ApiError::NotFound(String::from("Access code not registered.")))
+            // Passes are short enough to guess, unlike cards.
+            None if swipe.guessing().await => return Err(ApiError::TooManyRequests(ErrorCode::TooManyUnknownCodes)),
+            None => return visitors::use_pass(&access.code, access.direction, aacp, swipe, pending, conn).await
         },
         Err(e) => return Err(ApiError::Internal(format!("{}", e)))
     };
@@ -76,34 +251,198 @@ pub async fn code(
     let user :User = match users::table
         .select(User::as_select())
         .filter(users::columns::id.eq(ac.user))
-    .first(&mut conn).await {
+    .first(conn).await {
         Ok(user) => user,
         Err(e) => return Err(ApiError::Internal(format!("{}", e)))
     };
 
-    let upwp :Vec<(UserPermission, Permission)> = match UserPermission::belonging_to(&user)
+    // Leaving is always allowed, it only needs to be recorded.
+    if access.direction == AccessDirection::Exit {
+        occupancy::leave(Entrant::User(user.id), conn).await?;
+        return Ok(AccessOutcome::Granted(NoContent))
+    }
+
+    let perms = get_user_permissions(&user, conn).await?;
+
+    match aacp.emergency().await {
+        EmergencyMode::Normal => {},
+        EmergencyMode::Lockdown => {
+            if !perms.iter().any(|perm| perm.name == BREAK_GLASS_PERMISSION) {
+                return Err(ApiError::BadRequest(ErrorCode::Lockdown))
+            }
+
+            occupancy::enter(Entrant::User(user.id), conn).await?;
+            return Ok(AccessOutcome::Granted(NoContent))
+        },
+        // The door is held open anyway.
+        EmergencyMode::Evacuation => return Ok(AccessOutcome::Granted(NoContent))
+    }
+
+    let aps = get_permitted_profiles(&perms, conn).await?;
+
+    let active_profile = match aps.into_iter().find(|prof| {
+        Some(prof.id) == active_id
+    }) {
+        Some(prof) => prof,
+        None => return Err(ApiError::BadRequest(ErrorCode::AccessDenied))
+    };
+
+    let settings = get_settings(&active_profile, conn).await?;
+
+    admit(Entrant::User(user.id), &settings, access.pin.as_deref(), swipe, pending, conn).await
+}
+
+/// The rules of the active profile every entry goes through, whether by card or by visitor pass: anti-passback,
+/// the occupancy limit and the entry rule. Lets the entrant in if they pass, along with the first of a pair.
+pub async fn admit<'a>(
+    entrant :Entrant,
+    settings :&AccessProfileSettings,
+    pin :Option<&str>,
+    swipe :&Swipe<'_>,
+    pending :&mut Option<PendingEntry>,
+    conn :&mut DbConnection<'a>
+) -> Result<AccessOutcome, ApiError> {
+    let swipes = swipe.swipes;
+    let inside = occupancy::is_inside(entrant, conn).await?;
+
+    if settings.anti_passback && inside {
+        return Err(ApiError::BadRequest(ErrorCode::AntiPassback))
+    }
+
+    if let Some(max_occupancy) = settings.max_occupancy {
+        if !inside && occupancy::count(conn).await? >= i64::from(max_occupancy) {
+            return Err(ApiError::BadRequest(ErrorCode::RoomFull))
+        }
+    }
+
+    match settings.entry_rule {
+        EntryRule::Single => {},
+        EntryRule::CardAndPin => {
+            // Visitors have no PIN, their pass is all they've got.
+            let user_id = match entrant {
+                Entrant::User(user_id) => user_id,
+                Entrant::Visitor(_) => return Err(ApiError::BadRequest(ErrorCode::PinRequired))
+            };
+
+            let pin = match pin {
+                Some(pin) => pin,
+                None => return Ok(AccessOutcome::pending("Enter your PIN."))
+            };
+
+            // Refused before the PIN is even looked at, so a locked out card can't go on guessing.
+            if swipes.pins.exhausted(&user_id).await {
+                return Err(ApiError::TooManyRequests(ErrorCode::TooManyWrongPins))
+            }
+
+            match get_pin(user_id, conn).await? {
+                Some(user_pin) => if !user_pin.matches(pin) {
+                    swipes.pins.hit(user_id).await;
+                    return Err(ApiError::BadRequest(ErrorCode::WrongPin))
+                },
+                None => return Err(ApiError::BadRequest(ErrorCode::PinRequired))
+            }
+        },
+        EntryRule::TwoPerson => {
+            let window = Duration::from_secs(settings.two_person_window as u64);
+
+            match pending.take() {
+                Some(first) if first.entrant != entrant
+                    && first.access_profile_id == settings.access_profile_id
+                    && first.swiped_at.elapsed() <= window => {
+                    let_in(first.entrant, conn).await?;
+                },
+                _ => {
+                    *pending = Some(PendingEntry {
+                        entrant,
+                        access_profile_id: settings.access_profile_id,
+                        swiped_at: Instant::now()
+                    });
+                    return Ok(AccessOutcome::pending("Waiting for a second person."))
+                }
+            }
+        }
+    }
+
+    let_in(entrant, conn).await?;
+
+    Ok(AccessOutcome::Granted(NoContent))
+}
+
+/// Records the entrant as inside, counting the use if they came with a visitor pass.
+async fn let_in<'a>(
+    entrant :Entrant,
+    conn :&mut DbConnection<'a>
+) -> Result<(), ApiError> {
+    if let Entrant::Visitor(pass_id) = entrant {
+        visitors::count_use(pass_id, conn).await?;
+    }
+
+    occupancy::enter(entrant, conn).await
+}
+
+/// Name of the user any of the codes belongs to, if anyone. See `codes::candidates`.
+pub async fn get_code_owner<'a>(
+    codes :&[String],
+    db :&mut DbConnection<'a>
+) -> Result<Option<String>, ApiError> {
+    let user_id :Option<i32> = match access_codes::table
+        .select(access_codes::columns::user)
+        .filter(access_codes::columns::code.eq_any(codes))
+    .first(db).await.optional() {
+        Ok(maybe_id) => maybe_id,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let user_id = match user_id {
+        Some(id) => id,
+        None => return Ok(None)
+    };
+
+    match users::table
+        .select(users::columns::name)
+        .filter(users::columns::id.eq(user_id))
+    .first(db).await.optional() {
+        Ok(maybe_name) => Ok(maybe_name),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Permissions of the user, whether assigned directly or through their groups, each once.
+pub async fn get_user_permissions<'a>(
+    user :&User,
+    db :&mut DbConnection<'a>
+) -> Result<Vec<Permission>, ApiError> {
+    let upwp :Vec<(UserPermission, Permission)> = match UserPermission::belonging_to(user)
         .inner_join(permissions::table)
         .select((UserPermission::as_select(), Permission::as_select()))
-    .load(&mut conn).await {
+    .load(db).await {
         Ok(uwpw) => uwpw,
         Err(e) => return Err(ApiError::Internal(format!("{}", e)))
     };
-    let perms :Vec<Permission> = upwp.into_iter().map(|v| { v.1 }).collect();
+    let mut perms :Vec<Permission> = upwp.into_iter().map(|v| { v.1 }).collect();
 
-    let apwp :Vec<(AccessProfilePermission, AccessProfile)> = match AccessProfilePermission::belonging_to(&perms)
+    let user_groups = groups::get_user_groups(user, db).await?;
+    for perm in groups::get_group_permissions(&user_groups, db).await? {
+        if !perms.iter().any(|p| p.id == perm.id) {
+            perms.push(perm);
+        }
+    }
+
+    Ok(perms)
+}
+
+/// Returns the access profiles during which the given permissions allow entering.
+pub async fn get_permitted_profiles<'a>(
+    perms :&[Permission],
+    db :&mut DbConnection<'a>
+) -> Result<Vec<AccessProfile>, ApiError> {
+    let apwp :Vec<(AccessProfilePermission, AccessProfile)> = match AccessProfilePermission::belonging_to(perms)
         .inner_join(access_profiles::table)
         .select((AccessProfilePermission::as_select(), AccessProfile::as_select()))
-    .load(&mut conn).await {
+    .load(db).await {
         Ok(apwp) => apwp,
         Err(e) => return Err(ApiError::Internal(format!("{}", e)))
     };
-    let aps :Vec<AccessProfile> = apwp.into_iter().map(|v| { v.1 }).collect();
 
-    if aps.iter().any(|prof| {
-        prof.name == apn
-    }) {
-        Ok(NoContent)
-    } else {
-        Err(ApiError::BadRequest(String::from("You don't have the permission to enter now.")))
-    }
-}
\ No newline at end of file
+    Ok(apwp.into_iter().map(|v| { v.1 }).collect())
+}
@@ -1,16 +1,23 @@
 pub mod permissions;
+pub mod settings;
 
 use cherrydoor_models::{models::{AccessProfile, Permission, AccessProfilePermission}, full::AccessProfileFull, schema::{self, access_profiles}, insert::AccessProfileInsert, update::AccessProfileUpdate};
 use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, OptionalExtension, BelongingToDsl, result};
-use diesel_async::RunQueryDsl;
-use rocket::{get, post, patch, delete, serde::json::Json, State, response::status::Created};
-use crate::{guards::auth::{Auth, OperatorUser}, db::get_connection};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rocket::{get, post, put, patch, delete, serde::json::Json, State, response::status::Created};
+use crate::{guards::{auth::{Auth, OperatorUser, Capable}, capabilities::{ProfilesWrite, PermissionsWrite}}, db::get_connection};
 
-use crate::{error::ApiError, db::{DB, DbConnection}};
+use crate::{error::{ApiError, ErrorCode, FieldError}, db::{DB, DbConnection, transaction, for_update, by_key}, etag::{Tagged, IfMatch}, validation::{Validate, Validated, check_name, check_text}};
+
+use super::active_access_profile::{ActiveAccessProfile, Rgb};
+
+/// The controller's display fits two lines of 16 characters and can't show anything beyond printable ASCII.
+const DISPLAY_TEXT_MAX_LEN :usize = 32;
 
 type Error = ApiError;
 type AccessProfilesResponse = Result<Json<Vec<AccessProfile>>, Error>;
 type AccessProfileResponse = Result<Json<AccessProfileFull>, Error>;
+type AccessProfileResponseTagged = Result<Tagged<AccessProfileFull>, Error>;
 type AccessProfileResponseCreated = Result<Created<Json<AccessProfileFull>>, Error>;
 
 #[get("/")]
@@ -37,92 +44,166 @@ pub async fn get<'a>(
 
     name :&'a str,
     db :&State<DB>
-) -> AccessProfileResponse {
+) -> AccessProfileResponseTagged {
     let mut conn = get_connection(db).await?;
 
     match get_full_access_profile(name, &mut conn).await {
-        Ok(access_profile) => Ok(Json(access_profile)),
+        Ok(access_profile) => Ok(Tagged(access_profile)),
         Err(e) => Err(e)
     }
 }
 
 #[post("/", format = "application/json", data = "<access_profile>")]
 pub async fn create(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<ProfilesWrite>>,
 
-    access_profile :Json<AccessProfileInsert>,
+    access_profile :Validated<AccessProfileInsert>,
     db :&State<DB>
 ) -> AccessProfileResponseCreated {
     let mut conn = get_connection(db).await?;
-    let name = access_profile.name.clone();
-
-    if let Err(e) = diesel::insert_into(access_profiles::table)
-        .values(access_profile.0)
-    .execute(&mut conn).await {
-        if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
-            return Err(ApiError::Conflict(format!("Access profile {} already exists.", &name)))
-        } else {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
-    };
 
-    match get_full_access_profile(&name, &mut conn).await {
-        Ok(access_profile) => Ok(Created::new(format!("{}", access_profile.access_profile.name)).body(Json(access_profile))),
-        Err(e) => Err(e)
-    }
+    transaction(&mut conn, |conn| async move {
+        let name = access_profile.name.clone();
+
+        if let Err(e) = diesel::insert_into(access_profiles::table)
+            .values(access_profile.0)
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::AccessProfileConflict(name.clone())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match get_full_access_profile(&name, conn).await {
+            Ok(access_profile) => Ok(Created::new(format!("{}", access_profile.access_profile.name)).body(Json(access_profile))),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
 }
 
 #[patch("/<name>", format = "application/json", data = "<access_profile>")]
 pub async fn update<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<ProfilesWrite>>,
+    if_match :IfMatch,
 
     name :&'a str,
-    access_profile :Json<AccessProfileUpdate>,
+    access_profile :Validated<AccessProfileUpdate>,
+    aacp :&State<ActiveAccessProfile>,
     db :&State<DB>
-) -> AccessProfileResponse {
+) -> AccessProfileResponseTagged {
     let mut conn = get_connection(db).await?;
-    let old_access_profile = get_access_profile(name, &mut conn).await?;
 
-    if let Err(e) = diesel::update(&old_access_profile)
-        .set(&access_profile.0)
-    .execute(&mut conn).await {
-        return Err(ApiError::Internal(format!("{}", e))) 
-    }
+    let (old_access_profile, new_access_profile) = transaction(&mut conn, |conn| async move {
+        let old_access_profile = lock_access_profile(name, conn).await?;
+        if_match.check(&get_full_access_profile(name, conn).await?)?;
 
-    match get_full_access_profile(name, &mut conn).await {
-        Ok(access_profile) => Ok(Json(access_profile)),
-        Err(e) => Err(e)
+        if let Err(e) = diesel::update(&old_access_profile)
+            .set(&access_profile.0)
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::AccessProfileConflict(access_profile.0.name.unwrap_or_default())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        }
+
+        match get_full_access_profile(&old_access_profile.id.to_string(), conn).await {
+            Ok(new_access_profile) => Ok((old_access_profile, new_access_profile)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await?;
+
+    // The door keeps showing the old color and text until the active profile is pushed again.
+    if aacp.is_active(old_access_profile.id).await {
+        if let Err(e) = aacp.set(new_access_profile.access_profile.clone()).await {
+            return Err(ApiError::CommandServer(format!("The profile was saved, but the door couldn't be updated: {}", e)))
+        }
     }
+
+    Ok(Tagged(new_access_profile))
 }
 
 #[delete("/<name>")]
 pub async fn delete<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<ProfilesWrite>>,
 
     name :&'a str,
+    aacp :&State<ActiveAccessProfile>,
     db :&State<DB>
 ) -> AccessProfileResponse {
     let mut conn = get_connection(db).await?;
-    let access_profile = match get_full_access_profile(name, &mut conn).await {
-        Ok(access_profile) => access_profile,
-        Err(e) => return Err(e)
-    };
 
-    let tasks = vec![
-        diesel::delete(schema::access_profiles_permissions::table)
-            .filter(schema::access_profiles_permissions::columns::access_profile_id.eq(&access_profile.access_profile.id))
-            .execute(&mut conn).await,
-        diesel::delete(&access_profile.access_profile)
-            .execute(&mut conn).await
-    ];
-
-    for i in tasks {
-        if let Err(e) = i {
-            return Err(ApiError::Internal(format!("{}", e)));
+    transaction(&mut conn, |conn| async move {
+        let access_profile = match get_full_access_profile(name, conn).await {
+            Ok(access_profile) => access_profile,
+            Err(e) => return Err(e)
+        };
+
+        if aacp.is_active(access_profile.access_profile.id).await {
+            return Err(ApiError::Conflict(ErrorCode::AccessProfileActive(access_profile.access_profile.name)))
         }
+
+        let tasks = vec![
+            diesel::delete(schema::access_profiles_permissions::table)
+                .filter(schema::access_profiles_permissions::columns::access_profile_id.eq(&access_profile.access_profile.id))
+                .execute(conn).await,
+            diesel::delete(crate::schema::access_profiles_settings::table)
+                .filter(crate::schema::access_profiles_settings::columns::access_profile_id.eq(&access_profile.access_profile.id))
+                .execute(conn).await,
+            diesel::delete(&access_profile.access_profile)
+                .execute(conn).await
+        ];
+
+        for i in tasks {
+            if let Err(e) = i {
+                return Err(ApiError::Internal(format!("{}", e)));
+            }
+        }
+
+        Ok(Json(access_profile))
+    }.scope_boxed()).await
+}
+
+fn check_color(field :&str, color :&str, errors :&mut Vec<FieldError>) {
+    if Rgb::parse(color).is_err() {
+        errors.push(FieldError::new(field, "Must be #rgb, #rrggbb or a color name."));
     }
+}
 
-    Ok(Json(access_profile))
+fn check_display_text(field :&str, text :&str, errors :&mut Vec<FieldError>) {
+    if text.chars().count() > DISPLAY_TEXT_MAX_LEN {
+        errors.push(FieldError::new(field, &format!("Can't be longer than {} characters.", DISPLAY_TEXT_MAX_LEN)));
+    }
+    if !text.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+        errors.push(FieldError::new(field, "Can only contain printable ASCII characters."));
+    }
+}
+
+impl Validate for AccessProfileInsert {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_name("name", &self.name, errors);
+        check_text("description", &self.description, errors);
+        check_color("color", &self.color, errors);
+        check_display_text("display_text", &self.display_text, errors);
+    }
+}
+
+impl Validate for AccessProfileUpdate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        if let Some(name) = &self.name {
+            check_name("name", name, errors);
+        }
+        if let Some(description) = &self.description {
+            check_text("description", description, errors);
+        }
+        if let Some(color) = &self.color {
+            check_color("color", color, errors);
+        }
+        if let Some(display_text) = &self.display_text {
+            check_display_text("display_text", display_text, errors);
+        }
+    }
 }
 
 async fn get_full_access_profile<'a, 'v>(
@@ -142,20 +223,33 @@ async fn get_access_profile<'a,'v>(
     name :&'v str,
     db :&mut DbConnection<'a>
 ) -> Result<AccessProfile, Error> {
-    match schema::access_profiles::table
-        .select(AccessProfile::as_select())
-        .filter(schema::access_profiles::columns::name.eq(name))
-    .first(db).await.optional() {
+    match by_key!(schema::access_profiles::table.select(AccessProfile::as_select()), schema::access_profiles::columns::id, schema::access_profiles::columns::name, name, db) {
         Ok(maybe_profile) => {
             match maybe_profile {
                 Some(profile) => Ok(profile),
-                None => Err(ApiError::NotFound(format!("Access profile {} not found.", name)))
+                None => Err(ApiError::NotFound(ErrorCode::AccessProfileNotFound(name.to_string())))
             }
         },
         Err(e) => Err(ApiError::Internal(format!("/access-profiles/{}", e)))
     }
 }
 
+/// Like `get_access_profile`, but the profile stays locked until the transaction ends.
+async fn lock_access_profile<'a,'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<AccessProfile, Error> {
+    match by_key!(for_update!(schema::access_profiles::table.select(AccessProfile::as_select())), schema::access_profiles::columns::id, schema::access_profiles::columns::name, name, db) {
+        Ok(maybe_profile) => {
+            match maybe_profile {
+                Some(profile) => Ok(profile),
+                None => Err(ApiError::NotFound(ErrorCode::AccessProfileNotFound(name.to_string())))
+            }
+        },
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
 async fn get_all_permissions<'a>(
     access_profile :&AccessProfile,
     db :&mut DbConnection<'a>
@@ -2,14 +2,18 @@ use cherrydoor_models::{insert::AccessProfilePermissionInsert, schema::access_pr
 use serde::Deserialize;
 
 use super::*;
+use crate::batch::{BatchChange, Batched, IdOrName, Relation};
 
 type PermissionsResponse = Result<Json<Vec<Permission>>, Error>;
+type AccessProfileBatchResponse = Result<Json<Batched<AccessProfileFull>>, Error>;
 
 #[derive(Deserialize)]
 pub struct AccessProfilePermissionAppend {
     permission_id :i32
 }
 
+impl Validate for AccessProfilePermissionAppend {}
+
 impl AccessProfilePermissionAppend {
     pub fn into_insert(self, access_profile_id :i32) -> AccessProfilePermissionInsert {
         AccessProfilePermissionInsert { 
@@ -42,56 +46,104 @@ pub async fn list<'a>(
 
 #[post("/<name>/permissions", format = "application/json", data = "<permission>")]
 pub async fn assign<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
 
     name :&'a str,
-    permission :Json<AccessProfilePermissionAppend>,
+    permission :Validated<AccessProfilePermissionAppend>,
     db :&State<DB>,
 ) -> AccessProfileResponse {
     let mut conn = get_connection(db).await?;
-    let access_profile = get_access_profile(name, &mut conn).await?;
 
-    if let Err(e) = diesel::insert_into(access_profiles_permissions::table)
-        .values(permission.0.into_insert(access_profile.id))
-    .execute(&mut conn).await {
-        if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
-            return Err(ApiError::Conflict(format!("The permission is already assigned to profile {}.", &name)))
-        } else {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
-    };
+    transaction(&mut conn, |conn| async move {
+        let access_profile = get_access_profile(name, conn).await?;
 
-    match get_full_access_profile(name, &mut conn).await {
-        Ok(access_profile) => Ok(Json(access_profile)),
-        Err(e) => Err(e)
-    }
+        if let Err(e) = diesel::insert_into(access_profiles_permissions::table)
+            .values(permission.0.into_insert(access_profile.id))
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::AccessProfilePermissionConflict(name.to_string())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match get_full_access_profile(name, conn).await {
+            Ok(access_profile) => Ok(Json(access_profile)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
 }
 
 #[delete("/<name>/permissions/<id>")]
 pub async fn remove<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
 
     name :&'a str,
     id :i32,
     db :&State<DB>
 ) -> AccessProfileResponse {
     let mut conn = get_connection(db).await?;
-    let access_profile = get_access_profile(name, &mut conn).await?;
 
-    match diesel::delete(access_profiles_permissions::table)
-        .filter(access_profiles_permissions::columns::permission_id.eq(id))
-        .filter(access_profiles_permissions::columns::access_profile_id.eq(access_profile.id))
-    .execute(&mut conn).await {
-        Ok(del_count) => {
-            if del_count == 0 {
-                return Err(ApiError::NotFound(format!("Permission of id {} either does not exist or is not associated with profile {}.", id, name)))
+    transaction(&mut conn, |conn| async move {
+        let access_profile = get_access_profile(name, conn).await?;
+
+        match diesel::delete(access_profiles_permissions::table)
+            .filter(access_profiles_permissions::columns::permission_id.eq(id))
+            .filter(access_profiles_permissions::columns::access_profile_id.eq(access_profile.id))
+        .execute(conn).await {
+            Ok(del_count) => {
+                if del_count == 0 {
+                    return Err(ApiError::NotFound(ErrorCode::AccessProfilePermissionNotFound { id, access_profile: name.to_string() }))
+                }
             }
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        match get_full_access_profile(name, conn).await {
+            Ok(access_profile) => Ok(Json(access_profile)),
+            Err(e) => Err(e)
         }
-        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
-    };
+    }.scope_boxed()).await
+}
 
-    match get_full_access_profile(name, &mut conn).await {
-        Ok(access_profile) => Ok(Json(access_profile)),
-        Err(e) => Err(e)
-    }
-}
\ No newline at end of file
+#[post("/<name>/permissions/batch", format = "application/json", data = "<change>")]
+pub async fn batch<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    change :Validated<BatchChange>,
+    db :&State<DB>
+) -> AccessProfileBatchResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let access_profile = lock_access_profile(name, conn).await?;
+        let changes = Relation::AccessProfilePermissions(access_profile.id).change(change.0, conn).await?;
+
+        match get_full_access_profile(name, conn).await {
+            Ok(access_profile) => Ok(Json(Batched { resource: access_profile, changes })),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[put("/<name>/permissions", format = "application/json", data = "<permissions>")]
+pub async fn replace<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    permissions :Validated<Vec<IdOrName>>,
+    db :&State<DB>
+) -> AccessProfileBatchResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let access_profile = lock_access_profile(name, conn).await?;
+        let changes = Relation::AccessProfilePermissions(access_profile.id).replace(permissions.0, conn).await?;
+
+        match get_full_access_profile(name, conn).await {
+            Ok(access_profile) => Ok(Json(Batched { resource: access_profile, changes })),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
@@ -0,0 +1,96 @@
+use crate::{models::{AccessProfileSettings, AccessProfileSettingsUpdate}, schema::access_profiles_settings};
+
+use super::*;
+
+type AccessProfileSettingsResponse = Result<Tagged<AccessProfileSettings>, Error>;
+
+impl Validate for AccessProfileSettingsUpdate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        if let Some(Some(max_occupancy)) = self.max_occupancy {
+            if max_occupancy < 1 {
+                errors.push(FieldError::new("max_occupancy", "Must be at least 1."));
+            }
+        }
+        if let Some(two_person_window) = self.two_person_window {
+            if two_person_window < 1 {
+                errors.push(FieldError::new("two_person_window", "Must be at least 1 second."));
+            }
+        }
+    }
+}
+
+#[get("/<name>/settings")]
+pub async fn get<'a>(
+    _auth :Auth<OperatorUser>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> AccessProfileSettingsResponse {
+    let mut conn = get_connection(db).await?;
+    let access_profile = get_access_profile(name, &mut conn).await?;
+
+    match get_settings(&access_profile, &mut conn).await {
+        Ok(settings) => Ok(Tagged(settings)),
+        Err(e) => Err(e)
+    }
+}
+
+#[patch("/<name>/settings", format = "application/json", data = "<settings>")]
+pub async fn update<'a>(
+    _auth :Auth<Capable<ProfilesWrite>>,
+    if_match :IfMatch,
+
+    name :&'a str,
+    settings :Validated<AccessProfileSettingsUpdate>,
+    db :&State<DB>
+) -> AccessProfileSettingsResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        // The settings row may not exist yet, so the profile is what's locked.
+        let access_profile = lock_access_profile(name, conn).await?;
+
+        let old_settings :Option<AccessProfileSettings> = match access_profiles_settings::table
+            .select(AccessProfileSettings::as_select())
+            .filter(access_profiles_settings::columns::access_profile_id.eq(access_profile.id))
+        .first(conn).await.optional() {
+            Ok(maybe_settings) => maybe_settings,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+        let exists = old_settings.is_some();
+
+        let mut new_settings = old_settings.unwrap_or(AccessProfileSettings::default_for(access_profile.id));
+        if_match.check(&new_settings)?;
+        new_settings.apply(settings.0);
+
+        let res = if exists {
+            diesel::update(&new_settings)
+                .set(new_settings.clone())
+            .execute(conn).await
+        } else {
+            diesel::insert_into(access_profiles_settings::table)
+                .values(new_settings.clone())
+            .execute(conn).await
+        };
+
+        if let Err(e) = res {
+            return Err(ApiError::Internal(format!("{}", e)))
+        }
+
+        Ok(Tagged(new_settings))
+    }.scope_boxed()).await
+}
+
+/// Returns the settings of the access profile, or the defaults if none were saved.
+pub async fn get_settings<'a>(
+    access_profile :&AccessProfile,
+    db :&mut DbConnection<'a>
+) -> Result<AccessProfileSettings, Error> {
+    match access_profiles_settings::table
+        .select(AccessProfileSettings::as_select())
+        .filter(access_profiles_settings::columns::access_profile_id.eq(access_profile.id))
+    .first(db).await.optional() {
+        Ok(maybe_settings) => Ok(maybe_settings.unwrap_or(AccessProfileSettings::default_for(access_profile.id))),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
@@ -1,115 +1,268 @@
-use std::{sync::Arc, fmt::Display};
+use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, fmt::Display};
 use async_mutex::Mutex;
 use cherrydoor_command::Command;
 use cherrydoor_models::{schema::{self, AccessProfileAccessMode}, models::AccessProfile};
 use diesel::{QueryDsl, SelectableHelper, OptionalExtension, ExpressionMethods};
-use diesel_async::RunQueryDsl;
-use reqwest::StatusCode;
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
 use rocket::{serde::json::Json, get, post, response::status::NoContent, State};
 use std::error::Error;
 use serde::{Serialize, Deserialize};
 
-use crate::{db::{DB, get_connection}, guards::auth::{Auth, OperatorUser}, error::ApiError};
+use crate::{db::{DB, DbConnection, get_connection, transaction, by_key}, metrics::Metrics, events::{EventBus, Event}, door::Door, guards::{auth::{Auth, OperatorUser, Capable}, capabilities::ProfilesActivate}, error::{ApiError, ErrorCode, FieldError}, validation::{Validate, Validated, check_not_empty}, models::{EmergencyMode, EmergencyState, ActiveProfileState}, schema::{emergency_state, active_access_profile}};
 
-#[derive(Debug)]
-struct CommandResponseError {
-    message :String
-}
-
-impl Error for CommandResponseError {}
-
-impl Display for CommandResponseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
-    }
-}
+use super::emergency::get_state;
 
+#[derive(Clone)]
 pub struct ActiveAccessProfile {
-    active_profile_name :Arc<Mutex<String>>,
-    command_addr :String
+    active_profile :Arc<Mutex<Option<AccessProfile>>>,     // Compared by id, the name may change
+    emergency_mode :Arc<Mutex<EmergencyMode>>,
+    pushing :Arc<Mutex<()>>,      // Held while commands go to the door, so they arrive in the order the changes were made
+    in_sync :Arc<AtomicBool>,     // Whether the last command reached the door
+    controller :Door,
+    bus :EventBus
 }
 
 impl ActiveAccessProfile {
-    pub async fn new(db :&DB, command_addr :String) -> Self {
-        let mut conn = get_connection(db).await.unwrap();
+    /// Nothing is pushed to the door until `sync` is called, see `crate::setup`.
+    pub fn new(controller :Door, bus :EventBus) -> Self {
+        Self {
+            active_profile :Arc::new(Mutex::new(None)),
+            emergency_mode :Arc::new(Mutex::new(EmergencyMode::Normal)),
+            pushing :Arc::new(Mutex::new(())),
+            in_sync :Arc::new(AtomicBool::new(false)),
+            controller,
+            bus
+        }
+    }
 
-        let profile = schema::access_profiles::table
-            .select(AccessProfile::as_select())
-        .first(&mut conn).await.unwrap();
+    /// Loads the saved profile, or the first one if none was activated yet, and the emergency state
+    /// from the database, and pushes them to the door.
+    pub async fn sync(&self, db :&DB) -> Result<(), Box<dyn Error>> {
+        let mut conn = get_connection(db).await?;
 
-        let self_prototype = Self {
-            active_profile_name :Arc::new(Mutex::new(String::from(""))),
-            command_addr
+        let profile = match get_saved_profile(&mut conn).await? {
+            Some(profile) => profile,
+            None => schema::access_profiles::table
+                .select(AccessProfile::as_select())
+            .first(&mut conn).await?
         };
 
-        self_prototype.set(profile).await.unwrap();
+        let emergency :Option<EmergencyState> = emergency_state::table
+            .select(EmergencyState::as_select())
+        .first(&mut conn).await.optional()?;
+
+        self.set(profile).await?;
+
+        if let Some(emergency) = emergency {
+            self.set_emergency(emergency.mode).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Picks up a profile activated or renamed, or an emergency declared or lifted, by another process, e.g. the CLI.
+    /// That process has already pushed it to the door.
+    pub async fn refresh<'a>(&self, db :&mut DbConnection<'a>) -> Result<(), ApiError> {
+        if let Some(emergency) = get_state(db).await? {
+            let mut mode = self.emergency_mode.lock().await;
+
+            if *mode != emergency.mode {
+                tracing::info!(mode = ?emergency.mode, "emergency mode changed elsewhere");
+                self.bus.emit(Event::EmergencyChanged { mode: emergency.mode, by: emergency.changed_by });
+                *mode = emergency.mode;
+            }
+        }
+
+        if let Some(profile) = get_saved_profile(db).await? {
+            let mut active = self.active_profile.lock().await;
+
+            if active.as_ref().map(|active| active.id) != Some(profile.id) {
+                tracing::info!(profile = %profile.name, "active access profile changed elsewhere");
+
+                // Not on the first refresh after start, nothing changed then.
+                if active.is_some() {
+                    self.bus.emit(Event::ActiveProfileChanged { id: profile.id, name: profile.name.clone() });
+                }
+            }
+            *active = Some(profile);
+        }
+
+        Ok(())
+    }
+
+    /// Makes the profile active, saves it for the next start and pushes it to the door. It's given by id or name.
+    pub async fn activate<'a>(&self, name :&str, db :&mut DbConnection<'a>) -> Result<(), ApiError> {
+        let name = name.to_string();
+
+        let ap = transaction(db, |conn| async move {
+            // The saved state, not this process' copy, so another process in an emergency is respected as well.
+            if let Some(emergency) = get_state(conn).await? {
+                if emergency.mode != EmergencyMode::Normal {
+                    return Err(ApiError::Conflict(ErrorCode::EmergencyActive))
+                }
+            }
+
+            let ap :AccessProfile = match by_key!(schema::access_profiles::table.select(AccessProfile::as_select()), schema::access_profiles::columns::id, schema::access_profiles::columns::name, &name, conn) {
+                Ok(maybe_ap) => {
+                    match maybe_ap {
+                        Some(ap) => ap,
+                        None => return Err(ApiError::NotFound(ErrorCode::AccessProfileNotFound(name)))
+                    }
+                },
+                Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+            };
+
+            let state = ActiveProfileState {
+                id: ActiveProfileState::ID,
+                access_profile_id: ap.id
+            };
+
+            // Saved before talking to the door, like the emergency state.
+            let saved = match active_access_profile::table
+                .count()
+                .filter(active_access_profile::columns::id.eq(ActiveProfileState::ID))
+            .get_result::<i64>(conn).await {
+                Ok(count) => count > 0,
+                Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+            };
+
+            let res = if saved {
+                diesel::update(&state)
+                    .set(state.clone())
+                .execute(conn).await
+            } else {
+                diesel::insert_into(active_access_profile::table)
+                    .values(state.clone())
+                .execute(conn).await
+            };
+
+            if let Err(e) = res {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+
+            Ok(ap)
+        }.scope_boxed()).await?;
+
+        self.bus.emit(Event::ActiveProfileChanged { id: ap.id, name: ap.name.clone() });
+
+        match self.set(ap).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ApiError::CommandServer(format!("{}", e)))
+        }
+    }
 
-        self_prototype
+    /// Whether the last command sent to the door went through, i.e. the door shows the active profile.
+    pub fn is_in_sync(&self) -> bool {
+        AtomicBool::load(&self.in_sync, Ordering::Relaxed)
     }
 
+    /// Pushes the profile to the door and makes it the active one, even if the door couldn't be reached.
     pub async fn set(&self, access_profile :AccessProfile) -> Result<(), Box<dyn Error>> {
-        let mut s = self.active_profile_name.lock().await;
-        *s = access_profile.name;
+        // Access checks read the active profile, they shouldn't wait for the door.
+        let _pushing = self.pushing.lock().await;
+        // Kept as a string, the error can't be held across the await below.
+        let error = match self.push(&access_profile).await {
+            Ok(()) => None,
+            Err(e) => Some(e.to_string())
+        };
+        *self.active_profile.lock().await = Some(access_profile);
+
+        match error {
+            None => Ok(()),
+            Some(e) => Err(e.into())
+        }
+    }
+
+    async fn push(&self, access_profile :&AccessProfile) -> Result<(), Box<dyn Error>> {
+        if self.emergency().await != EmergencyMode::Normal {
+            // The profile will be pushed once the emergency is over.
+            return Ok(())
+        }
 
-        let color = Rgb::from_hex_string(&access_profile.color)?;
+        let color = Rgb::parse(&access_profile.color)?;
 
         let mut command = Command::new()
-            .display_text(access_profile.display_text)
+            .display_text(access_profile.display_text.clone())
             .set_color(color.r, color.g, color.b);
 
         if access_profile.access_mode == AccessProfileAccessMode::OpenLock {
             command = command.open()
         }
 
-        let client = reqwest::Client::new();
-
         // Close the door
-        let close_res = client.post(format!("{}/", self.command_addr))
-            .json(&Command::new().close())
-            .send()
-        .await?;
-    
-        match close_res.status() {
-                StatusCode::NO_CONTENT => {},
-                _ => {
-                    return Err(Box::new(CommandResponseError {
-                        message: format!("Command server returned {}", close_res.text().await.unwrap_or("garbage".to_string()))
-                    }))
-                }
-        };
+        self.send(&Command::new().close()).await?;
 
         // Execute the actual set command
-        let res = client.post(format!("{}/", self.command_addr))
-            .json(&command)
-            .send()
-        .await?;
-    
-        match res.status() {
-            StatusCode::NO_CONTENT => {},
-            _ => {
-                return Err(Box::new(CommandResponseError {
-                    message: format!("Command server returned {}", res.text().await.unwrap_or("garbage".to_string()))
-                }))
+        self.send(&command).await
+    }
+
+    /// Switches the door to an emergency mode. Switching back to `EmergencyMode::Normal` only lifts the override,
+    /// the caller has to push the active profile again with `set`.
+    pub async fn set_emergency(&self, mode :EmergencyMode) -> Result<(), Box<dyn Error>> {
+        let _pushing = self.pushing.lock().await;
+        *self.emergency_mode.lock().await = mode;
+
+        let command = match mode {
+            EmergencyMode::Normal => return Ok(()),
+            EmergencyMode::Lockdown => Command::new()
+                .close()
+                .display_text(String::from("BLOKADA"))
+                .set_color(255, 0, 0)
+                .play_sound(2),      // Alarm
+            EmergencyMode::Evacuation => Command::new()
+                .open()
+                .display_text(String::from("EWAKUACJA"))
+                .set_color(0, 255, 0)
+                .play_sound(3)       // Evacuation signal
+        };
+
+        self.send(&command).await
+    }
+
+    pub async fn emergency(&self) -> EmergencyMode {
+        *self.emergency_mode.lock().await
+    }
+
+    async fn send(&self, command :&Command) -> Result<(), Box<dyn Error>> {
+        match self.controller.send("set", command).await {
+            Ok(()) => {
+                self.in_sync.store(true, Ordering::Relaxed);
+                Ok(())
+            },
+            Err(e) => {
+                self.went_offline(e.to_string());
+                Err(Box::new(e))
             }
         }
-        
-        Ok(())
     }
 
-    pub async fn get(&self) -> String {
-        let v = self.active_profile_name.lock().await;
+    /// Only the first failure after a command went through is reported.
+    fn went_offline(&self, error :String) {
+        if self.in_sync.swap(false, Ordering::Relaxed) {
+            self.bus.emit(Event::ControllerOffline { error });
+        }
+    }
+
+    /// The active profile as of the last `set` or `refresh`, `None` before the first `sync`.
+    pub async fn get(&self) -> Option<AccessProfile> {
+        let v = self.active_profile.lock().await;
         v.clone()
     }
+
+    /// Whether the profile with this id is the active one.
+    pub async fn is_active(&self, id :i32) -> bool {
+        self.active_profile.lock().await.as_ref().is_some_and(|active| active.id == id)
+    }
 }
 
-struct Rgb {
+pub struct Rgb {
     pub r :u8,
     pub g :u8,
     pub b :u8
 }
 
 #[derive(Debug, Clone)]
-struct RgbParseError;
+pub struct RgbParseError;
 
 impl std::error::Error for RgbParseError {}
 
@@ -119,12 +272,39 @@ impl Display for RgbParseError {
     }
 }
 
+/// Colors that can be given by name instead of a hex string.
+const NAMED_COLORS :[(&str, Rgb); 10] = [
+    ("black", Rgb {r: 0, g: 0, b: 0}),
+    ("white", Rgb {r: 255, g: 255, b: 255}),
+    ("red", Rgb {r: 255, g: 0, b: 0}),
+    ("green", Rgb {r: 0, g: 255, b: 0}),
+    ("blue", Rgb {r: 0, g: 0, b: 255}),
+    ("yellow", Rgb {r: 255, g: 255, b: 0}),
+    ("cyan", Rgb {r: 0, g: 255, b: 255}),
+    ("magenta", Rgb {r: 255, g: 0, b: 255}),
+    ("orange", Rgb {r: 255, g: 165, b: 0}),
+    ("purple", Rgb {r: 128, g: 0, b: 128})
+];
+
 impl Rgb {
     fn parse_one(part :&str) -> Result<u8, RgbParseError> {
         u8::from_str_radix(part, 16).or(Err(RgbParseError))
     }
 
+    /// Parses `#rrggbb`, `#rgb` or one of the named colors.
+    pub fn parse(string :&str) -> Result<Self, RgbParseError> {
+        match NAMED_COLORS.iter().find(|(name, _)| name.eq_ignore_ascii_case(string)) {
+            Some((_, color)) => Ok(Rgb {r: color.r, g: color.g, b: color.b}),
+            None => Rgb::from_hex_string(string)
+        }
+    }
+
     pub fn from_hex_string(string :&str) -> Result<Self, RgbParseError> {
+        // Also keeps the slicing below from splitting a multi-byte character.
+        if !string.starts_with('#') || !string[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(RgbParseError)
+        }
+
         if string.len() == 7 {
             let r = Rgb::parse_one(&string[1..3])?;
             let g = Rgb::parse_one(&string[3..5])?;
@@ -133,9 +313,9 @@ impl Rgb {
             Ok(Rgb {r, g, b})          
         }
         else if string.len() == 4 {
-            let r = Rgb::parse_one(&string[1..3])?;
-            let g = Rgb::parse_one(&string[3..5])?;
-            let b = Rgb::parse_one(&string[5..7])?;
+            let r = Rgb::parse_one(&string[1..2])?;
+            let g = Rgb::parse_one(&string[2..3])?;
+            let b = Rgb::parse_one(&string[3..4])?;
 
             Ok(Rgb {r: r + 16 * r, g: g + 16 * g, b: b + 16 * b})
         }
@@ -145,47 +325,79 @@ impl Rgb {
     }
 }
 
-
-#[derive(Serialize, Deserialize)]
+#[derive(Deserialize)]
 pub struct ActiveAccessProfileModel {
+    name :String      // A number is taken as the id
+}
+
+#[derive(Serialize)]
+pub struct ActiveAccessProfileInfo {
+    id :i32,
     name :String
 }
 
+impl From<AccessProfile> for ActiveAccessProfileInfo {
+    fn from(profile :AccessProfile) -> Self {
+        Self { id: profile.id, name: profile.name }
+    }
+}
+
+impl Validate for ActiveAccessProfileModel {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_not_empty("name", &self.name, errors);
+    }
+}
+
 #[post("/", format = "application/json", data = "<data>")]
 pub async fn set(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<ProfilesActivate>>,
     db :&State<DB>,
     aacp :&State<ActiveAccessProfile>,
 
-    data :Json<ActiveAccessProfileModel> 
+    data :Validated<ActiveAccessProfileModel>,
+    metrics :&State<Metrics>
 ) -> Result<NoContent, ApiError> {
     let mut conn = get_connection(db).await?;
 
-    let ap :AccessProfile = match schema::access_profiles::table
-        .select(AccessProfile::as_select())
-        .filter(schema::access_profiles::columns::name.eq(&data.0.name))
-    .first(&mut conn).await.optional() {
-        Ok(maybe_ap) => {
-            match maybe_ap {
-                Some(ap) => ap,
-                None => return Err(ApiError::NotFound(format!("Access profile {} not found.", &data.0.name)))
-            }
-        },
-        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
-    };
+    aacp.activate(&data.name, &mut conn).await?;
+    metrics.profile_changes.inc();
 
-    match aacp.set(ap).await {
-        Ok(_) => Ok(NoContent),
-        Err(e) => Err(ApiError::Internal(format!("{}", e)))
-    }
+    Ok(NoContent)
 }
 
 #[get("/")]
 pub async fn get(
     _auth :Auth<OperatorUser>,
-    aacp :&State<ActiveAccessProfile>
-) -> Json<ActiveAccessProfileModel> {
-    Json(ActiveAccessProfileModel {
-        name: aacp.get().await
-    })
+    aacp :&State<ActiveAccessProfile>,
+    db :&State<DB>
+) -> Result<Json<Option<ActiveAccessProfileInfo>>, ApiError> {
+    let mut conn = get_connection(db).await?;
+    aacp.refresh(&mut conn).await?;
+
+    Ok(Json(aacp.get().await.map(ActiveAccessProfileInfo::from)))
+}
+
+async fn get_saved_profile<'a>(
+    db :&mut DbConnection<'a>
+) -> Result<Option<AccessProfile>, ApiError> {
+    let state :Option<ActiveProfileState> = match active_access_profile::table
+        .select(ActiveProfileState::as_select())
+        .filter(active_access_profile::columns::id.eq(ActiveProfileState::ID))
+    .first(db).await.optional() {
+        Ok(maybe_state) => maybe_state,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let state = match state {
+        Some(state) => state,
+        None => return Ok(None)
+    };
+
+    match schema::access_profiles::table
+        .select(AccessProfile::as_select())
+        .filter(schema::access_profiles::columns::id.eq(state.access_profile_id))
+    .first(db).await.optional() {
+        Ok(maybe_ap) => Ok(maybe_ap),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
 }
\ No newline at end of file
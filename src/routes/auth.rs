@@ -4,7 +4,9 @@ use diesel_async::RunQueryDsl;
 use rocket::{post, State, serde::json::Json};
 use serde::{Deserialize, Serialize};
 
-use crate::{db::{DB, get_connection}, error::ApiError, guards::auth::{SecretKeyWrapper, WebUIUserAuthorization}};
+use std::net::IpAddr;
+
+use crate::{db::{DB, get_connection}, metrics::Metrics, config::{Config, TokensConfig}, rate_limit::LoginRateLimiter, error::{ApiError, ErrorCode}, validation::{Validate, Validated}, guards::auth::{SecretKeyWrapper, WebUIUserAuthorization, load_capabilities}};
 
 #[derive(Deserialize)]
 pub struct WebUIUserLogin {
@@ -12,6 +14,8 @@ pub struct WebUIUserLogin {
     password :String
 }
 
+impl Validate for WebUIUserLogin {}
+
 #[derive(Serialize)]
 pub struct WebUIUserToken {
     token :String
@@ -22,38 +26,78 @@ type AuthResponse = Result<Json<WebUIUserToken>, Error>;
 
 #[post("/", format = "application/json", data = "<auth>")]
 pub async fn authenticate(
-    auth :Json<WebUIUserLogin>,
+    auth :Validated<WebUIUserLogin>,
     db :&State<DB>,
-    secret :&State<SecretKeyWrapper>
+    secret :&State<SecretKeyWrapper>,
+    metrics :&State<Metrics>,
+    config :&State<Config>,
+    limiter :&State<LoginRateLimiter>,
+    client_ip :Option<IpAddr>
+) -> AuthResponse {
+    let allowed = match client_ip {
+        Some(ip) => limiter.0.hit(ip).await,
+        None => true
+    };
+
+    let result = if allowed {
+        login(auth.0, db, secret, &config.tokens).await
+    } else {
+        Err(ApiError::TooManyRequests(ErrorCode::TooManyLoginAttempts))
+    };
+
+    let outcome = match &result {
+        Ok(_) => "success",
+        Err(e) => e.code()
+    };
+    metrics.logins.with_label_values(&[outcome]).inc();
+
+    result
+}
+
+async fn login(
+    auth :WebUIUserLogin,
+    db :&DB,
+    secret :&SecretKeyWrapper,
+    tokens :&TokensConfig
 ) -> AuthResponse {
     let mut conn = get_connection(db).await?;
 
     let user :WebUIUser = match web_ui_users::table
         .select(WebUIUser::as_select())
-        .filter(web_ui_users::columns::name.eq(&auth.0.name))
+        .filter(web_ui_users::columns::name.eq(&auth.name))
     .first(&mut conn).await.optional() {
         Ok(maybe_user) => {
             match maybe_user {
                 Some(user) => user,
-                None => return  Err(ApiError::NotFound(format!("User {} not found.", &auth.0.name)))
+                None => return  Err(ApiError::NotFound(ErrorCode::WebUIUserNotFound(auth.name.clone())))
             }
         },
         Err(e) => return Err(ApiError::Internal(format!("{}", e)))
     };
 
-    let password_hash = sha256::digest(auth.0.password);
+    let password_hash = sha256::digest(auth.password);
 
     if password_hash != user.password_hash {
-        return Err(ApiError::Forbidden("Bad password.".to_string()))
+        return Err(ApiError::Forbidden(ErrorCode::BadPassword))
     }
 
+    // Capabilities are resolved again on every request, these only let the web UI know what to show.
+    let capabilities = match load_capabilities(user.id, &mut conn).await {
+        Ok(capabilities) => capabilities,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let now = jsonwebtoken::get_current_timestamp();
     let auth = WebUIUserAuthorization {
         exp: if !user.ac_does_not_expire {
-            jsonwebtoken::get_current_timestamp() + 3600
-        } else { u64::MAX },
+            now + tokens.lifetime
+        } else {
+            tokens.non_expiring_lifetime.map(|lifetime| now + lifetime).unwrap_or(u64::MAX)
+        },
         name: user.name,
         is_admin: user.is_admin,
-        ac_does_not_expire: user.ac_does_not_expire
+        ac_does_not_expire: user.ac_does_not_expire,
+        capabilities
     };
 
     let token = match jsonwebtoken::encode(
@@ -0,0 +1,151 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, OptionalExtension};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rocket::{get, post, delete, serde::json::Json, State};
+use serde::Serialize;
+
+use crate::{
+    db::{DB, DbConnection, get_connection, transaction}, error::ApiError, guards::auth::{Auth, AdminUser, OperatorUser},
+    models::{EmergencyMode, EmergencyState}, schema::emergency_state, events::{EventBus, Event}
+};
+
+use super::active_access_profile::ActiveAccessProfile;
+
+/// Users holding this permission may still enter during a lockdown.
+pub const BREAK_GLASS_PERMISSION :&str = "break-glass";
+
+#[derive(Serialize)]
+pub struct EmergencyStatus {
+    mode :EmergencyMode,
+    changed_by :Option<String>,
+    changed_at :Option<NaiveDateTime>
+}
+
+impl From<EmergencyState> for EmergencyStatus {
+    fn from(state :EmergencyState) -> Self {
+        Self {
+            mode: state.mode,
+            changed_by: Some(state.changed_by),
+            changed_at: Some(state.changed_at)
+        }
+    }
+}
+
+type Error = ApiError;
+type EmergencyResponse = Result<Json<EmergencyStatus>, Error>;
+
+#[get("/")]
+pub async fn get(
+    _auth :Auth<OperatorUser>,
+
+    db :&State<DB>
+) -> EmergencyResponse {
+    let mut conn = get_connection(db).await?;
+
+    match get_state(&mut conn).await? {
+        Some(state) => Ok(Json(state.into())),
+        None => Ok(Json(EmergencyStatus {
+            mode: EmergencyMode::Normal,
+            changed_by: None,
+            changed_at: None
+        }))
+    }
+}
+
+#[post("/lockdown")]
+pub async fn lockdown(
+    auth :Auth<AdminUser>,
+
+    aacp :&State<ActiveAccessProfile>,
+    bus :&State<EventBus>,
+    db :&State<DB>
+) -> EmergencyResponse {
+    change_mode(EmergencyMode::Lockdown, auth.claim.name, aacp, bus, db).await
+}
+
+#[post("/evacuate")]
+pub async fn evacuate(
+    auth :Auth<AdminUser>,
+
+    aacp :&State<ActiveAccessProfile>,
+    bus :&State<EventBus>,
+    db :&State<DB>
+) -> EmergencyResponse {
+    change_mode(EmergencyMode::Evacuation, auth.claim.name, aacp, bus, db).await
+}
+
+#[delete("/")]
+pub async fn clear(
+    auth :Auth<AdminUser>,
+
+    aacp :&State<ActiveAccessProfile>,
+    bus :&State<EventBus>,
+    db :&State<DB>
+) -> EmergencyResponse {
+    change_mode(EmergencyMode::Normal, auth.claim.name, aacp, bus, db).await
+}
+
+async fn change_mode(
+    mode :EmergencyMode,
+    by :String,
+    aacp :&ActiveAccessProfile,
+    bus :&EventBus,
+    db :&DB
+) -> EmergencyResponse {
+    let state = EmergencyState {
+        id: EmergencyState::ID,
+        mode,
+        changed_by: by.clone(),
+        changed_at: Utc::now().naive_utc()
+    };
+
+    let mut conn = get_connection(db).await?;
+
+    // The state is saved before talking to the door, so the mode is enforced even if the controller is unreachable.
+    let state = transaction(&mut conn, |conn| async move {
+        let res = if get_state(conn).await?.is_some() {
+            diesel::update(&state)
+                .set(state.clone())
+            .execute(conn).await
+        } else {
+            diesel::insert_into(emergency_state::table)
+                .values(state.clone())
+            .execute(conn).await
+        };
+
+        match res {
+            Ok(_) => Ok(state),
+            Err(e) => Err(ApiError::Internal(format!("{}", e)))
+        }
+    }.scope_boxed()).await?;
+
+    bus.emit(Event::EmergencyChanged { mode, by });
+
+    if let Err(e) = aacp.set_emergency(mode).await {
+        return Err(ApiError::CommandServer(format!("{}", e)))
+    }
+
+    if mode == EmergencyMode::Normal {
+        aacp.refresh(&mut conn).await?;
+
+        if let Some(ap) = aacp.get().await {
+            if let Err(e) = aacp.set(ap).await {
+                return Err(ApiError::CommandServer(format!("{}", e)))
+            }
+        }
+    }
+
+    Ok(Json(state.into()))
+}
+
+pub async fn get_state<'a>(
+    db :&mut DbConnection<'a>
+) -> Result<Option<EmergencyState>, Error> {
+    match emergency_state::table
+        .select(EmergencyState::as_select())
+        .filter(emergency_state::columns::id.eq(EmergencyState::ID))
+    .first(db).await.optional() {
+        Ok(maybe_state) => Ok(maybe_state),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
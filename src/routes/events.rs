@@ -0,0 +1,28 @@
+use rocket::{get, State, Shutdown, response::stream::{EventStream, Event as SseEvent}, tokio::{select, sync::broadcast::error::RecvError}};
+
+use crate::{events::EventBus, guards::auth::{Auth, OperatorUser}};
+
+#[get("/")]
+pub async fn stream(
+    _auth :Auth<OperatorUser>,
+
+    bus :&State<EventBus>,
+    mut shutdown :Shutdown
+) -> EventStream![] {
+    let mut rx = bus.subscribe();
+
+    EventStream! {
+        loop {
+            let event = select! {
+                msg = rx.recv() => match msg {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue
+                },
+                _ = &mut shutdown => break
+            };
+
+            yield SseEvent::json(&event);
+        }
+    }
+}
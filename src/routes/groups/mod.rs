@@ -0,0 +1,290 @@
+pub mod users;
+pub mod permissions;
+
+use cherrydoor_models::{models::{User, Permission}, schema};
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, result};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rocket::{get, post, patch, delete, serde::json::Json, State, response::status::Created};
+use serde::Serialize;
+
+use crate::{
+    db::{DB, DbConnection, get_connection, transaction, for_update, by_key}, etag::{Tagged, IfMatch}, error::{ApiError, ErrorCode, FieldError}, validation::{Validate, Validated, check_name, check_text},
+    guards::{auth::{Auth, OperatorUser, Capable}, capabilities::{UsersWrite, PermissionsWrite}},
+    models::{Group, GroupInsert, GroupUpdate}, schema::{groups, groups_users, groups_permissions}
+};
+
+#[derive(Serialize)]
+pub struct GroupFull {
+    #[serde(flatten)]
+    pub group :Group,
+    pub users :Vec<User>,
+    pub permissions :Vec<Permission>
+}
+
+impl Validate for GroupInsert {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_name("name", &self.name, errors);
+        check_text("description", &self.description, errors);
+    }
+}
+
+impl Validate for GroupUpdate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        if let Some(name) = &self.name {
+            check_name("name", name, errors);
+        }
+        if let Some(description) = &self.description {
+            check_text("description", description, errors);
+        }
+    }
+}
+
+type Error = ApiError;
+type GroupsResponse = Result<Json<Vec<Group>>, Error>;
+type GroupResponse = Result<Json<GroupFull>, Error>;
+type GroupResponseTagged = Result<Tagged<GroupFull>, Error>;
+type GroupResponseCreated = Result<Created<Json<GroupFull>>, Error>;
+
+#[get("/")]
+pub async fn list(
+    _auth :Auth<OperatorUser>,
+
+    db :&State<DB>
+) -> GroupsResponse {
+    let mut conn = get_connection(db).await?;
+
+    match groups::table
+        .select(Group::as_select())
+    .load(&mut conn).await {
+        Ok(groups) => Ok(Json(groups)),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+#[get("/<name>")]
+pub async fn get<'a>(
+    _auth :Auth<OperatorUser>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> GroupResponseTagged {
+    let mut conn = get_connection(db).await?;
+
+    match get_full_group(name, &mut conn).await {
+        Ok(group) => Ok(Tagged(group)),
+        Err(e) => Err(e)
+    }
+}
+
+#[post("/", format = "application/json", data = "<group>")]
+pub async fn create(
+    _auth :Auth<Capable<UsersWrite>>,
+
+    group :Validated<GroupInsert>,
+    db :&State<DB>
+) -> GroupResponseCreated {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let name = group.0.name.clone();
+
+        if let Err(e) = diesel::insert_into(groups::table)
+            .values(group.0)
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::GroupConflict(name)))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        }
+
+        match get_full_group(&name, conn).await {
+            Ok(group) => Ok(Created::new(format!("/groups/{}", group.group.name)).body(Json(group))),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[patch("/<name>", format = "application/json", data = "<group>")]
+pub async fn update<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+    if_match :IfMatch,
+
+    name :&'a str,
+    group :Validated<GroupUpdate>,
+    db :&State<DB>
+) -> GroupResponseTagged {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let old_group = lock_group(name, conn).await?;
+        if_match.check(&get_full_group(name, conn).await?)?;
+
+        if group.name.is_some() || group.description.is_some() {
+            if let Err(e) = diesel::update(&old_group)
+                .set(&group.0)
+            .execute(conn).await {
+                if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                    return Err(ApiError::Conflict(ErrorCode::GroupConflict(group.0.name.unwrap_or_default())))
+                } else {
+                    return Err(ApiError::Internal(format!("{}", e)))
+                }
+            }
+        }
+
+        match get_full_group(&old_group.id.to_string(), conn).await {
+            Ok(group) => Ok(Tagged(group)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+/// Deleting a group only takes away the permissions its members had through it.
+#[delete("/<name>")]
+pub async fn delete<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> GroupResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let group = get_full_group(name, conn).await?;
+
+        let tasks = [
+            diesel::delete(groups_users::table)
+                .filter(groups_users::columns::group_id.eq(group.group.id))
+                .execute(conn).await,
+            diesel::delete(groups_permissions::table)
+                .filter(groups_permissions::columns::group_id.eq(group.group.id))
+                .execute(conn).await,
+            diesel::delete(&group.group).execute(conn).await
+        ];
+
+        for i in tasks {
+            if let Err(e) = i {
+                return Err(ApiError::Internal(format!("{}", e)));
+            }
+        }
+
+        Ok(Json(group))
+    }.scope_boxed()).await
+}
+
+async fn get_group<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<Group, Error> {
+    match by_key!(groups::table.select(Group::as_select()), groups::columns::id, groups::columns::name, name, db) {
+        Ok(maybe_group) => match maybe_group {
+            Some(group) => Ok(group),
+            None => Err(ApiError::NotFound(ErrorCode::GroupNotFound(name.to_string())))
+        },
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Like `get_group`, but the group stays locked until the transaction ends.
+async fn lock_group<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<Group, Error> {
+    match by_key!(for_update!(groups::table.select(Group::as_select())), groups::columns::id, groups::columns::name, name, db) {
+        Ok(maybe_group) => match maybe_group {
+            Some(group) => Ok(group),
+            None => Err(ApiError::NotFound(ErrorCode::GroupNotFound(name.to_string())))
+        },
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+async fn get_full_group<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<GroupFull, Error> {
+    let group = get_group(name, db).await?;
+    let users = get_all_users(&group, db).await?;
+    let permissions = get_all_permissions(&[group.id], db).await?;
+
+    Ok(GroupFull {
+        group,
+        users,
+        permissions
+    })
+}
+
+async fn get_all_users<'a>(
+    group :&Group,
+    db :&mut DbConnection<'a>
+) -> Result<Vec<User>, Error> {
+    let ids :Vec<i32> = match groups_users::table
+        .select(groups_users::columns::user_id)
+        .filter(groups_users::columns::group_id.eq(group.id))
+    .load(db).await {
+        Ok(ids) => ids,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    match schema::users::table
+        .select(User::as_select())
+        .filter(schema::users::columns::id.eq_any(ids))
+    .load(db).await {
+        Ok(users) => Ok(users),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Permissions granted to any of the groups, each once.
+async fn get_all_permissions<'a>(
+    group_ids :&[i32],
+    db :&mut DbConnection<'a>
+) -> Result<Vec<Permission>, Error> {
+    let ids :Vec<i32> = match groups_permissions::table
+        .select(groups_permissions::columns::permission_id)
+        .filter(groups_permissions::columns::group_id.eq_any(group_ids))
+    .load(db).await {
+        Ok(ids) => ids,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    match schema::permissions::table
+        .select(Permission::as_select())
+        .filter(schema::permissions::columns::id.eq_any(ids))
+    .load(db).await {
+        Ok(perms) => Ok(perms),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Groups the user is a member of.
+pub async fn get_user_groups<'a>(
+    user :&User,
+    db :&mut DbConnection<'a>
+) -> Result<Vec<Group>, Error> {
+    let ids :Vec<i32> = match groups_users::table
+        .select(groups_users::columns::group_id)
+        .filter(groups_users::columns::user_id.eq(user.id))
+    .load(db).await {
+        Ok(ids) => ids,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    match groups::table
+        .select(Group::as_select())
+        .filter(groups::columns::id.eq_any(ids))
+    .load(db).await {
+        Ok(groups) => Ok(groups),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Permissions the user has through their groups.
+pub async fn get_group_permissions<'a>(
+    groups :&[Group],
+    db :&mut DbConnection<'a>
+) -> Result<Vec<Permission>, Error> {
+    let ids :Vec<i32> = groups.iter().map(|group| group.id).collect();
+
+    get_all_permissions(&ids, db).await
+}
@@ -0,0 +1,104 @@
+use serde::Deserialize;
+
+use crate::models::GroupPermission;
+
+use super::*;
+
+type PermissionsResponse = Result<Json<Vec<Permission>>, Error>;
+
+#[derive(Deserialize)]
+pub struct GroupPermissionAppend {
+    permission_id :i32
+}
+
+impl Validate for GroupPermissionAppend {}
+
+impl GroupPermissionAppend {
+    pub fn into_insert(self, group_id :i32) -> GroupPermission {
+        GroupPermission {
+            group_id,
+            permission_id: self.permission_id
+        }
+    }
+}
+
+#[get("/<name>/permissions")]
+pub async fn list<'a>(
+    _auth :Auth<OperatorUser>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> PermissionsResponse {
+    let mut conn = get_connection(db).await?;
+    let group = get_group(name, &mut conn).await?;
+
+    match get_all_permissions(&[group.id], &mut conn).await {
+        Ok(perms) => Ok(Json(perms)),
+        Err(e) => Err(e)
+    }
+}
+
+#[post("/<name>/permissions", format = "application/json", data = "<permission>")]
+pub async fn assign<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    permission :Validated<GroupPermissionAppend>,
+    db :&State<DB>
+) -> GroupResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let group = get_group(name, conn).await?;
+        let permission_id = permission.permission_id;
+
+        if let Err(e) = diesel::insert_into(groups_permissions::table)
+            .values(permission.0.into_insert(group.id))
+        .execute(conn).await {
+            match e {
+                result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) =>
+                    return Err(ApiError::Conflict(ErrorCode::GroupPermissionConflict(name.to_string()))),
+                result::Error::DatabaseError(result::DatabaseErrorKind::ForeignKeyViolation, _) =>
+                    return Err(ApiError::NotFound(ErrorCode::GroupPermissionNotFound { id: permission_id, group: name.to_string() })),
+                e => return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match get_full_group(name, conn).await {
+            Ok(group) => Ok(Json(group)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[delete("/<name>/permissions/<id>")]
+pub async fn remove<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    id :i32,
+    db :&State<DB>
+) -> GroupResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let group = get_group(name, conn).await?;
+
+        match diesel::delete(groups_permissions::table)
+            .filter(groups_permissions::columns::permission_id.eq(id))
+            .filter(groups_permissions::columns::group_id.eq(group.id))
+        .execute(conn).await {
+            Ok(del_count) => {
+                if del_count == 0 {
+                    return Err(ApiError::NotFound(ErrorCode::GroupPermissionNotFound { id, group: name.to_string() }))
+                }
+            }
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        }
+
+        match get_full_group(name, conn).await {
+            Ok(group) => Ok(Json(group)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
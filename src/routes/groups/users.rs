@@ -0,0 +1,104 @@
+use serde::Deserialize;
+
+use crate::models::GroupUser;
+
+use super::*;
+
+type UsersResponse = Result<Json<Vec<User>>, Error>;
+
+#[derive(Deserialize)]
+pub struct GroupUserAppend {
+    user_id :i32
+}
+
+impl Validate for GroupUserAppend {}
+
+impl GroupUserAppend {
+    pub fn into_insert(self, group_id :i32) -> GroupUser {
+        GroupUser {
+            group_id,
+            user_id: self.user_id
+        }
+    }
+}
+
+#[get("/<name>/users")]
+pub async fn list<'a>(
+    _auth :Auth<OperatorUser>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> UsersResponse {
+    let mut conn = get_connection(db).await?;
+    let group = get_group(name, &mut conn).await?;
+
+    match get_all_users(&group, &mut conn).await {
+        Ok(users) => Ok(Json(users)),
+        Err(e) => Err(e)
+    }
+}
+
+#[post("/<name>/users", format = "application/json", data = "<user>")]
+pub async fn assign<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+
+    name :&'a str,
+    user :Validated<GroupUserAppend>,
+    db :&State<DB>
+) -> GroupResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let group = get_group(name, conn).await?;
+        let user_id = user.user_id;
+
+        if let Err(e) = diesel::insert_into(groups_users::table)
+            .values(user.0.into_insert(group.id))
+        .execute(conn).await {
+            match e {
+                result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) =>
+                    return Err(ApiError::Conflict(ErrorCode::GroupUserConflict(name.to_string()))),
+                result::Error::DatabaseError(result::DatabaseErrorKind::ForeignKeyViolation, _) =>
+                    return Err(ApiError::NotFound(ErrorCode::GroupUserNotFound { id: user_id, group: name.to_string() })),
+                e => return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match get_full_group(name, conn).await {
+            Ok(group) => Ok(Json(group)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[delete("/<name>/users/<id>")]
+pub async fn remove<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+
+    name :&'a str,
+    id :i32,
+    db :&State<DB>
+) -> GroupResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let group = get_group(name, conn).await?;
+
+        match diesel::delete(groups_users::table)
+            .filter(groups_users::columns::user_id.eq(id))
+            .filter(groups_users::columns::group_id.eq(group.id))
+        .execute(conn).await {
+            Ok(del_count) => {
+                if del_count == 0 {
+                    return Err(ApiError::NotFound(ErrorCode::GroupUserNotFound { id, group: name.to_string() }))
+                }
+            }
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        }
+
+        match get_full_group(name, conn).await {
+            Ok(group) => Ok(Json(group)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use rocket::{get, State, serde::json::Json, response::status::{Custom, NoContent}, http::Status, tokio::time::timeout};
+use serde::Serialize;
+
+use crate::{db::{DB, get_connection}, migrations, door::{Door, DoorError}};
+
+use super::{status::StatusEntry, active_access_profile::ActiveAccessProfile};
+
+/// How long a single check may take. Orchestrators usually give up on the whole probe after a few seconds.
+const CHECK_TIMEOUT :Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+pub struct Readiness {
+    database :StatusEntry,
+    schema :StatusEntry,
+    command_server :StatusEntry,
+    active_profile :StatusEntry
+}
+
+impl Readiness {
+    fn is_ready(&self) -> bool {
+        [&self.database, &self.schema, &self.command_server, &self.active_profile]
+            .iter()
+            .all(|entry| matches!(entry, StatusEntry::Ok))
+    }
+}
+
+/// The process is up and handling requests.
+#[get("/healthz")]
+pub async fn healthz() -> NoContent {
+    NoContent
+}
+
+/// Everything the server needs to do its job works. Responds with 503 and the failed checks otherwise.
+#[get("/readyz")]
+pub async fn readyz(
+    controller :&State<Door>,
+    aacp :&State<ActiveAccessProfile>,
+    db :&State<DB>
+) -> Custom<Json<Readiness>> {
+    let (database, schema) = check_database(db).await;
+
+    let readiness = Readiness {
+        database,
+        schema,
+        command_server: check_controller(controller).await,
+        active_profile: if aacp.is_in_sync() {
+            StatusEntry::Ok
+        } else {
+            StatusEntry::Err { message: String::from("The active access profile couldn't be pushed to the door.") }
+        }
+    };
+
+    let status = if readiness.is_ready() { Status::Ok } else { Status::ServiceUnavailable };
+
+    Custom(status, Json(readiness))
+}
+
+/// Checks the connection, then whether all migrations have been applied.
+async fn check_database(db :&DB) -> (StatusEntry, StatusEntry) {
+    let mut conn = match timeout(CHECK_TIMEOUT, get_connection(db)).await {
+        Ok(Ok(conn)) => conn,
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "readiness check: database unreachable");
+            return (
+                StatusEntry::Err { message: String::from("Couldn't connect to the database.") },
+                StatusEntry::Err { message: String::from("The database is unreachable.") }
+            )
+        },
+        Err(_) => return (
+            StatusEntry::Err { message: String::from("Timed out while connecting to the database.") },
+            StatusEntry::Err { message: String::from("The database is unreachable.") }
+        )
+    };
+
+    let schema = match migrations::pending(&mut conn).await {
+        Ok(pending) if pending.is_empty() => StatusEntry::Ok,
+        Ok(pending) => StatusEntry::Err { message: format!("Pending migrations: {}.", pending.iter()
+            .map(|m| format!("{}_{}", m.version, m.name))
+            .collect::<Vec<String>>()
+        .join(", ")) },
+        Err(e) => {
+            tracing::warn!(error = %e, "readiness check: couldn't read the applied migrations");
+            StatusEntry::Err { message: String::from("Couldn't read the applied migrations.") }
+        }
+    };
+
+    (StatusEntry::Ok, schema)
+}
+
+async fn check_controller(controller :&Door) -> StatusEntry {
+    match timeout(CHECK_TIMEOUT, controller.status()).await {
+        Ok(Ok(())) => StatusEntry::Ok,
+        Ok(Err(DoorError::Rejected(status))) => {
+            tracing::warn!(%status, "readiness check: door controller responded with an error");
+            StatusEntry::Err { message: format!("The door controller responded with {}.", status) }
+        },
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "readiness check: door controller unreachable");
+            StatusEntry::Err { message: String::from("Couldn't connect to the door controller.") }
+        },
+        Err(_) => StatusEntry::Err { message: String::from("Timed out while connecting to the door controller.") }
+    }
+}
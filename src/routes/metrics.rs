@@ -0,0 +1,16 @@
+use rocket::{get, State, http::ContentType};
+
+use crate::{db::DB, metrics::Metrics};
+
+/// Metrics in the Prometheus text format. Not authenticated, so that scrapers don't need a web UI account.
+#[get("/")]
+pub async fn get(
+    metrics :&State<Metrics>,
+    db :&State<DB>
+) -> (ContentType, String) {
+    let state = db.0.state();
+    metrics.db_connections.set(i64::from(state.connections));
+    metrics.db_idle_connections.set(i64::from(state.idle_connections));
+
+    (ContentType::Plain, metrics.render())
+}
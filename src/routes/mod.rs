@@ -2,8 +2,17 @@ pub mod auth;
 pub mod access;
 pub mod status;
 pub mod active_access_profile;
+pub mod occupancy;
+pub mod emergency;
+pub mod events;
+pub mod visitors;
+pub mod metrics;
+pub mod health;
+pub mod setup;
 
 pub mod web_ui_users;
+pub mod roles;
 pub mod users;
+pub mod groups;
 pub mod permissions;
-pub mod access_profiles;
\ No newline at end of file
+pub mod access_profiles;pub mod webhooks;
@@ -0,0 +1,239 @@
+use chrono::{NaiveDateTime, Utc};
+use cherrydoor_models::{models::User, schema::users};
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, OptionalExtension};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rocket::{get, delete, serde::json::Json, State};
+use serde::Serialize;
+
+use crate::{db::{DB, DbConnection, get_connection, transaction, by_key}, error::{ApiError, ErrorCode}, guards::{auth::{Auth, OperatorUser, Capable}, capabilities::UsersWrite}, models::{Occupancy, VisitorPass}, schema::{occupancy, visitor_passes}};
+
+use super::{active_access_profile::ActiveAccessProfile, access_profiles::settings::get_settings};
+
+#[derive(Serialize)]
+pub struct OccupancyEntry {
+    #[serde(flatten)]
+    user :User,
+    entered_at :NaiveDateTime
+}
+
+#[derive(Serialize)]
+pub struct OccupancyVisitor {
+    pass_id :i32,
+    guest_name :String,
+    entered_at :NaiveDateTime
+}
+
+#[derive(Serialize)]
+pub struct OccupancyStatus {
+    count :usize,
+    max_occupancy :Option<i32>,
+    users :Vec<OccupancyEntry>,
+    visitors :Vec<OccupancyVisitor>
+}
+
+/// Someone coming in or going out.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Entrant {
+    User(i32),
+    /// A visitor, by the id of their pass.
+    Visitor(i32)
+}
+
+type Error = ApiError;
+type OccupancyResponse = Result<Json<OccupancyStatus>, Error>;
+
+#[get("/")]
+pub async fn list(
+    _auth :Auth<OperatorUser>,
+
+    aacp :&State<ActiveAccessProfile>,
+    db :&State<DB>
+) -> OccupancyResponse {
+    let mut conn = get_connection(db).await?;
+
+    match get_status(aacp, &mut conn).await {
+        Ok(status) => Ok(Json(status)),
+        Err(e) => Err(e)
+    }
+}
+
+/// Removes a user from the room without an exit swipe, e.g. when they left while the door was held open.
+#[delete("/<name>")]
+pub async fn remove<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+
+    name :&'a str,
+    aacp :&State<ActiveAccessProfile>,
+    db :&State<DB>
+) -> OccupancyResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let user :User = match by_key!(users::table.select(User::as_select()), users::columns::id, users::columns::name, name, conn) {
+            Ok(maybe_user) => match maybe_user {
+                Some(user) => user,
+                None => return Err(ApiError::NotFound(ErrorCode::UserNotFound(name.to_string())))
+            },
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        if !leave(Entrant::User(user.id), conn).await? {
+            return Err(ApiError::NotFound(ErrorCode::UserNotInside(user.name)))
+        }
+
+        match get_status(aacp, conn).await {
+            Ok(status) => Ok(Json(status)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+async fn get_status<'a>(
+    aacp :&ActiveAccessProfile,
+    db :&mut DbConnection<'a>
+) -> Result<OccupancyStatus, Error> {
+    let occupancies :Vec<Occupancy> = match occupancy::table
+        .select(Occupancy::as_select())
+        .order(occupancy::columns::entered_at.asc())
+    .load(db).await {
+        Ok(occupancies) => occupancies,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let users_inside :Vec<User> = match users::table
+        .select(User::as_select())
+        .filter(users::columns::id.eq_any(occupancies.iter().map(|occ| occ.user_id)))
+    .load(db).await {
+        Ok(users) => users,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let passes :Vec<VisitorPass> = match visitor_passes::table
+        .select(VisitorPass::as_select())
+        .filter(visitor_passes::columns::entered_at.is_not_null())
+        .order(visitor_passes::columns::entered_at.asc())
+    .load(db).await {
+        Ok(passes) => passes,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let active_profile = aacp.get().await;
+
+    let max_occupancy = match active_profile {
+        Some(profile) => get_settings(&profile, db).await?.max_occupancy,
+        None => None
+    };
+
+    let users :Vec<OccupancyEntry> = occupancies.into_iter().filter_map(|occ| {
+        users_inside.iter().find(|user| user.id == occ.user_id).map(|user| OccupancyEntry {
+            user: user.clone(),
+            entered_at: occ.entered_at
+        })
+    }).collect();
+
+    let visitors :Vec<OccupancyVisitor> = passes.into_iter().filter_map(|pass| pass.entered_at.map(|entered_at| OccupancyVisitor {
+        pass_id: pass.id,
+        guest_name: pass.guest_name,
+        entered_at
+    })).collect();
+
+    Ok(OccupancyStatus {
+        count: users.len() + visitors.len(),
+        max_occupancy,
+        users,
+        visitors
+    })
+}
+
+pub async fn is_inside<'a>(
+    entrant :Entrant,
+    db :&mut DbConnection<'a>
+) -> Result<bool, Error> {
+    let res = match entrant {
+        Entrant::User(user_id) => occupancy::table
+            .select(occupancy::columns::user_id)
+            .filter(occupancy::columns::user_id.eq(user_id))
+        .first::<i32>(db).await.optional().map(|maybe_occ| maybe_occ.is_some()),
+        Entrant::Visitor(pass_id) => visitor_passes::table
+            .select(visitor_passes::columns::entered_at)
+            .filter(visitor_passes::columns::id.eq(pass_id))
+        .first::<Option<NaiveDateTime>>(db).await.optional().map(|maybe_pass| maybe_pass.flatten().is_some())
+    };
+
+    match res {
+        Ok(inside) => Ok(inside),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Users and visitors inside.
+pub async fn count<'a>(
+    db :&mut DbConnection<'a>
+) -> Result<i64, Error> {
+    let users :i64 = match occupancy::table
+        .count()
+    .get_result(db).await {
+        Ok(count) => count,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    match visitor_passes::table
+        .count()
+        .filter(visitor_passes::columns::entered_at.is_not_null())
+    .get_result::<i64>(db).await {
+        Ok(visitors) => Ok(users + visitors),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Records the entrant as inside, refreshing the entry time if they already were.
+pub async fn enter<'a>(
+    entrant :Entrant,
+    db :&mut DbConnection<'a>
+) -> Result<(), Error> {
+    let now = Utc::now().naive_utc();
+
+    let res = match entrant {
+        Entrant::User(user_id) => if is_inside(entrant, db).await? {
+            diesel::update(occupancy::table)
+                .filter(occupancy::columns::user_id.eq(user_id))
+                .set(occupancy::columns::entered_at.eq(now))
+            .execute(db).await
+        } else {
+            diesel::insert_into(occupancy::table)
+                .values(Occupancy { user_id, entered_at: now })
+            .execute(db).await
+        },
+        Entrant::Visitor(pass_id) => diesel::update(visitor_passes::table)
+            .filter(visitor_passes::columns::id.eq(pass_id))
+            .set(visitor_passes::columns::entered_at.eq(now))
+        .execute(db).await
+    };
+
+    match res {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Records the entrant as outside. Returns whether they were inside.
+pub async fn leave<'a>(
+    entrant :Entrant,
+    db :&mut DbConnection<'a>
+) -> Result<bool, Error> {
+    let res = match entrant {
+        Entrant::User(user_id) => diesel::delete(occupancy::table)
+            .filter(occupancy::columns::user_id.eq(user_id))
+        .execute(db).await,
+        Entrant::Visitor(pass_id) => diesel::update(visitor_passes::table)
+            .filter(visitor_passes::columns::id.eq(pass_id))
+            .filter(visitor_passes::columns::entered_at.is_not_null())
+            .set(visitor_passes::columns::entered_at.eq(None::<NaiveDateTime>))
+        .execute(db).await
+    };
+
+    match res {
+        Ok(count) => Ok(count > 0),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
@@ -2,14 +2,18 @@ use cherrydoor_models::{insert::AccessProfilePermissionInsert, schema::access_pr
 use serde::Deserialize;
 
 use super::*;
+use crate::batch::{BatchChange, Batched, IdOrName, Relation};
 
 type AccessProfilesResponse = Result<Json<Vec<AccessProfile>>, Error>;
+type PermissionBatchResponse = Result<Json<Batched<PermissionFull>>, Error>;
 
 #[derive(Deserialize)]
 pub struct AccessProfilePermissionAppend {
     access_profile_id :i32
 }
 
+impl Validate for AccessProfilePermissionAppend {}
+
 impl AccessProfilePermissionAppend {
     pub fn into_insert(self, permission_id :i32) -> AccessProfilePermissionInsert {
         AccessProfilePermissionInsert {
@@ -37,56 +41,104 @@ pub async fn list<'a>(
 
 #[post("/<name>/access-profiles", format = "application/json", data="<access_profile>")]
 pub async fn assign<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
 
     name :&'a str,
-    access_profile :Json<AccessProfilePermissionAppend>,
+    access_profile :Validated<AccessProfilePermissionAppend>,
     db :&State<DB>
 ) -> PermissionResponse {
     let mut conn = get_connection(db).await?;
-    let perm = get_permission(name, &mut conn).await?;
 
-    if let Err(e) = diesel::insert_into(schema::access_profiles_permissions::table)
-        .values(access_profile.0.into_insert(perm.id))
-    .execute(&mut conn).await {
-        if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
-            return Err(ApiError::Conflict(format!("The access profile is already assigned to permission {}.", &name)))
-        } else {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
-    };
+    transaction(&mut conn, |conn| async move {
+        let perm = get_permission(name, conn).await?;
 
-    match get_full_permission(name, &mut conn).await {
-        Ok(perm) => Ok(Json(perm)),
-        Err(e) => Err(e)
-    }
+        if let Err(e) = diesel::insert_into(schema::access_profiles_permissions::table)
+            .values(access_profile.0.into_insert(perm.id))
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::PermissionAccessProfileConflict(name.to_string())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match get_full_permission(name, conn).await {
+            Ok(perm) => Ok(Json(perm)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
 }
 
 #[delete("/<name>/access-profiles/<id>")]
 pub async fn remove<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
     
     name :&'a str,
     id :i32,
     db :&State<DB>
 ) -> PermissionResponse {
     let mut conn = get_connection(db).await?;
-    let perm = get_permission(name, &mut conn).await?;
 
-    match diesel::delete(access_profiles_permissions::table)
-        .filter(access_profiles_permissions::columns::access_profile_id.eq(id))
-        .filter(access_profiles_permissions::columns::permission_id.eq(perm.id))
-    .execute(&mut conn).await {
-        Ok(del_count) => {
-            if del_count == 0 {
-                return Err(ApiError::NotFound(format!("User with ID {} either does not exist, or does not have permission {}.", id, name)))
+    transaction(&mut conn, |conn| async move {
+        let perm = get_permission(name, conn).await?;
+
+        match diesel::delete(access_profiles_permissions::table)
+            .filter(access_profiles_permissions::columns::access_profile_id.eq(id))
+            .filter(access_profiles_permissions::columns::permission_id.eq(perm.id))
+        .execute(conn).await {
+            Ok(del_count) => {
+                if del_count == 0 {
+                    return Err(ApiError::NotFound(ErrorCode::PermissionAccessProfileNotFound { id, permission: name.to_string() }))
+                }
             }
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
         }
-        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
-    }
 
-    match get_full_permission(name, &mut conn).await {
-        Ok(perm) => Ok(Json(perm)),
-        Err(e) => Err(e)
-    }
-}
\ No newline at end of file
+        match get_full_permission(name, conn).await {
+            Ok(perm) => Ok(Json(perm)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[post("/<name>/access-profiles/batch", format = "application/json", data = "<change>")]
+pub async fn batch<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    change :Validated<BatchChange>,
+    db :&State<DB>
+) -> PermissionBatchResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let perm = lock_permission(name, conn).await?;
+        let changes = Relation::PermissionAccessProfiles(perm.id).change(change.0, conn).await?;
+
+        match get_full_permission(name, conn).await {
+            Ok(perm) => Ok(Json(Batched { resource: perm, changes })),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[put("/<name>/access-profiles", format = "application/json", data = "<access_profiles>")]
+pub async fn replace<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    access_profiles :Validated<Vec<IdOrName>>,
+    db :&State<DB>
+) -> PermissionBatchResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let perm = lock_permission(name, conn).await?;
+        let changes = Relation::PermissionAccessProfiles(perm.id).replace(access_profiles.0, conn).await?;
+
+        match get_full_permission(name, conn).await {
+            Ok(perm) => Ok(Json(Batched { resource: perm, changes })),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
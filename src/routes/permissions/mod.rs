@@ -2,17 +2,36 @@ pub mod users;
 pub mod access_profiles;
 
 use cherrydoor_models::{models::{Permission, UserPermission, User, AccessProfile, AccessProfilePermission}, full::PermissionFull, schema::{self, users_permissions, access_profiles_permissions}, insert::PermissionInsert, update::PermissionUpdate};
-use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, BelongingToDsl, OptionalExtension, result};
-use diesel_async::RunQueryDsl;
-use rocket::{get, post, patch, delete, serde::json::Json, State, response::status::Created};
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, BelongingToDsl, result};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rocket::{get, post, put, patch, delete, serde::json::Json, State, response::status::Created};
 
-use crate::{error::ApiError, db::{DB, DbConnection, get_connection}, guards::auth::{Auth, OperatorUser}};
+use crate::{error::{ApiError, ErrorCode, FieldError}, db::{DB, DbConnection, get_connection, transaction, for_update, by_key}, etag::{Tagged, IfMatch}, guards::{auth::{Auth, OperatorUser, Capable}, capabilities::PermissionsWrite}, validation::{Validate, Validated, check_name, check_text}};
 
 type Error = ApiError;
 type PermissionsResponse = Result<Json<Vec<Permission>>, Error>;
 type PermissionResponse = Result<Json<PermissionFull>, Error>;
+type PermissionResponseTagged = Result<Tagged<PermissionFull>, Error>;
 type PermissionResponseCreated = Result<Created<Json<PermissionFull>>, Error>;
 
+impl Validate for PermissionInsert {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_name("name", &self.name, errors);
+        check_text("description", &self.description, errors);
+    }
+}
+
+impl Validate for PermissionUpdate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        if let Some(name) = &self.name {
+            check_name("name", name, errors);
+        }
+        if let Some(description) = &self.description {
+            check_text("description", description, errors);
+        }
+    }
+}
+
 
 #[get("/")]
 pub async fn list(
@@ -38,92 +57,109 @@ pub async fn get<'a>(
 
     name :&'a str,
     db :&State<DB>
-) -> PermissionResponse {
+) -> PermissionResponseTagged {
     let mut conn = get_connection(db).await?;
 
     match get_full_permission(name, &mut conn).await {
-        Ok(perm) => Ok(Json(perm)),
+        Ok(perm) => Ok(Tagged(perm)),
         Err(e) => Err(e)
     }
 }
 
 #[post("/", format = "application/json", data = "<permission>")]
 pub async fn create(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
 
-    permission :Json<PermissionInsert>,
+    permission :Validated<PermissionInsert>,
     db :&State<DB>
 ) -> PermissionResponseCreated {
     let mut conn = get_connection(db).await?;
-    let name = permission.0.name.clone();
-
-    if let Err(e) = diesel::insert_into(schema::permissions::table)
-        .values(permission.0)
-    .execute(&mut conn).await {
-        if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
-            return Err(ApiError::Conflict(format!("Permission {} already exists.", &name)))
-        } else {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
-    };
 
-    match get_full_permission(&name, &mut conn).await {
-        Ok(perm) => Ok(Created::new(format!("/permissions/{}", perm.permission.name)).body(Json(perm))),
-        Err(e) => Err(e)
-    }
+    transaction(&mut conn, |conn| async move {
+        let name = permission.0.name.clone();
+
+        if let Err(e) = diesel::insert_into(schema::permissions::table)
+            .values(permission.0)
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::PermissionConflict(name.to_string())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match get_full_permission(&name, conn).await {
+            Ok(perm) => Ok(Created::new(format!("/permissions/{}", perm.permission.name)).body(Json(perm))),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
 }
 
 #[patch("/<name>", format = "application/json", data = "<permission>")]
 pub async fn update<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
+    if_match :IfMatch,
 
-    permission :Json<PermissionUpdate>,
+    permission :Validated<PermissionUpdate>,
     name :&'a str,
     db :&State<DB>
-) -> PermissionResponse {
+) -> PermissionResponseTagged {
     let mut conn = get_connection(db).await?;
-    let old_permission = get_permission(name, &mut conn).await?;
 
-    if let Err(e) = diesel::update(&old_permission)
-        .set(&permission.0)
-    .execute(&mut conn).await {
-        return Err(ApiError::Internal(format!("{}", e)))
-    };
+    transaction(&mut conn, |conn| async move {
+        let old_permission = lock_permission(name, conn).await?;
+        if_match.check(&get_full_permission(name, conn).await?)?;
+
+        if let Err(e) = diesel::update(&old_permission)
+            .set(&permission.0)
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::PermissionConflict(permission.0.name.unwrap_or_default())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
 
-    match get_full_permission(name, &mut conn).await {
-        Ok(perm) => Ok(Json(perm)),
-        Err(e) => Err(e)
-    }
+        match get_full_permission(&old_permission.id.to_string(), conn).await {
+            Ok(perm) => Ok(Tagged(perm)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
 }
 
 #[delete("/<name>")]
 pub async fn delete<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
     
     name :&'a str,
     db :&State<DB>
 ) -> PermissionResponse {
     let mut conn = get_connection(db).await?;
 
-    let permission = get_full_permission(name, &mut conn).await?;
-
-    let tasks = [
-        diesel::delete(users_permissions::table)
-            .filter(users_permissions::columns::permission_id.eq(permission.permission.id))
-            .execute(&mut conn).await,
-        diesel::delete(access_profiles_permissions::table)
-            .filter(access_profiles_permissions::columns::permission_id.eq(permission.permission.id))
-            .execute(&mut conn).await,
-        diesel::delete(&permission.permission).execute(&mut conn).await
-    ];
-
-    for i in tasks {
-        if let Err(e) = i {
-            return Err(ApiError::Internal(format!("{}", e)));
+    transaction(&mut conn, |conn| async move {
+        let permission = get_full_permission(name, conn).await?;
+
+        let tasks = [
+            diesel::delete(users_permissions::table)
+                .filter(users_permissions::columns::permission_id.eq(permission.permission.id))
+                .execute(conn).await,
+            diesel::delete(access_profiles_permissions::table)
+                .filter(access_profiles_permissions::columns::permission_id.eq(permission.permission.id))
+                .execute(conn).await,
+            diesel::delete(crate::schema::groups_permissions::table)
+                .filter(crate::schema::groups_permissions::columns::permission_id.eq(permission.permission.id))
+                .execute(conn).await,
+            diesel::delete(&permission.permission).execute(conn).await
+        ];
+
+        for i in tasks {
+            if let Err(e) = i {
+                return Err(ApiError::Internal(format!("{}", e)));
+            }
         }
-    }
 
-    Ok(Json(permission))
+        Ok(Json(permission))
+    }.scope_boxed()).await
 }
 
 async fn get_full_permission<'a, 'v>(
@@ -145,14 +181,27 @@ async fn get_permission<'a, 'v>(
     name :&'v str,
     db :&mut DbConnection<'a>
 ) -> Result<Permission, Error> {
-    match schema::permissions::table
-        .select(Permission::as_select())
-        .filter(schema::permissions::columns::name.eq(name))
-    .first(db).await.optional() {
+    match by_key!(schema::permissions::table.select(Permission::as_select()), schema::permissions::columns::id, schema::permissions::columns::name, name, db) {
+        Ok(maybe_perm) => {
+            match maybe_perm {
+                Some(perm) => Ok(perm),
+                None => Err(ApiError::NotFound(ErrorCode::PermissionNotFound(name.to_string())))
+            }
+        },
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Like `get_permission`, but the permission stays locked until the transaction ends.
+async fn lock_permission<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<Permission, Error> {
+    match by_key!(for_update!(schema::permissions::table.select(Permission::as_select())), schema::permissions::columns::id, schema::permissions::columns::name, name, db) {
         Ok(maybe_perm) => {
             match maybe_perm {
                 Some(perm) => Ok(perm),
-                None => Err(ApiError::NotFound(format!("Permission {} not found.", name)))
+                None => Err(ApiError::NotFound(ErrorCode::PermissionNotFound(name.to_string())))
             }
         },
         Err(e) => Err(ApiError::Internal(format!("{}", e)))
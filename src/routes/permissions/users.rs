@@ -2,14 +2,18 @@ use cherrydoor_models::{insert::UserPermissionInsert, schema::users_permissions}
 use serde::Deserialize;
 
 use super::*;
+use crate::batch::{BatchChange, Batched, IdOrName, Relation};
 
 type UsersResponse = Result<Json<Vec<User>>, Error>;
+type PermissionBatchResponse = Result<Json<Batched<PermissionFull>>, Error>;
 
 #[derive(Deserialize)]
 pub struct UserPermissionAppend {
     user_id :i32
 }
 
+impl Validate for UserPermissionAppend {}
+
 impl UserPermissionAppend {
     pub fn into_insert(self, permission_id :i32) -> UserPermissionInsert {
         UserPermissionInsert {
@@ -37,56 +41,104 @@ pub async fn list<'a>(
 
 #[post("/<name>/users", format = "application/json", data = "<user>")]
 pub async fn assign<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
 
     name :&'a str,
-    user :Json<UserPermissionAppend>,
+    user :Validated<UserPermissionAppend>,
     db :&State<DB>
 ) -> PermissionResponse {
     let mut conn = get_connection(db).await?;
-    let perm = get_permission(name, &mut conn).await?;
 
-    if let Err(e) = diesel::insert_into(schema::users_permissions::table)
-        .values(user.0.into_insert(perm.id))
-    .execute(&mut conn).await {
-        if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
-            return Err(ApiError::Conflict(format!("The user already has the permission {}.", &name)))
-        } else {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
-    };
+    transaction(&mut conn, |conn| async move {
+        let perm = get_permission(name, conn).await?;
 
-    match get_full_permission(name, &mut conn).await {
-        Ok(perm) => Ok(Json(perm)),
-        Err(e) => Err(e)
-    }
+        if let Err(e) = diesel::insert_into(schema::users_permissions::table)
+            .values(user.0.into_insert(perm.id))
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::PermissionUserConflict(name.to_string())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match get_full_permission(name, conn).await {
+            Ok(perm) => Ok(Json(perm)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
 }
 
 #[delete("/<name>/users/<id>")]
 pub async fn remove<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
     
     name :&'a str,
     id :i32,
     db :&State<DB>
 ) -> PermissionResponse {
     let mut conn = get_connection(db).await?;
-    let perm = get_permission(name, &mut conn).await?;
 
-    match diesel::delete(users_permissions::table)
-        .filter(users_permissions::columns::user_id.eq(id))
-        .filter(users_permissions::columns::permission_id.eq(perm.id))
-    .execute(&mut conn).await {
-        Ok(del_count) => {
-            if del_count == 0 {
-                return Err(ApiError::NotFound(format!("User with ID {} either does not exist, or does not have permission {}.", id, name)))
+    transaction(&mut conn, |conn| async move {
+        let perm = get_permission(name, conn).await?;
+
+        match diesel::delete(users_permissions::table)
+            .filter(users_permissions::columns::user_id.eq(id))
+            .filter(users_permissions::columns::permission_id.eq(perm.id))
+        .execute(conn).await {
+            Ok(del_count) => {
+                if del_count == 0 {
+                    return Err(ApiError::NotFound(ErrorCode::PermissionUserNotFound { id, permission: name.to_string() }))
+                }
             }
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        match get_full_permission(name, conn).await {
+            Ok(perm) => Ok(Json(perm)),
+            Err(e) => Err(e)
         }
-        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
-    };
+    }.scope_boxed()).await
+}
 
-    match get_full_permission(name, &mut conn).await {
-        Ok(perm) => Ok(Json(perm)),
-        Err(e) => Err(e)
-    }
-}
\ No newline at end of file
+#[post("/<name>/users/batch", format = "application/json", data = "<change>")]
+pub async fn batch<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    change :Validated<BatchChange>,
+    db :&State<DB>
+) -> PermissionBatchResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let perm = lock_permission(name, conn).await?;
+        let changes = Relation::PermissionUsers(perm.id).change(change.0, conn).await?;
+
+        match get_full_permission(name, conn).await {
+            Ok(perm) => Ok(Json(Batched { resource: perm, changes })),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[put("/<name>/users", format = "application/json", data = "<users>")]
+pub async fn replace<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    users :Validated<Vec<IdOrName>>,
+    db :&State<DB>
+) -> PermissionBatchResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let perm = lock_permission(name, conn).await?;
+        let changes = Relation::PermissionUsers(perm.id).replace(users.0, conn).await?;
+
+        match get_full_permission(name, conn).await {
+            Ok(perm) => Ok(Json(Batched { resource: perm, changes })),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
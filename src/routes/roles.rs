@@ -0,0 +1,296 @@
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, BelongingToDsl, result};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rocket::{get, post, patch, delete, serde::json::Json, State, response::status::Created};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{DB, DbConnection, get_connection, transaction, for_update, by_key}, etag::{Tagged, IfMatch}, error::{ApiError, ErrorCode, FieldError}, validation::{Validate, Validated, check_name, check_text},
+    guards::{auth::{Auth, OperatorUser, Capable, WebUIUserAuthorization}, capabilities::{self, WebUIUsersManage}},
+    models::{Role, RoleInsert, RoleUpdate, RoleCapability}, schema::{roles, roles_capabilities, web_ui_users_roles}
+};
+
+#[derive(Serialize)]
+pub struct RoleFull {
+    #[serde(flatten)]
+    pub role :Role,
+    pub capabilities :Vec<String>
+}
+
+#[derive(Deserialize)]
+pub struct RoleCreate {
+    name :String,
+    description :String,
+    #[serde(default)]
+    capabilities :Vec<String>
+}
+
+#[derive(Deserialize)]
+pub struct RolePatch {
+    name :Option<String>,
+    description :Option<String>,
+    capabilities :Option<Vec<String>>     // Replaces all capabilities of the role
+}
+
+impl Validate for RoleCreate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_name("name", &self.name, errors);
+        check_text("description", &self.description, errors);
+        check_capabilities(&self.capabilities, errors);
+    }
+}
+
+impl Validate for RolePatch {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        if let Some(name) = &self.name {
+            check_name("name", name, errors);
+        }
+        if let Some(description) = &self.description {
+            check_text("description", description, errors);
+        }
+        if let Some(capabilities) = &self.capabilities {
+            check_capabilities(capabilities, errors);
+        }
+    }
+}
+
+type Error = ApiError;
+type RolesResponse = Result<Json<Vec<Role>>, Error>;
+type RoleResponse = Result<Json<RoleFull>, Error>;
+type RoleResponseTagged = Result<Tagged<RoleFull>, Error>;
+type RoleResponseCreated = Result<Created<Json<RoleFull>>, Error>;
+
+#[get("/")]
+pub async fn list(
+    _auth :Auth<OperatorUser>,
+
+    db :&State<DB>
+) -> RolesResponse {
+    let mut conn = get_connection(db).await?;
+
+    match roles::table
+        .select(Role::as_select())
+    .load(&mut conn).await {
+        Ok(roles) => Ok(Json(roles)),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+#[get("/<name>")]
+pub async fn get<'a>(
+    _auth :Auth<OperatorUser>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> RoleResponseTagged {
+    let mut conn = get_connection(db).await?;
+
+    match get_full_role(name, &mut conn).await {
+        Ok(role) => Ok(Tagged(role)),
+        Err(e) => Err(e)
+    }
+}
+
+#[post("/", format = "application/json", data = "<role>")]
+pub async fn create(
+    auth :Auth<Capable<WebUIUsersManage>>,
+
+    role :Validated<RoleCreate>,
+    db :&State<DB>
+) -> RoleResponseCreated {
+    check_held(&auth.claim, &role.0.capabilities)?;
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let role = role.0;
+
+        if let Err(e) = diesel::insert_into(roles::table)
+            .values(RoleInsert {
+                name: role.name.clone(),
+                description: role.description
+            })
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::RoleConflict(role.name.clone())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        }
+
+        let new_role = get_role(&role.name, conn).await?;
+        set_capabilities(&new_role, role.capabilities, conn).await?;
+
+        match get_full_role(&role.name, conn).await {
+            Ok(role) => Ok(Created::new(format!("/roles/{}", role.role.name)).body(Json(role))),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[patch("/<name>", format = "application/json", data = "<role>")]
+pub async fn update<'a>(
+    auth :Auth<Capable<WebUIUsersManage>>,
+    if_match :IfMatch,
+
+    name :&'a str,
+    role :Validated<RolePatch>,
+    db :&State<DB>
+) -> RoleResponseTagged {
+    if let Some(capabilities) = &role.0.capabilities {
+        check_held(&auth.claim, capabilities)?;
+    }
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let old_role = lock_role(name, conn).await?;
+        if_match.check(&get_full_role(name, conn).await?)?;
+        let role = role.0;
+
+        if role.name.is_some() || role.description.is_some() {
+            let new_name = role.name.clone();
+            if let Err(e) = diesel::update(&old_role)
+                .set(RoleUpdate { name: role.name, description: role.description })
+            .execute(conn).await {
+                if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                    return Err(ApiError::Conflict(ErrorCode::RoleConflict(new_name.unwrap_or_default())))
+                } else {
+                    return Err(ApiError::Internal(format!("{}", e)))
+                }
+            }
+        }
+
+        if let Some(capabilities) = role.capabilities {
+            set_capabilities(&old_role, capabilities, conn).await?;
+        }
+
+        match get_full_role(&old_role.id.to_string(), conn).await {
+            Ok(role) => Ok(Tagged(role)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[delete("/<name>")]
+pub async fn delete<'a>(
+    _auth :Auth<Capable<WebUIUsersManage>>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> RoleResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let role = get_full_role(name, conn).await?;
+
+        let tasks = [
+            diesel::delete(roles_capabilities::table)
+                .filter(roles_capabilities::columns::role_id.eq(role.role.id))
+                .execute(conn).await,
+            diesel::delete(web_ui_users_roles::table)
+                .filter(web_ui_users_roles::columns::role_id.eq(role.role.id))
+                .execute(conn).await,
+            diesel::delete(&role.role).execute(conn).await
+        ];
+
+        for i in tasks {
+            if let Err(e) = i {
+                return Err(ApiError::Internal(format!("{}", e)));
+            }
+        }
+
+        Ok(Json(role))
+    }.scope_boxed()).await
+}
+
+fn check_capabilities(capabilities :&[String], errors :&mut Vec<FieldError>) {
+    for (i, capability) in capabilities.iter().enumerate() {
+        if !capabilities::ALL.contains(&capability.as_str()) {
+            errors.push(FieldError::new(&format!("capabilities[{}]", i), &format!("Unknown capability {}.", capability)));
+        }
+    }
+}
+
+/// Nobody can grant a capability they don't have themselves, or they could give it to anyone through a role.
+pub fn check_held(auth :&WebUIUserAuthorization, capabilities :&[String]) -> Result<(), Error> {
+    match capabilities.iter().find(|capability| !auth.has_capability(capability)) {
+        Some(capability) => Err(ApiError::Forbidden(ErrorCode::CapabilityNotHeld(capability.clone()))),
+        None => Ok(())
+    }
+}
+
+async fn set_capabilities<'a>(
+    role :&Role,
+    capabilities :Vec<String>,
+    db :&mut DbConnection<'a>
+) -> Result<(), Error> {
+    if let Err(e) = diesel::delete(roles_capabilities::table)
+        .filter(roles_capabilities::columns::role_id.eq(role.id))
+    .execute(db).await {
+        return Err(ApiError::Internal(format!("{}", e)))
+    }
+
+    let mut capabilities = capabilities;
+    capabilities.sort();
+    capabilities.dedup();
+
+    let inserts :Vec<RoleCapability> = capabilities.into_iter().map(|capability| RoleCapability {
+        role_id: role.id,
+        capability
+    }).collect();
+
+    if inserts.is_empty() {
+        return Ok(())
+    }
+
+    match diesel::insert_into(roles_capabilities::table)
+        .values(inserts)
+    .execute(db).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+pub async fn get_role<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<Role, Error> {
+    match by_key!(roles::table.select(Role::as_select()), roles::columns::id, roles::columns::name, name, db) {
+        Ok(maybe_role) => match maybe_role {
+            Some(role) => Ok(role),
+            None => Err(ApiError::NotFound(ErrorCode::RoleNotFound(name.to_string())))
+        },
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Like `get_role`, but the role stays locked until the transaction ends.
+async fn lock_role<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<Role, Error> {
+    match by_key!(for_update!(roles::table.select(Role::as_select())), roles::columns::id, roles::columns::name, name, db) {
+        Ok(maybe_role) => match maybe_role {
+            Some(role) => Ok(role),
+            None => Err(ApiError::NotFound(ErrorCode::RoleNotFound(name.to_string())))
+        },
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+async fn get_full_role<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<RoleFull, Error> {
+    let role = get_role(name, db).await?;
+
+    let capabilities :Vec<RoleCapability> = match RoleCapability::belonging_to(&role)
+        .select(RoleCapability::as_select())
+    .load(db).await {
+        Ok(capabilities) => capabilities,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    Ok(RoleFull {
+        role,
+        capabilities: capabilities.into_iter().map(|v| { v.capability }).collect()
+    })
+}
@@ -0,0 +1,79 @@
+use cherrydoor_models::schema::web_ui_users;
+use diesel::QueryDsl;
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rocket::{post, serde::json::Json, State, response::status::Created};
+use serde::Deserialize;
+
+use crate::{db::{DB, get_connection, transaction}, error::{ApiError, ErrorCode, FieldError}, validation::{Validate, Validated, check_name, check_not_empty}, setup::SetupToken};
+
+use super::web_ui_users::{WebUIUserOutput, WebUIUserCreate, create_web_ui_user};
+
+#[derive(Deserialize)]
+pub struct FirstAdmin {
+    token :String,
+    name :String,
+    password :String
+}
+
+impl Validate for FirstAdmin {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_not_empty("token", &self.token, errors);
+        check_name("name", &self.name, errors);
+        check_not_empty("password", &self.password, errors);
+    }
+}
+
+/// Creates the first admin of a fresh installation, with the setup token from the server log.
+#[post("/", format = "application/json", data = "<admin>")]
+pub async fn create_admin(
+    admin :Validated<FirstAdmin>,
+    token :&State<SetupToken>,
+    db :&State<DB>
+) -> Result<Created<Json<WebUIUserOutput>>, ApiError> {
+    // Held until the admin is created, so the token can't be used twice.
+    let mut token = token.0.lock().await;
+
+    match token.as_deref() {
+        Some(t) if t == admin.token => {},
+        Some(_) => return Err(ApiError::Forbidden(ErrorCode::BadSetupToken)),
+        None => return Err(ApiError::Conflict(ErrorCode::AlreadySetUp))
+    }
+
+    let mut conn = get_connection(db).await?;
+
+    let created = transaction(&mut conn, |conn| async move {
+        let count :i64 = match web_ui_users::table
+            .count()
+        .get_result(conn).await {
+            Ok(count) => count,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        // Someone, e.g. the CLI, created a user in the meantime.
+        if count > 0 {
+            return Ok(None)
+        }
+
+        let admin = admin.0;
+
+        let wu_user = create_web_ui_user(WebUIUserCreate {
+            name: admin.name,
+            password: admin.password,
+            is_admin: true,
+            ac_does_not_expire: false
+        }, conn).await?;
+
+        Ok(Some(wu_user))
+    }.scope_boxed()).await?;
+
+    *token = None;
+
+    let wu_user = match created {
+        Some(wu_user) => wu_user,
+        None => return Err(ApiError::Conflict(ErrorCode::AlreadySetUp))
+    };
+
+    tracing::info!(name = %wu_user.name, "first admin created");
+
+    Ok(Created::new(format!("/web-ui-users/{}", &wu_user.name)).body(Json(wu_user.into())))
+}
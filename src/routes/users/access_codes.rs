@@ -1,8 +1,7 @@
 use cherrydoor_models::insert::AccessCodeInsert;
-use reqwest::StatusCode;
 use serde::Deserialize;
 
-use crate::{db::get_connection, routes::access::CommandAddress};
+use crate::{db::get_connection, door::Door, events::{EventBus, Event}, config::Config, codes::{self, CodeFormat}};
 
 use super::*;
 
@@ -12,13 +11,30 @@ type AccessCodeResponse = Result<Json<AccessCode>, Error>;
 
 #[derive(Deserialize)]
 pub struct AccessCodeCreate {
-    code :String
+    code :String,
+    /// How `code` is written, it's stored normalized.
+    #[serde(default)]
+    format :CodeFormat
+}
+
+impl Validate for AccessCodeCreate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_text("code", &self.code, errors);
+        if let Err(message) = codes::normalize(&self.code, self.format) {
+            errors.push(FieldError::new("code", message));
+        }
+    }
 }
 
 impl AccessCodeCreate {
+    /// The code as it's stored.
+    pub fn normalized(&self) -> String {
+        codes::normalize(&self.code, self.format).unwrap_or(self.code.clone())
+    }
+
     pub fn into_insert(self, user_id :i32) -> AccessCodeInsert {
         AccessCodeInsert {
-            code: self.code,
+            code: self.normalized(),
             user: user_id
         }
     }
@@ -55,97 +71,188 @@ pub async fn get<'a>(
 
 #[post("/<name>/access-codes", format = "application/json", data = "<code>")]
 pub async fn manual_add<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<UsersWrite>>,
     
     name :&'a str,
-    code :Json<AccessCodeCreate>,
-    db :&State<DB>
+    code :Validated<AccessCodeCreate>,
+    db :&State<DB>,
+    bus :&State<EventBus>
 ) -> UserResponse {
     let mut conn = get_connection(db).await?;
-    let user = get_user(name, &mut conn).await?;
-    
-    if let Err(e) = diesel::insert_into(schema::access_codes::table)
-        .values(code.0.into_insert(user.id))
-    .execute(&mut conn).await {
-        if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
-            return Err(ApiError::Conflict(format!("This access code is already registered.")))
-        } else {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
-    };
-    
-    match get_full_user(name, &mut conn).await {
-        Ok(user) => Ok(Json(user)),
-        Err(e) => Err(e)
-    }
+
+    let value = code.normalized();
+    let user = transaction(&mut conn, |conn| async move {
+        let user = get_user(name, conn).await?;
+
+        if let Err(e) = diesel::insert_into(schema::access_codes::table)
+            .values(code.0.into_insert(user.id))
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::AccessCodeConflict))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        get_full_user(name, conn).await
+    }.scope_boxed()).await?;
+
+    emit_created(&user, &value, bus);
+    Ok(Json(user))
 }
 
 #[post("/<name>/access-codes/register")]
 pub async fn register<'a>(
-    _auth :Auth<OperatorUser>,
-    command_addr :&State<CommandAddress>,
+    _auth :Auth<Capable<UsersWrite>>,
+    controller :&State<Door>,
+    config :&State<Config>,
 
     name :&'a str,
-    db :&State<DB>
+    db :&State<DB>,
+    bus :&State<EventBus>
 ) -> UserResponse {
-    let client = reqwest::Client::new();
-    let res = client.get(format!("{}/register", command_addr.0)).send().await;
-
-    let ac = match res {
-        Ok(v) => match v.status() {
-            StatusCode::OK => match v.text().await {
-                Ok(code) => code,
-                Err(_) => return Err(ApiError::Internal(String::from("Command server returned garbage,")))
-            },
-            StatusCode::NOT_FOUND => return Err(ApiError::NotFound(String::from("Request timed out."))),
-            _ => return Err(ApiError::Internal(format!("Command server returned {}", v.text().await.unwrap_or("garbage".to_string()))))
-        }
-        Err(e) => {
-            return Err(ApiError::Internal(format!("Command server returned {}", e)))
-        }
-    };
+    let ac = controller.register().await?;
 
     let code = AccessCodeCreate {
-        code: ac
+        code: read_code(&ac, config.door.code_format)?,
+        format: CodeFormat::Hex
     };
+    let value = code.normalized();
 
     let mut conn = get_connection(db).await?;
-    let user = get_user(name, &mut conn).await?;
-    
-    if let Err(e) = diesel::insert_into(schema::access_codes::table)
-        .values(code.into_insert(user.id))
-    .execute(&mut conn).await {
-        if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
-            return Err(ApiError::Conflict(format!("This access code is already registered.")))
-        } else {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
+
+    let user = transaction(&mut conn, |conn| async move {
+        let user = get_user(name, conn).await?;
+
+        if let Err(e) = diesel::insert_into(schema::access_codes::table)
+            .values(code.into_insert(user.id))
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::AccessCodeConflict))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        get_full_user(name, conn).await
+    }.scope_boxed()).await?;
+
+    emit_created(&user, &value, bus);
+    Ok(Json(user))
+}
+
+/// Normalizes a code read by the controller. One that isn't in the configured format means it's set up wrong.
+pub fn read_code(code :&str, format :CodeFormat) -> Result<String, Error> {
+    match codes::normalize(code, format) {
+        Ok(code) => Ok(code),
+        Err(message) => Err(ApiError::CommandServer(format!("The door controller read '{}', which isn't a {:?} code: {}", code, format, message)))
+    }
+}
+
+/// The code's id is only known once it's read back with the user.
+fn emit_created(user :&UserDetails, code :&str, bus :&EventBus) {
+    if let Some(ac) = user.full.access_codes.iter().find(|ac| ac.code == code) {
+        bus.emit(Event::AccessCodeCreated { id: ac.id, user: user.full.user.name.clone() });
+    }
+}
+
+/// Deletes an access code by its value, written in `format`, whoever it belongs to. Returns the deleted code.
+pub async fn revoke_code<'a>(
+    code :&str,
+    format :CodeFormat,
+    db :&mut DbConnection<'a>
+) -> Result<AccessCode, Error> {
+    let ac :AccessCode = match schema::access_codes::table
+        .select(AccessCode::as_select())
+        .filter(schema::access_codes::columns::code.eq_any(codes::candidates(code, format)))
+    .first(db).await.optional() {
+        Ok(maybe_ac) => match maybe_ac {
+            Some(ac) => ac,
+            None => return Err(ApiError::NotFound(ErrorCode::AccessCodeNotRegistered))
+        },
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
     };
-    
-    match get_full_user(name, &mut conn).await {
-        Ok(user) => Ok(Json(user)),
-        Err(e) => Err(e)
+
+    if let Err(e) = diesel::delete(schema::access_codes::table)
+        .filter(schema::access_codes::columns::id.eq(ac.id))
+    .execute(db).await {
+        return Err(ApiError::Internal(format!("{}", e)))
     }
+
+    Ok(ac)
+}
+
+pub struct CodeNormalization {
+    pub normalized :usize,
+    /// Codes that aren't written in the format, or whose normalized form belongs to another code already.
+    pub kept :Vec<String>
+}
+
+/// Rewrites the codes stored before codes were normalized in their canonical form, taking them as written in `format`.
+pub async fn normalize_codes<'a>(
+    format :CodeFormat,
+    db :&mut DbConnection<'a>
+) -> Result<CodeNormalization, Error> {
+    transaction(db, |conn| async move {
+        let stored :Vec<AccessCode> = match schema::access_codes::table
+            .select(AccessCode::as_select())
+            .order(schema::access_codes::columns::id)
+        .load(conn).await {
+            Ok(stored) => stored,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        let mut result = CodeNormalization { normalized: 0, kept: vec![] };
+        for ac in &stored {
+            let code = match codes::normalize(&ac.code, format) {
+                Ok(code) if code == ac.code => continue,
+                Ok(code) if !stored.iter().any(|other| other.code == code) => code,
+                _ => {
+                    result.kept.push(ac.code.clone());
+                    continue
+                }
+            };
+
+            if let Err(e) = diesel::update(schema::access_codes::table)
+                .filter(schema::access_codes::columns::id.eq(ac.id))
+                .set(schema::access_codes::columns::code.eq(code))
+            .execute(conn).await {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+            result.normalized += 1;
+        }
+
+        Ok(result)
+    }.scope_boxed()).await
 }
 
 #[delete("/<name>/access-codes/<id>")]
 pub async fn delete<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+
     name :&'a str,
     id :i32,
-    db :&State<DB>
+    db :&State<DB>,
+    bus :&State<EventBus>
 ) -> UserResponse {
     let mut conn = get_connection(db).await?;
-    let user = get_user(name, &mut conn).await?;
 
-    if let Err(e) = diesel::delete(schema::access_codes::table)
-        .filter(schema::access_codes::columns::id.eq(id))
-        .filter(schema::access_codes::columns::user.eq(user.id))
-    .execute(&mut conn).await {
-        return Err(ApiError::Internal(format!("{}", e)))
-    }
+    let (user, deleted) = transaction(&mut conn, |conn| async move {
+        let user = get_user(name, conn).await?;
 
-    match get_full_user(name, &mut conn).await {
-        Ok(user) => Ok(Json(user)),
-        Err(e) => Err(e)
+        let deleted = match diesel::delete(schema::access_codes::table)
+            .filter(schema::access_codes::columns::id.eq(id))
+            .filter(schema::access_codes::columns::user.eq(user.id))
+        .execute(conn).await {
+            Ok(count) => count,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        Ok((get_full_user(name, conn).await?, deleted))
+    }.scope_boxed()).await?;
+
+    if deleted > 0 {
+        bus.emit(Event::AccessCodeDeleted { id, user: user.full.user.name.clone() });
     }
+    Ok(Json(user))
 }
\ No newline at end of file
@@ -1,17 +1,75 @@
 pub mod access_codes;
 pub mod permissions;
+pub mod pin;
+pub mod registrations;
 
 use cherrydoor_models::{models::{User, AccessCode, Permission, UserPermission}, full::UserFull, schema::{users, self, users_permissions}, insert::UserInsert, update::UserUpdate};
 use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, BelongingToDsl, OptionalExtension, result};
-use diesel_async::RunQueryDsl;
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
 use rocket::{get, post, patch, delete, serde::json::Json, State, response::status::Created};
+use serde::Serialize;
 
-use crate::{error::ApiError, db::{DB, DbConnection, get_connection}, guards::auth::{Auth, OperatorUser}};
+use crate::{error::{ApiError, ErrorCode, FieldError}, db::{DB, DbConnection, get_connection, transaction, for_update, by_key}, etag::{Tagged, IfMatch}, guards::{auth::{Auth, OperatorUser, Capable}, capabilities::{UsersWrite, PermissionsWrite}}, validation::{Validate, Validated, check_name, check_not_empty, check_text}, models::Group, events::{EventBus, Event}};
+
+use super::groups::{get_user_groups, get_group_permissions};
 
 type Error = ApiError;
 type UsersResponse = Result<Json<Vec<User>>, Error>;
-type UserResponse = Result<Json<UserFull>, Error>;
-type UserResponseCreated = Result<Created<Json<UserFull>>, Error>;
+type UserResponse = Result<Json<UserDetails>, Error>;
+type UserResponseTagged = Result<Tagged<UserDetails>, Error>;
+type UserResponseCreated = Result<Created<Json<UserDetails>>, Error>;
+type UserDeletedResponse = Result<Json<UserDeleted>, Error>;
+
+/// `UserFull` with the groups the user is in and the permissions they have through them.
+#[derive(Serialize)]
+pub struct UserDetails {
+    #[serde(flatten)]
+    pub full :UserFull,
+    pub groups :Vec<Group>,
+    pub group_permissions :Vec<Permission>
+}
+
+/// How many rows referring to a deleted user were deleted with them.
+#[derive(Serialize)]
+pub struct UserRemovals {
+    pub access_codes :usize,
+    pub permissions :usize,
+    pub groups :usize,
+    pub pins :usize,
+    pub occupancy :usize,
+    pub visitor_passes :usize
+}
+
+#[derive(Serialize)]
+pub struct UserDeleted {
+    #[serde(flatten)]
+    pub user :UserDetails,
+    pub removed :UserRemovals
+}
+
+impl Validate for UserInsert {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_name("name", &self.name, errors);
+        check_not_empty("full_name", &self.full_name, errors);
+        check_text("full_name", &self.full_name, errors);
+        check_text("role", &self.role, errors);
+    }
+}
+
+impl Validate for UserUpdate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        if let Some(name) = &self.name {
+            check_name("name", name, errors);
+        }
+        if let Some(full_name) = &self.full_name {
+            check_not_empty("full_name", full_name, errors);
+            check_text("full_name", full_name, errors);
+        }
+        if let Some(role) = &self.role {
+            check_text("role", role, errors);
+        }
+    }
+}
 
 #[get("/?<page>")]
 pub async fn list(
@@ -22,19 +80,7 @@ pub async fn list(
 ) -> UsersResponse {
     let mut conn = get_connection(db).await?;
 
-    let users = match users::table
-        .select(User::as_select())
-        .order(users::columns::id.asc())
-        .limit(10)
-        .offset(10 * page.unwrap_or(0))
-    .load(&mut conn).await {
-        Ok(users) => users,
-        Err(e) => {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
-    };
-
-    Ok(Json(users))
+    Ok(Json(list_users(page.unwrap_or(0), &mut conn).await?))
 }
 
 #[get("/<name>")]
@@ -43,107 +89,210 @@ pub async fn get<'a>(
 
     name :&'a str,
     db :&State<DB>
-) -> UserResponse {
+) -> UserResponseTagged {
     let mut conn = get_connection(db).await?;
     
     match get_full_user(name, &mut conn).await {
-        Ok(user) => Ok(Json(user)),
+        Ok(user) => Ok(Tagged(user)),
         Err(e) => Err(e)
     }
 }
 
 #[post("/", format = "application/json", data = "<user>")]
 pub async fn create(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<UsersWrite>>,
 
-    user :Json<UserInsert>,
-    db :&State<DB>
+    user :Validated<UserInsert>,
+    db :&State<DB>,
+    bus :&State<EventBus>
 ) -> UserResponseCreated {
     let mut conn = get_connection(db).await?;
-    let name = user.0.name.clone();
-
-    if let Err(e) = diesel::insert_into(users::table)
-        .values(user.0)
-    .execute(&mut conn).await {
-        if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
-            return Err(ApiError::Conflict(format!("User {} already exists.", &name)))
-        } else {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
-    }
 
-    match get_full_user(&name, &mut conn).await {
-        Ok(user) => Ok(Created::new(format!("/users/{}", user.user.name)).body(Json(user))),
-        Err(e) => Err(e)
-    }
+    let user = create_user(user.0, &mut conn).await?;
+    bus.emit(Event::UserCreated { id: user.full.user.id, name: user.full.user.name.clone() });
+
+    Ok(Created::new(format!("/users/{}", user.full.user.name)).body(Json(user)))
 }
 
 #[patch("/<name>", format = "application/json", data = "<user>")]
 pub async fn update<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<UsersWrite>>,
+    if_match :IfMatch,
 
     name :&'a str,
-    user :Json<UserUpdate>,
+    user :Validated<UserUpdate>,
     db :&State<DB>
-) -> UserResponse {
+) -> UserResponseTagged {
     let mut conn = get_connection(db).await?;
-    let old_user = get_user(name, &mut conn).await?;
 
-    if let Err(e) = diesel::update(&old_user)
-        .set(&user.0)
-    .execute(&mut conn).await {
-        return Err(ApiError::Internal(format!("{}", e)))
-    }
+    transaction(&mut conn, |conn| async move {
+        let old_user = lock_user(name, conn).await?;
+        if_match.check(&get_full_user(name, conn).await?)?;
 
-    match get_full_user(name, &mut conn).await {
-        Ok(user) => Ok(Json(user)),
-        Err(e) => Err(e)
-    }
+        if let Err(e) = diesel::update(&old_user)
+            .set(&user.0)
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::UserConflict(user.0.name.unwrap_or_default())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        }
+
+        // By id, the name in the URL is gone if the user was renamed.
+        match get_full_user(&old_user.id.to_string(), conn).await {
+            Ok(user) => Ok(Tagged(user)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
 }
 
 #[delete("/<name>")]
 pub async fn delete<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<UsersWrite>>,
 
     name :&'a str,
-    db :&State<DB>
-) -> UserResponse {
+    db :&State<DB>,
+    bus :&State<EventBus>
+) -> UserDeletedResponse {
     let mut conn = get_connection(db).await?;
 
-    let user = get_full_user(name, &mut conn).await?;
-
-    let tasks = vec![
-        diesel::delete(users_permissions::table)
-            .filter(users_permissions::columns::user_id.eq(&user.user.id))
-            .execute(&mut conn).await,
-        diesel::delete(schema::access_codes::table)
-            .filter(schema::access_codes::columns::user.eq(&user.user.id))
-            .execute(&mut conn).await,
-        diesel::delete(&user.user)
-            .execute(&mut conn).await
-    ];
-
-    for i in tasks {
-        if let Err(e) = i {
-            return Err(ApiError::Internal(format!("{}", e)));
-        }
+    let deleted = delete_user(name, &mut conn).await?;
+    bus.emit(Event::UserDeleted { id: deleted.user.full.user.id, name: deleted.user.full.user.name.clone() });
+
+    Ok(Json(deleted))
+}
+
+/// A page of 10 users, ordered by ID.
+pub async fn list_users<'a>(
+    page :i64,
+    db :&mut DbConnection<'a>
+) -> Result<Vec<User>, Error> {
+    match users::table
+        .select(User::as_select())
+        .order(users::columns::id.asc())
+        .limit(10)
+        .offset(10 * page)
+    .load(db).await {
+        Ok(users) => Ok(users),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
     }
+}
 
-    Ok(Json(user))
+pub async fn create_user<'a>(
+    user :UserInsert,
+    db :&mut DbConnection<'a>
+) -> Result<UserDetails, Error> {
+    transaction(db, |conn| async move {
+        let name = user.name.clone();
+
+        if let Err(e) = diesel::insert_into(users::table)
+            .values(user)
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::UserConflict(name.clone())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        }
+
+        get_full_user(&name, conn).await
+    }.scope_boxed()).await
+}
+
+/// Deletes the user along with their access codes, permissions, group memberships, PIN, presence in the room and the visitor passes
+/// they're the host of. Either all of it goes, or nothing does.
+pub async fn delete_user<'a>(
+    name :&str,
+    db :&mut DbConnection<'a>
+) -> Result<UserDeleted, Error> {
+    let name = name.to_string();
+
+    transaction(db, |conn| async move {
+        let user = get_full_user(&name, conn).await?;
+        let id = user.full.user.id;
+
+        let permissions = match diesel::delete(users_permissions::table)
+            .filter(users_permissions::columns::user_id.eq(id))
+        .execute(conn).await {
+            Ok(count) => count,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        let groups = match diesel::delete(crate::schema::groups_users::table)
+            .filter(crate::schema::groups_users::columns::user_id.eq(id))
+        .execute(conn).await {
+            Ok(count) => count,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        let access_codes = match diesel::delete(schema::access_codes::table)
+            .filter(schema::access_codes::columns::user.eq(id))
+        .execute(conn).await {
+            Ok(count) => count,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        let occupancy = match diesel::delete(crate::schema::occupancy::table)
+            .filter(crate::schema::occupancy::columns::user_id.eq(id))
+        .execute(conn).await {
+            Ok(count) => count,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        let pins = match diesel::delete(crate::schema::users_pins::table)
+            .filter(crate::schema::users_pins::columns::user_id.eq(id))
+        .execute(conn).await {
+            Ok(count) => count,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        let visitor_passes = match diesel::delete(crate::schema::visitor_passes::table)
+            .filter(crate::schema::visitor_passes::columns::host_user.eq(id))
+        .execute(conn).await {
+            Ok(count) => count,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        if let Err(e) = diesel::delete(&user.full.user)
+        .execute(conn).await {
+            return Err(ApiError::Internal(format!("{}", e)))
+        }
+
+        Ok(UserDeleted {
+            user,
+            removed: UserRemovals { access_codes, permissions, groups, pins, occupancy, visitor_passes }
+        })
+    }.scope_boxed()).await
 }
 
 async fn get_user<'a, 'v>(
     name :&'v str,
     db :&mut DbConnection<'a>
 ) -> Result<User, Error> {
-    match users::table
-        .select(User::as_select())
-        .filter(users::columns::name.eq(name))
-    .first(db).await.optional() {
+    match by_key!(users::table.select(User::as_select()), users::columns::id, users::columns::name, name, db) {
+        Ok(maybe_user) => {
+            match maybe_user {
+                Some(user) => Ok(user),
+                None => Err(ApiError::NotFound(ErrorCode::UserNotFound(name.to_string())))
+            }
+        }
+        Err(e) => {
+            Err(ApiError::Internal(format!("{}", e)))
+        }
+    }
+}
+
+/// Like `get_user`, but the user stays locked until the transaction ends.
+async fn lock_user<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<User, Error> {
+    match by_key!(for_update!(users::table.select(User::as_select())), users::columns::id, users::columns::name, name, db) {
         Ok(maybe_user) => {
             match maybe_user {
                 Some(user) => Ok(user),
-                None => Err(ApiError::NotFound(format!("User {} not found.", name)))
+                None => Err(ApiError::NotFound(ErrorCode::UserNotFound(name.to_string())))
             }
         }
         Err(e) => {
@@ -195,7 +344,7 @@ async fn get_access_code<'a>(
         Ok(maybe_access_code) => {
             match maybe_access_code {
                 Some(access_code) => Ok(access_code),
-                None => Err(ApiError::NotFound(format!("Access code {} does not exist or does not belong to user {}", id, user.name)))
+                None => Err(ApiError::NotFound(ErrorCode::AccessCodeNotFound { id, user: user.name.clone() }))
             }
         }
         Err(e) => {
@@ -207,12 +356,16 @@ async fn get_access_code<'a>(
 async fn get_full_user<'a, 'v>(
     name :&'v str,
     db :&mut DbConnection<'a>
-) -> Result<UserFull, Error> {
+) -> Result<UserDetails, Error> {
     let user = get_user(name, db).await?;
     let access_codes = get_all_access_codes(&user, db).await?;
     let permissions = get_all_permissions(&user, db).await?;
+    let groups = get_user_groups(&user, db).await?;
+    let group_permissions = get_group_permissions(&groups, db).await?;
 
-    Ok(UserFull {
-        user, access_codes, permissions
+    Ok(UserDetails {
+        full: UserFull { user, access_codes, permissions },
+        groups,
+        group_permissions
     })
 }
\ No newline at end of file
@@ -1,15 +1,19 @@
 use super::*;
+use crate::batch::{BatchChange, Batched, IdOrName, Relation};
 use cherrydoor_models::{insert::UserPermissionInsert, schema::users_permissions};
-use rocket::{get, post, delete};
+use rocket::{get, post, put, delete};
 use serde::Deserialize;
 
 type UserPermissionsResponse = Result<Json<Vec<Permission>>, Error>;
+type UserBatchResponse = Result<Json<Batched<UserDetails>>, Error>;
 
 #[derive(Deserialize)]
 pub struct UserPermissionAppend {
     permission_id :i32
 }
 
+impl Validate for UserPermissionAppend {}
+
 impl UserPermissionAppend {
     pub fn into_insert(self, user_id :i32) -> UserPermissionInsert {
         UserPermissionInsert { 
@@ -42,56 +46,104 @@ pub async fn list<'a>(
 
 #[post("/<name>/permissions", format = "application/json", data = "<permission>")]
 pub async fn assign<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
 
     name :&'a str,
-    permission :Json<UserPermissionAppend>,
+    permission :Validated<UserPermissionAppend>,
     db :&State<DB>
 ) -> UserResponse {
     let mut conn = get_connection(db).await?;
-    let user = get_user(name, &mut conn).await?;
 
-    if let Err(e) = diesel::insert_into(users_permissions::table)
-        .values(permission.0.into_insert(user.id))
-    .execute(&mut conn).await {
-        if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
-            return Err(ApiError::Conflict(format!("User {} already has this permission.", &name)))
-        } else {
-            return Err(ApiError::Internal(format!("{}", e)))
-        }
-    };
+    transaction(&mut conn, |conn| async move {
+        let user = get_user(name, conn).await?;
 
-    match get_full_user(name, &mut conn).await {
-        Ok(user) => Ok(Json(user)),
-        Err(e) => Err(e)
-    }
+        if let Err(e) = diesel::insert_into(users_permissions::table)
+            .values(permission.0.into_insert(user.id))
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::UserPermissionConflict(name.to_string())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match get_full_user(name, conn).await {
+            Ok(user) => Ok(Json(user)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
 }
 
 #[delete("/<name>/permissions/<id>")]
 pub async fn remove<'a>(
-    _auth :Auth<OperatorUser>,
+    _auth :Auth<Capable<PermissionsWrite>>,
 
     name :&'a str,
     id :i32,
     db :&State<DB>
 ) -> UserResponse {
     let mut conn = get_connection(db).await?;
-    let user = get_user(name, &mut conn).await?;
-    
-    match diesel::delete(users_permissions::table)
-        .filter(users_permissions::columns::permission_id.eq(id))
-        .filter(users_permissions::columns::user_id.eq(user.id))
-    .execute(&mut conn).await {
-        Ok(del_count) => {
-            if del_count == 0 {
-                return Err(ApiError::NotFound(format!("Permission {} either does not exist, or does not belong to user {}", id, name)));
+
+    transaction(&mut conn, |conn| async move {
+        let user = get_user(name, conn).await?;
+
+        match diesel::delete(users_permissions::table)
+            .filter(users_permissions::columns::permission_id.eq(id))
+            .filter(users_permissions::columns::user_id.eq(user.id))
+        .execute(conn).await {
+            Ok(del_count) => {
+                if del_count == 0 {
+                    return Err(ApiError::NotFound(ErrorCode::UserPermissionNotFound { id, user: name.to_string() }));
+                }
             }
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
         }
-        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
-    }
 
-    match get_full_user(name, &mut conn).await {
-        Ok(user) => Ok(Json(user)),
-        Err(e) => Err(e)
-    }
-}
\ No newline at end of file
+        match get_full_user(name, conn).await {
+            Ok(user) => Ok(Json(user)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[post("/<name>/permissions/batch", format = "application/json", data = "<change>")]
+pub async fn batch<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    change :Validated<BatchChange>,
+    db :&State<DB>
+) -> UserBatchResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let user = lock_user(name, conn).await?;
+        let changes = Relation::UserPermissions(user.id).change(change.0, conn).await?;
+
+        match get_full_user(name, conn).await {
+            Ok(user) => Ok(Json(Batched { resource: user, changes })),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[put("/<name>/permissions", format = "application/json", data = "<permissions>")]
+pub async fn replace<'a>(
+    _auth :Auth<Capable<PermissionsWrite>>,
+
+    name :&'a str,
+    permissions :Validated<Vec<IdOrName>>,
+    db :&State<DB>
+) -> UserBatchResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let user = lock_user(name, conn).await?;
+        let changes = Relation::UserPermissions(user.id).replace(permissions.0, conn).await?;
+
+        match get_full_user(name, conn).await {
+            Ok(user) => Ok(Json(Batched { resource: user, changes })),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
@@ -0,0 +1,102 @@
+use rocket::put;
+use serde::Deserialize;
+
+use crate::{models::UserPin, schema::users_pins};
+
+use super::*;
+
+#[derive(Deserialize)]
+pub struct UserPinSet {
+    pin :String
+}
+
+impl Validate for UserPinSet {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        if self.pin.len() < 4 || self.pin.len() > 8 || !self.pin.chars().all(|c| c.is_ascii_digit()) {
+            errors.push(FieldError::new("pin", "Must consist of 4 to 8 digits."));
+        }
+    }
+}
+
+#[put("/<name>/pin", format = "application/json", data = "<pin>")]
+pub async fn set<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+
+    name :&'a str,
+    pin :Validated<UserPinSet>,
+    db :&State<DB>
+) -> UserResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        // Locked, so two requests can't both find no PIN and both insert one.
+        let user = lock_user(name, conn).await?;
+
+        let user_pin = match UserPin::new(user.id, &pin.0.pin) {
+            Ok(user_pin) => user_pin,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        let res = if get_pin(user.id, conn).await?.is_some() {
+            diesel::update(&user_pin)
+                .set(&user_pin)
+            .execute(conn).await
+        } else {
+            diesel::insert_into(users_pins::table)
+                .values(&user_pin)
+            .execute(conn).await
+        };
+
+        if let Err(e) = res {
+            return Err(ApiError::Internal(format!("{}", e)))
+        }
+
+        match get_full_user(name, conn).await {
+            Ok(user) => Ok(Json(user)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[delete("/<name>/pin")]
+pub async fn remove<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> UserResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let user = get_user(name, conn).await?;
+
+        match diesel::delete(users_pins::table)
+            .filter(users_pins::columns::user_id.eq(user.id))
+        .execute(conn).await {
+            Ok(del_count) => {
+                if del_count == 0 {
+                    return Err(ApiError::NotFound(ErrorCode::PinNotFound(name.to_string())))
+                }
+            }
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        }
+
+        match get_full_user(name, conn).await {
+            Ok(user) => Ok(Json(user)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+pub async fn get_pin<'a>(
+    user_id :i32,
+    db :&mut DbConnection<'a>
+) -> Result<Option<UserPin>, Error> {
+    match users_pins::table
+        .select(UserPin::as_select())
+        .filter(users_pins::columns::user_id.eq(user_id))
+    .first(db).await.optional() {
+        Ok(maybe_pin) => Ok(maybe_pin),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
@@ -0,0 +1,398 @@
+// Registering a card without holding a request open for it. A session prompts for a card at the door, waits for
+// one in the background, and assigns it to the user unless it already belongs to someone. Sessions live in memory
+// only, a restart forgets them.
+
+use std::{collections::HashMap, sync::{Arc, atomic::{AtomicU32, Ordering}}, time::Duration};
+
+use async_mutex::Mutex;
+use chrono::{NaiveDateTime, Utc};
+use cherrydoor_command::Command;
+use cherrydoor_models::insert::AccessCodeInsert;
+use rocket::{
+    Shutdown, response::stream::{EventStream, Event as SseEvent},
+    tokio::{self, select, sync::{watch, Notify}, time::Instant}
+};
+
+use crate::{db::get_connection, config::{Config, DoorConfig}, door::{Door, DoorError}, routes::access::get_code_owner};
+
+use super::*;
+use super::access_codes::read_code;
+
+/// How long finished sessions can still be looked at.
+const KEEP_FINISHED :Duration = Duration::from_secs(600);
+/// Pause before asking again when the controller gives up waiting for a card before the session does.
+const NO_CARD_RETRY :Duration = Duration::from_secs(1);
+/// Longest a single wait for a card may take. A stream controller can't take commands while waiting, so the
+/// door isn't kept from opening for the whole session.
+const REGISTER_ATTEMPT :Duration = Duration::from_secs(5);
+/// How long the door shows the outcome.
+const FEEDBACK_DURATION :u64 = 3000;
+
+#[derive(Serialize, Clone, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum RegistrationState {
+    Waiting,
+    /// Being assigned.
+    CardRead {code :String},
+    /// The card already belongs to `owner`, it wasn't assigned.
+    Duplicate {code :String, owner :String},
+    Assigned {code :String, access_code_id :i32},
+    Cancelled,
+    Expired,
+    /// `error` is the error code, e.g. `command_server_unreachable`.
+    Failed {error :String}
+}
+
+impl RegistrationState {
+    fn is_finished(&self) -> bool {
+        !matches!(self, Self::Waiting | Self::CardRead {..})
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct Registration {
+    id :u32,
+    user :String,
+    started_at :NaiveDateTime,
+    expires_at :NaiveDateTime,
+    finished_at :Option<NaiveDateTime>,
+    #[serde(flatten)]
+    state :RegistrationState
+}
+
+type SessionState = Arc<watch::Sender<Registration>>;
+
+struct Session {
+    user_id :i32,
+    state :SessionState,
+    cancel :Arc<Notify>
+}
+
+/// Registration sessions, only one of which can wait for a card at a time, as the door has a single reader.
+/// Cloning is cheap, clones share the sessions.
+#[derive(Clone)]
+pub struct Registrations {
+    sessions :Arc<Mutex<HashMap<u32, Session>>>,
+    next_id :Arc<AtomicU32>
+}
+
+impl Registrations {
+    pub fn new() -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            next_id: Arc::new(AtomicU32::new(1))
+        }
+    }
+
+    /// The session, if it's for the user.
+    async fn get(&self, id :u32, user :&User) -> Result<(SessionState, Arc<Notify>), ApiError> {
+        match self.sessions.lock().await.get(&id) {
+            Some(session) if session.user_id == user.id => Ok((session.state.clone(), session.cancel.clone())),
+            _ => Err(ApiError::NotFound(ErrorCode::RegistrationNotFound { id, user: user.name.clone() }))
+        }
+    }
+}
+
+/// Moves the session on, unless it has already finished, e.g. because it was cancelled. Returns whether it moved.
+fn advance(state :&watch::Sender<Registration>, next :RegistrationState) -> bool {
+    state.send_if_modified(|registration| {
+        if registration.state.is_finished() {
+            return false
+        }
+
+        if next.is_finished() {
+            registration.finished_at = Some(Utc::now().naive_utc());
+        }
+        registration.state = next;
+        true
+    })
+}
+
+#[post("/<name>/access-codes/registrations")]
+pub async fn start<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+    registrations :&State<Registrations>,
+    controller :&State<Door>,
+    config :&State<Config>,
+
+    name :&'a str,
+    db :&State<DB>,
+    bus :&State<EventBus>
+) -> Result<Created<Json<Registration>>, Error> {
+    let mut conn = get_connection(db).await?;
+    let user = get_user(name, &mut conn).await?;
+
+    let mut sessions = registrations.sessions.lock().await;
+
+    sessions.retain(|_, session| match session.state.borrow().finished_at {
+        Some(finished_at) => Utc::now().naive_utc() - finished_at < chrono::Duration::from_std(KEEP_FINISHED).unwrap_or(chrono::Duration::zero()),
+        None => true
+    });
+    if sessions.values().any(|session| !session.state.borrow().state.is_finished()) {
+        return Err(ApiError::Conflict(ErrorCode::RegistrationInProgress))
+    }
+
+    let timeout = Duration::from_secs(config.door.registration_timeout);
+    let now = Utc::now().naive_utc();
+    let registration = Registration {
+        id: registrations.next_id.fetch_add(1, Ordering::Relaxed),
+        user: user.name.clone(),
+        started_at: now,
+        expires_at: now + chrono::Duration::from_std(timeout).unwrap_or(chrono::Duration::zero()),
+        finished_at: None,
+        state: RegistrationState::Waiting
+    };
+
+    let state = Arc::new(watch::channel(registration.clone()).0);
+    let cancel = Arc::new(Notify::new());
+
+    tokio::spawn(run(
+        state.clone(),
+        cancel.clone(),
+        config.door.clone(),
+        user.id,
+        Door::clone(controller),
+        DB::clone(db),
+        EventBus::clone(bus)
+    ));
+
+    sessions.insert(registration.id, Session { user_id: user.id, state, cancel });
+
+    Ok(Created::new(format!("/users/{}/access-codes/registrations/{}", user.name, registration.id)).body(Json(registration)))
+}
+
+#[get("/<name>/access-codes/registrations/<id>")]
+pub async fn get<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+    registrations :&State<Registrations>,
+
+    name :&'a str,
+    id :u32,
+    db :&State<DB>
+) -> Result<Json<Registration>, Error> {
+    let mut conn = get_connection(db).await?;
+    let user = get_user(name, &mut conn).await?;
+
+    let (state, _) = registrations.get(id, &user).await?;
+    let registration = state.borrow().clone();
+
+    Ok(Json(registration))
+}
+
+/// Sends the session as it is, then again on every change, and ends once it has finished.
+#[get("/<name>/access-codes/registrations/<id>/events")]
+pub async fn events<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+    registrations :&State<Registrations>,
+
+    name :&'a str,
+    id :u32,
+    db :&State<DB>,
+    mut shutdown :Shutdown
+) -> Result<EventStream![], Error> {
+    let mut conn = get_connection(db).await?;
+    let user = get_user(name, &mut conn).await?;
+
+    let (state, _) = registrations.get(id, &user).await?;
+    let mut state = state.subscribe();
+
+    Ok(EventStream! {
+        loop {
+            let registration = state.borrow_and_update().clone();
+            yield SseEvent::json(&registration);
+
+            if registration.state.is_finished() {
+                break
+            }
+
+            select! {
+                changed = state.changed() => if changed.is_err() { break },
+                _ = &mut shutdown => break
+            }
+        }
+    })
+}
+
+/// Only a session still waiting for a card can be cancelled.
+#[delete("/<name>/access-codes/registrations/<id>")]
+pub async fn cancel<'a>(
+    _auth :Auth<Capable<UsersWrite>>,
+    registrations :&State<Registrations>,
+
+    name :&'a str,
+    id :u32,
+    db :&State<DB>
+) -> Result<Json<Registration>, Error> {
+    let mut conn = get_connection(db).await?;
+    let user = get_user(name, &mut conn).await?;
+
+    let (state, cancel) = registrations.get(id, &user).await?;
+
+    // Checked and changed at once, the card may be getting read right now.
+    let cancelled = state.send_if_modified(|registration| {
+        if registration.state != RegistrationState::Waiting {
+            return false
+        }
+
+        registration.state = RegistrationState::Cancelled;
+        registration.finished_at = Some(Utc::now().naive_utc());
+        true
+    });
+    if !cancelled {
+        return Err(ApiError::Conflict(ErrorCode::RegistrationFinished(id)))
+    }
+    cancel.notify_one();
+
+    let registration = state.borrow().clone();
+    Ok(Json(registration))
+}
+
+/// Waits for a card until the session expires or gets cancelled, then assigns it.
+#[tracing::instrument(name = "registration", skip_all, fields(id = state.borrow().id, user = user_id))]
+async fn run(
+    state :SessionState,
+    cancel :Arc<Notify>,
+    door :DoorConfig,
+    user_id :i32,
+    controller :Door,
+    db :DB,
+    bus :EventBus
+) {
+    let timeout = Duration::from_secs(door.registration_timeout);
+    let deadline = Instant::now() + timeout;
+    let millis = timeout.as_millis() as u64;
+
+    let prompt = Command::new()
+        .display_text_for(String::from("Przyloz karte"), millis)
+        .set_color_for(0, 0, 255, millis);
+    if let Err(e) = controller.send("prompt", &prompt).await {
+        fail(&state, &controller, ApiError::from(e).code()).await;
+        return
+    }
+
+    let mut retry = false;
+    let code = loop {
+        select! {
+            res = read_card(&controller, retry, deadline) => match res {
+                Ok(code) => break code,
+                // The controller, or the attempt, gave up before the session did.
+                Err(DoorError::NoCard) => retry = true,
+                Err(e) => {
+                    fail(&state, &controller, ApiError::from(e).code()).await;
+                    return
+                }
+            },
+            _ = cancel.notified() => {
+                tracing::info!("cancelled");
+                feedback(&controller, "Anulowano", (255, 0, 0)).await;
+                return
+            },
+            _ = tokio::time::sleep_until(deadline) => {
+                tracing::info!("no card was presented in time");
+                if advance(&state, RegistrationState::Expired) {
+                    feedback(&controller, "Anulowano", (255, 0, 0)).await;
+                }
+                return
+            }
+        }
+    };
+
+    let code = match read_code(&code, door.code_format) {
+        Ok(code) => code,
+        Err(e) => {
+            tracing::warn!(error = %e, "the controller read an unexpected code");
+            fail(&state, &controller, e.code()).await;
+            return
+        }
+    };
+
+    // Cancelled just as the card was read.
+    if !advance(&state, RegistrationState::CardRead { code: code.clone() }) {
+        return
+    }
+
+    let outcome = match assign(&code, user_id, &db).await {
+        Ok(Ok(id)) => RegistrationState::Assigned { code, access_code_id: id },
+        Ok(Err(owner)) => RegistrationState::Duplicate { code, owner },
+        Err(e) => {
+            if let ApiError::Internal(message) = &e {
+                tracing::error!(error = %message, "couldn't assign the card");
+            }
+            RegistrationState::Failed { error: String::from(e.code()) }
+        }
+    };
+
+    match &outcome {
+        RegistrationState::Assigned { access_code_id, .. } => {
+            bus.emit(Event::AccessCodeCreated { id: *access_code_id, user: state.borrow().user.clone() });
+            feedback(&controller, "Karta zapisana", (0, 255, 0)).await;
+        },
+        RegistrationState::Duplicate {..} => feedback(&controller, "Karta juz zapisana", (255, 0, 0)).await,
+        RegistrationState::Failed {..} => feedback(&controller, "Blad", (255, 0, 0)).await,
+        _ => {}
+    }
+
+    advance(&state, outcome);
+}
+
+/// Waits for a card for at most `REGISTER_ATTEMPT`, and never past the deadline.
+async fn read_card(controller :&Door, retry :bool, deadline :Instant) -> Result<String, DoorError> {
+    if retry {
+        tokio::time::sleep(NO_CARD_RETRY).await;
+    }
+
+    let attempt = REGISTER_ATTEMPT.min(deadline.saturating_duration_since(Instant::now()));
+    match tokio::time::timeout(attempt, controller.register()).await {
+        Ok(res) => res,
+        Err(_) => Err(DoorError::NoCard)
+    }
+}
+
+/// Ends the session as failed and tells the door, which would otherwise go on asking for a card.
+async fn fail(state :&SessionState, controller :&Door, error :&str) {
+    if advance(state, RegistrationState::Failed { error: String::from(error) }) {
+        feedback(controller, "Blad", (255, 0, 0)).await;
+    }
+}
+
+/// Shows the outcome at the door. It's only a courtesy, so failing to is just logged, by `Door`.
+async fn feedback(controller :&Door, text :&str, (r, g, b) :(u8, u8, u8)) {
+    let command = Command::new()
+        .display_text_for(String::from(text), FEEDBACK_DURATION)
+        .set_color_for(r, g, b, FEEDBACK_DURATION);
+
+    let _ = controller.send("prompt", &command).await;
+}
+
+/// Returns the id of the new access code, or the owner of the card if it's already taken.
+async fn assign(code :&str, user_id :i32, db :&DB) -> Result<Result<i32, String>, ApiError> {
+    let mut conn = get_connection(db).await?;
+    let code = String::from(code);
+
+    transaction(&mut conn, |conn| async move {
+        if let Some(owner) = get_code_owner(std::slice::from_ref(&code), conn).await? {
+            return Ok(Err(owner))
+        }
+
+        // By id, the user may have been renamed since.
+        let user = get_user(&user_id.to_string(), conn).await?;
+
+        if let Err(e) = diesel::insert_into(schema::access_codes::table)
+            .values(AccessCodeInsert { code: code.clone(), user: user.id })
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::AccessCodeConflict))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match schema::access_codes::table
+            .select(schema::access_codes::columns::id)
+            .filter(schema::access_codes::columns::code.eq(&code))
+        .first(conn).await {
+            Ok(id) => Ok(Ok(id)),
+            Err(e) => Err(ApiError::Internal(format!("{}", e)))
+        }
+    }.scope_boxed()).await
+}
@@ -0,0 +1,340 @@
+use chrono::{NaiveDateTime, Utc};
+use cherrydoor_models::{models::User, schema::{users, access_codes}};
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, OptionalExtension, result};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rand::{Rng, distributions::Alphanumeric};
+use rocket::{get, post, delete, serde::json::Json, State, response::status::{Created, NoContent}};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{DB, DbConnection, get_connection, transaction, by_key}, error::{ApiError, ErrorCode, FieldError}, validation::{Validate, Validated, check_not_empty, check_text}, guards::{auth::{Auth, OperatorUser, Capable}, capabilities::VisitorsWrite},
+    models::{VisitorPass, VisitorPassInsert, EmergencyMode}, schema::visitor_passes
+};
+
+use super::{
+    access::{AccessDirection, AccessOutcome, Swipe, PendingEntry, admit, get_user_permissions, get_permitted_profiles},
+    active_access_profile::ActiveAccessProfile, access_profiles::settings::get_settings, occupancy::{self, Entrant}
+};
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VisitorPassKind {
+    #[default]
+    Pin,    // A short numeric code, to be typed on the keypad
+    Qr      // A long alphanumeric code, to be encoded in a QR code
+}
+
+impl VisitorPassKind {
+    fn generate_code(self) -> String {
+        let mut rng = rand::thread_rng();
+
+        match self {
+            Self::Pin => (0..6).map(|_| char::from(b'0' + rng.gen_range(0..10))).collect(),
+            Self::Qr => (0..24).map(|_| char::from(rng.sample(Alphanumeric))).collect()
+        }
+    }
+}
+
+fn default_max_uses() -> i32 {
+    1
+}
+
+#[derive(Deserialize)]
+pub struct VisitorPassCreate {
+    guest_name :String,
+    host :String,
+    valid_from :Option<NaiveDateTime>,
+    valid_until :NaiveDateTime,
+    #[serde(default = "default_max_uses")]
+    max_uses :i32,
+    #[serde(default)]
+    kind :VisitorPassKind
+}
+
+impl Validate for VisitorPassCreate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_not_empty("guest_name", &self.guest_name, errors);
+        check_text("guest_name", &self.guest_name, errors);
+        check_not_empty("host", &self.host, errors);
+
+        if self.max_uses < 1 {
+            errors.push(FieldError::new("max_uses", "Must be at least 1."));
+        }
+        if self.valid_until <= self.valid_from.unwrap_or(Utc::now().naive_utc()) {
+            errors.push(FieldError::new("valid_until", "Must be after valid_from."));
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct VisitorPassFull {
+    #[serde(flatten)]
+    pass :VisitorPass,
+    host :User
+}
+
+type Error = ApiError;
+type VisitorPassesResponse = Result<Json<Vec<VisitorPassFull>>, Error>;
+type VisitorPassResponse = Result<Json<VisitorPassFull>, Error>;
+type VisitorPassResponseCreated = Result<Created<Json<VisitorPassFull>>, Error>;
+
+/// Lists passes that can still be used, or all of them if `all` is set.
+#[get("/?<all>")]
+pub async fn list(
+    _auth :Auth<OperatorUser>,
+
+    all :Option<bool>,
+    db :&State<DB>
+) -> VisitorPassesResponse {
+    let mut conn = get_connection(db).await?;
+
+    let mut query = visitor_passes::table
+        .select(VisitorPass::as_select())
+        .order(visitor_passes::columns::valid_from.asc())
+        .into_boxed();
+
+    if !all.unwrap_or(false) {
+        query = query
+            .filter(visitor_passes::columns::valid_until.ge(Utc::now().naive_utc()))
+            .filter(visitor_passes::columns::uses.lt(visitor_passes::columns::max_uses));
+    }
+
+    let passes :Vec<VisitorPass> = match query.load(&mut conn).await {
+        Ok(passes) => passes,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let hosts :Vec<User> = match users::table
+        .select(User::as_select())
+        .filter(users::columns::id.eq_any(passes.iter().map(|pass| pass.host_user)))
+    .load(&mut conn).await {
+        Ok(hosts) => hosts,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    Ok(Json(passes.into_iter().filter_map(|pass| {
+        hosts.iter().find(|host| host.id == pass.host_user).map(|host| VisitorPassFull {
+            host: host.clone(),
+            pass
+        })
+    }).collect()))
+}
+
+#[get("/<id>")]
+pub async fn get(
+    _auth :Auth<OperatorUser>,
+
+    id :i32,
+    db :&State<DB>
+) -> VisitorPassResponse {
+    let mut conn = get_connection(db).await?;
+
+    match get_full_pass(id, &mut conn).await {
+        Ok(pass) => Ok(Json(pass)),
+        Err(e) => Err(e)
+    }
+}
+
+#[post("/", format = "application/json", data = "<pass>")]
+pub async fn create(
+    _auth :Auth<Capable<VisitorsWrite>>,
+
+    pass :Validated<VisitorPassCreate>,
+    db :&State<DB>
+) -> VisitorPassResponseCreated {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let pass = pass.0;
+        let valid_from = pass.valid_from.unwrap_or(Utc::now().naive_utc());
+
+        let host :User = match by_key!(users::table.select(User::as_select()), users::columns::id, users::columns::name, &pass.host, conn) {
+            Ok(maybe_host) => match maybe_host {
+                Some(host) => host,
+                None => return Err(ApiError::NotFound(ErrorCode::UserNotFound(pass.host.clone())))
+            },
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        // Codes are random, so in the unlikely case of a collision just try another one.
+        for _ in 0..5 {
+            let code = pass.kind.generate_code();
+
+            match access_codes::table
+                .count()
+                .filter(access_codes::columns::code.eq(&code))
+            .get_result::<i64>(conn).await {
+                Ok(0) => {},
+                Ok(_) => continue,
+                Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+            }
+
+            let insert = VisitorPassInsert {
+                guest_name: pass.guest_name.clone(),
+                host_user: host.id,
+                code: code.clone(),
+                valid_from,
+                valid_until: pass.valid_until,
+                max_uses: pass.max_uses,
+                uses: 0
+            };
+
+            match diesel::insert_into(visitor_passes::table)
+                .values(insert)
+            .execute(conn).await {
+                Ok(_) => {},
+                Err(result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _)) => continue,
+                Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+            }
+
+            let new_pass = match get_pass_by_code(&code, conn).await? {
+                Some(new_pass) => new_pass,
+                None => return Err(ApiError::Internal(String::from("Visitor pass disappeared after creation.")))
+            };
+
+            return Ok(Created::new(format!("/visitors/{}", new_pass.id)).body(Json(VisitorPassFull {
+                pass: new_pass,
+                host
+            })))
+        }
+
+        Err(ApiError::Conflict(ErrorCode::VisitorCodeConflict))
+    }.scope_boxed()).await
+}
+
+#[delete("/<id>")]
+pub async fn delete(
+    _auth :Auth<Capable<VisitorsWrite>>,
+
+    id :i32,
+    db :&State<DB>
+) -> VisitorPassResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let pass = get_full_pass(id, conn).await?;
+
+        if let Err(e) = diesel::delete(&pass.pass)
+        .execute(conn).await {
+            return Err(ApiError::Internal(format!("{}", e)))
+        }
+
+        Ok(Json(pass))
+    }.scope_boxed()).await
+}
+
+/// Decides whether a code that doesn't belong to any user is a visitor pass allowing entry. Visitors go through
+/// the same rules of the active profile as everyone else, see `access::admit`.
+pub async fn use_pass<'a>(
+    code :&str,
+    direction :AccessDirection,
+    aacp :&ActiveAccessProfile,
+    swipe :&Swipe<'_>,
+    pending :&mut Option<PendingEntry>,
+    db :&mut DbConnection<'a>
+) -> Result<AccessOutcome, Error> {
+    let pass = match get_pass_by_code(code, db).await? {
+        Some(pass) => pass,
+        None => return Err(ApiError::NotFound(ErrorCode::AccessCodeNotRegistered))
+    };
+    let visitor = Entrant::Visitor(pass.id);
+
+    if direction == AccessDirection::Exit {
+        occupancy::leave(visitor, db).await?;
+        return Ok(AccessOutcome::Granted(NoContent))
+    }
+
+    match aacp.emergency().await {
+        EmergencyMode::Normal => {},
+        EmergencyMode::Lockdown => return Err(ApiError::BadRequest(ErrorCode::Lockdown)),
+        EmergencyMode::Evacuation => return Ok(AccessOutcome::Granted(NoContent))
+    }
+
+    let now = Utc::now().naive_utc();
+    if now < pass.valid_from {
+        return Err(ApiError::BadRequest(ErrorCode::VisitorPassNotYetValid))
+    }
+    if now > pass.valid_until {
+        return Err(ApiError::BadRequest(ErrorCode::VisitorPassExpired))
+    }
+    if pass.uses >= pass.max_uses {
+        return Err(ApiError::BadRequest(ErrorCode::VisitorPassUsedUp))
+    }
+
+    // Visitors may only come in when their host could.
+    let host :User = match users::table
+        .select(User::as_select())
+        .filter(users::columns::id.eq(pass.host_user))
+    .first(db).await {
+        Ok(host) => host,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let perms = get_user_permissions(&host, db).await?;
+    let aps = get_permitted_profiles(&perms, db).await?;
+
+    let active_profile = match aacp.get().await {
+        Some(profile) if aps.iter().any(|prof| prof.id == profile.id) => profile,
+        _ => return Err(ApiError::BadRequest(ErrorCode::HostNotPermitted))
+    };
+
+    let settings = get_settings(&active_profile, db).await?;
+
+    admit(visitor, &settings, None, swipe, pending, db).await
+}
+
+/// Counts a use of the pass. The limit is checked in the update itself, so two swipes at once can't both use the
+/// last entry.
+pub async fn count_use<'a>(
+    pass_id :i32,
+    db :&mut DbConnection<'a>
+) -> Result<(), Error> {
+    match diesel::update(visitor_passes::table)
+        .filter(visitor_passes::columns::id.eq(pass_id))
+        .filter(visitor_passes::columns::uses.lt(visitor_passes::columns::max_uses))
+        .set(visitor_passes::columns::uses.eq(visitor_passes::columns::uses + 1))
+    .execute(db).await {
+        Ok(0) => Err(ApiError::BadRequest(ErrorCode::VisitorPassUsedUp)),
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+async fn get_pass_by_code<'a>(
+    code :&str,
+    db :&mut DbConnection<'a>
+) -> Result<Option<VisitorPass>, Error> {
+    match visitor_passes::table
+        .select(VisitorPass::as_select())
+        .filter(visitor_passes::columns::code.eq(code))
+    .first(db).await.optional() {
+        Ok(maybe_pass) => Ok(maybe_pass),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+async fn get_full_pass<'a>(
+    id :i32,
+    db :&mut DbConnection<'a>
+) -> Result<VisitorPassFull, Error> {
+    let pass :VisitorPass = match visitor_passes::table
+        .select(VisitorPass::as_select())
+        .filter(visitor_passes::columns::id.eq(id))
+    .first(db).await.optional() {
+        Ok(maybe_pass) => match maybe_pass {
+            Some(pass) => pass,
+            None => return Err(ApiError::NotFound(ErrorCode::VisitorPassNotFound(id)))
+        },
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    let host :User = match users::table
+        .select(User::as_select())
+        .filter(users::columns::id.eq(pass.host_user))
+    .first(db).await {
+        Ok(host) => host,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    Ok(VisitorPassFull { pass, host })
+}
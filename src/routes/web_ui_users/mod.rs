@@ -0,0 +1,307 @@
+pub mod roles;
+
+use cherrydoor_models::{models::WebUIUser, insert::WebUIUserInsert, update::WebUIUserUpdate, schema::web_ui_users};
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, result};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rocket::{get, post, patch, delete, serde::json::Json, State, response::status::Created};
+use serde::{Serialize, Deserialize};
+
+use crate::{db::{DB, DbConnection, get_connection, transaction, for_update, by_key}, etag::{Tagged, IfMatch}, error::{ApiError, ErrorCode, FieldError}, validation::{Validate, Validated, check_name, check_not_empty}, guards::{auth::{Auth, OperatorUser, Capable}, capabilities::WebUIUsersManage}, schema::web_ui_users_roles};
+
+#[derive(Serialize)]
+pub struct WebUIUserOutput {
+    pub id :i32,
+    pub name :String,
+    pub is_admin :bool,
+    pub ac_does_not_expire :bool
+}
+
+#[derive(Deserialize)]
+pub struct WebUIUserCreate {
+    pub name :String,
+    pub password :String,
+    pub is_admin :bool,
+    pub ac_does_not_expire :bool
+}
+
+#[derive(Deserialize)]
+pub struct WebUIUserPatch {
+    pub password :Option<String>,
+    pub is_admin :Option<bool>,
+    pub ac_does_not_expire :Option<bool>
+}
+
+impl Validate for WebUIUserCreate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_name("name", &self.name, errors);
+        check_not_empty("password", &self.password, errors);
+    }
+}
+
+impl Validate for WebUIUserPatch {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        if let Some(password) = &self.password {
+            check_not_empty("password", password, errors);
+        }
+    }
+}
+
+// Safety measure
+impl From<WebUIUser> for WebUIUserOutput {
+    fn from(user :WebUIUser) -> Self {
+        Self {
+            id: user.id,
+            name: user.name,
+            is_admin: user.is_admin,
+            ac_does_not_expire: user.ac_does_not_expire
+        }
+    }
+}
+
+impl WebUIUserCreate {
+    fn into_insert(self) -> WebUIUserInsert {
+        WebUIUserInsert { 
+            name: self.name, 
+            password_hash: sha256::digest(self.password),
+            is_admin: self.is_admin,
+            ac_does_not_expire: self.ac_does_not_expire
+        }
+    }
+}
+
+impl WebUIUserPatch {
+    fn into_update(self) -> WebUIUserUpdate {
+        WebUIUserUpdate {
+            password_hash: self.password.map(sha256::digest),
+            is_admin: self.is_admin,
+            ac_does_not_expire: self.ac_does_not_expire
+        }
+    }
+}
+
+type Error = ApiError;
+type WebUIUsersResponse = Result<Json<Vec<WebUIUserOutput>>, Error>;
+type WebUIUserResponse = Result<Json<WebUIUserOutput>, Error>;
+type WebUIUserResponseTagged = Result<Tagged<WebUIUserOutput>, Error>;
+type WebUIUserCreatedResponse = Result<Created<Json<WebUIUserOutput>>, Error>;
+
+
+#[get("/")]
+pub async fn list(
+    _auth :Auth<OperatorUser>,
+
+    db :&State<DB>
+) -> WebUIUsersResponse {
+    let mut conn = get_connection(db).await?;
+
+    let wu_users :Vec<WebUIUser> = match web_ui_users::table
+        .select(WebUIUser::as_select())
+    .load(&mut conn).await {
+        Ok(wu_users) => wu_users,
+        Err(e) => {
+            return Err(ApiError::Internal(format!("{}", e)))
+        }
+    };
+
+    Ok(Json(wu_users.into_iter().map(|v| { v.into() }).collect()))
+}
+
+#[get("/<name>")]
+pub async fn get<'a>(
+    _auth :Auth<OperatorUser>,
+
+    name :&'a str,
+    db: &State<DB>
+) -> WebUIUserResponseTagged {
+    let mut conn = get_connection(db).await?;
+
+    let wu_user = get_wu_user(name, &mut conn).await?;
+
+    Ok(Tagged(wu_user.into()))
+}
+
+#[post("/", format = "application/json", data = "<wu_user>")]
+pub async fn create(
+    auth :Auth<Capable<WebUIUsersManage>>,
+
+    wu_user :Validated<WebUIUserCreate>,
+    db :&State<DB>
+) -> WebUIUserCreatedResponse {
+    // Otherwise anyone managing users could make themselves an admin.
+    if wu_user.0.is_admin && !auth.claim.is_admin {
+        return Err(ApiError::Forbidden(ErrorCode::AdminRequired))
+    }
+
+    let mut conn = get_connection(db).await?;
+
+    let wu_user = create_web_ui_user(wu_user.0, &mut conn).await?;
+
+    Ok(Created::new(format!("/web-ui-users/{}", &wu_user.name)).body(Json(wu_user.into())))
+}
+
+#[patch("/<name>", format = "application/json", data = "<wu_user>")]
+pub async fn update<'a>(
+    auth :Auth<Capable<WebUIUsersManage>>,
+    if_match :IfMatch,
+
+    name :&'a str,
+    wu_user :Validated<WebUIUserPatch>,
+    db :&State<DB>
+) -> WebUIUserResponseTagged {
+    if wu_user.0.is_admin.is_some() && !auth.claim.is_admin {
+        return Err(ApiError::Forbidden(ErrorCode::AdminRequired))
+    }
+
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let old_wu_user = lock_wu_user(name, conn).await?;
+        // Otherwise anyone managing users could set an admin's password and log in as them.
+        if old_wu_user.is_admin && !auth.claim.is_admin {
+            return Err(ApiError::Forbidden(ErrorCode::AdminTargetRequiresAdmin))
+        }
+        if_match.check(&WebUIUserOutput::from(old_wu_user))?;
+
+        Ok(Tagged(update_web_ui_user(name, wu_user.0, conn).await?.into()))
+    }.scope_boxed()).await
+}
+
+#[delete("/<name>")]
+pub async fn delete<'a>(
+    auth :Auth<Capable<WebUIUsersManage>>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> WebUIUserResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let wu_user = lock_wu_user(name, conn).await?;
+
+        if wu_user.is_admin && !auth.claim.is_admin {
+            return Err(ApiError::Forbidden(ErrorCode::AdminTargetRequiresAdmin))
+        }
+
+        let tasks = [
+            diesel::delete(web_ui_users_roles::table)
+                .filter(web_ui_users_roles::columns::web_ui_user_id.eq(wu_user.id))
+                .execute(conn).await,
+            diesel::delete(&wu_user)
+                .execute(conn).await
+        ];
+
+        for i in tasks {
+            if let Err(e) = i {
+                return Err(ApiError::Internal(format!("{}", e)));
+            }
+        }
+
+
+        Ok(Json(wu_user.into()))
+    }.scope_boxed()).await
+}
+
+async fn get_wu_user<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<WebUIUser, Error> {
+    match by_key!(web_ui_users::table.select(WebUIUser::as_select()), web_ui_users::columns::id, web_ui_users::columns::name, name, db) {
+        Ok(maybe_wu_user) => {
+            match maybe_wu_user {
+                Some(wu_user) => Ok(wu_user),
+                None => Err(ApiError::NotFound(ErrorCode::WebUIUserNotFound(name.to_string())))
+            }
+        },
+        Err(e) => {
+            Err(ApiError::Internal(format!("{}", e)))
+        }
+    }
+}
+
+/// Like `get_wu_user`, but the user stays locked until the transaction ends.
+async fn lock_wu_user<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<WebUIUser, Error> {
+    match by_key!(for_update!(web_ui_users::table.select(WebUIUser::as_select())), web_ui_users::columns::id, web_ui_users::columns::name, name, db) {
+        Ok(maybe_wu_user) => {
+            match maybe_wu_user {
+                Some(wu_user) => Ok(wu_user),
+                None => Err(ApiError::NotFound(ErrorCode::WebUIUserNotFound(name.to_string())))
+            }
+        },
+        Err(e) => {
+            Err(ApiError::Internal(format!("{}", e)))
+        }
+    }
+}
+
+/// Creates the user. Whether the caller may grant admin rights is up to them.
+pub async fn create_web_ui_user<'a>(
+    wu_user :WebUIUserCreate,
+    db :&mut DbConnection<'a>
+) -> Result<WebUIUser, Error> {
+    transaction(db, |conn| async move {
+        let name = wu_user.name.clone();
+
+        if let Err(e) = diesel::insert_into(web_ui_users::table)
+            .values(wu_user.into_insert())
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::WebUIUserConflict(name.clone())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        }
+
+        let wu_user :WebUIUser = match web_ui_users::table
+            .select(WebUIUser::as_select())
+            .filter(web_ui_users::columns::name.eq(name))
+        .first(conn).await {
+            Ok(wu_user) => wu_user,
+            Err(e) => {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        Ok(wu_user)
+    }.scope_boxed()).await
+}
+
+pub async fn update_web_ui_user<'a>(
+    name :&str,
+    wu_user :WebUIUserPatch,
+    db :&mut DbConnection<'a>
+) -> Result<WebUIUser, Error> {
+    let name = name.to_string();
+
+    transaction(db, |conn| async move {
+        let name = name.as_str();
+        let old_wu_user = lock_wu_user(name, conn).await?;
+
+        match diesel::update(&old_wu_user)
+            .set(wu_user.into_update())
+        .execute(conn).await {
+            Ok(updated) => {
+                if updated == 0 {
+                    return Err(ApiError::NotFound(ErrorCode::WebUIUserNotFound(name.to_string())));
+                }
+            },
+            Err(e) => {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        let new_wu_user = match web_ui_users::table
+            .select(WebUIUser::as_select())
+            .filter(web_ui_users::columns::id.eq(old_wu_user.id))
+        .first(conn).await {
+            Ok(new_wu_user) => new_wu_user,
+            Err(e) => {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        Ok(new_wu_user)
+    }.scope_boxed()).await
+}
@@ -0,0 +1,128 @@
+use crate::{models::{Role, WebUIUserRole}, schema::{roles, roles_capabilities, web_ui_users_roles}, routes::roles::check_held};
+
+use super::*;
+
+type RolesResponse = Result<Json<Vec<Role>>, Error>;
+
+#[derive(Deserialize)]
+pub struct WebUIUserRoleAppend {
+    role_id :i32
+}
+
+impl Validate for WebUIUserRoleAppend {}
+
+impl WebUIUserRoleAppend {
+    pub fn into_insert(self, web_ui_user_id :i32) -> WebUIUserRole {
+        WebUIUserRole {
+            web_ui_user_id,
+            role_id: self.role_id
+        }
+    }
+}
+
+#[get("/<name>/roles")]
+pub async fn list<'a>(
+    _auth :Auth<OperatorUser>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> RolesResponse {
+    let mut conn = get_connection(db).await?;
+    let wu_user = get_wu_user(name, &mut conn).await?;
+
+    match get_all_roles(&wu_user, &mut conn).await {
+        Ok(roles) => Ok(Json(roles)),
+        Err(e) => Err(e)
+    }
+}
+
+#[post("/<name>/roles", format = "application/json", data = "<role>")]
+pub async fn assign<'a>(
+    auth :Auth<Capable<WebUIUsersManage>>,
+
+    name :&'a str,
+    role :Validated<WebUIUserRoleAppend>,
+    db :&State<DB>
+) -> RolesResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let wu_user = get_wu_user(name, conn).await?;
+
+        if wu_user.name == auth.claim.name {
+            return Err(ApiError::Forbidden(ErrorCode::OwnRolesLocked))
+        }
+
+        let capabilities :Vec<String> = match roles_capabilities::table
+            .select(roles_capabilities::columns::capability)
+            .filter(roles_capabilities::columns::role_id.eq(role.0.role_id))
+        .load(conn).await {
+            Ok(capabilities) => capabilities,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+        check_held(&auth.claim, &capabilities)?;
+
+        if let Err(e) = diesel::insert_into(web_ui_users_roles::table)
+            .values(role.0.into_insert(wu_user.id))
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::WebUIUserRoleConflict(name.to_string())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        };
+
+        match get_all_roles(&wu_user, conn).await {
+            Ok(roles) => Ok(Json(roles)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[delete("/<name>/roles/<id>")]
+pub async fn remove<'a>(
+    _auth :Auth<Capable<WebUIUsersManage>>,
+
+    name :&'a str,
+    id :i32,
+    db :&State<DB>
+) -> RolesResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let wu_user = get_wu_user(name, conn).await?;
+
+        match diesel::delete(web_ui_users_roles::table)
+            .filter(web_ui_users_roles::columns::role_id.eq(id))
+            .filter(web_ui_users_roles::columns::web_ui_user_id.eq(wu_user.id))
+        .execute(conn).await {
+            Ok(del_count) => {
+                if del_count == 0 {
+                    return Err(ApiError::NotFound(ErrorCode::WebUIUserRoleNotFound { id, user: name.to_string() }))
+                }
+            }
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        }
+
+        match get_all_roles(&wu_user, conn).await {
+            Ok(roles) => Ok(Json(roles)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+async fn get_all_roles<'a>(
+    wu_user :&WebUIUser,
+    db :&mut DbConnection<'a>
+) -> Result<Vec<Role>, Error> {
+    let rwr :Vec<(WebUIUserRole, Role)> = match web_ui_users_roles::table
+        .inner_join(roles::table)
+        .filter(web_ui_users_roles::columns::web_ui_user_id.eq(wu_user.id))
+        .select((WebUIUserRole::as_select(), Role::as_select()))
+    .load(db).await {
+        Ok(rwr) => rwr,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    Ok(rwr.into_iter().map(|v| { v.1 }).collect())
+}
@@ -0,0 +1,357 @@
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods, BelongingToDsl, result};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use rand::{Rng, distributions::Alphanumeric};
+use rocket::{get, post, patch, delete, serde::json::Json, State, response::status::{Created, Custom}, http::Status};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{DB, DbConnection, get_connection, transaction, for_update, by_key}, etag::{Tagged, IfMatch}, error::{ApiError, ErrorCode, FieldError}, validation::{Validate, Validated, check_name, check_length, check_url},
+    guards::{auth::{Auth, Capable}, capabilities::WebhooksManage}, events::{self, Event}, webhooks::Dispatcher,
+    models::{Webhook, WebhookInsert, WebhookUpdate, WebhookEvent, WebhookDelivery}, schema::{webhooks, webhooks_events, webhook_deliveries}
+};
+
+/// Matching the columns.
+const URL_MAX_LEN :usize = 2048;
+const SECRET_MAX_LEN :usize = 255;
+
+/// How many deliveries `GET /<name>/deliveries` returns.
+const DELIVERIES_SHOWN :i64 = 100;
+
+#[derive(Serialize)]
+pub struct WebhookFull {
+    #[serde(flatten)]
+    pub webhook :Webhook,
+    pub events :Vec<String>
+}
+
+#[derive(Deserialize)]
+pub struct WebhookCreate {
+    name :String,
+    url :String,
+    secret :Option<String>,     // Generated if not given
+    events :Vec<String>,
+    #[serde(default = "active_by_default")]
+    active :bool
+}
+
+fn active_by_default() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct WebhookPatch {
+    name :Option<String>,
+    url :Option<String>,
+    secret :Option<String>,
+    events :Option<Vec<String>>,    // Replaces all events of the webhook
+    active :Option<bool>
+}
+
+impl Validate for WebhookCreate {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        check_name("name", &self.name, errors);
+        check_url("url", &self.url, URL_MAX_LEN, errors);
+        if let Some(secret) = &self.secret {
+            check_secret(secret, errors);
+        }
+        check_events(&self.events, errors);
+    }
+}
+
+impl Validate for WebhookPatch {
+    fn validate(&self, errors :&mut Vec<FieldError>) {
+        if let Some(name) = &self.name {
+            check_name("name", name, errors);
+        }
+        if let Some(url) = &self.url {
+            check_url("url", url, URL_MAX_LEN, errors);
+        }
+        if let Some(secret) = &self.secret {
+            check_secret(secret, errors);
+        }
+        if let Some(events) = &self.events {
+            check_events(events, errors);
+        }
+    }
+}
+
+type Error = ApiError;
+type WebhooksResponse = Result<Json<Vec<Webhook>>, Error>;
+type WebhookResponse = Result<Json<WebhookFull>, Error>;
+type WebhookResponseTagged = Result<Tagged<WebhookFull>, Error>;
+type WebhookResponseCreated = Result<Created<Json<WebhookFull>>, Error>;
+
+#[get("/")]
+pub async fn list(
+    _auth :Auth<Capable<WebhooksManage>>,
+
+    db :&State<DB>
+) -> WebhooksResponse {
+    let mut conn = get_connection(db).await?;
+
+    match webhooks::table
+        .select(Webhook::as_select())
+    .load(&mut conn).await {
+        Ok(webhooks) => Ok(Json(webhooks)),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+#[get("/<name>")]
+pub async fn get<'a>(
+    _auth :Auth<Capable<WebhooksManage>>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> WebhookResponseTagged {
+    let mut conn = get_connection(db).await?;
+
+    match get_full_webhook(name, &mut conn).await {
+        Ok(webhook) => Ok(Tagged(webhook)),
+        Err(e) => Err(e)
+    }
+}
+
+#[post("/", format = "application/json", data = "<webhook>")]
+pub async fn create(
+    _auth :Auth<Capable<WebhooksManage>>,
+
+    webhook :Validated<WebhookCreate>,
+    db :&State<DB>
+) -> WebhookResponseCreated {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let webhook = webhook.0;
+
+        if let Err(e) = diesel::insert_into(webhooks::table)
+            .values(WebhookInsert {
+                name: webhook.name.clone(),
+                url: webhook.url,
+                secret: webhook.secret.unwrap_or_else(generate_secret),
+                active: webhook.active
+            })
+        .execute(conn).await {
+            if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                return Err(ApiError::Conflict(ErrorCode::WebhookConflict(webhook.name.clone())))
+            } else {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+        }
+
+        let new_webhook = get_webhook(&webhook.name, conn).await?;
+        set_events(&new_webhook, webhook.events, conn).await?;
+
+        match get_full_webhook(&webhook.name, conn).await {
+            Ok(webhook) => Ok(Created::new(format!("/webhooks/{}", webhook.webhook.name)).body(Json(webhook))),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+#[patch("/<name>", format = "application/json", data = "<webhook>")]
+pub async fn update<'a>(
+    _auth :Auth<Capable<WebhooksManage>>,
+    if_match :IfMatch,
+
+    name :&'a str,
+    webhook :Validated<WebhookPatch>,
+    db :&State<DB>
+) -> WebhookResponseTagged {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let old_webhook = lock_webhook(name, conn).await?;
+        if_match.check(&get_full_webhook(name, conn).await?)?;
+        let webhook = webhook.0;
+
+        if webhook.name.is_some() || webhook.url.is_some() || webhook.secret.is_some() || webhook.active.is_some() {
+            let new_name = webhook.name.clone();
+            if let Err(e) = diesel::update(&old_webhook)
+                .set(WebhookUpdate { name: webhook.name, url: webhook.url, secret: webhook.secret, active: webhook.active })
+            .execute(conn).await {
+                if let result::Error::DatabaseError(result::DatabaseErrorKind::UniqueViolation, _) = e {
+                    return Err(ApiError::Conflict(ErrorCode::WebhookConflict(new_name.unwrap_or_default())))
+                } else {
+                    return Err(ApiError::Internal(format!("{}", e)))
+                }
+            }
+        }
+
+        if let Some(events) = webhook.events {
+            set_events(&old_webhook, events, conn).await?;
+        }
+
+        match get_full_webhook(&old_webhook.id.to_string(), conn).await {
+            Ok(webhook) => Ok(Tagged(webhook)),
+            Err(e) => Err(e)
+        }
+    }.scope_boxed()).await
+}
+
+/// Also deletes the delivery log of the webhook.
+#[delete("/<name>")]
+pub async fn delete<'a>(
+    _auth :Auth<Capable<WebhooksManage>>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> WebhookResponse {
+    let mut conn = get_connection(db).await?;
+
+    transaction(&mut conn, |conn| async move {
+        let webhook = get_full_webhook(name, conn).await?;
+
+        let tasks = [
+            diesel::delete(webhooks_events::table)
+                .filter(webhooks_events::columns::webhook_id.eq(webhook.webhook.id))
+                .execute(conn).await,
+            diesel::delete(webhook_deliveries::table)
+                .filter(webhook_deliveries::columns::webhook_id.eq(webhook.webhook.id))
+                .execute(conn).await,
+            diesel::delete(&webhook.webhook).execute(conn).await
+        ];
+
+        for i in tasks {
+            if let Err(e) = i {
+                return Err(ApiError::Internal(format!("{}", e)));
+            }
+        }
+
+        Ok(Json(webhook))
+    }.scope_boxed()).await
+}
+
+/// The latest deliveries, newest first.
+#[get("/<name>/deliveries")]
+pub async fn deliveries<'a>(
+    _auth :Auth<Capable<WebhooksManage>>,
+
+    name :&'a str,
+    db :&State<DB>
+) -> Result<Json<Vec<WebhookDelivery>>, Error> {
+    let mut conn = get_connection(db).await?;
+    let webhook = get_webhook(name, &mut conn).await?;
+
+    match WebhookDelivery::belonging_to(&webhook)
+        .select(WebhookDelivery::as_select())
+        .order(webhook_deliveries::columns::id.desc())
+        .limit(DELIVERIES_SHOWN)
+    .load(&mut conn).await {
+        Ok(deliveries) => Ok(Json(deliveries)),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Sends a `ping` event to the webhook, even if it's inactive. The delivery is returned before the first attempt.
+#[post("/<name>/test")]
+pub async fn test<'a>(
+    _auth :Auth<Capable<WebhooksManage>>,
+
+    name :&'a str,
+    db :&State<DB>,
+    dispatcher :&State<Dispatcher>
+) -> Result<Custom<Json<WebhookDelivery>>, Error> {
+    let mut conn = get_connection(db).await?;
+    let webhook = get_webhook(name, &mut conn).await?;
+
+    let delivery = dispatcher.send(webhook, &Event::Ping, &mut conn).await?;
+
+    Ok(Custom(Status::Accepted, Json(delivery)))
+}
+
+fn generate_secret() -> String {
+    rand::thread_rng().sample_iter(Alphanumeric).take(32).map(char::from).collect()
+}
+
+fn check_secret(secret :&str, errors :&mut Vec<FieldError>) {
+    if secret.is_empty() {
+        errors.push(FieldError::new("secret", "Can't be empty."));
+    }
+    check_length("secret", secret, SECRET_MAX_LEN, errors);
+}
+
+fn check_events(events :&[String], errors :&mut Vec<FieldError>) {
+    if events.is_empty() {
+        errors.push(FieldError::new("events", "Can't be empty, the webhook would never be called."));
+    }
+    for (i, event) in events.iter().enumerate() {
+        if !events::ALL.contains(&event.as_str()) {
+            errors.push(FieldError::new(&format!("events[{}]", i), &format!("Unknown event {}.", event)));
+        }
+    }
+}
+
+async fn set_events<'a>(
+    webhook :&Webhook,
+    events :Vec<String>,
+    db :&mut DbConnection<'a>
+) -> Result<(), Error> {
+    if let Err(e) = diesel::delete(webhooks_events::table)
+        .filter(webhooks_events::columns::webhook_id.eq(webhook.id))
+    .execute(db).await {
+        return Err(ApiError::Internal(format!("{}", e)))
+    }
+
+    let mut events = events;
+    events.sort();
+    events.dedup();
+
+    let inserts :Vec<WebhookEvent> = events.into_iter().map(|event| WebhookEvent {
+        webhook_id: webhook.id,
+        event
+    }).collect();
+
+    match diesel::insert_into(webhooks_events::table)
+        .values(inserts)
+    .execute(db).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+async fn get_webhook<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<Webhook, Error> {
+    match by_key!(webhooks::table.select(Webhook::as_select()), webhooks::columns::id, webhooks::columns::name, name, db) {
+        Ok(maybe_webhook) => match maybe_webhook {
+            Some(webhook) => Ok(webhook),
+            None => Err(ApiError::NotFound(ErrorCode::WebhookNotFound(name.to_string())))
+        },
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+/// Like `get_webhook`, but the webhook stays locked until the transaction ends.
+async fn lock_webhook<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<Webhook, Error> {
+    match by_key!(for_update!(webhooks::table.select(Webhook::as_select())), webhooks::columns::id, webhooks::columns::name, name, db) {
+        Ok(maybe_webhook) => match maybe_webhook {
+            Some(webhook) => Ok(webhook),
+            None => Err(ApiError::NotFound(ErrorCode::WebhookNotFound(name.to_string())))
+        },
+        Err(e) => Err(ApiError::Internal(format!("{}", e)))
+    }
+}
+
+async fn get_full_webhook<'a, 'v>(
+    name :&'v str,
+    db :&mut DbConnection<'a>
+) -> Result<WebhookFull, Error> {
+    let webhook = get_webhook(name, db).await?;
+
+    let events :Vec<WebhookEvent> = match WebhookEvent::belonging_to(&webhook)
+        .select(WebhookEvent::as_select())
+    .load(db).await {
+        Ok(events) => events,
+        Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+    };
+
+    Ok(WebhookFull {
+        webhook,
+        events: events.into_iter().map(|v| { v.event }).collect()
+    })
+}
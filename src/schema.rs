@@ -0,0 +1,143 @@
+// Tables used only by the web server. Tables shared with the rest of the system live in cherrydoor_models::schema.
+// Diesel can't join across crates, so relations to those tables are resolved with separate queries.
+
+diesel::table! {
+    occupancy (user_id) {
+        user_id -> Integer,
+        entered_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    access_profiles_settings (access_profile_id) {
+        access_profile_id -> Integer,
+        anti_passback -> Bool,
+        max_occupancy -> Nullable<Integer>,
+        entry_rule -> Varchar,
+        two_person_window -> Integer,
+    }
+}
+
+diesel::table! {
+    users_pins (user_id) {
+        user_id -> Integer,
+        pin_hash -> Varchar,
+    }
+}
+
+diesel::table! {
+    emergency_state (id) {
+        id -> Integer,
+        mode -> Varchar,
+        changed_by -> Varchar,
+        changed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    active_access_profile (id) {
+        id -> Integer,
+        access_profile_id -> Integer,
+    }
+}
+
+diesel::table! {
+    visitor_passes (id) {
+        id -> Integer,
+        guest_name -> Varchar,
+        host_user -> Integer,
+        code -> Varchar,
+        valid_from -> Timestamp,
+        valid_until -> Timestamp,
+        max_uses -> Integer,
+        uses -> Integer,
+        entered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    roles (id) {
+        id -> Integer,
+        name -> Varchar,
+        description -> Varchar,
+    }
+}
+
+diesel::table! {
+    roles_capabilities (role_id, capability) {
+        role_id -> Integer,
+        capability -> Varchar,
+    }
+}
+
+diesel::table! {
+    web_ui_users_roles (web_ui_user_id, role_id) {
+        web_ui_user_id -> Integer,
+        role_id -> Integer,
+    }
+}
+
+diesel::table! {
+    groups (id) {
+        id -> Integer,
+        name -> Varchar,
+        description -> Varchar,
+    }
+}
+
+diesel::table! {
+    groups_users (group_id, user_id) {
+        group_id -> Integer,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    groups_permissions (group_id, permission_id) {
+        group_id -> Integer,
+        permission_id -> Integer,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Integer,
+        name -> Varchar,
+        url -> Varchar,
+        secret -> Varchar,
+        active -> Bool,
+    }
+}
+
+diesel::table! {
+    webhooks_events (webhook_id, event) {
+        webhook_id -> Integer,
+        event -> Varchar,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Integer,
+        webhook_id -> Integer,
+        event -> Varchar,
+        payload -> Text,
+        status -> Varchar,
+        attempts -> Integer,
+        response_status -> Nullable<Integer>,
+        error -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_attempt_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::joinable!(roles_capabilities -> roles (role_id));
+diesel::joinable!(web_ui_users_roles -> roles (role_id));
+diesel::joinable!(groups_users -> groups (group_id));
+diesel::joinable!(groups_permissions -> groups (group_id));
+diesel::joinable!(webhooks_events -> webhooks (webhook_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
+
+diesel::allow_tables_to_appear_in_same_query!(roles, roles_capabilities, web_ui_users_roles);
+diesel::allow_tables_to_appear_in_same_query!(groups, groups_users, groups_permissions);
+diesel::allow_tables_to_appear_in_same_query!(webhooks, webhooks_events, webhook_deliveries);
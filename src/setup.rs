@@ -0,0 +1,142 @@
+use std::{error::Error, sync::Arc, time::Duration};
+
+use async_mutex::Mutex;
+use cherrydoor_models::schema::{access_profiles, web_ui_users, AccessProfileAccessMode};
+use diesel::{QueryDsl, ExpressionMethods};
+use diesel_async::RunQueryDsl;
+use rand::{Rng, distributions::Alphanumeric};
+
+use crate::{db::{DB, DbConnection, get_connection}, migrations, config::BootstrapConfig, routes::{active_access_profile::ActiveAccessProfile, web_ui_users::{WebUIUserCreate, create_web_ui_user}}};
+
+/// How long to wait before trying again, when the database or the command server isn't up yet.
+const RETRY_INTERVAL :Duration = Duration::from_secs(5);
+
+/// The token for creating the first admin with `POST /setup`, while there are no web UI users.
+#[derive(Clone)]
+pub struct SetupToken(pub Arc<Mutex<Option<String>>>);
+
+impl SetupToken {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(None)))
+    }
+}
+
+/// Gets the database and the door ready: applies migrations, fills in what a fresh installation lacks
+/// and pushes the active profile. If that fails, the server starts anyway and keeps retrying in the background.
+pub async fn run(
+    db :&DB,
+    run_migrations :bool,
+    bootstrap :BootstrapConfig,
+    aacp :&ActiveAccessProfile,
+    token :SetupToken
+) {
+    if let Err(e) = try_run(db, run_migrations, &bootstrap, aacp, &token).await {
+        tracing::warn!(error = %e, "couldn't finish the setup, retrying in the background");
+
+        let db = db.clone();
+        let aacp = aacp.clone();
+
+        rocket::tokio::spawn(async move {
+            loop {
+                rocket::tokio::time::sleep(RETRY_INTERVAL).await;
+
+                match try_run(&db, run_migrations, &bootstrap, &aacp, &token).await {
+                    Ok(_) => {
+                        tracing::info!("setup finished");
+                        break
+                    },
+                    Err(e) => tracing::warn!(error = %e, "couldn't finish the setup")
+                }
+            }
+        });
+    }
+}
+
+async fn try_run(
+    db :&DB,
+    run_migrations :bool,
+    bootstrap :&BootstrapConfig,
+    aacp :&ActiveAccessProfile,
+    token :&SetupToken
+) -> Result<(), Box<dyn Error>> {
+    let mut conn = get_connection(db).await?;
+
+    if run_migrations {
+        migrations::run(&mut conn).await?;
+    }
+
+    create_default_profile(&mut conn).await?;
+    create_first_admin(&mut conn, bootstrap, token).await?;
+
+    drop(conn);
+
+    // A profile set from the web UI in the meantime mustn't be overwritten.
+    if !aacp.is_in_sync() {
+        aacp.sync(db).await?;
+    }
+
+    Ok(())
+}
+
+/// The door needs some profile to be active.
+async fn create_default_profile<'a>(conn :&mut DbConnection<'a>) -> Result<(), Box<dyn Error>> {
+    let count :i64 = access_profiles::table.count().get_result(conn).await?;
+
+    if count > 0 {
+        return Ok(())
+    }
+
+    tracing::info!("no access profiles, creating the default one");
+
+    diesel::insert_into(access_profiles::table)
+        .values((
+            access_profiles::columns::name.eq("default"),
+            access_profiles::columns::description.eq("Created on the first start."),
+            access_profiles::columns::display_text.eq("Cherrydoor"),
+            access_profiles::columns::color.eq("white"),
+            access_profiles::columns::access_mode.eq(AccessProfileAccessMode::CheckAccess)
+        ))
+    .execute(conn).await?;
+
+    Ok(())
+}
+
+/// Without web UI users nobody could log in. Creates the admin from the configuration, or lets the first
+/// person holding the logged token do it.
+async fn create_first_admin<'a>(
+    conn :&mut DbConnection<'a>,
+    bootstrap :&BootstrapConfig,
+    token :&SetupToken
+) -> Result<(), Box<dyn Error>> {
+    let count :i64 = web_ui_users::table.count().get_result(conn).await?;
+
+    if count > 0 {
+        return Ok(())
+    }
+
+    let password = match &bootstrap.admin_password {
+        Some(password) => password,
+        None => {
+            let mut token = token.0.lock().await;
+
+            if token.is_none() {
+                let new_token :String = rand::thread_rng().sample_iter(Alphanumeric).take(32).map(char::from).collect();
+                tracing::warn!(token = %new_token, "no web UI users, create the first admin with POST /setup and this token");
+                *token = Some(new_token);
+            }
+
+            return Ok(())
+        }
+    };
+
+    tracing::info!(name = %bootstrap.admin_name, "no web UI users, creating the admin from the configuration");
+
+    create_web_ui_user(WebUIUserCreate {
+        name: bootstrap.admin_name.clone(),
+        password: password.clone(),
+        is_admin: true,
+        ac_does_not_expire: false
+    }, conn).await?;
+
+    Ok(())
+}
@@ -0,0 +1,367 @@
+use chrono::Utc;
+use cherrydoor_command::Command;
+use cherrydoor_models::{insert::AccessCodeInsert, schema::access_codes};
+use diesel_async::RunQueryDsl;
+use rocket::http::Status;
+use serde_json::{json, Value};
+
+use super::*;
+use crate::{models::{UserPin, EmergencyState, EmergencyMode}, schema::emergency_state, db::{DB, get_connection}, codes::CodeFormat, routes::users::access_codes::{normalize_codes, revoke_code}};
+
+/// Adds alice, allowed in during the default profile with the card `1111`, and bob, with the card `2222`
+/// and no permissions.
+async fn add_users(app :&TestApp, token :&str) {
+    let (_, permission) = app.post("/permissions", token, json!({"name": "staff", "description": ""})).await;
+    let permission_id = permission["id"].clone();
+
+    app.post("/users", token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.post("/users/alice/access-codes", token, json!({"code": "1111"})).await;
+    app.post("/users/alice/permissions", token, json!({"permission_id": permission_id})).await;
+    app.post("/access-profiles/default/permissions", token, json!({"permission_id": permission_id})).await;
+
+    app.post("/users", token, json!({"name": "bob", "full_name": "Bob Nowak", "role": ""})).await;
+    app.post("/users/bob/access-codes", token, json!({"code": "2222"})).await;
+}
+
+#[rocket::async_test]
+async fn permitted_users_get_in() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::NoContent);
+
+    let (_, occupancy) = app.get("/occupancy", &token).await;
+    assert_eq!(occupancy["count"], 1);
+}
+
+#[rocket::async_test]
+async fn users_without_permission_are_denied() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    let (status, error) = app.swipe(json!({"code": "2222"})).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["code"], "access_denied");
+
+    // Leaving is always allowed.
+    let (status, _) = app.swipe(json!({"code": "2222", "direction": "exit"})).await;
+    assert_eq!(status, Status::NoContent);
+}
+
+#[rocket::async_test]
+async fn an_emergency_declared_elsewhere_applies_to_swipes() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    // As another server sharing the database would save it.
+    let mut conn = get_connection(app.client.rocket().state::<DB>().unwrap()).await.unwrap();
+    diesel::insert_into(emergency_state::table)
+        .values(EmergencyState { id: EmergencyState::ID, mode: EmergencyMode::Lockdown, changed_by: String::from("elsewhere"), changed_at: Utc::now().naive_utc() })
+    .execute(&mut conn).await.unwrap();
+
+    let (status, error) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["code"], "lockdown");
+}
+
+#[rocket::async_test]
+async fn unknown_codes_are_rejected() {
+    let app = TestApp::start().await;
+
+    let (status, error) = app.swipe(json!({"code": "9999"})).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(error["code"], "access_code_not_registered");
+}
+
+#[rocket::async_test]
+async fn anti_passback_needs_an_exit_before_the_next_entry() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    let (status, _) = app.patch("/access-profiles/default/settings", &token, json!({"anti_passback": true})).await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::NoContent);
+
+    let (status, error) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["code"], "anti_passback");
+
+    let (status, _) = app.swipe(json!({"code": "1111", "direction": "exit"})).await;
+    assert_eq!(status, Status::NoContent);
+
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::NoContent);
+}
+
+#[rocket::async_test]
+async fn card_and_pin_waits_for_the_pin() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    app.patch("/access-profiles/default/settings", &token, json!({"entry_rule": "card_and_pin"})).await;
+    let (status, _) = app.put("/users/alice/pin", &token, json!({"pin": "4321"})).await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, pending) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::Accepted);
+    assert!(pending["reason"].is_string());
+
+    let (status, error) = app.swipe(json!({"code": "1111", "pin": "0000"})).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["code"], "wrong_pin");
+
+    let (status, _) = app.swipe(json!({"code": "1111", "pin": "4321"})).await;
+    assert_eq!(status, Status::NoContent);
+}
+
+#[rocket::async_test]
+async fn wrong_pins_lock_the_card_out() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    app.patch("/access-profiles/default/settings", &token, json!({"entry_rule": "card_and_pin"})).await;
+    app.put("/users/alice/pin", &token, json!({"pin": "4321"})).await;
+
+    // 5 by default.
+    for pin in ["0000", "0001", "0002", "0003", "0004"] {
+        let (status, error) = app.swipe(json!({"code": "1111", "pin": pin})).await;
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["code"], "wrong_pin");
+    }
+
+    // Not even the right PIN gets through now.
+    let (status, error) = app.swipe(json!({"code": "1111", "pin": "4321"})).await;
+    assert_eq!(status, Status::TooManyRequests);
+    assert_eq!(error["code"], "too_many_wrong_pins");
+}
+
+#[test]
+fn pins_are_hashed_with_a_salt_of_their_own() {
+    let pin = UserPin::new(1, "4321").unwrap();
+    let again = UserPin::new(1, "4321").unwrap();
+
+    assert!(pin.pin_hash.starts_with("$argon2"));
+    assert_ne!(pin.pin_hash, again.pin_hash);
+    assert!(pin.matches("4321") && again.matches("4321"));
+    assert!(!pin.matches("4320"));
+}
+
+/// Swipes from the reader at `address`.
+async fn swipe_from(app :&TestApp, address :&str, data :Value) -> (Status, Value) {
+    let res = app.client.post("/access/code")
+        .remote(address.parse().unwrap())
+        .json(&data)
+    .dispatch().await;
+
+    (res.status(), body(res).await)
+}
+
+#[rocket::async_test]
+async fn visitors_go_through_the_same_rules() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    let (_, pass) = app.post("/visitors", &token, json!({
+        "guest_name": "Jan Kowalski", "host": "alice", "valid_until": "2099-01-01T00:00:00", "max_uses": 5
+    })).await;
+    app.patch("/access-profiles/default/settings", &token, json!({"anti_passback": true, "max_occupancy": 1})).await;
+
+    let (status, _) = app.swipe(json!({"code": pass["code"]})).await;
+    assert_eq!(status, Status::NoContent);
+    let (_, occupancy) = app.get("/occupancy", &token).await;
+    assert_eq!(occupancy["count"], 1);
+    assert_eq!(occupancy["visitors"][0]["guest_name"], "Jan Kowalski");
+
+    let (_, error) = app.swipe(json!({"code": pass["code"]})).await;
+    assert_eq!(error["code"], "anti_passback");
+    let (_, error) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(error["code"], "room_full");
+
+    let (status, _) = app.swipe(json!({"code": pass["code"], "direction": "exit"})).await;
+    assert_eq!(status, Status::NoContent);
+
+    app.patch("/access-profiles/default/settings", &token, json!({"max_occupancy": null, "entry_rule": "two_person"})).await;
+    let (status, _) = app.swipe(json!({"code": pass["code"]})).await;
+    assert_eq!(status, Status::Accepted);
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::NoContent);
+    let (_, occupancy) = app.get("/occupancy", &token).await;
+    assert_eq!(occupancy["count"], 2);
+
+    app.patch("/access-profiles/default/settings", &token, json!({"anti_passback": false, "entry_rule": "card_and_pin"})).await;
+    let (_, error) = app.swipe(json!({"code": pass["code"]})).await;
+    assert_eq!(error["code"], "pin_required");
+
+    // Refused entries weren't counted.
+    let (_, pass) = app.get(&format!("/visitors/{}", pass["id"]), &token).await;
+    assert_eq!(pass["uses"], 2);
+}
+
+#[rocket::async_test]
+async fn guessing_codes_locks_out_visitor_passes() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    let (status, pass) = app.post("/visitors", &token, json!({
+        "guest_name": "Jan Kowalski", "host": "alice", "valid_until": "2099-01-01T00:00:00", "max_uses": 5
+    })).await;
+    assert_eq!(status, Status::Created);
+
+    // 10 by default.
+    for code in 0..10 {
+        let (status, _) = swipe_from(&app, "10.0.0.1:1000", json!({"code": format!("9{:05}", code)})).await;
+        assert_eq!(status, Status::NotFound);
+    }
+
+    // Everyone's guesses come from the controller, so the door refuses passes until the window ends, even a
+    // visitor who has a valid one and didn't guess anything.
+    let (status, error) = swipe_from(&app, "10.0.0.1:1000", json!({"code": pass["code"]})).await;
+    assert_eq!(status, Status::TooManyRequests);
+    assert_eq!(error["code"], "too_many_unknown_codes");
+
+    // Cards still work, and other doors aren't affected.
+    let (status, _) = swipe_from(&app, "10.0.0.1:1000", json!({"code": "1111"})).await;
+    assert_eq!(status, Status::NoContent);
+    let (status, _) = swipe_from(&app, "10.0.0.2:1000", json!({"code": pass["code"]})).await;
+    assert_eq!(status, Status::NoContent);
+}
+
+#[rocket::async_test]
+async fn two_person_rule_needs_a_second_card() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    let (_, permission) = app.get("/permissions/staff", &token).await;
+    app.post("/users/bob/permissions", &token, json!({"permission_id": permission["id"]})).await;
+    app.patch("/access-profiles/default/settings", &token, json!({"entry_rule": "two_person"})).await;
+
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::Accepted);
+
+    // The same card again doesn't count as a second person.
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::Accepted);
+
+    let (status, _) = app.swipe(json!({"code": "2222"})).await;
+    assert_eq!(status, Status::NoContent);
+
+    let (_, occupancy) = app.get("/occupancy", &token).await;
+    assert_eq!(occupancy["count"], 2);
+}
+
+#[rocket::async_test]
+async fn a_failed_second_swipe_leaves_the_first_one_waiting() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    let (_, permission) = app.get("/permissions/staff", &token).await;
+    app.post("/users/bob/permissions", &token, json!({"permission_id": permission["id"]})).await;
+    app.patch("/access-profiles/default/settings", &token, json!({"entry_rule": "two_person"})).await;
+
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::Accepted);
+
+    // Letting alice in fails once she's gone, and bob's entry is rolled back with it.
+    app.delete("/users/alice", &token).await;
+    for _ in 0..2 {
+        let (status, _) = app.swipe(json!({"code": "2222"})).await;
+        assert_eq!(status, Status::InternalServerError);
+    }
+
+    let (_, occupancy) = app.get("/occupancy", &token).await;
+    assert_eq!(occupancy["count"], 0);
+}
+
+#[rocket::async_test]
+async fn opening_from_the_web_ui_sends_a_command() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    app.mock.clear_commands().await;
+
+    let (status, _) = app.post("/access/open", &token, json!({})).await;
+    assert_eq!(status, Status::NoContent);
+
+    let duration :u16 = 5000;
+    assert_eq!(app.mock.commands().await, vec![command(Command::new()
+        .open_for(duration.into())
+        .display_text_for("Wejdz".to_string(), duration.into())
+        .set_color_for(0, 0, 0, duration.into())
+        .play_sound(1)
+    )]);
+}
+
+#[rocket::async_test]
+async fn opening_reports_a_failing_command_server() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    app.mock.reject_commands(Some(500)).await;
+
+    let (status, error) = app.post("/access/open", &token, json!({})).await;
+    assert_eq!(status, Status::BadGateway);
+    assert_eq!(error["code"], "command_server_error");
+}
+
+#[rocket::async_test]
+async fn codes_match_however_they_are_written() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    let (status, user) = app.post("/users/alice/access-codes", &token, json!({"code": "04:a1:b2:c3"})).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(user["access_codes"][1]["code"], "04A1B2C3");
+
+    let (status, _) = app.swipe(json!({"code": "04a1b2c3"})).await;
+    assert_eq!(status, Status::NoContent);
+
+    let (status, _) = app.post("/users/bob/access-codes", &token, json!({"code": "0x04A1B2C3"})).await;
+    assert_eq!(status, Status::Conflict);
+
+    let (_, user) = app.post("/users/bob/access-codes", &token, json!({"code": "123/45678", "format": "wiegand26"})).await;
+    assert_eq!(user["access_codes"][1]["code"], "7BB26E");
+
+    let (status, error) = app.post("/users/bob/access-codes", &token, json!({"code": "12:zz"})).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(error["fields"][0]["field"], "code");
+}
+
+#[rocket::async_test]
+async fn codes_stored_before_normalization_can_be_normalized() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_users(&app, &token).await;
+
+    let (_, bob) = app.get("/users/bob", &token).await;
+    let bob_id = bob["id"].as_i64().unwrap() as i32;
+
+    let mut conn = get_connection(app.client.rocket().state::<DB>().unwrap()).await.unwrap();
+    for code in ["c3:b2:a1:04", "0x1111", "not a card"] {
+        diesel::insert_into(access_codes::table)
+            .values(AccessCodeInsert { code: code.to_string(), user: bob_id })
+        .execute(&mut conn).await.unwrap();
+    }
+
+    let result = normalize_codes(CodeFormat::Hex, &mut conn).await.unwrap();
+    assert_eq!(result.normalized, 1);
+    // Alice's 1111 has the form 0x1111 would take.
+    assert_eq!(result.kept, vec![String::from("0x1111"), String::from("not a card")]);
+
+    let (_, bob) = app.get("/users/bob", &token).await;
+    assert!(bob["access_codes"].as_array().unwrap().iter().any(|ac| ac["code"] == "C3B2A104"));
+
+    let revoked = revoke_code("c3b2a104", CodeFormat::Hex, &mut conn).await.unwrap();
+    assert_eq!(revoked.code, "C3B2A104");
+}
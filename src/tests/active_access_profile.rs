@@ -0,0 +1,112 @@
+use cherrydoor_command::Command;
+use cherrydoor_models::schema::AccessProfileAccessMode;
+use rocket::http::Status;
+use serde_json::json;
+
+use super::*;
+
+#[rocket::async_test]
+async fn the_first_profile_is_pushed_at_startup() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    assert_eq!(app.mock.commands().await, vec![
+        command(Command::new().close()),
+        command(Command::new().display_text(String::from("Cherrydoor")).set_color(255, 255, 255))
+    ]);
+
+    let (status, profile) = app.get("/active-profile", &token).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(profile["name"], "default");
+}
+
+#[rocket::async_test]
+async fn activating_a_profile_pushes_it_to_the_door() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    let (status, _) = app.post("/access-profiles", &token, json!({
+        "name": "open-day",
+        "description": "",
+        "display_text": "Otwarte",
+        "color": "#00ff00",
+        "access_mode": AccessProfileAccessMode::OpenLock
+    })).await;
+    assert_eq!(status, Status::Created);
+
+    app.mock.clear_commands().await;
+
+    let (status, _) = app.post("/active-profile", &token, json!({"name": "open-day"})).await;
+    assert_eq!(status, Status::NoContent);
+
+    assert_eq!(app.mock.commands().await, vec![
+        command(Command::new().close()),
+        command(Command::new().display_text(String::from("Otwarte")).set_color(0, 255, 0).open())
+    ]);
+
+    let (_, profile) = app.get("/active-profile", &token).await;
+    assert_eq!(profile["name"], "open-day");
+}
+
+#[rocket::async_test]
+async fn activating_an_unknown_profile_fails() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    app.mock.clear_commands().await;
+
+    let (status, error) = app.post("/active-profile", &token, json!({"name": "nope"})).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(error["code"], "access_profile_not_found");
+    assert!(app.mock.commands().await.is_empty());
+}
+
+#[rocket::async_test]
+async fn a_rejected_push_shows_in_readiness() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    let res = app.client.get("/readyz").dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+
+    app.mock.reject_commands(Some(500)).await;
+
+    let (status, _) = app.post("/active-profile", &token, json!({"name": "default"})).await;
+    assert_eq!(status, Status::BadGateway);
+
+    let res = app.client.get("/readyz").dispatch().await;
+    assert_eq!(res.status(), Status::ServiceUnavailable);
+}
+
+#[rocket::async_test]
+async fn a_command_server_answering_with_an_error_is_not_ready() {
+    let app = TestApp::start().await;
+    app.mock.reject_commands(Some(503)).await;
+
+    let res = app.client.get("/readyz").dispatch().await;
+    assert_eq!(res.status(), Status::ServiceUnavailable);
+    assert_eq!(body(res).await["command_server"]["message"], "The door controller responded with status 503 Service Unavailable.");
+}
+
+#[rocket::async_test]
+async fn profiles_cant_be_changed_during_an_emergency() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    let (status, _) = app.post("/emergency/lockdown", &token, json!({})).await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, error) = app.post("/active-profile", &token, json!({"name": "default"})).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["code"], "emergency_active");
+
+    // Clearing the emergency puts the active profile back on the door.
+    app.mock.clear_commands().await;
+
+    let (status, _) = app.delete("/emergency", &token).await;
+    assert_eq!(status, Status::Ok);
+
+    assert_eq!(app.mock.commands().await, vec![
+        command(Command::new().close()),
+        command(Command::new().display_text(String::from("Cherrydoor")).set_color(255, 255, 255))
+    ]);
+}
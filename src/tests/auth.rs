@@ -0,0 +1,185 @@
+use rocket::http::Status;
+use serde_json::json;
+
+use super::*;
+
+#[rocket::async_test]
+async fn login_gives_a_token_accepted_by_the_routes() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    let (status, _) = app.get("/users", &token).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn login_with_a_wrong_password_is_rejected() {
+    let app = TestApp::start().await;
+
+    let res = app.client.post("/auth")
+        .json(&json!({"name": ADMIN, "password": "not the password"}))
+    .dispatch().await;
+
+    assert_eq!(res.status(), Status::Forbidden);
+    assert_eq!(body(res).await["code"], "bad_password");
+}
+
+#[rocket::async_test]
+async fn login_of_an_unknown_user_is_rejected() {
+    let app = TestApp::start().await;
+
+    let res = app.client.post("/auth")
+        .json(&json!({"name": "nobody", "password": "password"}))
+    .dispatch().await;
+
+    assert_eq!(res.status(), Status::NotFound);
+    assert_eq!(body(res).await["code"], "web_ui_user_not_found");
+}
+
+#[rocket::async_test]
+async fn routes_require_a_valid_token() {
+    let app = TestApp::start().await;
+
+    let res = app.client.get("/users").dispatch().await;
+    assert_eq!(res.status(), Status::Unauthorized);
+
+    let (status, _) = app.get("/users", "not-a-token").await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn only_admins_manage_web_ui_users() {
+    let app = TestApp::start().await;
+    let admin = app.admin_token().await;
+
+    let (status, _) = app.post("/web-ui-users", &admin, json!({
+        "name": "operator",
+        "password": "operator-password",
+        "is_admin": false,
+        "ac_does_not_expire": false
+    })).await;
+    assert_eq!(status, Status::Created);
+
+    let operator = app.login("operator", "operator-password").await;
+
+    let (status, _) = app.get("/users", &operator).await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, _) = app.post("/web-ui-users", &operator, json!({
+        "name": "another",
+        "password": "password",
+        "is_admin": true,
+        "ac_does_not_expire": false
+    })).await;
+    assert_eq!(status, Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn managers_can_not_touch_admins() {
+    let app = TestApp::start().await;
+    let admin = app.admin_token().await;
+
+    let (_, role) = app.post("/roles", &admin, json!({
+        "name": "managers", "description": "", "capabilities": ["webui_users.manage"]
+    })).await;
+    app.post("/web-ui-users", &admin, json!({
+        "name": "manager", "password": "manager-password", "is_admin": false, "ac_does_not_expire": false
+    })).await;
+    app.post("/web-ui-users/manager/roles", &admin, json!({"role_id": role["id"]})).await;
+    let manager = app.login("manager", "manager-password").await;
+
+    let (status, error) = app.patch(&format!("/web-ui-users/{}", ADMIN), &manager, json!({"password": "taken-over"})).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(error["code"], "admin_target_requires_admin");
+
+    let (status, error) = app.delete(&format!("/web-ui-users/{}", ADMIN), &manager).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(error["code"], "admin_target_requires_admin");
+
+    app.login(ADMIN, ADMIN_PASSWORD).await;
+
+    // Other users are still theirs to manage.
+    let (status, _) = app.patch("/web-ui-users/manager", &manager, json!({"ac_does_not_expire": true})).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn managers_can_not_grant_more_than_they_have() {
+    let app = TestApp::start().await;
+    let admin = app.admin_token().await;
+
+    let (_, managers) = app.post("/roles", &admin, json!({
+        "name": "managers", "description": "", "capabilities": ["webui_users.manage"]
+    })).await;
+    let (_, openers) = app.post("/roles", &admin, json!({
+        "name": "openers", "description": "", "capabilities": ["door.open"]
+    })).await;
+    for name in ["manager", "operator"] {
+        app.post("/web-ui-users", &admin, json!({
+            "name": name, "password": "password", "is_admin": false, "ac_does_not_expire": false
+        })).await;
+    }
+    app.post("/web-ui-users/manager/roles", &admin, json!({"role_id": managers["id"]})).await;
+    let manager = app.login("manager", "password").await;
+
+    let (status, error) = app.post("/roles", &manager, json!({
+        "name": "everything", "description": "", "capabilities": ["webui_users.manage", "door.open"]
+    })).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(error["code"], "capability_not_held");
+
+    let (status, error) = app.patch("/roles/managers", &manager, json!({"capabilities": ["door.open"]})).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(error["code"], "capability_not_held");
+
+    let (status, error) = app.post("/web-ui-users/operator/roles", &manager, json!({"role_id": openers["id"]})).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(error["code"], "capability_not_held");
+
+    let (status, error) = app.post("/web-ui-users/manager/roles", &manager, json!({"role_id": openers["id"]})).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(error["code"], "own_roles_locked");
+
+    let (status, _) = app.post("/web-ui-users/operator/roles", &manager, json!({"role_id": managers["id"]})).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn revoked_capabilities_apply_to_tokens_already_handed_out() {
+    let app = TestApp::start().await;
+    let admin = app.admin_token().await;
+
+    let (_, role) = app.post("/roles", &admin, json!({
+        "name": "managers", "description": "", "capabilities": ["webui_users.manage"]
+    })).await;
+    app.post("/web-ui-users", &admin, json!({
+        "name": "manager", "password": "password", "is_admin": false, "ac_does_not_expire": false
+    })).await;
+    app.post("/web-ui-users/manager/roles", &admin, json!({"role_id": role["id"]})).await;
+    let manager = app.login("manager", "password").await;
+
+    let (status, _) = app.post("/roles", &manager, json!({"name": "empty", "description": ""})).await;
+    assert_eq!(status, Status::Created);
+
+    app.delete(&format!("/web-ui-users/manager/roles/{}", role["id"]), &admin).await;
+
+    let (status, _) = app.post("/roles", &manager, json!({"name": "another", "description": ""})).await;
+    assert_eq!(status, Status::Forbidden);
+
+    app.delete("/web-ui-users/manager", &admin).await;
+
+    let (status, _) = app.get("/users", &manager).await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn setup_is_closed_once_an_admin_exists() {
+    let app = TestApp::start().await;
+
+    let res = app.client.post("/setup")
+        .json(&json!({"token": "guess", "name": "intruder", "password": "password"}))
+    .dispatch().await;
+
+    assert_eq!(res.status(), Status::Conflict);
+    assert_eq!(body(res).await["code"], "already_set_up");
+}
@@ -0,0 +1,60 @@
+use rocket::http::Status;
+use serde_json::json;
+
+use super::*;
+
+#[rocket::async_test]
+async fn batches_report_what_happened_to_each_item() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let (_, staff) = app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+    app.post("/permissions", &token, json!({"name": "students", "description": ""})).await;
+    app.post("/permissions", &token, json!({"name": "guests", "description": ""})).await;
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.post("/users/alice/permissions", &token, json!({"permission_id": staff["id"]})).await;
+
+    let (status, user) = app.post("/users/alice/permissions/batch", &token, json!({
+        "add": [staff["id"], "students"],
+        "remove": ["guests"]
+    })).await;
+    assert_eq!(status, Status::Ok);
+
+    let statuses :Vec<&str> = user["changes"].as_array().unwrap().iter().map(|c| c["status"].as_str().unwrap()).collect();
+    assert_eq!(statuses, ["already_assigned", "added", "not_assigned"]);
+    assert_eq!(user["changes"][1]["item"], "students");
+    assert_eq!(user["permissions"].as_array().unwrap().len(), 2);
+}
+
+#[rocket::async_test]
+async fn a_missing_item_fails_the_whole_batch() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+
+    let (status, error) = app.post("/permissions/staff/users/batch", &token, json!({"add": ["alice", "bob", 9999]})).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(error["fields"][0]["field"], "add[1]");
+    assert_eq!(error["fields"][1]["field"], "add[2]");
+
+    let (_, users) = app.get("/permissions/staff/users", &token).await;
+    assert_eq!(users, json!([]));
+}
+
+#[rocket::async_test]
+async fn put_replaces_the_whole_set() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let (_, staff) = app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+    app.post("/permissions", &token, json!({"name": "students", "description": ""})).await;
+    app.post("/access-profiles/default/permissions", &token, json!({"permission_id": staff["id"]})).await;
+
+    let (status, profile) = app.put("/access-profiles/default/permissions", &token, json!(["students"])).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(profile["changes"][0], json!({"item": staff["id"], "id": staff["id"], "status": "removed"}));
+    assert_eq!(profile["changes"][1]["status"], "added");
+
+    let (_, permissions) = app.get("/access-profiles/default/permissions", &token).await;
+    assert_eq!(permissions.as_array().unwrap().len(), 1);
+    assert_eq!(permissions[0]["name"], "students");
+}
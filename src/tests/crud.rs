@@ -0,0 +1,152 @@
+use rocket::http::Status;
+use serde_json::json;
+
+use super::*;
+
+#[rocket::async_test]
+async fn users_can_be_created_read_updated_and_deleted() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    let (status, user) = app.post("/users", &token, json!({
+        "name": "alice",
+        "full_name": "Alice Kowalska",
+        "role": "student"
+    })).await;
+    assert_eq!(status, Status::Created);
+    assert_eq!(user["name"], "alice");
+
+    let (status, users) = app.get("/users", &token).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(users.as_array().unwrap().len(), 1);
+
+    let (status, user) = app.patch("/users/alice", &token, json!({"role": "teacher"})).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(user["role"], "teacher");
+
+    let (status, user) = app.get("/users/alice", &token).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(user["full_name"], "Alice Kowalska");
+    assert_eq!(user["role"], "teacher");
+
+    let (status, _) = app.delete("/users/alice", &token).await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, error) = app.get("/users/alice", &token).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(error["code"], "user_not_found");
+}
+
+#[rocket::async_test]
+async fn duplicate_names_conflict() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let user = json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""});
+
+    let (status, _) = app.post("/users", &token, user.clone()).await;
+    assert_eq!(status, Status::Created);
+
+    let (status, error) = app.post("/users", &token, user).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["code"], "user_conflict");
+}
+
+#[rocket::async_test]
+async fn invalid_bodies_list_the_fields() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    let (status, error) = app.post("/users", &token, json!({"name": "", "full_name": "", "role": ""})).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+
+    let fields :Vec<&str> = error["fields"].as_array().unwrap().iter().map(|f| f["field"].as_str().unwrap()).collect();
+    assert!(fields.contains(&"name"));
+    assert!(fields.contains(&"full_name"));
+}
+
+#[rocket::async_test]
+async fn permissions_are_assigned_to_users_and_profiles() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    let (status, permission) = app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+    assert_eq!(status, Status::Created);
+    let permission_id = permission["id"].clone();
+
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+
+    let (status, _) = app.post("/users/alice/permissions", &token, json!({"permission_id": permission_id})).await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, _) = app.post("/access-profiles/default/permissions", &token, json!({"permission_id": permission_id})).await;
+    assert_eq!(status, Status::Ok);
+
+    let (_, permissions) = app.get("/users/alice/permissions", &token).await;
+    assert_eq!(permissions[0]["name"], "staff");
+
+    let (_, permissions) = app.get("/access-profiles/default/permissions", &token).await;
+    assert_eq!(permissions[0]["name"], "staff");
+
+    let (status, error) = app.post("/users/alice/permissions", &token, json!({"permission_id": permission_id})).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["code"], "user_permission_conflict");
+}
+
+#[rocket::async_test]
+async fn registering_a_code_takes_it_from_the_command_server() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+
+    app.mock.on_register(RegisterReply::Code(String::from("04A1B2C3"))).await;
+    let (status, user) = app.post("/users/alice/access-codes/register", &token, json!({})).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(user["access_codes"][0]["code"], "04A1B2C3");
+
+    app.mock.on_register(RegisterReply::TimedOut).await;
+    let (status, error) = app.post("/users/alice/access-codes/register", &token, json!({})).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(error["code"], "registration_timed_out");
+
+    app.mock.on_register(RegisterReply::Error(500)).await;
+    let (status, _) = app.post("/users/alice/access-codes/register", &token, json!({})).await;
+    assert_eq!(status, Status::BadGateway);
+
+    let (_, user) = app.get("/users/alice", &token).await;
+    assert_eq!(user["access_codes"].as_array().unwrap().len(), 1);
+}
+
+#[rocket::async_test]
+async fn deleting_a_user_reports_what_went_with_them() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    let (_, permission) = app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.post("/users/alice/access-codes", &token, json!({"code": "1111"})).await;
+    app.post("/users/alice/access-codes", &token, json!({"code": "1112"})).await;
+    app.post("/users/alice/permissions", &token, json!({"permission_id": permission["id"]})).await;
+    app.put("/users/alice/pin", &token, json!({"pin": "4321"})).await;
+
+    let (status, deleted) = app.delete("/users/alice", &token).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(deleted["name"], "alice");
+    assert_eq!(deleted["removed"], json!({
+        "access_codes": 2,
+        "permissions": 1,
+        "groups": 0,
+        "pins": 1,
+        "occupancy": 0,
+        "visitor_passes": 0
+    }));
+
+    // Nothing is left behind for a new user of the same name to inherit.
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Nowak", "role": ""})).await;
+    let (_, user) = app.get("/users/alice", &token).await;
+    assert!(user["access_codes"].as_array().unwrap().is_empty());
+    assert!(user["permissions"].as_array().unwrap().is_empty());
+
+    let (_, permission) = app.get("/permissions/staff", &token).await;
+    assert!(permission["users"].as_array().unwrap().is_empty());
+}
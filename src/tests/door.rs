@@ -0,0 +1,138 @@
+use std::{sync::Arc, time::Duration};
+
+use cherrydoor_command::Command;
+use rocket::tokio::{self, net::TcpListener, io::{AsyncBufReadExt, AsyncWriteExt, BufReader}};
+use serde_json::{json, Value};
+
+use super::*;
+use crate::{door::{Door, DoorError, Simulator, StreamController}, metrics::Metrics};
+
+/// A controller on a random local port, answering requests as `reply` says and recording them. Each connection
+/// is answered until `reply` returns `None`, which closes it.
+async fn start_controller(reply :fn(&Value) -> Option<Value>) -> (String, Arc<Mutex<Vec<Value>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let received = Arc::new(Mutex::new(vec![]));
+
+    let log = received.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+
+            while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                let request :Value = serde_json::from_str(&line).unwrap();
+                line.clear();
+                log.lock().await.push(request.clone());
+
+                match reply(&request) {
+                    Some(answer) => stream.write_all(format!("{}\n", answer).as_bytes()).await.unwrap(),
+                    None => break
+                }
+            }
+        }
+    });
+
+    (address, received)
+}
+
+#[rocket::async_test]
+async fn the_simulator_keeps_commands_and_reads_queued_cards() {
+    let simulator = Simulator::new();
+    let door = Door::new(Arc::new(simulator.clone()), Metrics::new());
+
+    door.send("open", &Command::new().open()).await.unwrap();
+    assert_eq!(simulator.commands().await, vec![command(Command::new().open())]);
+
+    simulator.swipe("card-1").await;
+    assert_eq!(door.register().await.unwrap(), "card-1");
+    assert!(!door.register().await.unwrap().is_empty(), "without a queued card one is made up");
+
+    simulator.set_offline(true).await;
+    assert!(matches!(door.send("open", &Command::new().open()).await, Err(DoorError::Unreachable(_))));
+    assert!(door.status().await.is_err());
+}
+
+#[rocket::async_test]
+async fn tcp_controllers_get_a_json_line_per_request() {
+    let (address, received) = start_controller(|request| Some(match request["type"].as_str() {
+        Some("register") => json!({"ok": false, "error": "no_card"}),
+        Some("command") if request["command"] == command(Command::new().close()) => json!({"ok": false, "error": "jammed"}),
+        _ => json!({"ok": true})
+    })).await;
+    let door = Door::new(Arc::new(StreamController::tcp(&address)), Metrics::new());
+
+    door.send("open", &Command::new().open()).await.unwrap();
+    assert!(matches!(door.send("close", &Command::new().close()).await, Err(DoorError::Rejected(e)) if e == "jammed"));
+    assert!(matches!(door.register().await, Err(DoorError::NoCard)));
+    door.status().await.unwrap();
+
+    assert_eq!(*received.lock().await, vec![
+        json!({"type": "command", "command": command(Command::new().open())}),
+        json!({"type": "command", "command": command(Command::new().close())}),
+        json!({"type": "register"}),
+        json!({"type": "status"})
+    ]);
+}
+
+#[rocket::async_test]
+async fn tcp_controllers_are_reconnected_after_a_failure() {
+    // Hangs up on every status request.
+    let (address, received) = start_controller(|request| match request["type"].as_str() {
+        Some("status") => None,
+        _ => Some(json!({"ok": true, "code": "card-1"}))
+    }).await;
+    let door = Door::new(Arc::new(StreamController::tcp(&address)), Metrics::new());
+
+    assert!(matches!(door.status().await, Err(DoorError::Unreachable(_))));
+    assert_eq!(door.register().await.unwrap(), "card-1");
+    assert_eq!(received.lock().await.len(), 2);
+}
+
+#[rocket::async_test]
+async fn a_busy_tcp_controller_reports_how_it_was_at_the_last_request() {
+    // Never answers a registration, and hangs up on closing.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+
+                while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                    let request :Value = serde_json::from_str(&line).unwrap();
+                    line.clear();
+
+                    if request["type"] == "register" {
+                        std::future::pending::<()>().await;
+                    }
+                    if request["command"] == command(Command::new().close()) {
+                        break
+                    }
+                    stream.write_all(b"{\"ok\": true}\n").await.unwrap();
+                }
+            });
+        }
+    });
+    let door = Door::new(Arc::new(StreamController::tcp(&address)), Metrics::new());
+
+    door.send("open", &Command::new().open()).await.unwrap();
+    let registration = tokio::spawn({
+        let door = door.clone();
+        async move { door.register().await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    door.status().await.unwrap();
+    registration.abort();
+    let _ = registration.await;
+
+    assert!(matches!(door.send("close", &Command::new().close()).await, Err(DoorError::Unreachable(_))));
+    let registration = tokio::spawn({
+        let door = door.clone();
+        async move { door.register().await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(matches!(door.status().await, Err(DoorError::Unreachable(_))));
+    registration.abort();
+}
@@ -0,0 +1,72 @@
+use rocket::http::Status;
+use serde_json::json;
+
+use super::*;
+
+#[rocket::async_test]
+async fn changes_based_on_the_current_etag_are_applied() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": "student"})).await;
+
+    let etag = app.etag("/users/alice", &token).await;
+
+    let (status, user) = app.patch_if_match("/users/alice", &token, &etag, json!({"role": "teacher"})).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(user["role"], "teacher");
+
+    assert_ne!(app.etag("/users/alice", &token).await, etag);
+}
+
+#[rocket::async_test]
+async fn changes_based_on_a_stale_etag_are_rejected() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": "student"})).await;
+
+    let etag = app.etag("/users/alice", &token).await;
+
+    // Someone else changes the user in the meantime.
+    app.patch("/users/alice", &token, json!({"role": "teacher"})).await;
+
+    let (status, error) = app.patch_if_match("/users/alice", &token, &etag, json!({"full_name": "Alice Nowak"})).await;
+    assert_eq!(status, Status::PreconditionFailed);
+    assert_eq!(error["code"], "resource_modified");
+
+    let (_, user) = app.get("/users/alice", &token).await;
+    assert_eq!(user["full_name"], "Alice Kowalska");
+    assert_eq!(user["role"], "teacher");
+}
+
+#[rocket::async_test]
+async fn any_of_the_listed_etags_will_do() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+
+    let etag = app.etag("/permissions/staff", &token).await;
+
+    let (status, _) = app.patch_if_match("/permissions/staff", &token, &format!("\"stale\", {}", etag), json!({"description": "Staff"})).await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, _) = app.patch_if_match("/permissions/staff", &token, "*", json!({"description": "Everyone working here"})).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn settings_are_guarded_even_before_they_are_saved() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    // The defaults are served, and tagged, while the profile has no settings of its own.
+    let etag = app.etag("/access-profiles/default/settings", &token).await;
+
+    let (status, _) = app.patch_if_match("/access-profiles/default/settings", &token, &etag, json!({"anti_passback": true})).await;
+    assert_eq!(status, Status::Ok);
+
+    let (status, _) = app.patch_if_match("/access-profiles/default/settings", &token, &etag, json!({"anti_passback": false})).await;
+    assert_eq!(status, Status::PreconditionFailed);
+
+    let (_, settings) = app.get("/access-profiles/default/settings", &token).await;
+    assert_eq!(settings["anti_passback"], true);
+}
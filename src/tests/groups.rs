@@ -0,0 +1,86 @@
+use rocket::http::Status;
+use serde_json::json;
+
+use super::*;
+
+/// Adds the group `staff`, allowed in during the default profile, and alice with the card `1111`, who isn't
+/// in it yet.
+async fn add_group(app :&TestApp, token :&str) {
+    let (_, permission) = app.post("/permissions", token, json!({"name": "staff", "description": ""})).await;
+    app.post("/access-profiles/default/permissions", token, json!({"permission_id": permission["id"]})).await;
+
+    let (status, _) = app.post("/groups", token, json!({"name": "staff", "description": "Staff"})).await;
+    assert_eq!(status, Status::Created);
+    let (status, _) = app.post("/groups/staff/permissions", token, json!({"permission_id": permission["id"]})).await;
+    assert_eq!(status, Status::Ok);
+
+    app.post("/users", token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.post("/users/alice/access-codes", token, json!({"code": "1111"})).await;
+}
+
+#[rocket::async_test]
+async fn members_get_in_with_the_permissions_of_their_groups() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_group(&app, &token).await;
+
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::BadRequest);
+
+    let (_, alice) = app.get("/users/alice", &token).await;
+    let (status, group) = app.post("/groups/staff/users", &token, json!({"user_id": alice["id"]})).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(group["users"][0]["name"], "alice");
+
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::NoContent);
+
+    let (_, alice) = app.get("/users/alice", &token).await;
+    assert_eq!(alice["permissions"], json!([]));
+    assert_eq!(alice["groups"][0]["name"], "staff");
+    assert_eq!(alice["group_permissions"][0]["name"], "staff");
+}
+
+#[rocket::async_test]
+async fn deleting_a_group_takes_away_what_it_granted() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_group(&app, &token).await;
+
+    let (_, alice) = app.get("/users/alice", &token).await;
+    app.post("/groups/staff/users", &token, json!({"user_id": alice["id"]})).await;
+
+    let (status, group) = app.delete("/groups/staff", &token).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(group["permissions"][0]["name"], "staff");
+
+    let (status, error) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(error["code"], "access_denied");
+
+    let (_, alice) = app.get("/users/alice", &token).await;
+    assert_eq!(alice["groups"], json!([]));
+}
+
+#[rocket::async_test]
+async fn membership_is_checked() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    add_group(&app, &token).await;
+    let (_, alice) = app.get("/users/alice", &token).await;
+
+    app.post("/groups/staff/users", &token, json!({"user_id": alice["id"]})).await;
+    let (status, error) = app.post("/groups/staff/users", &token, json!({"user_id": alice["id"]})).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["code"], "group_user_conflict");
+
+    let (status, error) = app.post("/groups/staff/users", &token, json!({"user_id": 9999})).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(error["code"], "group_user_not_found");
+
+    let (status, _) = app.delete(&format!("/groups/staff/users/{}", alice["id"]), &token).await;
+    assert_eq!(status, Status::Ok);
+    let (status, error) = app.delete(&format!("/groups/staff/users/{}", alice["id"]), &token).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(error["code"], "group_user_not_found");
+}
@@ -0,0 +1,115 @@
+use rocket::http::Status;
+use serde_json::json;
+
+use super::*;
+
+#[rocket::async_test]
+async fn resources_can_be_addressed_by_id() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let (_, user) = app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+
+    let (status, by_id) = app.get(&format!("/users/{}", user["id"]), &token).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(by_id["name"], "alice");
+
+    let (status, error) = app.get("/users/12345", &token).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(error["code"], "user_not_found");
+}
+
+#[rocket::async_test]
+async fn web_ui_users_occupants_and_hosts_can_be_addressed_by_id() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let (_, operator) = app.post("/web-ui-users", &token, json!({
+        "name": "operator", "password": "operator-password", "is_admin": false, "ac_does_not_expire": false
+    })).await;
+    let path = format!("/web-ui-users/{}", operator["id"]);
+
+    let (status, by_id) = app.get(&path, &token).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(by_id["name"], "operator");
+
+    let (status, updated) = app.patch(&path, &token, json!({"ac_does_not_expire": true})).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(updated["ac_does_not_expire"], true);
+
+    let (status, _) = app.delete(&path, &token).await;
+    assert_eq!(status, Status::Ok);
+    let (status, _) = app.get("/web-ui-users/operator", &token).await;
+    assert_eq!(status, Status::NotFound);
+
+    let (_, permission) = app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+    let (_, user) = app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.post("/users/alice/access-codes", &token, json!({"code": "1111"})).await;
+    app.post("/users/alice/permissions", &token, json!({"permission_id": permission["id"]})).await;
+    app.post("/access-profiles/default/permissions", &token, json!({"permission_id": permission["id"]})).await;
+    app.swipe(json!({"code": "1111"})).await;
+
+    let (status, occupancy) = app.delete(&format!("/occupancy/{}", user["id"]), &token).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(occupancy["count"], 0);
+    let (status, pass) = app.post("/visitors", &token, json!({
+        "guest_name": "Jan Kowalski", "host": user["id"].to_string(), "valid_until": "2099-01-01T00:00:00"
+    })).await;
+    assert_eq!(status, Status::Created);
+    assert_eq!(pass["host"]["name"], "alice");
+}
+
+#[rocket::async_test]
+async fn names_made_of_digits_are_rejected() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    let (status, error) = app.post("/permissions", &token, json!({"name": "1234", "description": ""})).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(error["fields"][0]["field"], "name");
+}
+
+#[rocket::async_test]
+async fn renamed_users_keep_their_permissions() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let (_, permission) = app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.post("/users/alice/permissions", &token, json!({"permission_id": permission["id"]})).await;
+    app.post("/users", &token, json!({"name": "bob", "full_name": "Bob Nowak", "role": ""})).await;
+
+    let (status, user) = app.patch("/users/alice", &token, json!({"name": "alicja"})).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(user["name"], "alicja");
+    assert_eq!(user["permissions"][0]["name"], "staff");
+
+    let (status, _) = app.get("/users/alice", &token).await;
+    assert_eq!(status, Status::NotFound);
+
+    let (status, error) = app.patch("/users/alicja", &token, json!({"name": "bob"})).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["code"], "user_conflict");
+}
+
+#[rocket::async_test]
+async fn renaming_the_active_profile_keeps_it_active() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let (_, permission) = app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.post("/users/alice/access-codes", &token, json!({"code": "1111"})).await;
+    app.post("/users/alice/permissions", &token, json!({"permission_id": permission["id"]})).await;
+    app.post("/access-profiles/default/permissions", &token, json!({"permission_id": permission["id"]})).await;
+
+    let (status, _) = app.patch("/access-profiles/default", &token, json!({"name": "weekdays"})).await;
+    assert_eq!(status, Status::Ok);
+
+    let (_, active) = app.get("/active-profile", &token).await;
+    assert_eq!(active["name"], "weekdays");
+
+    let (status, _) = app.swipe(json!({"code": "1111"})).await;
+    assert_eq!(status, Status::NoContent);
+
+    // Still protected from deletion under the new name.
+    let (status, error) = app.delete("/access-profiles/weekdays", &token).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["code"], "access_profile_active");
+}
@@ -0,0 +1,21 @@
+use diesel::QueryDsl;
+use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+
+use super::*;
+use crate::{config::DatabaseConfig, db::{DB, get_connection}, metrics::Metrics, migrations::{self, MIGRATIONS}, schema::{roles, occupancy}};
+
+#[rocket::async_test]
+async fn a_database_made_from_the_old_init_sql_is_adopted_and_upgraded() {
+    let database = TestDatabase::create().await;
+    let config = DatabaseConfig { uri: database.uri.clone(), pool_size: 1, run_migrations: true };
+    let db = DB::new(&config, Metrics::new().db_wait);
+    let mut conn = get_connection(&db).await.unwrap();
+
+    // What db/init.sql made, without any record of migrations.
+    conn.batch_execute(MIGRATIONS[0].up).await.unwrap();
+
+    migrations::run(&mut conn).await.unwrap();
+    assert!(migrations::pending(&mut conn).await.unwrap().is_empty());
+    assert_eq!(occupancy::table.count().get_result::<i64>(&mut conn).await.unwrap(), 0);
+    assert_eq!(roles::table.count().get_result::<i64>(&mut conn).await.unwrap(), 0);
+}
@@ -0,0 +1,371 @@
+// Tests driving the whole server through a local client. Every test gets a throwaway database and a mock
+// command server of its own. With the sqlite feature the database is in memory; with the other backends
+// CHERRYDOOR_TEST_DATABASE_URI has to point to a server the tests can create databases on.
+
+mod auth;
+mod crud;
+mod access;
+mod active_access_profile;
+mod etag;
+mod batch;
+mod groups;
+mod keys;
+mod webhooks;
+mod door;
+mod registrations;
+mod migrations;
+
+use std::{collections::VecDeque, net::Ipv4Addr, sync::Arc};
+
+use async_mutex::Mutex;
+use cherrydoor_command::Command;
+use rocket::{
+    get, post, routes, State, Shutdown,
+    config::LogLevel, fairing::AdHoc, http::{ContentType, Header, Status},
+    local::asynchronous::{Client, LocalResponse}, serde::json::Json, tokio::sync::oneshot
+};
+use serde_json::{json, Value};
+
+use crate::config::Config;
+
+pub const ADMIN :&str = "admin";
+pub const ADMIN_PASSWORD :&str = "admin-password";
+
+/// What the mock command server answers to `GET /register`.
+pub enum RegisterReply {
+    /// A card with this code was swiped.
+    Code(String),
+    /// Nobody swiped a card in time.
+    TimedOut,
+    /// The command server failed with this status.
+    Error(u16)
+}
+
+#[derive(Default)]
+struct MockState {
+    commands :Vec<Value>,
+    register :VecDeque<RegisterReply>,
+    /// Status to reject commands with, instead of accepting them.
+    reject_commands :Option<u16>
+}
+
+type MockHandle = Arc<Mutex<MockState>>;
+
+#[post("/", format = "application/json", data = "<command>")]
+async fn mock_command(command :Json<Value>, state :&State<MockHandle>) -> Status {
+    let mut state = state.lock().await;
+
+    // Whatever the server sends has to be something the real command server understands.
+    assert!(serde_json::from_value::<Command>(command.0.clone()).is_ok(), "not a command: {}", command.0);
+
+    match state.reject_commands {
+        Some(status) => Status::new(status),
+        None => {
+            state.commands.push(command.0);
+            Status::NoContent
+        }
+    }
+}
+
+#[get("/register")]
+async fn mock_register(state :&State<MockHandle>) -> (Status, String) {
+    match state.lock().await.register.pop_front() {
+        Some(RegisterReply::Code(code)) => (Status::Ok, code),
+        Some(RegisterReply::TimedOut) | None => (Status::NotFound, String::new()),
+        Some(RegisterReply::Error(status)) => (Status::new(status), String::from("mock failure"))
+    }
+}
+
+/// A command server rejecting commands is taken to be broken as a whole.
+#[get("/")]
+async fn mock_index(state :&State<MockHandle>) -> Status {
+    match state.lock().await.reject_commands {
+        Some(status) => Status::new(status),
+        None => Status::NoContent
+    }
+}
+
+/// A command server on a random local port, recording the commands it receives.
+pub struct MockCommandServer {
+    pub address :String,
+    state :MockHandle,
+    shutdown :Shutdown
+}
+
+impl MockCommandServer {
+    pub async fn start() -> Self {
+        let state = MockHandle::default();
+        let (port_sender, port_receiver) = oneshot::channel();
+
+        let config = rocket::Config {
+            address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            log_level: LogLevel::Off,
+            ..rocket::Config::debug_default()
+        };
+
+        let mock = rocket::custom(config)
+            .manage(state.clone())
+            .mount("/", routes![mock_command, mock_register, mock_index])
+            .attach(AdHoc::on_liftoff("Port", |rocket| Box::pin(async move {
+                let _ = port_sender.send(rocket.config().port);
+            })))
+            .ignite().await
+        .expect("the mock command server couldn't start");
+
+        let shutdown = mock.shutdown();
+        rocket::tokio::spawn(mock.launch());
+
+        let port = port_receiver.await.expect("the mock command server didn't start listening");
+
+        Self {
+            address: format!("http://127.0.0.1:{}", port),
+            state,
+            shutdown
+        }
+    }
+
+    /// Commands received so far, as JSON.
+    pub async fn commands(&self) -> Vec<Value> {
+        self.state.lock().await.commands.clone()
+    }
+
+    pub async fn clear_commands(&self) {
+        self.state.lock().await.commands.clear();
+    }
+
+    /// Queues the answer to the next `GET /register`. Without one, registration times out.
+    pub async fn on_register(&self, reply :RegisterReply) {
+        self.state.lock().await.register.push_back(reply);
+    }
+
+    /// Makes the mock reject commands with the given status, or accept them again with `None`.
+    pub async fn reject_commands(&self, status :Option<u16>) {
+        self.state.lock().await.reject_commands = status;
+    }
+}
+
+impl Drop for MockCommandServer {
+    fn drop(&mut self) {
+        self.shutdown.clone().notify();
+    }
+}
+
+/// The command as the mock command server records it.
+pub fn command(command :Command) -> Value {
+    serde_json::to_value(command).unwrap()
+}
+
+/// The server, set up like on a first start, with `ADMIN` as the only web UI user and the `default` profile active.
+pub struct TestApp {
+    pub client :Client,
+    pub mock :MockCommandServer,
+    _database :TestDatabase     // Last, so it's dropped once the server is gone
+}
+
+impl TestApp {
+    pub async fn start() -> Self {
+        let mock = MockCommandServer::start().await;
+        let database = TestDatabase::create().await;
+
+        let figment = rocket::Config::figment()
+            .merge(("log_level", LogLevel::Off))
+            .merge(("database.uri", &database.uri))
+            .merge(("database.pool_size", 2))
+            .merge(("command_server.address", &mock.address))
+            .merge(("bootstrap.admin_name", ADMIN))
+            .merge(("bootstrap.admin_password", ADMIN_PASSWORD))
+            .merge(("rate_limits.login_attempts", 0))
+            .merge(("webhooks.retry_delay", 10))
+            .merge(("door.registration_timeout", 2));
+
+        let app = rocket::custom(figment);
+        let config = Config::load(app.figment()).expect("invalid test configuration");
+
+        let client = Client::tracked(crate::server(app, config).await).await.expect("the server couldn't start");
+
+        Self { client, mock, _database: database }
+    }
+
+    /// Logs in and returns the token.
+    pub async fn login(&self, name :&str, password :&str) -> String {
+        let res = self.client.post("/auth")
+            .json(&json!({"name": name, "password": password}))
+        .dispatch().await;
+
+        assert_eq!(res.status(), Status::Ok, "couldn't log in as {}", name);
+        body(res).await["token"].as_str().unwrap().to_string()
+    }
+
+    pub async fn admin_token(&self) -> String {
+        self.login(ADMIN, ADMIN_PASSWORD).await
+    }
+
+    pub async fn get(&self, path :&str, token :&str) -> (Status, Value) {
+        let res = self.client.get(path.to_string())
+            .header(bearer(token))
+        .dispatch().await;
+
+        (res.status(), body(res).await)
+    }
+
+    pub async fn post(&self, path :&str, token :&str, data :Value) -> (Status, Value) {
+        let res = self.client.post(path.to_string())
+            .header(bearer(token))
+            .json(&data)
+        .dispatch().await;
+
+        (res.status(), body(res).await)
+    }
+
+    pub async fn patch(&self, path :&str, token :&str, data :Value) -> (Status, Value) {
+        let res = self.client.patch(path.to_string())
+            .header(bearer(token))
+            .json(&data)
+        .dispatch().await;
+
+        (res.status(), body(res).await)
+    }
+
+    /// Like `patch`, but only if the resource still has the given ETag.
+    pub async fn patch_if_match(&self, path :&str, token :&str, etag :&str, data :Value) -> (Status, Value) {
+        let res = self.client.patch(path.to_string())
+            .header(bearer(token))
+            .header(Header::new("If-Match", etag.to_string()))
+            .json(&data)
+        .dispatch().await;
+
+        (res.status(), body(res).await)
+    }
+
+    /// The ETag the resource is served with.
+    pub async fn etag(&self, path :&str, token :&str) -> String {
+        let res = self.client.get(path.to_string())
+            .header(bearer(token))
+        .dispatch().await;
+
+        assert_eq!(res.status(), Status::Ok, "couldn't get {}", path);
+        res.headers().get_one("ETag").expect("no ETag").to_string()
+    }
+
+    pub async fn put(&self, path :&str, token :&str, data :Value) -> (Status, Value) {
+        let res = self.client.put(path.to_string())
+            .header(bearer(token))
+            .json(&data)
+        .dispatch().await;
+
+        (res.status(), body(res).await)
+    }
+
+    pub async fn delete(&self, path :&str, token :&str) -> (Status, Value) {
+        let res = self.client.delete(path.to_string())
+            .header(bearer(token))
+        .dispatch().await;
+
+        (res.status(), body(res).await)
+    }
+
+    /// Swipes a card, as the door controller does.
+    pub async fn swipe(&self, data :Value) -> (Status, Value) {
+        let res = self.client.post("/access/code")
+            .header(ContentType::JSON)
+            .json(&data)
+        .dispatch().await;
+
+        (res.status(), body(res).await)
+    }
+}
+
+pub fn bearer(token :&str) -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
+/// The JSON body of a response, `Value::Null` for an empty one.
+pub async fn body(res :LocalResponse<'_>) -> Value {
+    match res.into_string().await {
+        Some(s) if !s.is_empty() => serde_json::from_str(&s).expect("the response isn't JSON"),
+        _ => Value::Null
+    }
+}
+
+/// A database of a test's own. On a database server it has a random name, and it's dropped along with the guard.
+pub struct TestDatabase {
+    pub uri :String,
+    #[cfg(not(feature = "sqlite"))]
+    server :String,
+    #[cfg(not(feature = "sqlite"))]
+    name :String
+}
+
+#[cfg(feature = "sqlite")]
+impl TestDatabase {
+    pub async fn create() -> Self {
+        Self { uri: String::from("sqlite://:memory:") }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl TestDatabase {
+    pub async fn create() -> Self {
+        use diesel_async::{AsyncConnection, SimpleAsyncConnection};
+        use rand::{Rng, distributions::Alphanumeric};
+
+        let server = std::env::var("CHERRYDOOR_TEST_DATABASE_URI").expect(
+            "set CHERRYDOOR_TEST_DATABASE_URI to a database server the tests can create databases on, or test with --no-default-features --features sqlite"
+        );
+
+        let suffix :String = rand::thread_rng().sample_iter(Alphanumeric).take(12).map(char::from).collect();
+        let name = format!("cherrydoor_test_{}", suffix.to_lowercase());
+
+        let mut conn = crate::db::Connection::establish(&server).await.expect("couldn't connect to the test database server");
+        conn.batch_execute(&format!("CREATE DATABASE {}", name)).await.expect("couldn't create a test database");
+
+        let mut uri = reqwest::Url::parse(&server).expect("CHERRYDOOR_TEST_DATABASE_URI isn't a URI");
+        uri.set_path(&name);
+
+        Self { uri: uri.to_string(), server, name }
+    }
+
+    /// Runs on a runtime of its own, the test's one can't run anything while it's dropping things.
+    fn drop_database(server :&str, name :&str) -> Result<(), String> {
+        use diesel_async::{AsyncConnection, SimpleAsyncConnection};
+
+        let runtime = match rocket::tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => return Err(e.to_string())
+        };
+
+        runtime.block_on(async {
+            let mut conn = match crate::db::Connection::establish(server).await {
+                Ok(conn) => conn,
+                Err(e) => return Err(e.to_string())
+            };
+
+            match conn.batch_execute(&format!("DROP DATABASE {}{}", name, DROP_DATABASE_OPTIONS)).await {
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.to_string())
+            }
+        })
+    }
+}
+
+/// The pool's connections may not be closed yet when the database is dropped.
+#[cfg(feature = "postgres")]
+const DROP_DATABASE_OPTIONS :&str = " WITH (FORCE)";
+#[cfg(feature = "mysql")]
+const DROP_DATABASE_OPTIONS :&str = "";
+
+#[cfg(not(feature = "sqlite"))]
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let server = self.server.clone();
+        let name = self.name.clone();
+
+        // A failure is only reported, panicking here would hide the test's own panic.
+        let dropped = std::thread::spawn(move || Self::drop_database(&server, &name)).join();
+
+        if let Ok(Err(e)) = dropped {
+            eprintln!("couldn't drop the test database {}: {}", self.name, e);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use cherrydoor_command::Command;
+use rocket::{http::Status, tokio};
+use serde_json::{json, Value};
+
+use super::*;
+
+/// Polls the session until it has finished.
+async fn finished(app :&TestApp, token :&str, path :&str) -> Value {
+    for _ in 0..200 {
+        let (status, registration) = app.get(path, token).await;
+        assert_eq!(status, Status::Ok);
+        if registration["finished_at"] != Value::Null {
+            return registration
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    panic!("the registration is still going");
+}
+
+#[rocket::async_test]
+async fn a_registration_assigns_the_presented_card() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.mock.on_register(RegisterReply::Code(String::from("04A1B2C3"))).await;
+
+    let (status, registration) = app.post("/users/alice/access-codes/registrations", &token, json!({})).await;
+    assert_eq!(status, Status::Created);
+    let path = format!("/users/alice/access-codes/registrations/{}", registration["id"]);
+
+    // The stream ends once the session has finished.
+    let res = app.client.get(format!("{}/events", path)).header(bearer(&token)).dispatch().await;
+    let events = res.into_string().await.unwrap();
+    let last = events.lines().filter_map(|line| line.strip_prefix("data:")).next_back().unwrap();
+    let last :Value = serde_json::from_str(last).unwrap();
+    assert_eq!(last["state"], "assigned");
+    assert_eq!(last["code"], "04A1B2C3");
+
+    let (_, user) = app.get("/users/alice", &token).await;
+    assert_eq!(user["access_codes"][0]["code"], "04A1B2C3");
+    assert_eq!(user["access_codes"][0]["id"], last["access_code_id"]);
+
+    let (status, error) = app.get("/users/admin/access-codes/registrations/1", &token).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(error["code"], "user_not_found");
+}
+
+#[rocket::async_test]
+async fn a_card_of_another_user_is_not_assigned() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.post("/users", &token, json!({"name": "bob", "full_name": "Bob Nowak", "role": ""})).await;
+    app.post("/users/bob/access-codes", &token, json!({"code": "04A1B2C3"})).await;
+    app.mock.on_register(RegisterReply::Code(String::from("04A1B2C3"))).await;
+
+    let (_, registration) = app.post("/users/alice/access-codes/registrations", &token, json!({})).await;
+    let registration = finished(&app, &token, &format!("/users/alice/access-codes/registrations/{}", registration["id"])).await;
+    assert_eq!(registration["state"], "duplicate");
+    assert_eq!(registration["user"], "alice");
+    assert_eq!(registration["owner"], "bob");
+
+    let (_, user) = app.get("/users/alice", &token).await;
+    assert_eq!(user["access_codes"], json!([]));
+}
+
+#[rocket::async_test]
+async fn registrations_can_be_cancelled_or_expire() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+
+    // Nobody swipes a card.
+    let (_, registration) = app.post("/users/alice/access-codes/registrations", &token, json!({})).await;
+    let path = format!("/users/alice/access-codes/registrations/{}", registration["id"]);
+
+    let (status, error) = app.post("/users/alice/access-codes/registrations", &token, json!({})).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["code"], "registration_in_progress");
+
+    let (status, registration) = app.delete(&path, &token).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(registration["state"], "cancelled");
+
+    let (status, error) = app.delete(&path, &token).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(error["code"], "registration_finished");
+
+    let (status, registration) = app.post("/users/alice/access-codes/registrations", &token, json!({})).await;
+    assert_eq!(status, Status::Created);
+    let registration = finished(&app, &token, &format!("/users/alice/access-codes/registrations/{}", registration["id"])).await;
+    assert_eq!(registration["state"], "expired");
+
+    // The door was asked for a card both times.
+    assert!(app.mock.commands().await.len() >= 2);
+}
+
+#[rocket::async_test]
+async fn a_failed_registration_is_shown_at_the_door() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+    app.mock.on_register(RegisterReply::Error(500)).await;
+
+    let (_, registration) = app.post("/users/alice/access-codes/registrations", &token, json!({})).await;
+    let registration = finished(&app, &token, &format!("/users/alice/access-codes/registrations/{}", registration["id"])).await;
+    assert_eq!(registration["state"], "failed");
+
+    // The session shows as failed just before the door is told.
+    let error = command(Command::new().display_text_for(String::from("Blad"), 3000).set_color_for(255, 0, 0, 3000));
+    for _ in 0..200 {
+        if app.mock.commands().await.last() == Some(&error) {
+            return
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    panic!("the door wasn't told about the failure");
+}
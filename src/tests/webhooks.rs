@@ -0,0 +1,233 @@
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+
+use async_mutex::Mutex;
+use chrono::Utc;
+use diesel_async::RunQueryDsl;
+use rocket::{
+    post, routes, State, Shutdown, Request,
+    config::LogLevel, fairing::AdHoc, http::Status, request::{FromRequest, Outcome}, tokio::{self, sync::oneshot}
+};
+use serde_json::{json, Value};
+
+use super::*;
+use crate::{db::{DB, get_connection}, models::{WebhookDeliveryInsert, DeliveryStatus}, schema::webhook_deliveries, webhooks::{sign, Dispatcher}};
+
+const SECRET :&str = "webhook-secret";
+
+/// A request received by the mock receiver.
+#[derive(Clone)]
+struct Received {
+    event :String,
+    signature :String,
+    body :String
+}
+
+#[derive(Default)]
+struct ReceiverState {
+    received :Vec<Received>,
+    /// Requests to fail with a 500 before accepting them.
+    failures :u32
+}
+
+type ReceiverHandle = Arc<Mutex<ReceiverState>>;
+
+struct Headers {
+    event :String,
+    signature :String
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Headers {
+    type Error = ();
+
+    async fn from_request(req :&'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Headers {
+            event: req.headers().get_one("X-Cherrydoor-Event").unwrap_or_default().to_string(),
+            signature: req.headers().get_one("X-Cherrydoor-Signature").unwrap_or_default().to_string()
+        })
+    }
+}
+
+#[post("/", data = "<body>")]
+async fn receive(headers :Headers, body :String, state :&State<ReceiverHandle>) -> Status {
+    let mut state = state.lock().await;
+
+    state.received.push(Received { event: headers.event, signature: headers.signature, body });
+
+    if state.failures > 0 {
+        state.failures -= 1;
+        Status::InternalServerError
+    } else {
+        Status::NoContent
+    }
+}
+
+/// A webhook receiver on a random local port, recording every request, failed ones included.
+struct MockReceiver {
+    url :String,
+    state :ReceiverHandle,
+    shutdown :Shutdown
+}
+
+impl MockReceiver {
+    async fn start(failures :u32) -> Self {
+        let state = ReceiverHandle::new(Mutex::new(ReceiverState { received: vec![], failures }));
+        let (port_sender, port_receiver) = oneshot::channel();
+
+        let config = rocket::Config {
+            address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            log_level: LogLevel::Off,
+            ..rocket::Config::debug_default()
+        };
+
+        let receiver = rocket::custom(config)
+            .manage(state.clone())
+            .mount("/", routes![receive])
+            .attach(AdHoc::on_liftoff("Port", |rocket| Box::pin(async move {
+                let _ = port_sender.send(rocket.config().port);
+            })))
+            .ignite().await
+        .expect("the mock receiver couldn't start");
+
+        let shutdown = receiver.shutdown();
+        tokio::spawn(receiver.launch());
+
+        let port = port_receiver.await.expect("the mock receiver didn't start listening");
+
+        Self {
+            url: format!("http://127.0.0.1:{}/", port),
+            state,
+            shutdown
+        }
+    }
+
+    /// Waits until `count` requests have arrived and returns them.
+    async fn wait_for(&self, count :usize) -> Vec<Received> {
+        for _ in 0..200 {
+            let received = self.state.lock().await.received.clone();
+            if received.len() >= count {
+                return received
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        panic!("the mock receiver got fewer than {} requests", count);
+    }
+}
+
+impl Drop for MockReceiver {
+    fn drop(&mut self) {
+        self.shutdown.clone().notify();
+    }
+}
+
+/// Polls the delivery log until the latest delivery is no longer pending.
+async fn settled_delivery(app :&TestApp, token :&str, webhook :&str) -> Value {
+    for _ in 0..200 {
+        let (_, deliveries) = app.get(&format!("/webhooks/{}/deliveries", webhook), token).await;
+        if deliveries[0]["status"] != "pending" {
+            return deliveries[0].clone()
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    panic!("the delivery is still pending");
+}
+
+#[rocket::async_test]
+async fn subscribed_events_are_delivered_signed() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let receiver = MockReceiver::start(0).await;
+
+    let (status, _) = app.post("/webhooks", &token, json!({
+        "name": "audit", "url": receiver.url, "secret": SECRET, "events": ["user_created"]
+    })).await;
+    assert_eq!(status, Status::Created);
+
+    // Not subscribed to, so only the user shows up.
+    app.post("/permissions", &token, json!({"name": "staff", "description": ""})).await;
+    app.post("/users", &token, json!({"name": "alice", "full_name": "Alice Kowalska", "role": ""})).await;
+
+    let received = receiver.wait_for(1).await;
+    assert_eq!(received[0].event, "user_created");
+    assert_eq!(received[0].signature, sign(SECRET, &received[0].body));
+
+    let payload :Value = serde_json::from_str(&received[0].body).unwrap();
+    assert_eq!(payload["webhook"], "audit");
+    assert_eq!(payload["event"]["type"], "user_created");
+    assert_eq!(payload["event"]["name"], "alice");
+
+    let delivery = settled_delivery(&app, &token, "audit").await;
+    assert_eq!(delivery["status"], "delivered");
+    assert_eq!(delivery["attempts"], 1);
+}
+
+#[rocket::async_test]
+async fn failed_deliveries_are_retried() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let receiver = MockReceiver::start(2).await;
+
+    app.post("/webhooks", &token, json!({"name": "audit", "url": receiver.url, "events": ["access_denied"]})).await;
+
+    let (status, _) = app.swipe(json!({"code": "unknown"})).await;
+    assert_eq!(status, Status::NotFound);
+
+    let received = receiver.wait_for(3).await;
+    assert!(received.iter().all(|r| r.body == received[0].body), "every attempt has to send the same payload");
+
+    let delivery = settled_delivery(&app, &token, "audit").await;
+    assert_eq!(delivery["status"], "delivered");
+    assert_eq!(delivery["attempts"], 3);
+    assert_eq!(delivery["response_status"], 204);
+}
+
+#[rocket::async_test]
+async fn pinging_a_webhook() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let receiver = MockReceiver::start(0).await;
+
+    let (status, error) = app.post("/webhooks", &token, json!({"name": "audit", "url": receiver.url, "events": ["reboot"]})).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(error["fields"][0]["field"], "events[0]");
+
+    app.post("/webhooks", &token, json!({"name": "audit", "url": receiver.url, "events": ["door_opened"], "active": false})).await;
+
+    let (status, delivery) = app.post("/webhooks/audit/test", &token, json!({})).await;
+    assert_eq!(status, Status::Accepted);
+    assert_eq!(delivery["event"], "ping");
+
+    let received = receiver.wait_for(1).await;
+    assert_eq!(received[0].event, "ping");
+}
+
+#[rocket::async_test]
+async fn pending_deliveries_are_resumed() {
+    let app = TestApp::start().await;
+    let token = app.admin_token().await;
+    let receiver = MockReceiver::start(0).await;
+    let (_, webhook) = app.post("/webhooks", &token, json!({"name": "audit", "url": receiver.url, "events": ["door_opened"]})).await;
+
+    // As if the server stopped after the first attempt failed.
+    let mut conn = get_connection(app.client.rocket().state::<DB>().unwrap()).await.unwrap();
+    diesel::insert_into(webhook_deliveries::table)
+        .values(WebhookDeliveryInsert {
+            webhook_id: webhook["id"].as_i64().unwrap() as i32,
+            event: String::from("door_opened"),
+            payload: String::from("{}"),
+            status: DeliveryStatus::Pending,
+            attempts: 1,
+            created_at: Utc::now().naive_utc()
+        })
+    .execute(&mut conn).await.unwrap();
+
+    app.client.rocket().state::<Dispatcher>().unwrap().resume().await.unwrap();
+
+    let received = receiver.wait_for(1).await;
+    assert_eq!(received[0].body, "{}");
+
+    let delivery = settled_delivery(&app, &token, "audit").await;
+    assert_eq!(delivery["status"], "delivered");
+    assert_eq!(delivery["attempts"], 2);
+}
@@ -0,0 +1,138 @@
+use std::ops::Deref;
+
+use rocket::{Request, Data, data::{self, FromData, Limits}, http::Status, outcome::Outcome};
+use serde::de::DeserializeOwned;
+
+use crate::error::{ApiError, FieldError};
+
+/// Longest allowed name of anything that ends up in an URL.
+pub const NAME_MAX_LEN :usize = 64;
+/// Longest allowed free-form text, matching `varchar(255)` columns.
+pub const TEXT_MAX_LEN :usize = 255;
+
+/// A request body that can check itself beyond what deserialization already guarantees.
+/// Bodies with nothing more to check can use the default implementation.
+pub trait Validate {
+    fn validate(&self, _errors :&mut Vec<FieldError>) {}
+}
+
+/// Field errors of the request body, kept for the catchers, which don't get to see the data guard's error.
+pub struct BodyErrors(pub Vec<FieldError>);
+
+/// A JSON request body that was deserialized and validated. Use it instead of `Json<T>`.
+pub struct Validated<T>(pub T);
+
+impl<T> Deref for Validated<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+fn fail<'r, T>(req :&'r Request<'_>, status :Status, errors :Vec<FieldError>) -> data::Outcome<'r, T, ApiError> {
+    req.local_cache(|| BodyErrors(errors.clone()));
+    Outcome::Failure((status, ApiError::Validation(errors)))
+}
+
+#[rocket::async_trait]
+impl<'r, T :DeserializeOwned + Validate> FromData<'r> for Validated<T> {
+    type Error = ApiError;
+
+    async fn from_data(req :&'r Request<'_>, data :Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = req.limits().get("json").unwrap_or(Limits::JSON);
+
+        let body = match data.open(limit).into_string().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return fail(req, Status::PayloadTooLarge, vec![
+                FieldError::new("", &format!("The body can't be larger than {}.", limit))
+            ]),
+            Err(e) => return fail(req, Status::BadRequest, vec![FieldError::new("", &e.to_string())])
+        };
+
+        let de = &mut serde_json::Deserializer::from_str(&body);
+        let value :T = match serde_path_to_error::deserialize(de) {
+            Ok(value) => value,
+            Err(e) => {
+                let path = e.path().to_string();
+                let inner = e.into_inner();
+                let status = if inner.is_data() { Status::UnprocessableEntity } else { Status::BadRequest };
+
+                return fail(req, status, vec![FieldError::new(&field_of(&path, &inner.to_string()), &inner.to_string())])
+            }
+        };
+
+        let mut errors = vec![];
+        value.validate(&mut errors);
+
+        if errors.is_empty() {
+            Outcome::Success(Validated(value))
+        } else {
+            fail(req, Status::UnprocessableEntity, errors)
+        }
+    }
+}
+
+/// Serde reports a missing field at the path of the object that lacks it, so the field name is taken from the message.
+fn field_of(path :&str, message :&str) -> String {
+    let path = if path == "." { "" } else { path };
+
+    match message.strip_prefix("missing field `").and_then(|rest| rest.split('`').next()) {
+        Some(field) if path.is_empty() => String::from(field),
+        Some(field) => format!("{}.{}", path, field),
+        None => String::from(path)
+    }
+}
+
+/// Names are used as path segments, so they are limited to characters that don't need escaping.
+pub fn check_name(field :&str, name :&str, errors :&mut Vec<FieldError>) {
+    if name.is_empty() {
+        errors.push(FieldError::new(field, "Can't be empty."));
+    } else if name.len() > NAME_MAX_LEN {
+        errors.push(FieldError::new(field, &format!("Can't be longer than {} characters.", NAME_MAX_LEN)));
+    } else if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+        errors.push(FieldError::new(field, "Can only contain letters, digits, '-', '_' and '.'."));
+    } else if name == "." || name == ".." {
+        errors.push(FieldError::new(field, "Can't be a relative path segment."));
+    } else if name.chars().all(|c| c.is_ascii_digit()) {
+        errors.push(FieldError::new(field, "Can't be only digits, those are taken as ids."));
+    }
+}
+
+pub fn check_not_empty(field :&str, value :&str, errors :&mut Vec<FieldError>) {
+    if value.trim().is_empty() {
+        errors.push(FieldError::new(field, "Can't be empty."));
+    }
+}
+
+pub fn check_length(field :&str, value :&str, max :usize, errors :&mut Vec<FieldError>) {
+    if value.chars().count() > max {
+        errors.push(FieldError::new(field, &format!("Can't be longer than {} characters.", max)));
+    }
+}
+
+pub fn check_text(field :&str, value :&str, errors :&mut Vec<FieldError>) {
+    check_length(field, value, TEXT_MAX_LEN, errors)
+}
+
+pub fn check_url(field :&str, value :&str, max :usize, errors :&mut Vec<FieldError>) {
+    if !is_http_url(value) {
+        errors.push(FieldError::new(field, "Must be an http:// or https:// URL."));
+    }
+    check_length(field, value, max, errors)
+}
+
+pub fn is_http_url(s :&str) -> bool {
+    match reqwest::Url::parse(s) {
+        Ok(url) => (url.scheme() == "http" || url.scheme() == "https") && url.has_host(),
+        Err(_) => false
+    }
+}
+
+/// `host:port`, the host being a name or an address.
+pub fn is_host_port(s :&str) -> bool {
+    match s.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false
+    }
+}
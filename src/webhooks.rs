@@ -0,0 +1,251 @@
+// Delivers events from the bus to the webhooks subscribed to them. Every delivery is recorded in
+// webhook_deliveries before the first attempt and updated after each one, so the log shows what's still pending,
+// and deliveries cut short by a restart are picked up again when the server starts.
+
+use std::{error::Error, time::Duration};
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::{QueryDsl, SelectableHelper, ExpressionMethods};
+use diesel_async::{RunQueryDsl, scoped_futures::ScopedFutureExt};
+use hmac::{Hmac, Mac};
+use rocket::tokio::{self, sync::broadcast::error::RecvError};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::{
+    db::{DB, DbConnection, get_connection, transaction, last_insert_id}, error::ApiError, config::WebhooksConfig, events::{Event, EventBus},
+    models::{Webhook, WebhookDelivery, WebhookDeliveryInsert, DeliveryStatus}, schema::{webhooks, webhooks_events, webhook_deliveries}
+};
+
+/// Longest error message kept in the delivery log, matching the column.
+const ERROR_MAX_LEN :usize = 1024;
+
+/// The body of every request to a webhook.
+#[derive(Serialize)]
+struct Payload<'a> {
+    webhook :&'a str,
+    occurred_at :NaiveDateTime,
+    event :&'a Event
+}
+
+/// HMAC-SHA256 of the body with the webhook's secret, sent as `X-Cherrydoor-Signature: sha256=<hex>`.
+pub fn sign(secret :&str, body :&str) -> String {
+    // HMAC takes keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC rejected the key");
+    mac.update(body.as_bytes());
+
+    let hex :String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+/// Cloning is cheap, clones share the HTTP client.
+#[derive(Clone)]
+pub struct Dispatcher {
+    db :DB,
+    client :reqwest::Client,
+    config :WebhooksConfig
+}
+
+impl Dispatcher {
+    pub fn new(db :DB, config :WebhooksConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+        .expect("the HTTP client couldn't be set up");
+
+        Self { db, client, config }
+    }
+
+    /// Sends every event emitted on the bus to the webhooks subscribed to it, until the bus is gone, and resumes the
+    /// deliveries still pending from before.
+    pub fn start(&self, bus :&EventBus) {
+        let dispatcher = self.clone();
+        let mut rx = bus.subscribe();
+
+        let resuming = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = resuming.resume().await {
+                tracing::warn!(error = %e, "couldn't resume pending webhook deliveries");
+            }
+        });
+
+        tokio::spawn(async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!(missed, "webhooks fell behind, some events won't be delivered");
+                        continue
+                    }
+                };
+
+                if let Err(e) = dispatcher.dispatch(&event).await {
+                    tracing::warn!(error = %e, event = event.kind(), "couldn't queue webhook deliveries");
+                }
+            }
+        });
+    }
+
+    async fn dispatch(&self, event :&Event) -> Result<(), Box<dyn Error>> {
+        let mut conn = get_connection(&self.db).await?;
+
+        let subscribed :Vec<Webhook> = webhooks::table
+            .inner_join(webhooks_events::table)
+            .select(Webhook::as_select())
+            .filter(webhooks::columns::active.eq(true))
+            .filter(webhooks_events::columns::event.eq(event.kind()))
+        .load(&mut conn).await?;
+
+        for webhook in subscribed {
+            self.send(webhook, event, &mut conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes the remaining attempts of every pending delivery, e.g. ones a restart interrupted, in the background.
+    pub async fn resume(&self) -> Result<(), Box<dyn Error>> {
+        let mut conn = get_connection(&self.db).await?;
+        let pending_status = || webhook_deliveries::columns::status.eq(String::from(DeliveryStatus::Pending));
+
+        // Out of attempts already, if max_attempts was lowered since.
+        diesel::update(webhook_deliveries::table)
+            .filter(pending_status())
+            .filter(webhook_deliveries::columns::attempts.ge(self.config.max_attempts as i32))
+            .set(webhook_deliveries::columns::status.eq(String::from(DeliveryStatus::Failed)))
+        .execute(&mut conn).await?;
+
+        let pending :Vec<(WebhookDelivery, Webhook)> = webhook_deliveries::table
+            .inner_join(webhooks::table)
+            .select((WebhookDelivery::as_select(), Webhook::as_select()))
+            .filter(pending_status())
+            .order(webhook_deliveries::columns::id.asc())
+        .load(&mut conn).await?;
+
+        for (delivery, webhook) in pending {
+            tracing::info!(webhook = %webhook.name, delivery = delivery.id, "resuming a webhook delivery");
+            tokio::spawn(self.clone().deliver(webhook, delivery));
+        }
+
+        Ok(())
+    }
+
+    /// Records the delivery of the event and makes the attempts in the background.
+    pub async fn send<'a>(
+        &self,
+        webhook :Webhook,
+        event :&Event,
+        conn :&mut DbConnection<'a>
+    ) -> Result<WebhookDelivery, ApiError> {
+        let now = Utc::now().naive_utc();
+
+        let payload = match serde_json::to_string(&Payload { webhook: &webhook.name, occurred_at: now, event }) {
+            Ok(payload) => payload,
+            Err(e) => return Err(ApiError::Internal(format!("{}", e)))
+        };
+
+        let insert = WebhookDeliveryInsert {
+            webhook_id: webhook.id,
+            event: String::from(event.kind()),
+            payload,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            created_at: now
+        };
+
+        let delivery = transaction(conn, |conn| async move {
+            if let Err(e) = diesel::insert_into(webhook_deliveries::table)
+                .values(insert)
+            .execute(conn).await {
+                return Err(ApiError::Internal(format!("{}", e)))
+            }
+
+            let id = last_insert_id(conn).await?;
+
+            match webhook_deliveries::table
+                .select(WebhookDelivery::as_select())
+                .filter(webhook_deliveries::columns::id.eq(id))
+            .first(conn).await {
+                Ok(delivery) => Ok(delivery),
+                Err(e) => Err(ApiError::Internal(format!("{}", e)))
+            }
+        }.scope_boxed()).await?;
+
+        tokio::spawn(self.clone().deliver(webhook, delivery.clone()));
+
+        Ok(delivery)
+    }
+
+    #[tracing::instrument(name = "webhook", skip_all, fields(webhook = %webhook.name, delivery = delivery.id))]
+    async fn deliver(self, webhook :Webhook, delivery :WebhookDelivery) {
+        let signature = sign(&webhook.secret, &delivery.payload);
+        let mut delay = Duration::from_millis(self.config.retry_delay);
+
+        // A resumed delivery goes on from the attempts it already had, without waiting first.
+        let first = u32::try_from(delivery.attempts).unwrap_or(0) + 1;
+
+        for attempt in first..=self.config.max_attempts {
+            let res = self.client.post(&webhook.url)
+                .header("Content-Type", "application/json")
+                .header("X-Cherrydoor-Event", &delivery.event)
+                .header("X-Cherrydoor-Delivery", delivery.id)
+                .header("X-Cherrydoor-Signature", &signature)
+                .body(delivery.payload.clone())
+                .send()
+            .await;
+
+            let (response_status, error) = match res {
+                Ok(res) if res.status().is_success() => (Some(res.status().as_u16()), None),
+                Ok(res) => (Some(res.status().as_u16()), Some(format!("The receiver responded with {}.", res.status()))),
+                Err(e) => (None, Some(e.to_string()))
+            };
+
+            let status = match &error {
+                None => DeliveryStatus::Delivered,
+                Some(_) if attempt == self.config.max_attempts => DeliveryStatus::Failed,
+                Some(_) => DeliveryStatus::Pending
+            };
+
+            match &error {
+                None => tracing::debug!(attempt, "delivered"),
+                Some(e) => tracing::warn!(attempt, error = %e, "delivery failed")
+            }
+
+            if let Err(e) = self.record(delivery.id, status, attempt, response_status, error).await {
+                tracing::warn!(error = %e, "couldn't record the attempt");
+            }
+
+            if status != DeliveryStatus::Pending {
+                break
+            }
+
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    async fn record(
+        &self,
+        id :i32,
+        status :DeliveryStatus,
+        attempts :u32,
+        response_status :Option<u16>,
+        error :Option<String>
+    ) -> Result<(), Box<dyn Error>> {
+        let mut conn = get_connection(&self.db).await?;
+
+        diesel::update(webhook_deliveries::table)
+            .filter(webhook_deliveries::columns::id.eq(id))
+            .set((
+                webhook_deliveries::columns::status.eq(String::from(status)),
+                webhook_deliveries::columns::attempts.eq(attempts as i32),
+                webhook_deliveries::columns::response_status.eq(response_status.map(i32::from)),
+                webhook_deliveries::columns::error.eq(error.map(|e| e.chars().take(ERROR_MAX_LEN).collect::<String>())),
+                webhook_deliveries::columns::last_attempt_at.eq(Utc::now().naive_utc())
+            ))
+        .execute(&mut conn).await?;
+
+        Ok(())
+    }
+}